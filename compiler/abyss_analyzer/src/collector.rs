@@ -8,27 +8,33 @@ impl Collector {
     pub fn collect(program: &FlatProgram) -> Result<Context, String> {
         let mut ctx = Context::new();
 
+        for enum_def in &program.enums {
+            if !enum_def.has_payload() {
+                ctx.register_c_enum(enum_def.name.clone());
+            }
+        }
+
         for struct_def in &program.structs {
             let mut fields = Vec::new();
             for (field_name, field_type) in &struct_def.fields {
-                let lir_type = Self::convert_type(field_type)?;
+                let lir_type = Self::convert_type(&ctx, field_type)?;
                 fields.push((field_name.clone(), lir_type));
             }
             ctx.register_struct(struct_def.name.clone(), fields);
         }
 
         for static_def in &program.statics {
-            let lir_type = Self::convert_type(&static_def.ty)?;
+            let lir_type = Self::convert_type(&ctx, &static_def.ty)?;
             ctx.declare_global(static_def.name.clone(), lir_type);
         }
 
         for func_def in &program.functions {
             let mut params = Vec::new();
             for (_, param_type) in &func_def.params {
-                params.push(Self::convert_type(param_type)?);
+                params.push(Self::convert_type(&ctx, param_type)?);
             }
 
-            let ret_ty = Self::convert_type(&func_def.return_type)?;
+            let ret_ty = Self::convert_type(&ctx, &func_def.return_type)?;
 
             ctx.register_function(func_def.name.clone(), params, ret_ty);
         }
@@ -36,7 +42,7 @@ impl Collector {
         Ok(ctx)
     }
 
-    fn convert_type(ast_type: &Type) -> Result<LirType, String> {
+    fn convert_type(ctx: &Context, ast_type: &Type) -> Result<LirType, String> {
         match ast_type {
             Type::U8 => Ok(LirType::U8),
             Type::U16 => Ok(LirType::U16),
@@ -55,15 +61,23 @@ impl Collector {
             Type::Void => Ok(LirType::Void),
 
             Type::Pointer(inner) => {
-                let inner_lir = Self::convert_type(inner)?;
+                let inner_lir = Self::convert_type(ctx, inner)?;
                 Ok(LirType::Pointer(Box::new(inner_lir)))
             }
 
+            Type::Optional(pointer) => Self::convert_type(ctx, pointer),
+            Type::Null => Ok(LirType::U8),
+
             Type::Const(inner) => {
-                let inner_lir = Self::convert_type(inner)?;
+                let inner_lir = Self::convert_type(ctx, inner)?;
                 Ok(LirType::Const(Box::new(inner_lir)))
             }
 
+            Type::Array(inner, size) => {
+                let inner_lir = Self::convert_type(ctx, inner)?;
+                Ok(LirType::Array(Box::new(inner_lir), *size))
+            }
+
             Type::Struct(path, generics) => {
                 if !generics.is_empty() {
                     return Err(format!(
@@ -73,13 +87,28 @@ impl Collector {
                 }
 
                 let struct_name = path.join("_");
+                if ctx.is_c_enum(&struct_name) {
+                    return Ok(LirType::I64);
+                }
                 Ok(LirType::Struct(struct_name))
             }
 
+            Type::Function(args, ret, _) => {
+                let mut lir_args = Vec::new();
+                for arg in args {
+                    lir_args.push(Self::convert_type(ctx, arg)?);
+                }
+                let lir_ret = Self::convert_type(ctx, ret)?;
+                Ok(LirType::FunctionPtr(lir_args, Box::new(lir_ret)))
+            }
+
+            Type::Dyn(path) => Ok(LirType::Struct(format!("__Dyn_{}", path.join("__")))),
+            Type::Closure(..) => Ok(LirType::Struct(format!("__{}", ast_type.get_name()))),
+
             Type::Union(types) => {
                 let mut lir_types = Vec::new();
                 for t in types {
-                    lir_types.push(Self::convert_type(t)?);
+                    lir_types.push(Self::convert_type(ctx, t)?);
                 }
                 Ok(LirType::Union(lir_types))
             }
@@ -1,8 +1,13 @@
-use crate::hir::FlatProgram;
-use abyss_parser::ast::{
-    Expr, FunctionBody, FunctionDef, Lit, Pattern, Program, StaticDef, Stmt, StructDef, Type,
+use crate::{hir::FlatProgram, type_checker::walk_stmt};
+use abyss_parser::{
+    ast::{
+        Closure, EnumDef, Expr, ExprKind, FunctionBody, FunctionDef, Lit, Path, Pattern, Program,
+        StaticDef, Stmt, StmtKind, StructDef, TraitDef, TraitImpl, Type,
+    },
+    diagnostic::Diagnostic,
+    source_map::Span,
 };
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 #[derive(Clone, Debug)]
 pub struct SymbolInfo {
@@ -14,6 +19,8 @@ pub struct SymbolInfo {
 #[derive(Clone, Debug)]
 pub enum SymbolKind {
     Struct,
+    Enum,
+    Trait,
     Function,
     Static,
 }
@@ -28,6 +35,7 @@ pub struct Flattener {
     scopes: Vec<Scope>,
     global_symbols: HashMap<String, SymbolInfo>,
     output: FlatProgram,
+    diagnostics: Vec<Diagnostic>,
 }
 
 impl Flattener {
@@ -40,14 +48,19 @@ impl Flattener {
             }],
             global_symbols: HashMap::new(),
             output: FlatProgram::new(),
+            diagnostics: Vec::new(),
         }
     }
 
-    pub fn flatten(mut self, program: Program) -> FlatProgram {
+    pub fn flatten(&mut self, program: Program) -> FlatProgram {
         self.collect_definitions(&program, &vec![]);
 
         self.visit_program(program);
-        self.output
+        std::mem::replace(&mut self.output, FlatProgram::new())
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
     }
 
     fn collect_definitions(&mut self, program: &Program, current_path: &[String]) {
@@ -69,6 +82,30 @@ impl Flattener {
             );
         }
 
+        for e in &program.enums {
+            let mangled = format!("{}{}", prefix, e.name);
+            self.global_symbols.insert(
+                mangled.clone(),
+                SymbolInfo {
+                    mangled_name: mangled,
+                    is_pub: e.is_pub,
+                    kind: SymbolKind::Enum,
+                },
+            );
+        }
+
+        for t in &program.traits {
+            let mangled = format!("{}{}", prefix, t.name);
+            self.global_symbols.insert(
+                mangled.clone(),
+                SymbolInfo {
+                    mangled_name: mangled,
+                    is_pub: t.is_pub,
+                    kind: SymbolKind::Trait,
+                },
+            );
+        }
+
         for f in &program.functions {
             let is_extern = matches!(f.body, FunctionBody::Extern);
 
@@ -142,7 +179,37 @@ impl Flattener {
         name.to_string()
     }
 
-    fn check_visibility(&self, target_mangled: &str) {
+    /// Mangles a path naming a global, such as a trait in a bound.
+    fn resolve_path(&mut self, path: &[String], span: Span) -> String {
+        let resolved = if let [name] = path {
+            self.resolve_name(name)
+        } else {
+            path.join("__")
+        };
+        if resolved.contains("__") {
+            self.check_visibility(&resolved, span);
+        }
+        resolved
+    }
+
+    /// Mangles `Enum::Variant` to `Enum__Variant`, resolving the enum like
+    /// any other name so variants are reachable through imports.
+    fn resolve_enum_variant(&self, path: &[String]) -> Option<String> {
+        let [enum_name, variant] = path else {
+            return None;
+        };
+
+        let resolved = self.resolve_name(enum_name);
+        match self.global_symbols.get(&resolved) {
+            Some(SymbolInfo {
+                kind: SymbolKind::Enum,
+                ..
+            }) => Some(format!("{}__{}", resolved, variant)),
+            _ => None,
+        }
+    }
+
+    fn check_visibility(&mut self, target_mangled: &str, span: Span) {
         if let Some(info) = self.global_symbols.get(target_mangled) {
             if !info.is_pub {
                 let current_context = if self.path.is_empty() {
@@ -159,27 +226,30 @@ impl Flattener {
                 let has_access = current_context == target_module_path;
 
                 if !has_access {
-                    eprintln!(
-                        "Error: Symbol '{}' is private and cannot be accessed from module '{}'",
-                        target_mangled,
-                        if current_context.is_empty() {
-                            "Root".to_string()
-                        } else {
-                            current_context
-                        }
+                    let module = if current_context.is_empty() {
+                        "Root".to_string()
+                    } else {
+                        current_context
+                    };
+                    self.diagnostics.push(
+                        Diagnostic::error(format!(
+                            "symbol '{}' is private and cannot be accessed from module '{}'",
+                            target_mangled, module
+                        ))
+                        .with_span(span),
                     );
                 }
             }
         }
     }
 
-    fn process_top_level_imports(&mut self, uses: Vec<Vec<String>>) {
-        for path in uses {
+    fn process_top_level_imports(&mut self, uses: Vec<(Path, Span)>) {
+        for (path, span) in uses {
             let mangled_target = path.join("__");
 
             let local_alias = path.last().unwrap().clone();
 
-            self.check_visibility(&mangled_target);
+            self.check_visibility(&mangled_target, span);
 
             self.add_import(local_alias, mangled_target);
         }
@@ -201,6 +271,16 @@ impl Flattener {
             self.add_local_rename(u.name.clone(), mangled);
         }
 
+        for e in &program.enums {
+            let mangled = format!("{}{}", prefix, e.name);
+            self.add_local_rename(e.name.clone(), mangled);
+        }
+
+        for t in &program.traits {
+            let mangled = format!("{}{}", prefix, t.name);
+            self.add_local_rename(t.name.clone(), mangled);
+        }
+
         for f in &program.functions {
             let is_extern = matches!(f.body, FunctionBody::Extern);
 
@@ -221,6 +301,8 @@ impl Flattener {
 
         self.process_modules(program.modules);
         self.process_top_level_structs(program.structs);
+        self.process_top_level_enums(program.enums);
+        self.process_top_level_traits(program.traits, program.trait_impls);
         self.process_top_level_statics(program.statics);
         self.process_top_level_functions(program.functions);
     }
@@ -246,6 +328,48 @@ impl Flattener {
         }
     }
 
+    fn process_top_level_enums(&mut self, enums: Vec<EnumDef>) {
+        for mut e in enums {
+            e.name = self.resolve_name(&e.name);
+
+            for variant in &mut e.variants {
+                for ty in &mut variant.payload {
+                    self.rename_in_type(ty);
+                }
+            }
+            self.output.enums.push(e);
+        }
+    }
+
+    fn process_top_level_traits(&mut self, traits: Vec<TraitDef>, impls: Vec<TraitImpl>) {
+        for mut t in traits {
+            t.name = self.resolve_name(&t.name);
+
+            for method in &mut t.methods {
+                self.rename_signature(method);
+            }
+            self.output.traits.push(t);
+        }
+
+        for mut i in impls {
+            i.trait_path = vec![self.resolve_path(&i.trait_path, i.span)];
+            self.rename_in_type(&mut i.ty);
+            self.output.trait_impls.push(i);
+        }
+    }
+
+    /// Renames the types and bounds in a function's signature.
+    fn rename_signature(&mut self, func: &mut FunctionDef) {
+        for (_arg_name, ty) in &mut func.params {
+            self.rename_in_type(ty);
+        }
+        self.rename_in_type(&mut func.return_type);
+
+        for (_, trait_path) in &mut func.bounds {
+            *trait_path = vec![self.resolve_path(trait_path, func.span)];
+        }
+    }
+
     fn process_top_level_statics(&mut self, statics: Vec<StaticDef>) {
         for mut s in statics {
             s.name = self.resolve_name(&s.name);
@@ -262,20 +386,17 @@ impl Flattener {
             func.name = resolved_name;
 
             self.enter_scope();
+            self.rename_signature(&mut func);
 
-            for (_arg_name, ty) in &mut func.params {
-                self.rename_in_type(ty);
-            }
-            self.rename_in_type(&mut func.return_type);
-
+            lower_nested_fns(&mut func);
             if let FunctionBody::UserDefined(ref mut stmts) = func.body {
                 for stmt in stmts.iter() {
-                    match stmt {
-                        Stmt::FunctionDef(inner) => {
+                    match &stmt.kind {
+                        StmtKind::FunctionDef(inner) => {
                             let mangled = format!("{}__{}", func.name, inner.name);
                             self.add_local_rename(inner.name.clone(), mangled);
                         }
-                        Stmt::StructDef(inner) => {
+                        StmtKind::StructDef(inner) => {
                             let mangled = format!("{}__{}", func.name, inner.name);
                             self.add_local_rename(inner.name.clone(), mangled);
                         }
@@ -305,16 +426,16 @@ impl Flattener {
 
     fn process_stmts(&mut self, stmts: &mut [Stmt]) {
         for stmt in stmts {
-            match stmt {
-                Stmt::Use(path) => {
+            match &mut stmt.kind {
+                StmtKind::Use(path) => {
                     let mangled_target = path.join("__");
                     let local_alias = path.last().unwrap().clone();
 
-                    self.check_visibility(&mangled_target);
+                    self.check_visibility(&mangled_target, stmt.span);
 
                     self.add_import(local_alias, mangled_target);
                 }
-                Stmt::Let(_name, ty, expr) => {
+                StmtKind::Let(_name, ty, expr) => {
                     if let Some(t) = ty {
                         self.rename_in_type(t);
                     }
@@ -336,23 +457,20 @@ impl Flattener {
         }
 
         self.enter_scope();
-
-        for (_arg_name, ty) in &mut func.params {
-            self.rename_in_type(ty);
-        }
-        self.rename_in_type(&mut func.return_type);
+        self.rename_signature(&mut func);
 
         let mut extracted_inner_funcs = Vec::new();
 
+        lower_nested_fns(&mut func);
         if let FunctionBody::UserDefined(ref mut stmts) = func.body {
             for stmt in stmts.iter() {
-                match stmt {
-                    Stmt::FunctionDef(inner) => {
+                match &stmt.kind {
+                    StmtKind::FunctionDef(inner) => {
                         let inner_name = &inner.name;
                         let mangled = format!("{}__{}", func.name, inner_name);
                         self.add_local_rename(inner_name.clone(), mangled);
                     }
-                    Stmt::StructDef(inner) => {
+                    StmtKind::StructDef(inner) => {
                         let inner_name = &inner.name;
                         let mangled = format!("{}__{}", func.name, inner_name);
                         self.add_local_rename(inner_name.clone(), mangled);
@@ -395,8 +513,8 @@ impl Flattener {
         let mut inner_structs = Vec::new();
 
         for stmt in stmts.drain(..) {
-            match stmt {
-                Stmt::FunctionDef(inner_func_box) => {
+            match stmt.kind {
+                StmtKind::FunctionDef(inner_func_box) => {
                     let mut inner_func = *inner_func_box;
                     let old_name = inner_func.name.clone();
                     let new_name = format!("{}__{}", parent_mangled_name, old_name);
@@ -404,48 +522,49 @@ impl Flattener {
 
                     inner_funcs.push(inner_func);
                 }
-                Stmt::StructDef(inner_struct_box) => {
+                StmtKind::StructDef(inner_struct_box) => {
                     let mut inner_struct = *inner_struct_box;
                     let old_name = inner_struct.name.clone();
                     let new_name = format!("{}__{}", parent_mangled_name, old_name);
                     inner_struct.name = new_name;
                     inner_structs.push(inner_struct);
                 }
-                _ => cleaned_stmts.push(stmt),
+                kind => cleaned_stmts.push(Stmt::new(kind, stmt.span)),
             }
         }
 
         (inner_funcs, inner_structs, cleaned_stmts)
     }
 
-    fn rename_in_stmt(&self, stmt: &mut Stmt) {
-        match stmt {
-            Stmt::Let(_, Some(ty), Some(expr)) | Stmt::Const(_, Some(ty), Some(expr)) => {
+    fn rename_in_stmt(&mut self, stmt: &mut Stmt) {
+        match &mut stmt.kind {
+            StmtKind::Let(_, Some(ty), Some(expr)) | StmtKind::Const(_, Some(ty), Some(expr)) => {
                 self.rename_in_type(ty);
                 self.rename_in_expr(expr);
             }
-            Stmt::Let(_, Some(ty), None) | Stmt::Const(_, Some(ty), None) => {
+            StmtKind::Let(_, Some(ty), None) | StmtKind::Const(_, Some(ty), None) => {
                 self.rename_in_type(ty);
             }
-            Stmt::Ret(expr) | Stmt::Expr(expr) => {
+            StmtKind::Ret(expr) | StmtKind::Expr(expr) => {
                 self.rename_in_expr(expr);
             }
-            Stmt::Assign(lhs, rhs) => {
+            StmtKind::Assign(lhs, rhs) => {
                 self.rename_in_expr(lhs);
                 self.rename_in_expr(rhs);
             }
-            Stmt::If(cond, then_stmt, else_stmt) => {
+            StmtKind::If(cond, then_stmt, else_stmt) => {
                 self.rename_in_expr(cond);
                 self.rename_in_stmt(then_stmt);
                 if let Some(else_s) = else_stmt {
                     self.rename_in_stmt(else_s);
                 }
             }
-            Stmt::While(cond, body) => {
+            StmtKind::While(cond, body) => {
                 self.rename_in_expr(cond);
                 self.rename_in_stmt(body);
             }
-            Stmt::Block(stmts) => {
+            StmtKind::Defer(body) => self.rename_in_stmt(body),
+            StmtKind::Block(stmts) => {
                 for s in stmts {
                     self.rename_in_stmt(s);
                 }
@@ -454,22 +573,25 @@ impl Flattener {
         }
     }
 
-    fn rename_in_expr(&self, expr: &mut Expr) {
-        match expr {
-            Expr::Ident(path) => {
+    fn rename_in_expr(&mut self, expr: &mut Expr) {
+        let span = expr.span;
+        match &mut expr.kind {
+            ExprKind::Ident(path) => {
                 if path.len() == 1 {
                     let resolved = self.resolve_name(&path[0]);
                     if resolved.contains("__") {
-                        self.check_visibility(&resolved);
+                        self.check_visibility(&resolved, span);
                     }
                     path[0] = resolved;
+                } else if let Some(variant) = self.resolve_enum_variant(path) {
+                    *path = vec![variant];
                 } else {
                     let full_mangled = path.join("__");
-                    self.check_visibility(&full_mangled);
+                    self.check_visibility(&full_mangled, span);
                     *path = vec![full_mangled];
                 }
             }
-            Expr::Call(callee, args, generics) => {
+            ExprKind::Call(callee, args, generics) => {
                 self.rename_in_expr(callee);
                 for arg in args {
                     self.rename_in_expr(arg);
@@ -478,7 +600,7 @@ impl Flattener {
                     self.rename_in_type(g);
                 }
             }
-            Expr::StructInit(path, fields, generics) => {
+            ExprKind::StructInit(path, fields, generics) => {
                 if path.len() == 1 {
                     let resolved = self.resolve_name(&path[0]);
                     path[0] = resolved;
@@ -494,7 +616,7 @@ impl Flattener {
                     self.rename_in_type(g);
                 }
             }
-            Expr::UnionInit(path, variants) => {
+            ExprKind::UnionInit(path, variants) => {
                 if path.len() == 1 {
                     let resolved = self.resolve_name(&path[0]);
                     path[0] = resolved;
@@ -507,24 +629,29 @@ impl Flattener {
                     self.rename_in_expr(val_expr);
                 }
             }
-            Expr::Binary(left, _, right) => {
+            ExprKind::Binary(left, _, right) | ExprKind::OrElse(left, right) => {
                 self.rename_in_expr(left);
                 self.rename_in_expr(right);
             }
-            Expr::Unary(_, operand) => {
+            ExprKind::Unary(_, operand) => {
                 self.rename_in_expr(operand);
             }
-            Expr::Index(arr, idx) => {
+            ExprKind::Index(arr, idx) => {
                 self.rename_in_expr(arr);
                 self.rename_in_expr(idx);
             }
-            Expr::Deref(inner) | Expr::AddrOf(inner) => {
+            ExprKind::Range(lo, hi) => {
+                for bound in [lo, hi].into_iter().flatten() {
+                    self.rename_in_expr(bound);
+                }
+            }
+            ExprKind::Deref(inner) | ExprKind::AddrOf(inner) | ExprKind::Try(inner) => {
                 self.rename_in_expr(inner);
             }
-            Expr::Member(obj, _) => {
+            ExprKind::Member(obj, _) => {
                 self.rename_in_expr(obj);
             }
-            Expr::MethodCall(obj, _, args, generics) => {
+            ExprKind::MethodCall(obj, _, args, generics) => {
                 self.rename_in_expr(obj);
                 for arg in args {
                     self.rename_in_expr(arg);
@@ -533,44 +660,45 @@ impl Flattener {
                     self.rename_in_type(g);
                 }
             }
-            Expr::Cast(inner, ty) => {
+            ExprKind::Cast(inner, ty) => {
                 self.rename_in_expr(inner);
                 self.rename_in_type(ty);
             }
-            Expr::Is(inner, ty) => {
+            ExprKind::Is(inner, ty) => {
                 self.rename_in_expr(inner);
                 self.rename_in_type(ty);
             }
-            Expr::SizeOf(ty) => {
+            ExprKind::SizeOf(ty) | ExprKind::Reflect(_, ty) => {
                 self.rename_in_type(ty);
             }
-            Expr::Match(expr, arms) => {
+            ExprKind::Match(expr, arms) => {
                 self.rename_in_expr(expr);
-                for (pattern, arm_expr) in arms {
-                    if let Pattern::Variant(path, _) = pattern {
-                        if path.len() == 1 {
-                            let resolved = self.resolve_name(&path[0]);
-                            if resolved.contains("__") {
-                                self.check_visibility(&resolved);
-                            }
-                            path[0] = resolved;
-                        } else {
-                            let full_mangled = path.join("__");
-                            self.check_visibility(&full_mangled);
-                            *path = vec![full_mangled];
-                        }
+                for arm in arms {
+                    if let Pattern::Variant(ty, _) = &mut arm.pattern {
+                        self.rename_in_type(ty);
+                    }
+                    for stmt in &mut arm.body {
+                        self.rename_in_stmt(stmt);
                     }
-
-                    self.rename_in_expr(arm_expr);
                 }
             }
 
-            Expr::Ternary(cond, t_expr, f_expr) => {
+            ExprKind::Ternary(cond, t_expr, f_expr) => {
                 self.rename_in_expr(cond);
                 self.rename_in_expr(t_expr);
                 self.rename_in_expr(f_expr);
             }
-            Expr::Lit(lit) => {
+            ExprKind::Closure(closure) => {
+                for (_, ty) in &mut closure.params {
+                    self.rename_in_type(ty);
+                }
+                self.rename_in_type(&mut closure.return_type);
+
+                self.enter_scope();
+                self.process_stmts(&mut closure.body);
+                self.exit_scope();
+            }
+            ExprKind::Lit(lit) => {
                 if let Lit::Array(exprs) = lit {
                     for e in exprs {
                         self.rename_in_expr(e);
@@ -580,12 +708,14 @@ impl Flattener {
         }
     }
 
-    fn rename_in_type(&self, ty: &mut Type) {
+    fn rename_in_type(&mut self, ty: &mut Type) {
         match ty {
             Type::Struct(path, generics) => {
                 if path.len() == 1 {
                     let resolved = self.resolve_name(&path[0]);
                     path[0] = resolved;
+                } else if let Some(variant) = self.resolve_enum_variant(path) {
+                    *path = vec![variant];
                 } else if path.len() > 1 {
                     let new_name = path.join("__");
                     *path = vec![new_name];
@@ -594,10 +724,12 @@ impl Flattener {
                     self.rename_in_type(g);
                 }
             }
-            Type::Pointer(inner) => self.rename_in_type(inner),
+            Type::Pointer(inner) | Type::Optional(inner) => self.rename_in_type(inner),
             Type::Const(inner) => self.rename_in_type(inner),
-            Type::Array(inner, _) => self.rename_in_type(inner),
-            Type::Function(args, ret, _generics) => {
+            Type::Array(inner, _) | Type::ConstArray(inner, _) | Type::Slice(inner) => {
+                self.rename_in_type(inner)
+            }
+            Type::Function(args, ret, _) | Type::Closure(args, ret) => {
                 for arg in args {
                     self.rename_in_type(arg);
                 }
@@ -609,7 +741,212 @@ impl Flattener {
                     self.rename_in_type(t);
                 }
             }
+            Type::Dyn(path) => {
+                let resolved = if let [name] = path.as_slice() {
+                    self.resolve_name(name)
+                } else {
+                    path.join("__")
+                };
+                *path = vec![resolved];
+            }
             _ => {}
         }
     }
 }
+
+/// Turns the `fn`s nested in `func` that need its locals into closures, and
+/// moves the others nested in blocks up to its body, whose `fn`s become
+/// functions of their own.
+fn lower_nested_fns(func: &mut FunctionDef) {
+    let FunctionBody::UserDefined(stmts) = &mut func.body else {
+        return;
+    };
+    let mut locals: HashSet<String> = func.params.iter().map(|(n, _)| n.clone()).collect();
+    collect_locals(stmts, &mut locals);
+
+    // A `fn` calling one that becomes a closure has to reach it as a local too.
+    let mut nested = Vec::new();
+    for stmt in stmts.iter() {
+        nested_fns(stmt, &mut nested);
+    }
+    loop {
+        let closures: Vec<String> = nested
+            .iter()
+            .filter(|f| !locals.contains(&f.name) && uses_locals(f, &locals))
+            .map(|f| f.name.clone())
+            .collect();
+        if closures.is_empty() {
+            break;
+        }
+        locals.extend(closures);
+    }
+
+    let mut hoisted = Vec::new();
+    for stmt in stmts.iter_mut() {
+        lower_nested_fn(stmt, &locals, &mut hoisted, true);
+    }
+    stmts.extend(hoisted);
+}
+
+/// Adds the names `stmts` declare: with `let` or `const`, which is also how
+/// `for` binds its variable, and in the patterns of `match` arms, in nested
+/// blocks too.
+fn collect_locals(stmts: &[Stmt], locals: &mut HashSet<String>) {
+    fn declared(stmt: &Stmt, locals: &mut HashSet<String>) {
+        match &stmt.kind {
+            StmtKind::Let(name, ..) | StmtKind::Const(name, ..) => {
+                locals.insert(name.clone());
+            }
+            StmtKind::Block(stmts) => {
+                for s in stmts {
+                    declared(s, locals);
+                }
+            }
+            StmtKind::If(_, then_b, else_b) => {
+                declared(then_b, locals);
+                if let Some(e) = else_b {
+                    declared(e, locals);
+                }
+            }
+            StmtKind::While(_, body) | StmtKind::Defer(body) => declared(body, locals),
+            _ => {}
+        }
+    }
+
+    for stmt in stmts {
+        declared(stmt, locals);
+        walk_stmt(stmt, &mut |expr| {
+            if let ExprKind::Match(_, arms) = &expr.kind {
+                for arm in arms {
+                    if let Pattern::Variant(_, bindings) = &arm.pattern {
+                        locals.extend(bindings.iter().cloned());
+                    }
+                    for s in &arm.body {
+                        declared(s, locals);
+                    }
+                }
+            }
+        });
+    }
+}
+
+/// Adds the non-generic `fn`s defined in `stmt`, in nested blocks and
+/// `match` arms too.
+fn nested_fns<'a>(stmt: &'a Stmt, found: &mut Vec<&'a FunctionDef>) {
+    match &stmt.kind {
+        StmtKind::FunctionDef(func) if func.generics.is_empty() => found.push(func),
+        StmtKind::Block(stmts) => {
+            for s in stmts {
+                nested_fns(s, found);
+            }
+        }
+        StmtKind::If(_, then_b, else_b) => {
+            nested_fns(then_b, found);
+            if let Some(e) = else_b {
+                nested_fns(e, found);
+            }
+        }
+        StmtKind::While(_, body) | StmtKind::Defer(body) => nested_fns(body, found),
+        StmtKind::Expr(value)
+        | StmtKind::Let(_, _, Some(value))
+        | StmtKind::Ret(value)
+        | StmtKind::Assign(_, value) => {
+            if let ExprKind::Match(_, arms) = &value.kind {
+                for s in arms.iter().flat_map(|arm| &arm.body) {
+                    nested_fns(s, found);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Rewrites a nested `fn name` into `let name = fn(..) { .. }` when `locals`
+/// holds its name, as it does for those naming one of the enclosing
+/// function's locals, which only a closure can reach. Any other one nested in
+/// a block or `match` arm is moved to `hoisted`, as only the body's own
+/// statements are hoisted into functions. Generic ones stay functions.
+fn lower_nested_fn(
+    stmt: &mut Stmt,
+    locals: &HashSet<String>,
+    hoisted: &mut Vec<Stmt>,
+    top_level: bool,
+) {
+    match &mut stmt.kind {
+        StmtKind::FunctionDef(func) if func.generics.is_empty() => {
+            if !matches!(func.body, FunctionBody::UserDefined(_)) {
+                return;
+            }
+            if !locals.contains(&func.name) {
+                if !top_level {
+                    let placeholder = Stmt::new(StmtKind::Block(Vec::new()), stmt.span);
+                    hoisted.push(std::mem::replace(stmt, placeholder));
+                }
+                return;
+            }
+            let kind = std::mem::replace(&mut stmt.kind, StmtKind::Block(Vec::new()));
+            let StmtKind::FunctionDef(func) = kind else {
+                unreachable!()
+            };
+            let FunctionBody::UserDefined(body) = func.body else {
+                unreachable!()
+            };
+            let closure = Closure {
+                captures: None,
+                params: func.params,
+                return_type: func.return_type,
+                body,
+            };
+            stmt.kind = StmtKind::Let(
+                func.name,
+                None,
+                Some(Expr::new(ExprKind::Closure(Box::new(closure)), func.span)),
+            );
+        }
+        StmtKind::Block(stmts) => {
+            for s in stmts {
+                lower_nested_fn(s, locals, hoisted, false);
+            }
+        }
+        StmtKind::If(_, then_b, else_b) => {
+            lower_nested_fn(then_b, locals, hoisted, false);
+            if let Some(e) = else_b {
+                lower_nested_fn(e, locals, hoisted, false);
+            }
+        }
+        StmtKind::While(_, body) | StmtKind::Defer(body) => {
+            lower_nested_fn(body, locals, hoisted, false)
+        }
+        StmtKind::Expr(value)
+        | StmtKind::Let(_, _, Some(value))
+        | StmtKind::Ret(value)
+        | StmtKind::Assign(_, value) => {
+            if let ExprKind::Match(_, arms) = &mut value.kind {
+                for s in arms.iter_mut().flat_map(|arm| &mut arm.body) {
+                    lower_nested_fn(s, locals, hoisted, false);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Whether the nested `func` names one of `locals` that is not its own parameter.
+fn uses_locals(func: &FunctionDef, locals: &HashSet<String>) -> bool {
+    let FunctionBody::UserDefined(body) = &func.body else {
+        return false;
+    };
+    let mut found = false;
+    for stmt in body {
+        walk_stmt(stmt, &mut |expr| {
+            if let ExprKind::Ident(path) = &expr.kind
+                && let [name] = path.as_slice()
+                && locals.contains(name)
+                && !func.params.iter().any(|(param, _)| param == name)
+            {
+                found = true;
+            }
+        });
+    }
+    found
+}
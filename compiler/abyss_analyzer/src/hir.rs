@@ -1,11 +1,18 @@
-use abyss_parser::ast::{FunctionDef, StaticDef, StructDef, UnionDef};
+use abyss_parser::ast::{
+    EnumDef, FunctionDef, StaticDef, StructDef, TraitDef, TraitImpl, UnionDef,
+};
 
 #[derive(Debug, Clone)]
 pub struct FlatProgram {
     pub functions: Vec<FunctionDef>,
     pub structs: Vec<StructDef>,
     pub unions: Vec<UnionDef>,
+    pub enums: Vec<EnumDef>,
+    pub traits: Vec<TraitDef>,
+    pub trait_impls: Vec<TraitImpl>,
     pub statics: Vec<StaticDef>,
+    /// The `const` vtables behind `&dyn` values, one per trait and type.
+    pub vtables: Vec<StaticDef>,
     pub union_struct_defs: Vec<StructDef>,
 }
 
@@ -15,7 +22,11 @@ impl FlatProgram {
             functions: vec![],
             structs: vec![],
             unions: vec![],
+            enums: vec![],
+            traits: vec![],
+            trait_impls: vec![],
             statics: vec![],
+            vtables: vec![],
             union_struct_defs: vec![],
         }
     }
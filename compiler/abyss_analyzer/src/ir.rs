@@ -3,18 +3,35 @@ use std::collections::HashMap;
 use crate::{
     hir::FlatProgram,
     lir::{
-        LirExpr, LirFunctionDef, LirGlobalVar, LirLiteral, LirProgram, LirStmt, LirStructDef,
-        LirType, LirUnionDef,
+        LirExpr, LirExprKind, LirFunctionDef, LirGlobalVar, LirLiteral, LirProgram, LirStmt,
+        LirStmtKind, LirStructDef, LirType, LirUnionDef,
     },
     symbols::Context,
 };
-use abyss_parser::ast::{
-    Expr, FunctionBody, FunctionDef, Lit, StaticDef, Stmt, StructDef, Type, UnionDef,
+use abyss_parser::{
+    ast::{
+        BinaryOp, Expr, ExprKind, FunctionBody, FunctionDef, Lit, MatchArm, Pattern, StaticDef,
+        Stmt, StmtKind, StructDef, Type, UnionDef,
+    },
+    source_map::Span,
 };
 
 pub struct Ir {
     ctx: Context,
     local_scope: Vec<HashMap<String, LirType>>,
+    /// The statements deferred so far in each enclosing block, innermost last.
+    defers: Vec<Vec<Stmt>>,
+    /// How many blocks of `defers` enclose each loop being transpiled.
+    loops: Vec<usize>,
+    return_type: LirType,
+}
+
+/// Where the value of a `match` arm goes.
+#[derive(Clone)]
+enum ArmValue {
+    Discard,
+    Assign(LirExpr),
+    Return,
 }
 
 impl Ir {
@@ -22,6 +39,9 @@ impl Ir {
         let mut ir_builder = Ir {
             ctx,
             local_scope: vec![std::collections::HashMap::new()],
+            defers: Vec::new(),
+            loops: Vec::new(),
+            return_type: LirType::Void,
         };
 
         let mut lir = LirProgram::default();
@@ -42,6 +62,12 @@ impl Ir {
             lir.globals.push(ir_builder.transpile_static(s));
         }
 
+        for v in &flat_ast.vtables {
+            let mut vtable = ir_builder.transpile_static(v);
+            vtable.is_const = true;
+            lir.globals.push(vtable);
+        }
+
         for func in &flat_ast.functions {
             lir.functions.push(ir_builder.transpile_function(func));
         }
@@ -73,12 +99,12 @@ impl Ir {
     }
 
     fn resolve_expr_type(&self, expr: &Expr) -> LirType {
-        match expr {
-            Expr::Ident(path) => {
+        match &expr.kind {
+            ExprKind::Ident(path) => {
                 let name = path.last().expect("Empty path").clone();
                 self.lookup_type(&name).unwrap_or(LirType::Void)
             }
-            Expr::Member(lhs, field_name) => {
+            ExprKind::Member(lhs, field_name) => {
                 let lhs_type = self.resolve_expr_type(lhs);
                 if let LirType::Struct(struct_name) = lhs_type {
                     self.ctx
@@ -96,8 +122,16 @@ impl Ir {
                     LirType::Void
                 }
             }
-            Expr::StructInit(path, _, _) => LirType::Struct(path.join("__")),
-            Expr::MethodCall(lhs, method_name, _, _) => {
+            ExprKind::StructInit(path, _, _) => LirType::Struct(path.join("__")),
+            ExprKind::Deref(inner) => match self.resolve_expr_type(inner) {
+                LirType::Pointer(pointee) => *pointee,
+                _ => LirType::Void,
+            },
+            ExprKind::Index(arr, _) => match self.resolve_expr_type(arr) {
+                LirType::Pointer(elem) | LirType::Array(elem, _) => *elem,
+                _ => LirType::Void,
+            },
+            ExprKind::MethodCall(lhs, method_name, _, _) => {
                 let lhs_type = self.resolve_expr_type(lhs);
                 let struct_name = match &lhs_type {
                     LirType::Struct(n) => n,
@@ -118,12 +152,12 @@ impl Ir {
                     LirType::Void
                 }
             }
-            Expr::Lit(Lit::Int(_)) => LirType::I64,
-            Expr::Lit(Lit::Float(_)) => LirType::F64,
-            Expr::Lit(Lit::Bool(_)) => LirType::Bool,
-            Expr::Cast(_, ty) => self.transpile_type(ty),
-            Expr::Is(_, _) => LirType::Bool,
-            Expr::Ternary(_, true_expr, _) => self.resolve_expr_type(true_expr),
+            ExprKind::Lit(Lit::Int(_)) => LirType::I64,
+            ExprKind::Lit(Lit::Float(_)) => LirType::F64,
+            ExprKind::Lit(Lit::Bool(_)) => LirType::Bool,
+            ExprKind::Cast(_, ty) => self.transpile_type(ty),
+            ExprKind::Is(_, _) => LirType::Bool,
+            ExprKind::Ternary(_, true_expr, _) => self.resolve_expr_type(true_expr),
             _ => LirType::Void,
         }
     }
@@ -146,15 +180,29 @@ impl Ir {
             Type::Bool => LirType::Bool,
             Type::Void => LirType::Void,
             Type::Pointer(inner) => LirType::Pointer(Box::new(self.transpile_type(inner))),
+            Type::Optional(pointer) => self.transpile_type(pointer),
+            // The `null` variant carries no value; a byte keeps its field legal C.
+            Type::Null => LirType::U8,
             Type::Const(inner) => LirType::Const(Box::new(self.transpile_type(inner))),
             Type::Array(inner, size) => LirType::Array(Box::new(self.transpile_type(inner)), *size),
-            Type::Struct(path, _) => LirType::Struct(path.join("__")),
+            Type::Struct(path, _) => {
+                let name = path.join("__");
+                if self.ctx.is_c_enum(&name) {
+                    LirType::I64
+                } else {
+                    LirType::Struct(name)
+                }
+            }
             Type::Function(args, ret, _) => {
                 let lir_args = args.iter().map(|t| self.transpile_type(t)).collect();
                 let lir_ret = self.transpile_type(ret);
                 LirType::FunctionPtr(lir_args, Box::new(lir_ret))
             }
-            Type::Generic(_) => LirType::Void,
+            Type::Generic(_) | Type::ConstArray(..) | Type::ConstArg(_) | Type::Slice(_) => {
+                LirType::Void
+            }
+            Type::Dyn(path) => LirType::Struct(format!("__Dyn_{}", path.join("__"))),
+            Type::Closure(..) => LirType::Struct(format!("__{}", ty.get_name())),
             Type::Union(types) => {
                 let lir_types = types.iter().map(|t| self.transpile_type(t)).collect();
                 LirType::Union(lir_types)
@@ -192,26 +240,52 @@ impl Ir {
             name: def.name.clone(),
             ty: self.transpile_type(&def.ty),
             init_value: Some(self.transpile_expr(&def.value)),
+            is_persistent: def.is_persistent,
+            is_const: false,
         }
     }
 
     fn transpile_function(&mut self, func: &FunctionDef) -> LirFunctionDef {
         self.local_scope.clear();
         self.enter_local_scope();
+        self.return_type = self.transpile_type(&func.return_type);
 
+        let is_extern = matches!(func.body, FunctionBody::Extern);
+        let mut prologue = Vec::new();
         let params: Vec<(String, LirType)> = func
             .params
             .iter()
             .map(|(n, t)| {
                 let lir_ty = self.transpile_type(t);
                 self.register_local_var(n.clone(), lir_ty.clone());
-                (n.clone(), lir_ty)
+                if is_extern || !matches!(lir_ty, LirType::Array(..)) {
+                    return (n.clone(), lir_ty);
+                }
+
+                // C passes arrays as pointers, so an array parameter is copied
+                // into a local of the same name to keep it a value.
+                let incoming = format!("__arr_{}", n);
+                let local = LirExpr::new(LirExprKind::Ident(n.clone()), func.span);
+                prologue.push(LirStmt::new(
+                    LirStmtKind::Let(n.clone(), lir_ty.clone(), None),
+                    func.span,
+                ));
+                prologue.push(Self::copy_array(
+                    local,
+                    LirExpr::new(LirExprKind::Ident(incoming.clone()), func.span),
+                    &lir_ty,
+                    func.span,
+                ));
+                (incoming, lir_ty)
             })
             .collect();
 
-        let (body, is_extern) = match &func.body {
-            FunctionBody::UserDefined(stmts) => (self.transpile_block(stmts), false),
-            FunctionBody::Extern => (vec![], true),
+        let body = match &func.body {
+            FunctionBody::UserDefined(stmts) => {
+                prologue.extend(self.transpile_block(stmts));
+                prologue
+            }
+            FunctionBody::Extern => vec![],
         };
 
         self.exit_local_scope();
@@ -219,26 +293,114 @@ impl Ir {
         LirFunctionDef {
             name: func.name.clone(),
             params,
-            return_type: self.transpile_type(&func.return_type),
+            return_type: self.return_type.clone(),
             body,
             is_extern,
             is_variadic: func.is_variadic,
+            span: func.span,
         }
     }
 
     fn transpile_block(&mut self, stmts: &[Stmt]) -> Vec<LirStmt> {
         self.enter_local_scope();
+        self.defers.push(Vec::new());
         let mut result = Vec::new();
         for stmt in stmts {
             result.extend(self.transpile_stmt(stmt));
         }
+        let exits = stmts.iter().any(Stmt::always_exits);
+        result.extend(self.end_defer_scope(exits));
         self.exit_local_scope();
         result
     }
 
+    /// Closes the innermost block of `defers`, returning its statements in
+    /// the order they run, unless control never falls off the block's end.
+    fn end_defer_scope(&mut self, exits: bool) -> Vec<LirStmt> {
+        let deferred = self.defers.pop().unwrap_or_default();
+        if exits {
+            return Vec::new();
+        }
+        self.run_defers(&[deferred])
+    }
+
+    /// The deferred statements of `blocks`, innermost block and latest
+    /// statement first.
+    fn run_defers(&mut self, blocks: &[Vec<Stmt>]) -> Vec<LirStmt> {
+        let mut result = Vec::new();
+        for stmt in blocks.iter().rev().flat_map(|block| block.iter().rev()) {
+            result.extend(self.transpile_stmt(stmt));
+        }
+        result
+    }
+
+    /// `ret value`, running every deferred statement first. The value is
+    /// computed before them, so it cannot see what they free.
+    fn transpile_return(&mut self, value: LirExpr, span: Span) -> LirStmtKind {
+        let blocks = self.defers.clone();
+        if blocks.iter().all(Vec::is_empty) {
+            return LirStmtKind::Return(Some(value));
+        }
+
+        let (first, result) = match self.return_type {
+            LirType::Void => (LirStmtKind::ExprStmt(value), None),
+            _ => {
+                let name = "__deferred_ret".to_string();
+                let ty = self.return_type.clone();
+                let result = Some(LirExpr::new(LirExprKind::Ident(name.clone()), span));
+                (LirStmtKind::Let(name, ty, Some(value)), result)
+            }
+        };
+        let mut stmts = vec![LirStmt::new(first, span)];
+        stmts.extend(self.run_defers(&blocks));
+        stmts.push(LirStmt::new(LirStmtKind::Return(result), span));
+        LirStmtKind::Block(stmts)
+    }
+
+    /// `out` or `next`, running the statements deferred inside the loop first.
+    fn transpile_loop_exit(&mut self, exit: LirStmtKind, span: Span) -> LirStmtKind {
+        let depth = self.loops.last().copied().unwrap_or(0);
+        let blocks = self.defers[depth..].to_vec();
+        if blocks.iter().all(Vec::is_empty) {
+            return exit;
+        }
+
+        let mut stmts = self.run_defers(&blocks);
+        stmts.push(LirStmt::new(exit, span));
+        LirStmtKind::Block(stmts)
+    }
+
     fn transpile_stmt(&mut self, stmt: &Stmt) -> Vec<LirStmt> {
-        match stmt {
-            Stmt::Let(name, ty_opt, expr_opt) => {
+        let span = stmt.span;
+        let kind = match &stmt.kind {
+            StmtKind::Let(name, Some(ty), Some(expr))
+                if matches!(expr.kind, ExprKind::Match(..)) =>
+            {
+                let lir_ty = self.transpile_type(ty);
+                self.register_local_var(name.clone(), lir_ty.clone());
+
+                let local = LirExpr::new(LirExprKind::Ident(name.clone()), span);
+                let target = ArmValue::Assign(local);
+                return vec![
+                    LirStmt::new(LirStmtKind::Let(name.clone(), lir_ty, None), span),
+                    LirStmt::new(self.transpile_match(expr, target, span), span),
+                ];
+            }
+            StmtKind::Expr(expr) if matches!(expr.kind, ExprKind::Match(..)) => {
+                self.transpile_match(expr, ArmValue::Discard, span)
+            }
+            StmtKind::Ret(expr) if matches!(expr.kind, ExprKind::Match(..)) => {
+                self.transpile_match(expr, ArmValue::Return, span)
+            }
+            StmtKind::Expr(Expr {
+                kind: ExprKind::Binary(lhs, BinaryOp::Assign, rhs),
+                ..
+            }) if matches!(rhs.kind, ExprKind::Match(..)) => {
+                let target = ArmValue::Assign(self.transpile_expr(lhs));
+                self.transpile_match(rhs, target, span)
+            }
+
+            StmtKind::Let(name, ty_opt, expr_opt) => {
                 let lir_ty = if let Some(t) = ty_opt {
                     self.transpile_type(t)
                 } else {
@@ -251,25 +413,45 @@ impl Ir {
 
                 self.register_local_var(name.clone(), lir_ty.clone());
 
-                let lir_init = expr_opt.as_ref().map(|e| self.transpile_expr(e));
-                vec![LirStmt::Let(name.clone(), lir_ty, lir_init)]
+                let Some(init) = expr_opt.as_ref().map(|e| self.transpile_expr(e)) else {
+                    return vec![LirStmt::new(
+                        LirStmtKind::Let(name.clone(), lir_ty, None),
+                        span,
+                    )];
+                };
+
+                let local = LirExpr::new(LirExprKind::Ident(name.clone()), span);
+                if matches!(lir_ty, LirType::Array(..))
+                    && !matches!(init.kind, LirExprKind::ArrayInit(_))
+                {
+                    return vec![
+                        LirStmt::new(LirStmtKind::Let(name.clone(), lir_ty.clone(), None), span),
+                        Self::copy_array(local, init, &lir_ty, span),
+                    ];
+                }
+
+                let (init, copies) = self.split_array_fields(&local, init, span);
+                let mut stmts = vec![LirStmt::new(
+                    LirStmtKind::Let(name.clone(), lir_ty, Some(init)),
+                    span,
+                )];
+                stmts.extend(copies);
+                return stmts;
             }
 
-            Stmt::Const(name, ty_opt, expr_opt) => {
+            StmtKind::Const(name, ty_opt, expr_opt) => {
                 let lir_ty = ty_opt
                     .as_ref()
                     .map(|t| self.transpile_type(t))
                     .unwrap_or(LirType::I64);
                 self.register_local_var(name.clone(), lir_ty.clone());
                 let lir_init = expr_opt.as_ref().map(|e| self.transpile_expr(e));
-                vec![LirStmt::Let(name.clone(), lir_ty, lir_init)]
+                LirStmtKind::Let(name.clone(), lir_ty, lir_init)
             }
 
-            Stmt::Block(inner_stmts) => {
-                vec![LirStmt::Block(self.transpile_block(inner_stmts))]
-            }
+            StmtKind::Block(inner_stmts) => LirStmtKind::Block(self.transpile_block(inner_stmts)),
 
-            Stmt::If(cond, then_box, else_box) => {
+            StmtKind::If(cond, then_box, else_box) => {
                 let lir_cond = self.transpile_expr(cond);
                 let then_branch = self.transpile_stmt(then_box);
                 let else_branch = if let Some(else_stmt) = else_box {
@@ -277,42 +459,245 @@ impl Ir {
                 } else {
                     vec![]
                 };
-                vec![LirStmt::If {
+                LirStmtKind::If {
                     cond: lir_cond,
                     then_branch,
                     else_branch,
-                }]
+                }
             }
 
-            Stmt::While(cond, body) => {
-                vec![LirStmt::While {
+            StmtKind::While(cond, body) => {
+                self.loops.push(self.defers.len());
+                let body = self.transpile_stmt(body);
+                self.loops.pop();
+                LirStmtKind::While {
                     cond: self.transpile_expr(cond),
-                    body: self.transpile_stmt(body),
-                }]
-            }
-
-            Stmt::Assign(lhs, rhs) => vec![LirStmt::Assign(
-                self.transpile_expr(lhs),
-                self.transpile_expr(rhs),
-            )],
-            Stmt::Expr(e) => vec![LirStmt::ExprStmt(self.transpile_expr(e))],
-            Stmt::Ret(e) => vec![LirStmt::Return(Some(self.transpile_expr(e)))],
-            Stmt::Break => vec![LirStmt::Break],
-            Stmt::Continue => vec![LirStmt::Continue],
-            _ => vec![],
+                    body,
+                }
+            }
+
+            StmtKind::Expr(Expr {
+                kind: ExprKind::Binary(lhs, BinaryOp::Assign, rhs),
+                ..
+            }) => return self.transpile_assign(lhs, rhs, span),
+            StmtKind::Assign(lhs, rhs) => return self.transpile_assign(lhs, rhs, span),
+            StmtKind::Expr(e) => LirStmtKind::ExprStmt(self.transpile_expr(e)),
+            StmtKind::Ret(e) => {
+                let value = self.transpile_expr(e);
+                self.transpile_return(value, span)
+            }
+            StmtKind::Break => self.transpile_loop_exit(LirStmtKind::Break, span),
+            StmtKind::Continue => self.transpile_loop_exit(LirStmtKind::Continue, span),
+            StmtKind::Defer(body) => {
+                if let Some(block) = self.defers.last_mut() {
+                    block.push((**body).clone());
+                }
+                return vec![];
+            }
+            _ => return vec![],
+        };
+
+        vec![LirStmt::new(kind, span)]
+    }
+
+    /// `lhs = rhs`, copying element by element or byte for byte when the
+    /// value is an array, since C cannot assign one.
+    fn transpile_assign(&self, lhs: &Expr, rhs: &Expr, span: Span) -> Vec<LirStmt> {
+        let ty = self.resolve_expr_type(lhs);
+        let target = self.transpile_expr(lhs);
+        let value = self.transpile_expr(rhs);
+
+        match (&ty, value) {
+            (
+                LirType::Array(..),
+                LirExpr {
+                    kind: LirExprKind::ArrayInit(items),
+                    ..
+                },
+            ) => items
+                .into_iter()
+                .enumerate()
+                .map(|(i, item)| {
+                    let index = LirExprKind::Lit(LirLiteral::Int(i as i64));
+                    let element = LirExpr::new(
+                        LirExprKind::Index(
+                            Box::new(target.clone()),
+                            Box::new(LirExpr::new(index, item.span)),
+                        ),
+                        item.span,
+                    );
+                    LirStmt::new(LirStmtKind::Assign(element, item), span)
+                })
+                .collect(),
+            (LirType::Array(..), value) => vec![Self::copy_array(target, value, &ty, span)],
+            (_, value) => {
+                let (value, copies) = self.split_array_fields(&target, value, span);
+                let mut stmts = vec![LirStmt::new(LirStmtKind::Assign(target, value), span)];
+                stmts.extend(copies);
+                stmts
+            }
         }
     }
 
+    /// Takes the array fields of a struct literal that are not array literals
+    /// out of it, returning the copies that fill them in once `target` holds
+    /// the rest.
+    fn split_array_fields(
+        &self,
+        target: &LirExpr,
+        value: LirExpr,
+        span: Span,
+    ) -> (LirExpr, Vec<LirStmt>) {
+        let LirExpr {
+            kind:
+                LirExprKind::StructInit {
+                    struct_name,
+                    fields,
+                },
+            span: value_span,
+        } = value
+        else {
+            return (value, vec![]);
+        };
+
+        let mut kept = Vec::new();
+        let mut copies = Vec::new();
+        for (field, value) in fields {
+            match self.ctx.get_struct_field_type(&struct_name, &field) {
+                Some(ty @ LirType::Array(..))
+                    if !matches!(value.kind, LirExprKind::ArrayInit(_)) =>
+                {
+                    let dst = LirExprKind::MemberAccess(Box::new(target.clone()), field);
+                    let dst = LirExpr::new(dst, value.span);
+                    copies.push(Self::copy_array(dst, value, &ty, span));
+                }
+                _ => kept.push((field, value)),
+            }
+        }
+
+        let value = LirExprKind::StructInit {
+            struct_name,
+            fields: kept,
+        };
+        (LirExpr::new(value, value_span), copies)
+    }
+
+    /// Copies the array `src` into `dst` through the runtime's `__abyss_copy`.
+    fn copy_array(dst: LirExpr, src: LirExpr, ty: &LirType, span: Span) -> LirStmt {
+        let size = LirExpr::new(LirExprKind::SizeOf(ty.clone()), span);
+        let call = LirExprKind::Call {
+            func_name: "__abyss_copy".to_string(),
+            args: vec![dst, src, size],
+        };
+        LirStmt::new(LirStmtKind::ExprStmt(LirExpr::new(call, span)), span)
+    }
+
+    /// Lowers a checked `match` to a `switch` on its integer subject. Arms that
+    /// `out` of an enclosing loop would only leave the `switch` in C, so those
+    /// matches become an `if` chain over the subject instead.
+    fn transpile_match(&mut self, expr: &Expr, value: ArmValue, span: Span) -> LirStmtKind {
+        let ExprKind::Match(subject, arms) = &expr.kind else {
+            return LirStmtKind::ExprStmt(self.transpile_expr(expr));
+        };
+
+        let subject = self.transpile_expr(subject);
+        let mut cases = Vec::new();
+        let mut default = Vec::new();
+        for arm in arms {
+            let body = self.transpile_arm(arm, &value);
+            match &arm.pattern {
+                Pattern::Lit(lit) => cases.push((self.transpile_lit(lit), body)),
+                _ => default = body,
+            }
+        }
+
+        let breaks_loop = arms
+            .iter()
+            .any(|arm| arm.body.iter().any(Stmt::breaks_loop));
+        if !breaks_loop {
+            // Each case gets its own scope, so arms can bind the same names.
+            let scoped = |body| vec![LirStmt::new(LirStmtKind::Block(body), span)];
+            return LirStmtKind::Switch {
+                expr: subject,
+                cases: cases
+                    .into_iter()
+                    .map(|(lit, body)| (lit, scoped(body)))
+                    .collect(),
+                default: if default.is_empty() {
+                    default
+                } else {
+                    scoped(default)
+                },
+            };
+        }
+
+        let mut chain = default;
+        for (lit, then_branch) in cases.into_iter().rev() {
+            let lit = LirExpr::new(LirExprKind::Lit(lit), subject.span);
+            let cond = LirExprKind::Binary(Box::new(subject.clone()), BinaryOp::Eq, Box::new(lit));
+            let cond = LirExpr::new(cond, subject.span);
+            chain = vec![LirStmt::new(
+                LirStmtKind::If {
+                    cond,
+                    then_branch,
+                    else_branch: chain,
+                },
+                span,
+            )];
+        }
+        LirStmtKind::Block(chain)
+    }
+
+    /// An arm's statements, its trailing expression sent to `value`.
+    fn transpile_arm(&mut self, arm: &MatchArm, value: &ArmValue) -> Vec<LirStmt> {
+        self.enter_local_scope();
+        self.defers.push(Vec::new());
+
+        let (last, rest) = match arm.body.split_last() {
+            Some((last, rest)) if !matches!(value, ArmValue::Discard) => (Some(last), rest),
+            _ => (None, arm.body.as_slice()),
+        };
+
+        let mut body = Vec::new();
+        for stmt in rest {
+            body.extend(self.transpile_stmt(stmt));
+        }
+
+        if let Some(last) = last {
+            match &last.kind {
+                StmtKind::Expr(expr) if matches!(expr.kind, ExprKind::Match(..)) => {
+                    let kind = self.transpile_match(expr, value.clone(), last.span);
+                    body.push(LirStmt::new(kind, last.span));
+                }
+                StmtKind::Expr(expr) => {
+                    let expr = self.transpile_expr(expr);
+                    let kind = match value {
+                        ArmValue::Assign(target) => LirStmtKind::Assign(target.clone(), expr),
+                        ArmValue::Return => self.transpile_return(expr, last.span),
+                        ArmValue::Discard => LirStmtKind::ExprStmt(expr),
+                    };
+                    body.push(LirStmt::new(kind, last.span));
+                }
+                _ => body.extend(self.transpile_stmt(last)),
+            }
+        }
+
+        let exits = matches!(value, ArmValue::Return) || arm.body.iter().any(Stmt::always_exits);
+        body.extend(self.end_defer_scope(exits));
+        self.exit_local_scope();
+        body
+    }
+
     fn transpile_expr(&self, expr: &Expr) -> LirExpr {
-        match expr {
-            Expr::Lit(Lit::Array(elements)) => {
+        let kind = match &expr.kind {
+            ExprKind::Lit(Lit::Array(elements)) => {
                 let lir_elems = elements.iter().map(|e| self.transpile_expr(e)).collect();
 
-                LirExpr::ArrayInit(lir_elems)
+                LirExprKind::ArrayInit(lir_elems)
             }
 
-            Expr::Lit(Lit::Str(v)) => {
-                let mut lir_elems = Vec::new();
+            ExprKind::Lit(Lit::Str(v)) => {
+                let mut bytes = Vec::new();
 
                 let content = if v.starts_with('"') && v.ends_with('"') && v.len() >= 2 {
                     &v[1..v.len() - 1]
@@ -326,60 +711,63 @@ impl Ir {
                     if c == '\\' {
                         if let Some(next_char) = chars.next() {
                             match next_char {
-                                'n' => lir_elems.push(LirExpr::Lit(LirLiteral::Byte(10))),
-                                'r' => lir_elems.push(LirExpr::Lit(LirLiteral::Byte(13))),
-                                't' => lir_elems.push(LirExpr::Lit(LirLiteral::Byte(9))),
-                                '0' => lir_elems.push(LirExpr::Lit(LirLiteral::Byte(0))),
-                                '\\' => lir_elems.push(LirExpr::Lit(LirLiteral::Byte(92))),
-                                '"' => lir_elems.push(LirExpr::Lit(LirLiteral::Byte(34))),
+                                'n' => bytes.push(10),
+                                'r' => bytes.push(13),
+                                't' => bytes.push(9),
+                                '0' => bytes.push(0),
+                                '\\' => bytes.push(92),
+                                '"' => bytes.push(34),
                                 other => {
-                                    lir_elems.push(LirExpr::Lit(LirLiteral::Byte(other as u8)));
+                                    bytes.push(other as u8);
                                 }
                             }
                         } else {
-                            lir_elems.push(LirExpr::Lit(LirLiteral::Byte(92)));
+                            bytes.push(92);
                         }
                     } else {
-                        lir_elems.push(LirExpr::Lit(LirLiteral::Byte(c as u8)));
+                        bytes.push(c as u8);
                     }
                 }
 
-                lir_elems.push(LirExpr::Lit(LirLiteral::Byte(0)));
+                bytes.push(0);
 
-                LirExpr::ArrayInit(lir_elems)
+                let byte = |b| LirExpr::new(LirExprKind::Lit(LirLiteral::Byte(b)), expr.span);
+                LirExprKind::ArrayInit(bytes.into_iter().map(byte).collect())
             }
 
-            Expr::Lit(l) => LirExpr::Lit(self.transpile_lit(l)),
-            Expr::Ident(path) => LirExpr::Ident(path.join("__")),
-            Expr::Binary(l, op, r) => LirExpr::Binary(
+            ExprKind::Lit(l) => LirExprKind::Lit(self.transpile_lit(l)),
+            ExprKind::Ident(path) => LirExprKind::Ident(path.join("__")),
+            ExprKind::Binary(l, op, r) => LirExprKind::Binary(
                 Box::new(self.transpile_expr(l)),
                 *op,
                 Box::new(self.transpile_expr(r)),
             ),
-            Expr::Unary(op, inner) => LirExpr::Unary(*op, Box::new(self.transpile_expr(inner))),
-            Expr::Call(callee, args, _) => {
+            ExprKind::Unary(op, inner) => {
+                LirExprKind::Unary(*op, Box::new(self.transpile_expr(inner)))
+            }
+            ExprKind::Call(callee, args, _) => {
                 let lir_args = args.iter().map(|a| self.transpile_expr(a)).collect();
-                match &**callee {
-                    Expr::Ident(path) => LirExpr::Call {
+                match &callee.kind {
+                    ExprKind::Ident(path) => LirExprKind::Call {
                         func_name: path.join("__"),
                         args: lir_args,
                     },
-                    _ => LirExpr::CallPtr(Box::new(self.transpile_expr(callee)), lir_args),
+                    _ => LirExprKind::CallPtr(Box::new(self.transpile_expr(callee)), lir_args),
                 }
             }
 
-            Expr::Member(obj, field) => {
+            ExprKind::Member(obj, field) => {
                 let lhs_lir = self.transpile_expr(obj);
                 let lhs_type = self.resolve_expr_type(obj);
 
                 if let LirType::Pointer(_) = lhs_type {
-                    LirExpr::MemberAccessPtr(Box::new(lhs_lir), field.clone())
+                    LirExprKind::MemberAccessPtr(Box::new(lhs_lir), field.clone())
                 } else {
-                    LirExpr::MemberAccess(Box::new(lhs_lir), field.clone())
+                    LirExprKind::MemberAccess(Box::new(lhs_lir), field.clone())
                 }
             }
 
-            Expr::MethodCall(obj, method_name, args, _) => {
+            ExprKind::MethodCall(obj, method_name, args, _) => {
                 let obj_type = self.resolve_expr_type(obj);
 
                 let struct_name = match &obj_type {
@@ -400,7 +788,10 @@ impl Ir {
 
                 let obj_lir = self.transpile_expr(obj);
                 if let LirType::Struct(_) = obj_type {
-                    lir_args.push(LirExpr::AddrOf(Box::new(obj_lir)));
+                    lir_args.push(LirExpr::new(
+                        LirExprKind::AddrOf(Box::new(obj_lir)),
+                        obj.span,
+                    ));
                 } else {
                     lir_args.push(obj_lir);
                 }
@@ -409,53 +800,54 @@ impl Ir {
                     lir_args.push(self.transpile_expr(arg));
                 }
 
-                LirExpr::Call {
+                LirExprKind::Call {
                     func_name: mangled_func_name,
                     args: lir_args,
                 }
             }
 
-            Expr::StructInit(path, fields, _) => {
+            ExprKind::StructInit(path, fields, _) => {
                 let lir_fields = fields
                     .iter()
                     .map(|(n, e)| (n.clone(), self.transpile_expr(e)))
                     .collect();
-                LirExpr::StructInit {
+                LirExprKind::StructInit {
                     struct_name: path.join("__"),
                     fields: lir_fields,
                 }
             }
-            Expr::UnionInit(path, variants) => {
+            ExprKind::UnionInit(path, variants) => {
                 let lir_variants = variants
                     .iter()
                     .map(|(n, e)| (n.clone(), self.transpile_expr(e)))
                     .collect();
-                LirExpr::UnionInit {
+                LirExprKind::UnionInit {
                     union_name: path.join("__"),
                     variants: lir_variants,
                 }
             }
-            Expr::Index(arr, idx) => LirExpr::Index(
+            ExprKind::Index(arr, idx) => LirExprKind::Index(
                 Box::new(self.transpile_expr(arr)),
                 Box::new(self.transpile_expr(idx)),
             ),
-            Expr::Deref(i) => LirExpr::Deref(Box::new(self.transpile_expr(i))),
-            Expr::AddrOf(i) => LirExpr::AddrOf(Box::new(self.transpile_expr(i))),
-            Expr::Cast(i, t) => {
-                LirExpr::Cast(Box::new(self.transpile_expr(i)), self.transpile_type(t))
+            ExprKind::Deref(i) => LirExprKind::Deref(Box::new(self.transpile_expr(i))),
+            ExprKind::AddrOf(i) => LirExprKind::AddrOf(Box::new(self.transpile_expr(i))),
+            ExprKind::Cast(i, t) => {
+                LirExprKind::Cast(Box::new(self.transpile_expr(i)), self.transpile_type(t))
             }
-            Expr::SizeOf(t) => LirExpr::SizeOf(self.transpile_type(t)),
-            Expr::Is(inner, ty) => LirExpr::Is(
+            ExprKind::SizeOf(t) => LirExprKind::SizeOf(self.transpile_type(t)),
+            ExprKind::Is(inner, ty) => LirExprKind::Is(
                 Box::new(self.transpile_expr(inner)),
                 self.transpile_type(ty),
             ),
-            Expr::Ternary(cond, t, f) => LirExpr::Ternary(
+            ExprKind::Ternary(cond, t, f) => LirExprKind::Ternary(
                 Box::new(self.transpile_expr(cond)),
                 Box::new(self.transpile_expr(t)),
                 Box::new(self.transpile_expr(f)),
             ),
-            _ => LirExpr::Lit(LirLiteral::Null),
-        }
+            _ => LirExprKind::Lit(LirLiteral::Null),
+        };
+        LirExpr::new(kind, expr.span)
     }
 
     fn transpile_lit(&self, lit: &Lit) -> LirLiteral {
@@ -1,7 +1,23 @@
-use abyss_parser::ast::{BinaryOp, UnaryOp};
+use abyss_parser::{
+    ast::{BinaryOp, UnaryOp},
+    source_map::Span,
+};
 
+/// A statement with the span of the Abyss code it came from.
 #[derive(Debug, Clone)]
-pub enum LirStmt {
+pub struct LirStmt {
+    pub kind: LirStmtKind,
+    pub span: Span,
+}
+
+impl LirStmt {
+    pub fn new(kind: LirStmtKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum LirStmtKind {
     Let(String, LirType, Option<LirExpr>),
 
     Assign(LirExpr, LirExpr),
@@ -32,8 +48,22 @@ pub enum LirStmt {
     },
 }
 
+/// An expression with the span of the Abyss code it came from. Expressions
+/// the IR builds itself take the span of the code they stand in for.
+#[derive(Debug, Clone)]
+pub struct LirExpr {
+    pub kind: LirExprKind,
+    pub span: Span,
+}
+
+impl LirExpr {
+    pub fn new(kind: LirExprKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+}
+
 #[derive(Debug, Clone)]
-pub enum LirExpr {
+pub enum LirExprKind {
     Lit(LirLiteral),
 
     Ident(String),
@@ -155,6 +185,8 @@ pub struct LirGlobalVar {
     pub name: String,
     pub ty: LirType,
     pub init_value: Option<LirExpr>,
+    pub is_persistent: bool,
+    pub is_const: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -165,6 +197,7 @@ pub struct LirFunctionDef {
     pub body: Vec<LirStmt>,
     pub is_extern: bool,
     pub is_variadic: bool,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone, Default)]
@@ -1,5 +1,5 @@
 use crate::lir::LirType;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 #[derive(Debug, Clone)]
 pub struct FunctionInfo {
@@ -30,6 +30,8 @@ impl StructInfo {
 pub struct Context {
     pub functions: HashMap<String, FunctionInfo>,
     pub structs: HashMap<String, StructInfo>,
+    /// Enums without payloads, which are plain `i64`s.
+    pub c_enums: HashSet<String>,
 
     pub vars: Vec<HashMap<String, VarInfo>>,
 
@@ -41,6 +43,7 @@ impl Context {
         Self {
             functions: HashMap::new(),
             structs: HashMap::new(),
+            c_enums: HashSet::new(),
             vars: vec![HashMap::new()],
             globals: HashMap::new(),
         }
@@ -62,6 +65,14 @@ impl Context {
         self.structs.get(struct_name)?.get_field_type(field_name)
     }
 
+    pub fn register_c_enum(&mut self, name: String) {
+        self.c_enums.insert(name);
+    }
+
+    pub fn is_c_enum(&self, name: &str) -> bool {
+        self.c_enums.contains(name)
+    }
+
     pub fn enter_scope(&mut self) {
         self.vars.push(HashMap::new());
     }
@@ -1,8 +1,14 @@
 use crate::hir::FlatProgram;
-use abyss_parser::ast::{
-    BinaryOp, Expr, FunctionBody, FunctionDef, Lit, Stmt, StructDef, Type, UnionDef,
+use abyss_parser::{
+    ast::{
+        BinaryOp, Capture, Closure, EnumDef, EnumVariant, Expr, ExprKind, FunctionBody,
+        FunctionDef, GenericParam, Lit, MatchArm, Pattern, Reflect, StaticDef, Stmt, StmtKind,
+        StructDef, TraitDef, TraitImpl, Type, UnaryOp, UnionDef,
+    },
+    diagnostic::Diagnostic,
+    source_map::Span,
 };
-use std::collections::{HashMap, VecDeque};
+use std::collections::{HashMap, HashSet, VecDeque};
 
 pub struct TypeChecker {
     concrete_funcs: Vec<FunctionDef>,
@@ -14,10 +20,75 @@ pub struct TypeChecker {
     reverse_struct_map: HashMap<String, (String, Vec<Type>)>,
     pending_funcs: VecDeque<FunctionDef>,
     scopes: Vec<HashMap<String, Type>>,
+    /// What `is` and null tests have established about union variables, one
+    /// map per scope.
+    narrowings: Vec<HashMap<String, Narrowing>>,
     local_func_scopes: Vec<HashMap<String, FunctionDef>>,
-    used_type_tags: HashMap<String, i64>,
+    /// Ids handed out to non-primitive types, by type name. No two types
+    /// share one.
+    type_ids: HashMap<String, i64>,
     union_struct_defs: Vec<StructDef>,
     variant_cache: HashMap<String, Vec<Type>>,
+    enums: HashMap<String, EnumDef>,
+    traits: HashMap<String, TraitDef>,
+    /// `(trait, type)` pairs with an `impl Trait for Type`.
+    trait_impls: HashSet<(String, String)>,
+    /// `__Dyn_Trait` fat pointer structs created so far, with their trait.
+    dyn_structs: HashMap<String, String>,
+    vtables: Vec<StaticDef>,
+    /// `&fn(..)` fat pointer structs created so far.
+    closure_structs: HashSet<String>,
+    /// `[]T` slice structs created so far, with their element type.
+    slice_structs: HashMap<String, Type>,
+    /// Locals the closure being checked captured by reference, with their
+    /// types. Its body reaches them through the pointer `__ref_<name>`.
+    ref_captures: HashMap<String, Type>,
+    diagnostics: Vec<Diagnostic>,
+    /// Statements to run before the one being checked, such as the temporary
+    /// holding a `match` subject.
+    hoisted: Vec<Stmt>,
+    temp_counter: usize,
+    /// Whether to insert the run-time checks of a debug build.
+    debug: bool,
+    /// What the function being checked returns, which `ret` values are
+    /// wrapped into.
+    return_type: Type,
+}
+
+/// A payload value bound by a `match` arm, read through `fields` of the subject.
+struct MatchBinding {
+    name: String,
+    ty: Type,
+    fields: Vec<String>,
+}
+
+/// What the tests so far have established about a variable.
+#[derive(Clone)]
+enum Narrowing {
+    /// Holds one variant of its union, with the variant's index. An `?&T`
+    /// tested against null has no index: it is the pointer it already is.
+    Variant(Type, Option<usize>),
+    /// A union ruled out of some of its variants, holding one of these.
+    Among(Vec<usize>),
+}
+
+/// What a `match` dispatches on.
+enum MatchSubject {
+    Int,
+    Bool,
+    Union(Vec<Type>),
+    Enum(EnumDef),
+}
+
+impl MatchSubject {
+    /// Whether the subject is a struct switched on by its `tag`.
+    fn is_tagged(&self) -> bool {
+        match self {
+            MatchSubject::Union(_) => true,
+            MatchSubject::Enum(def) => def.has_payload(),
+            MatchSubject::Int | MatchSubject::Bool => false,
+        }
+    }
 }
 
 impl TypeChecker {
@@ -32,41 +103,182 @@ impl TypeChecker {
             reverse_struct_map: HashMap::new(),
             pending_funcs: VecDeque::new(),
             scopes: vec![HashMap::new()],
+            narrowings: vec![HashMap::new()],
             local_func_scopes: vec![HashMap::new()],
-            used_type_tags: HashMap::new(),
+            type_ids: HashMap::new(),
             union_struct_defs: Vec::new(),
             variant_cache: HashMap::new(),
+            enums: HashMap::new(),
+            traits: HashMap::new(),
+            trait_impls: HashSet::new(),
+            dyn_structs: HashMap::new(),
+            vtables: Vec::new(),
+            closure_structs: HashSet::new(),
+            slice_structs: HashMap::new(),
+            ref_captures: HashMap::new(),
+            diagnostics: Vec::new(),
+            hoisted: Vec::new(),
+            temp_counter: 0,
+            debug: false,
+            return_type: Type::Void,
         }
     }
 
+    /// Inserts the run-time checks of a debug build: indices against array
+    /// and slice lengths, null pointers, division by zero, signed overflow
+    /// and reads of a union variant it does not hold.
+    pub fn with_debug(mut self, enabled: bool) -> Self {
+        self.debug = enabled;
+        self
+    }
+
+    pub fn diagnostics(&self) -> &[Diagnostic] {
+        &self.diagnostics
+    }
+
+    fn error(&mut self, diagnostic: Diagnostic) {
+        self.diagnostics.push(diagnostic);
+    }
+
+    /// The tag of a union holding a `ty`, which `type_id(T)` returns too.
+    /// Primitives have fixed ids. Any other type gets one hashed from its
+    /// name, so it stays the same from build to build, moved past the ids
+    /// other types already hold.
     pub fn get_type_tag(&mut self, ty: &Type) -> i64 {
-        let (name, id) = match ty {
-            Type::U8 => ("TYPE_TAG_U8".to_string(), 1),
-            Type::U16 => ("TYPE_TAG_U16".to_string(), 2),
-            Type::U32 => ("TYPE_TAG_U32".to_string(), 3),
-            Type::U64 => ("TYPE_TAG_U64".to_string(), 4),
-            Type::Usize => ("TYPE_TAG_USIZE".to_string(), 5),
-            Type::I8 => ("TYPE_TAG_I8".to_string(), 6),
-            Type::I16 => ("TYPE_TAG_I16".to_string(), 7),
-            Type::I32 => ("TYPE_TAG_I32".to_string(), 8),
-            Type::I64 => ("TYPE_TAG_I64".to_string(), 9),
-            Type::Isize => ("TYPE_TAG_ISIZE".to_string(), 10),
-            Type::F32 => ("TYPE_TAG_F32".to_string(), 11),
-            Type::F64 => ("TYPE_TAG_F64".to_string(), 12),
-            Type::Bool => ("TYPE_TAG_BOOL".to_string(), 13),
-            Type::Char => ("TYPE_TAG_CHAR".to_string(), 14),
-            Type::Array(inner, _) if **inner == Type::U8 => ("TYPE_TAG_U8".to_string(), 1),
+        match ty {
+            Type::U8 => 1,
+            Type::U16 => 2,
+            Type::U32 => 3,
+            Type::U64 => 4,
+            Type::Usize => 5,
+            Type::I8 => 6,
+            Type::I16 => 7,
+            Type::I32 => 8,
+            Type::I64 => 9,
+            Type::Isize => 10,
+            Type::F32 => 11,
+            Type::F64 => 12,
+            Type::Bool => 13,
+            Type::Char => 14,
+            Type::Null => 0,
             _ => {
-                let s = format!("{:?}", ty);
-                let mut hash: i64 = 0;
-                for c in s.bytes() {
-                    hash = hash.wrapping_add(c as i64);
+                let name = self.type_name(ty);
+                if let Some(&id) = self.type_ids.get(&name) {
+                    return id;
                 }
-                (format!("TYPE_TAG_{}", hash), hash)
+                let mut id = hashed_type_id(&name);
+                while self.type_ids.values().any(|&taken| taken == id) {
+                    id += 1;
+                }
+                self.type_ids.insert(name, id);
+                id
             }
+        }
+    }
+
+    /// `ty` as a program spells it: a generic struct instantiation is shown
+    /// with its arguments rather than under its mangled name, and a union
+    /// with its variants in the order of their fields.
+    fn type_name(&self, ty: &Type) -> String {
+        if let Some(elem) = self.slice_elem(ty) {
+            return format!("[]{}", self.type_name(&elem));
+        }
+        if let Type::Struct(path, _) = ty
+            && let Some(variants) = path.last().and_then(|n| self.variant_cache.get(n))
+        {
+            return self.union_name(variants);
+        }
+        match ty {
+            Type::Struct(path, generics) if generics.is_empty() => {
+                match path.last().and_then(|n| self.reverse_struct_map.get(n)) {
+                    Some((base, args)) => {
+                        let args: Vec<String> = args.iter().map(|a| self.type_name(a)).collect();
+                        format!("{}<{}>", base, args.join(", "))
+                    }
+                    None => ty.to_string(),
+                }
+            }
+            Type::Pointer(inner) => format!("&{}", self.type_name(inner)),
+            Type::Optional(inner) => format!("?{}", self.type_name(inner)),
+            Type::Array(inner, len) => format!("{}[{}]", self.type_name(inner), len),
+            Type::Union(variants) => {
+                let mut variants = variants.clone();
+                variants.sort_by_key(|t| t.get_name());
+                self.union_name(&variants)
+            }
+            _ => ty.to_string(),
+        }
+    }
+
+    /// A union's variants joined by `|`, or `?T` for a `T | null`.
+    fn union_name(&self, variants: &[Type]) -> String {
+        if let [Type::Null, inner] | [inner, Type::Null] = variants {
+            return format!("?{}", self.type_name(inner));
+        }
+        let names: Vec<String> = variants.iter().map(|v| self.type_name(v)).collect();
+        names.join(" | ")
+    }
+
+    /// Answers `type_name`, `type_id`, `field_count` or `offset` about `ty`.
+    fn reflect(&mut self, query: Reflect, ty: Type, span: Span) -> (Expr, Type) {
+        let int = |value: i64| Expr::new(ExprKind::Lit(Lit::Int(value)), span);
+        let def = match &ty {
+            Type::Struct(path, _) => path
+                .last()
+                .and_then(|name| self.concrete_structs.iter().find(|s| &s.name == name))
+                .cloned(),
+            _ => None,
         };
-        self.used_type_tags.insert(name, id);
-        id
+
+        match (query, def) {
+            (Reflect::Name, _) => {
+                let name = self.type_name(&ty);
+                self.infer_lit(Lit::Str(format!("\"{}\"", name)), span)
+            }
+            (Reflect::Id, _) => (int(self.get_type_tag(&ty)), Type::I64),
+            (Reflect::FieldCount, Some(def)) => (int(def.fields.len() as i64), Type::I64),
+            (Reflect::Offset(field), Some(def)) => {
+                if !def.fields.iter().any(|(name, _)| name == &field) {
+                    self.error(
+                        Diagnostic::error(format!(
+                            "no field '{}' on struct '{}'",
+                            field,
+                            self.type_name(&ty)
+                        ))
+                        .with_span(span),
+                    );
+                    return (int(0), Type::I64);
+                }
+                // The address of `field` in a `T` placed at address zero.
+                let base = Expr::new(
+                    ExprKind::Cast(Box::new(int(0)), Type::Pointer(Box::new(ty))),
+                    span,
+                );
+                let member = Expr::new(
+                    ExprKind::Member(
+                        Box::new(Expr::new(ExprKind::Deref(Box::new(base)), span)),
+                        field,
+                    ),
+                    span,
+                );
+                let addr = Expr::new(ExprKind::AddrOf(Box::new(member)), span);
+                (
+                    Expr::new(ExprKind::Cast(Box::new(addr), Type::I64), span),
+                    Type::I64,
+                )
+            }
+            (query, None) => {
+                let builtin = match query {
+                    Reflect::FieldCount => "field_count",
+                    _ => "offset",
+                };
+                self.error(
+                    Diagnostic::error(format!("`{}` expects a struct, found {}", builtin, ty))
+                        .with_span(span),
+                );
+                (int(0), Type::I64)
+            }
+        }
     }
 
     fn get_union_name(&mut self, types: &[Type]) -> String {
@@ -119,6 +331,7 @@ impl TypeChecker {
                 name: struct_name.clone(),
                 generics: vec![],
                 fields,
+                span: Span::default(),
             };
             self.union_struct_defs.push(struct_def);
         }
@@ -126,6 +339,202 @@ impl TypeChecker {
         struct_name
     }
 
+    /// Records an enum's variants. A payload enum also gets its tagged struct,
+    /// `Name { tag, data }`, where `data` is a union of one `Name__Variant`
+    /// struct per variant carrying values, with fields `_0`, `_1`, ...
+    fn register_enum(&mut self, def: &EnumDef) {
+        for (i, variant) in def.variants.iter().enumerate() {
+            let earlier = &def.variants[..i];
+            let diagnostic = if earlier.iter().any(|v| v.name == variant.name) {
+                Diagnostic::error(format!(
+                    "variant `{}` is defined more than once in `{}`",
+                    variant.name, def.name
+                ))
+            } else if let Some(other) = earlier.iter().find(|v| v.value == variant.value) {
+                Diagnostic::error(format!(
+                    "`{}::{}` and `{}::{}` both have the value {}",
+                    def.name, other.name, def.name, variant.name, variant.value
+                ))
+            } else {
+                continue;
+            };
+            self.error(diagnostic.with_span(variant.span));
+        }
+
+        if def.has_payload() {
+            let data_name = format!("__EnumData_{}", def.name);
+            let mut data_fields = Vec::new();
+
+            for variant in def.variants.iter().filter(|v| !v.payload.is_empty()) {
+                let payload_name = format!("{}__{}", def.name, variant.name);
+                let fields = variant
+                    .payload
+                    .iter()
+                    .enumerate()
+                    .map(|(i, ty)| (format!("_{}", i), self.concrete_type(ty)))
+                    .collect();
+
+                self.concrete_structs.push(StructDef {
+                    is_pub: def.is_pub,
+                    name: payload_name.clone(),
+                    generics: vec![],
+                    fields,
+                    span: variant.span,
+                });
+                data_fields.push((
+                    variant.name.clone(),
+                    Type::Struct(vec![payload_name], vec![]),
+                ));
+            }
+
+            self.concrete_unions.push(UnionDef {
+                is_pub: def.is_pub,
+                name: data_name.clone(),
+                fields: data_fields,
+            });
+            self.union_struct_defs.push(StructDef {
+                is_pub: def.is_pub,
+                name: def.name.clone(),
+                generics: vec![],
+                fields: vec![
+                    ("tag".to_string(), Type::I64),
+                    ("data".to_string(), Type::Struct(vec![data_name], vec![])),
+                ],
+                span: def.span,
+            });
+        }
+
+        self.enums.insert(def.name.clone(), def.clone());
+    }
+
+    /// The enum `ty` names, if any.
+    fn enum_of(&self, ty: &Type) -> Option<&EnumDef> {
+        match ty {
+            Type::Struct(path, generics) if generics.is_empty() => self.enums.get(&path.join("__")),
+            _ => None,
+        }
+    }
+
+    /// Looks up a flattened `Enum__Variant` name.
+    fn enum_variant(&self, name: &str) -> Option<(&EnumDef, &EnumVariant)> {
+        let (enum_name, variant_name) = name.rsplit_once("__")?;
+        let def = self.enums.get(enum_name)?;
+        let variant = def.variants.iter().find(|v| v.name == variant_name)?;
+        Some((def, variant))
+    }
+
+    /// Builds a variant from its payload values: the integer itself for a
+    /// C-style enum, the tagged struct otherwise.
+    fn construct_variant(
+        &mut self,
+        def: EnumDef,
+        variant: EnumVariant,
+        args: Vec<Expr>,
+        span: Span,
+    ) -> (Expr, Type) {
+        let enum_ty = Type::Struct(vec![def.name.clone()], vec![]);
+        let tag = Expr::new(ExprKind::Lit(Lit::Int(variant.value)), span);
+
+        if args.len() != variant.payload.len() {
+            let note = if variant.payload.is_empty() {
+                format!("use `{}::{}` without arguments", def.name, variant.name)
+            } else {
+                format!(
+                    "construct it as `{}::{}(...)` with {} value(s)",
+                    def.name,
+                    variant.name,
+                    variant.payload.len()
+                )
+            };
+            self.error(
+                Diagnostic::error(format!(
+                    "`{}::{}` carries {} value(s) but {} were supplied",
+                    def.name,
+                    variant.name,
+                    variant.payload.len(),
+                    args.len()
+                ))
+                .with_span(span)
+                .with_note(note),
+            );
+        }
+
+        if !def.has_payload() {
+            return (tag, enum_ty);
+        }
+
+        let mut fields = vec![("tag".to_string(), tag)];
+
+        if !variant.payload.is_empty() {
+            let mut values = Vec::new();
+            for (i, (arg, expected)) in args.into_iter().zip(&variant.payload).enumerate() {
+                let arg_span = arg.span;
+                let expected = self.concrete_type(expected);
+                let (mut value, value_ty) = self.infer_expr(arg);
+
+                if let Some(variants) = self.union_variants(&expected) {
+                    if value_ty != expected {
+                        let Type::Struct(path, _) = &expected else {
+                            unreachable!()
+                        };
+                        value = self
+                            .wrap_expr_for_union(value, value_ty, &variants, path[0].clone())
+                            .0;
+                    }
+                } else if !self.are_types_compatible(&expected, &value_ty) {
+                    if self.same_number_kind(&expected, &value_ty) {
+                        value = Expr::new(ExprKind::Cast(Box::new(value), expected), arg_span);
+                    } else {
+                        self.error(
+                            Diagnostic::error(format!(
+                                "mismatched types in `{}::{}`: expected {}, found {}",
+                                def.name, variant.name, expected, value_ty
+                            ))
+                            .with_span(arg_span),
+                        );
+                    }
+                }
+                values.push((format!("_{}", i), value));
+            }
+
+            let payload = Expr::new(
+                ExprKind::StructInit(
+                    vec![format!("{}__{}", def.name, variant.name)],
+                    values,
+                    vec![],
+                ),
+                span,
+            );
+            let data = Expr::new(
+                ExprKind::UnionInit(
+                    vec![format!("__EnumData_{}", def.name)],
+                    vec![(variant.name.clone(), payload)],
+                ),
+                span,
+            );
+            fields.push(("data".to_string(), data));
+        }
+
+        (
+            Expr::new(ExprKind::StructInit(vec![def.name], fields, vec![]), span),
+            enum_ty,
+        )
+    }
+
+    /// `ty` as values of it are stored: unions become their tagged struct.
+    fn concrete_type(&mut self, ty: &Type) -> Type {
+        match ty {
+            Type::Union(variants) => {
+                Type::Struct(vec![self.get_or_create_union_struct(variants)], vec![])
+            }
+            _ => {
+                let mut ty = ty.clone();
+                self.lower_fat_pointers(&mut ty, Span::default());
+                ty
+            }
+        }
+    }
+
     fn wrap_expr_for_union(
         &mut self,
         mut expr: Expr,
@@ -133,19 +542,21 @@ impl TypeChecker {
         variants: &[Type],
         target_struct_name: String,
     ) -> (Expr, Type) {
-        if !variants.contains(&expr_ty) {
+        let span = expr.span;
+        let mut final_rhs_ty = expr_ty;
+        if !variants.contains(&final_rhs_ty) {
             for variant in variants {
-                let is_int_conv = self.is_integer(variant) && self.is_integer(&expr_ty);
-                let is_float_conv = self.is_float(variant) && self.is_float(&expr_ty);
+                let is_int_conv = self.is_integer(variant) && self.is_integer(&final_rhs_ty);
+                let is_float_conv = self.is_float(variant) && self.is_float(&final_rhs_ty);
 
                 if is_int_conv || is_float_conv {
-                    expr = Expr::Cast(Box::new(expr), variant.clone());
+                    expr = Expr::new(ExprKind::Cast(Box::new(expr), variant.clone()), span);
+                    final_rhs_ty = variant.clone();
                     break;
                 }
             }
         }
 
-        let (_, final_rhs_ty) = self.infer_expr(expr.clone());
         let tag_val = self.get_type_tag(&final_rhs_ty);
 
         let mut sorted_variants = variants.to_vec();
@@ -154,27 +565,44 @@ impl TypeChecker {
         if let Some(variant_index) = sorted_variants.iter().position(|t| t == &final_rhs_ty) {
             let inner_struct_name = target_struct_name.replace("__Union_", "__UnionInner_");
 
-            let inner_init = Expr::UnionInit(
-                vec![inner_struct_name.clone()],
-                vec![(format!("variant_{}", variant_index), expr)],
+            let inner_init = Expr::new(
+                ExprKind::UnionInit(
+                    vec![inner_struct_name.clone()],
+                    vec![(format!("variant_{}", variant_index), expr)],
+                ),
+                span,
             );
 
-            let wrapper_init = Expr::StructInit(
-                vec![target_struct_name.clone()],
-                vec![
-                    ("tag".to_string(), Expr::Lit(Lit::Int(tag_val))),
-                    ("data".to_string(), inner_init),
-                ],
-                vec![],
+            let wrapper_init = Expr::new(
+                ExprKind::StructInit(
+                    vec![target_struct_name.clone()],
+                    vec![
+                        (
+                            "tag".to_string(),
+                            Expr::new(ExprKind::Lit(Lit::Int(tag_val)), span),
+                        ),
+                        ("data".to_string(), inner_init),
+                    ],
+                    vec![],
+                ),
+                span,
             );
 
             let wrapper_type = Type::Struct(vec![target_struct_name], vec![]);
             (wrapper_init, wrapper_type)
         } else {
-            panic!(
-                "Type mismatch: Cannot assign {:?} to Union Wrapper {:?} (Variants: {:?})",
-                final_rhs_ty, target_struct_name, variants
+            self.error(
+                Diagnostic::error(format!(
+                    "mismatched types: {} is not a variant of this union",
+                    final_rhs_ty
+                ))
+                .with_span(span)
+                .with_note(format!(
+                    "expected one of {}",
+                    Type::Union(variants.to_vec())
+                )),
             );
+            (expr, Type::Struct(vec![target_struct_name], vec![]))
         }
     }
 
@@ -190,6 +618,8 @@ impl TypeChecker {
 
             (Type::Union(variants), src_ty) => variants.contains(src_ty),
 
+            (Type::Optional(pointer), src_ty) => self.are_types_compatible(pointer, src_ty),
+
             _ => false,
         }
     }
@@ -214,10 +644,26 @@ impl TypeChecker {
         matches!(t, Type::F32 | Type::F64)
     }
 
-    pub fn check(mut self, program: FlatProgram) -> FlatProgram {
+    pub fn check(&mut self, program: FlatProgram) -> FlatProgram {
+        for s in &program.statics {
+            self.register_var(s.name.clone(), s.ty.clone());
+        }
+
+        for e in &program.enums {
+            self.register_enum(e);
+        }
+
+        for t in program.traits {
+            self.traits.insert(t.name.clone(), t);
+        }
+        for i in &program.trait_impls {
+            self.check_trait_impl(i, &program.functions);
+        }
+
         for mut func in program.functions {
             if !func.generics.is_empty() {
                 self.resolve_generics_in_func(&mut func);
+                self.check_bounded_method_calls(&func);
                 self.generic_func_templates.insert(func.name.clone(), func);
             } else {
                 self.pending_funcs.push_back(func);
@@ -232,6 +678,7 @@ impl TypeChecker {
                 let empty_map = HashMap::new();
                 for (_, field_ty) in &mut s.fields {
                     self.substitute_type(field_ty, &empty_map);
+                    self.lower_fat_pointers(field_ty, s.span);
 
                     if let Type::Union(variants) = field_ty {
                         let struct_name = self.get_or_create_union_struct(variants);
@@ -247,21 +694,25 @@ impl TypeChecker {
         }
 
         let mut new_program = FlatProgram::new();
-        new_program.functions = self.concrete_funcs;
-        new_program.structs = self.concrete_structs;
+        new_program.functions = std::mem::take(&mut self.concrete_funcs);
+        new_program.structs = std::mem::take(&mut self.concrete_structs);
         new_program.statics = program.statics;
-        new_program.unions = self.concrete_unions;
-        new_program.union_struct_defs = self.union_struct_defs;
+        new_program.vtables = std::mem::take(&mut self.vtables);
+        new_program.unions = std::mem::take(&mut self.concrete_unions);
+        new_program.enums = program.enums;
+        new_program.union_struct_defs = std::mem::take(&mut self.union_struct_defs);
         new_program
     }
 
     fn enter_scope(&mut self) {
         self.scopes.push(HashMap::new());
+        self.narrowings.push(HashMap::new());
         self.local_func_scopes.push(HashMap::new());
     }
 
     fn exit_scope(&mut self) {
         self.scopes.pop();
+        self.narrowings.pop();
         self.local_func_scopes.pop();
     }
     fn register_local_func(&mut self, name: String, func: FunctionDef) {
@@ -277,12 +728,111 @@ impl TypeChecker {
         }
         None
     }
+    /// The pointer type of the non-generic function `name`, when a function
+    /// is used as a value.
+    fn function_type(&mut self, name: &str) -> Option<Type> {
+        let (params, ret) = match self.get_local_func(name) {
+            Some(func) => (func.params, func.return_type),
+            None => self
+                .concrete_funcs
+                .iter()
+                .chain(self.pending_funcs.iter())
+                .find(|f| f.name == name)
+                .map(|f| (f.params.clone(), f.return_type.clone()))?,
+        };
+        let params = params
+            .iter()
+            .map(|(_, ty)| self.concrete_type(ty))
+            .collect();
+        let ret = self.concrete_type(&ret);
+        Some(Type::Function(params, Box::new(ret), vec![]))
+    }
+
     fn register_var(&mut self, name: String, ty: Type) {
+        if let Some(narrowed) = self.narrowings.last_mut() {
+            narrowed.remove(&name);
+        }
         if let Some(scope) = self.scopes.last_mut() {
             scope.insert(name, ty);
         }
     }
 
+    /// What is known about the variable `name`, from the innermost scope
+    /// that narrowed it or declared it.
+    fn narrowing(&self, name: &str) -> Option<&Narrowing> {
+        for (scope, narrowed) in self.scopes.iter().zip(&self.narrowings).rev() {
+            if let Some(narrowing) = narrowed.get(name) {
+                return Some(narrowing);
+            }
+            if scope.contains_key(name) {
+                return None;
+            }
+        }
+        None
+    }
+
+    /// The variant the union variable `name` is known to hold, and its index.
+    fn narrowed_type(&self, name: &str) -> Option<(Type, Option<usize>)> {
+        match self.narrowing(name)? {
+            Narrowing::Variant(ty, index) => Some((ty.clone(), *index)),
+            Narrowing::Among(_) => None,
+        }
+    }
+
+    /// Narrows the union variables of `tests` in the current scope. Each test
+    /// rules variants out of those the variable may still hold, and once a
+    /// single one other than `null` is left the variable is that variant.
+    /// The `?&T` variables tested against null narrow to the pointer.
+    fn narrow(&mut self, tests: &[(String, Type, bool)]) {
+        for (name, ty, holds) in tests {
+            if self.captured_by_ref(name).is_some() || self.narrowed_type(name).is_some() {
+                continue;
+            }
+            let Some(var_ty) = self.get_var_type(name) else {
+                continue;
+            };
+            if let Type::Optional(pointer) = var_ty {
+                if *ty == Type::Null
+                    && !holds
+                    && let Some(narrowed) = self.narrowings.last_mut()
+                {
+                    narrowed.insert(name.clone(), Narrowing::Variant(*pointer, None));
+                }
+                continue;
+            }
+            let Some(variants) = self.union_variants(&var_ty) else {
+                continue;
+            };
+
+            let possible = match self.narrowing(name) {
+                Some(Narrowing::Among(indices)) => indices.clone(),
+                _ => (0..variants.len()).collect(),
+            };
+            let held: Vec<usize> = possible
+                .iter()
+                .copied()
+                .filter(|&index| (variants[index] == *ty) == *holds)
+                .collect();
+            let narrowing = match held.as_slice() {
+                [index] if variants[*index] != Type::Null => {
+                    Narrowing::Variant(variants[*index].clone(), Some(*index))
+                }
+                _ if held.len() < possible.len() => Narrowing::Among(held),
+                _ => continue,
+            };
+            if let Some(narrowed) = self.narrowings.last_mut() {
+                narrowed.insert(name.clone(), narrowing);
+            }
+        }
+    }
+
+    /// Ends the narrowing of `name`, which is being assigned a new value.
+    fn forget_narrowing(&mut self, name: &str) {
+        for narrowed in &mut self.narrowings {
+            narrowed.remove(name);
+        }
+    }
+
     fn get_var_type(&self, name: &str) -> Option<Type> {
         for scope in self.scopes.iter().rev() {
             if let Some(ty) = scope.get(name) {
@@ -292,8 +842,1047 @@ impl TypeChecker {
         None
     }
 
+    /// The name a type is implemented for: a generic struct instantiation
+    /// counts as its template.
+    fn impl_type_name(&self, ty: &Type) -> String {
+        match ty {
+            Type::Struct(path, _) => {
+                let name = path.last().cloned().unwrap_or_default();
+                match self.reverse_struct_map.get(&name) {
+                    Some((base, _)) => base.clone(),
+                    None => name,
+                }
+            }
+            _ => ty.get_name(),
+        }
+    }
+
+    /// Checks that `imp` defines exactly the methods of its trait, each with
+    /// the trait's signature once `Self` is the implementing type.
+    fn check_trait_impl(&mut self, imp: &TraitImpl, functions: &[FunctionDef]) {
+        let trait_name = imp.trait_path.join("__");
+        let display_name = trait_name.replace("__", "::");
+        let Some(def) = self.traits.get(&trait_name).cloned() else {
+            self.error(
+                Diagnostic::error(format!("cannot find trait `{}`", display_name))
+                    .with_span(imp.span),
+            );
+            return;
+        };
+        let type_name = self.impl_type_name(&imp.ty);
+
+        let mut missing = Vec::new();
+        for method in &def.methods {
+            let mangled = format!("{}__{}", type_name, method.name);
+            let Some(func) = functions.iter().find(|f| f.name == mangled) else {
+                missing.push(format!("`{}`", method.name));
+                continue;
+            };
+
+            let mut expected = method.clone();
+            for (_, ty) in &mut expected.params {
+                ty.replace_self(&imp.ty);
+            }
+            expected.return_type.replace_self(&imp.ty);
+
+            let same_params = func.params.len() == expected.params.len()
+                && func
+                    .params
+                    .iter()
+                    .zip(&expected.params)
+                    .all(|((_, a), (_, b))| a == b);
+            if !same_params || func.return_type != expected.return_type {
+                self.error(
+                    Diagnostic::error(format!(
+                        "method `{}` does not match its declaration in trait `{}`",
+                        method.name, display_name
+                    ))
+                    .with_span(func.span)
+                    .with_note(format!("expected `{}`", signature(&expected))),
+                );
+            }
+        }
+
+        if !missing.is_empty() {
+            self.error(
+                Diagnostic::error(format!(
+                    "`impl {} for {}` is missing {}",
+                    display_name,
+                    imp.ty,
+                    missing.join(", ")
+                ))
+                .with_span(imp.span),
+            );
+        }
+
+        for name in &imp.methods {
+            if !def.methods.iter().any(|m| &m.name == name) {
+                self.error(
+                    Diagnostic::error(format!(
+                        "method `{}` is not a member of trait `{}`",
+                        name, display_name
+                    ))
+                    .with_span(imp.span),
+                );
+            }
+        }
+
+        if !self.trait_impls.insert((trait_name, type_name)) {
+            self.error(
+                Diagnostic::error(format!(
+                    "conflicting implementations of trait `{}` for `{}`",
+                    display_name, imp.ty
+                ))
+                .with_span(imp.span),
+            );
+        }
+    }
+
+    /// The first bound of `template` that `generics` does not satisfy, as an
+    /// error to report at the call.
+    fn unsatisfied_bound(&self, template: &FunctionDef, generics: &[Type]) -> Option<Diagnostic> {
+        for (generic, trait_path) in &template.bounds {
+            let trait_name = trait_path.join("__");
+            let index = template.generics.iter().position(|g| &g.name == generic)?;
+            if !self.traits.contains_key(&trait_name) {
+                continue;
+            }
+
+            let ty = &generics[index];
+            if *ty == Type::Void {
+                // Inference already failed and was reported.
+                continue;
+            }
+            let key = (trait_name.clone(), self.impl_type_name(ty));
+            if !self.trait_impls.contains(&key) {
+                let trait_name = trait_name.replace("__", "::");
+                return Some(
+                    Diagnostic::error(format!(
+                        "the trait `{}` is not implemented for `{}`",
+                        trait_name, ty
+                    ))
+                    .with_note(format!(
+                        "required by the bound `{}: {}` on `{}`",
+                        generic,
+                        trait_name,
+                        template.name.replace("__", "::")
+                    )),
+                );
+            }
+        }
+        None
+    }
+
+    /// Checks a generic function's method calls on bounded parameters
+    /// against the traits they are bounded by. Calls on unbounded type
+    /// parameters are left to monomorphization.
+    fn check_bounded_method_calls(&mut self, func: &FunctionDef) {
+        let mut bounds: HashMap<&str, Vec<String>> = HashMap::new();
+        for (generic, trait_path) in &func.bounds {
+            let trait_name = trait_path.join("__");
+            if !self.traits.contains_key(&trait_name) {
+                self.error(
+                    Diagnostic::error(format!(
+                        "cannot find trait `{}`",
+                        trait_name.replace("__", "::")
+                    ))
+                    .with_span(func.span),
+                );
+                continue;
+            }
+            bounds.entry(generic).or_default().push(trait_name);
+        }
+
+        let mut params = HashMap::new();
+        for (name, ty) in &func.params {
+            let generic = match ty {
+                Type::Pointer(inner) => inner,
+                _ => ty,
+            };
+            let traits = match generic {
+                Type::Generic(g) => bounds.get_key_value(g.as_str()),
+                _ => None,
+            };
+            if let Some((g, traits)) = traits {
+                params.insert(name.as_str(), (*g, traits));
+            }
+        }
+        if params.is_empty() {
+            return;
+        }
+
+        let mut calls = Vec::new();
+        if let FunctionBody::UserDefined(stmts) = &func.body {
+            for stmt in stmts {
+                walk_stmt(stmt, &mut |expr| {
+                    if matches!(expr.kind, ExprKind::MethodCall(..)) {
+                        calls.push(expr);
+                    }
+                });
+            }
+        }
+
+        for call in calls {
+            let ExprKind::MethodCall(receiver, method, _, _) = &call.kind else {
+                continue;
+            };
+            let param = match &receiver.kind {
+                ExprKind::Ident(path) if path.len() == 1 => params.get(path[0].as_str()),
+                _ => None,
+            };
+            let Some((generic, traits)) = param else {
+                continue;
+            };
+
+            let found = traits
+                .iter()
+                .any(|t| self.traits[t].methods.iter().any(|m| &m.name == method));
+            if !found {
+                let names: Vec<String> = traits
+                    .iter()
+                    .map(|t| format!("`{}`", t.replace("__", "::")))
+                    .collect();
+                self.error(
+                    Diagnostic::error(format!(
+                        "no method `{}` on type parameter `{}`",
+                        method, generic
+                    ))
+                    .with_span(call.span)
+                    .with_note(format!(
+                        "`{}` is only known to implement {}",
+                        generic,
+                        names.join(", ")
+                    )),
+                );
+            }
+        }
+    }
+
+    /// Replaces every `&dyn Trait`, `&fn(..)` and `[]T` in `ty` with its fat
+    /// pointer struct.
+    fn lower_fat_pointers(&mut self, ty: &mut Type, span: Span) {
+        match ty {
+            Type::Dyn(path) => {
+                let name = self.dyn_struct(&path.join("__"), span);
+                *ty = Type::Struct(vec![name], vec![]);
+            }
+            Type::Pointer(inner)
+            | Type::Const(inner)
+            | Type::Array(inner, _)
+            | Type::ConstArray(inner, _)
+            | Type::Optional(inner) => {
+                self.lower_fat_pointers(inner, span);
+            }
+            Type::Struct(_, types) | Type::Union(types) => {
+                for t in types {
+                    self.lower_fat_pointers(t, span);
+                }
+            }
+            Type::Function(args, ret, _) => {
+                for arg in args {
+                    self.lower_fat_pointers(arg, span);
+                }
+                self.lower_fat_pointers(ret, span);
+            }
+            Type::Closure(args, ret) => {
+                for arg in args.iter_mut() {
+                    self.lower_fat_pointers(arg, span);
+                }
+                self.lower_fat_pointers(ret, span);
+                let name = self.closure_struct(args, ret, span);
+                *ty = Type::Struct(vec![name], vec![]);
+            }
+            Type::Slice(elem) => {
+                self.lower_fat_pointers(elem, span);
+                *ty = self.slice_struct(elem, span);
+            }
+            _ => {}
+        }
+    }
+
+    /// The fat pointer struct for `&dyn Trait`, `__Dyn_Trait { data, vtable }`,
+    /// created with its `__VTable_Trait` of one function pointer per method
+    /// the first time the trait is used as `dyn`.
+    fn dyn_struct(&mut self, trait_name: &str, span: Span) -> String {
+        let name = format!("__Dyn_{}", trait_name);
+        if self.dyn_structs.contains_key(&name) {
+            return name;
+        }
+
+        self.dyn_structs
+            .insert(name.clone(), trait_name.to_string());
+
+        let display_name = trait_name.replace("__", "::");
+        let Some(def) = self.traits.get(trait_name).cloned() else {
+            self.error(
+                Diagnostic::error(format!("cannot find trait `{}`", display_name)).with_span(span),
+            );
+            return name;
+        };
+
+        let mut slots = Vec::new();
+        for method in &def.methods {
+            match self.vtable_slot(method, span) {
+                Some(slot) => slots.push((method.name.clone(), slot)),
+                None => self.error(
+                    Diagnostic::error(format!(
+                        "trait `{}` cannot be used as `dyn`",
+                        display_name
+                    ))
+                    .with_span(span)
+                    .with_note(format!(
+                        "method `{}` must take `self: &Self` first and not use `Self` or generics otherwise",
+                        method.name
+                    )),
+                ),
+            }
+        }
+
+        let vtable_name = format!("__VTable_{}", trait_name);
+        self.concrete_structs.push(StructDef {
+            is_pub: def.is_pub,
+            name: vtable_name.clone(),
+            generics: vec![],
+            fields: slots,
+            span: def.span,
+        });
+        self.concrete_structs.push(StructDef {
+            is_pub: def.is_pub,
+            name: name.clone(),
+            generics: vec![],
+            fields: vec![
+                ("data".to_string(), Type::Pointer(Box::new(Type::Void))),
+                (
+                    "vtable".to_string(),
+                    Type::Pointer(Box::new(Type::Struct(vec![vtable_name], vec![]))),
+                ),
+            ],
+            span: def.span,
+        });
+        name
+    }
+
+    /// The function pointer type `method` is called through from a vtable,
+    /// with `self` erased to `&pass`.
+    fn vtable_slot(&mut self, method: &FunctionDef, span: Span) -> Option<Type> {
+        let self_ptr = Type::Pointer(Box::new(Type::Struct(vec!["Self".to_string()], vec![])));
+        let ((_, first), rest) = method.params.split_first()?;
+        if *first != self_ptr || !method.generics.is_empty() {
+            return None;
+        }
+
+        let mentions_self = |ty: &Type| {
+            let mut replaced = ty.clone();
+            replaced.replace_self(&Type::Void);
+            replaced != *ty
+        };
+        let mut params = vec![Type::Pointer(Box::new(Type::Void))];
+        for (_, ty) in rest {
+            if mentions_self(ty) {
+                return None;
+            }
+            params.push(ty.clone());
+        }
+        if mentions_self(&method.return_type) {
+            return None;
+        }
+
+        let mut slot = Type::Function(params, Box::new(method.return_type.clone()), vec![]);
+        self.lower_fat_pointers(&mut slot, span);
+        Some(slot)
+    }
+
+    /// Turns a pointer to an implementor into the `&dyn` fat pointer `target`
+    /// when `target` is one; anything else is returned unchanged.
+    fn coerce_to_dyn(&mut self, expr: Expr, expr_ty: Type, target: &Type) -> (Expr, Type) {
+        let trait_name = match target {
+            Type::Struct(path, _) => path.last().and_then(|n| self.dyn_structs.get(n)).cloned(),
+            _ => None,
+        };
+        let Some(trait_name) = trait_name else {
+            return (expr, expr_ty);
+        };
+        if expr_ty == *target || !self.traits.contains_key(&trait_name) {
+            return (expr, target.clone());
+        }
+
+        let span = expr.span;
+        let display_name = trait_name.replace("__", "::");
+        let pointee = match &expr_ty {
+            Type::Pointer(pointee) if matches!(**pointee, Type::Struct(..)) => (**pointee).clone(),
+            _ => {
+                self.error(
+                    Diagnostic::error(format!(
+                        "mismatched types: expected `&dyn {}`, found {}",
+                        display_name, expr_ty
+                    ))
+                    .with_span(span)
+                    .with_note(
+                        "only a pointer to a struct implementing the trait converts to `dyn`",
+                    ),
+                );
+                return (expr, target.clone());
+            }
+        };
+
+        let type_name = self.impl_type_name(&pointee);
+        if !self
+            .trait_impls
+            .contains(&(trait_name.clone(), type_name.clone()))
+        {
+            self.error(
+                Diagnostic::error(format!(
+                    "the trait `{}` is not implemented for `{}`",
+                    display_name, pointee
+                ))
+                .with_span(span)
+                .with_note(format!("required to use it as `&dyn {}`", display_name)),
+            );
+            return (expr, target.clone());
+        }
+        if matches!(&pointee, Type::Struct(path, _) if path.last() != Some(&type_name)) {
+            self.error(
+                Diagnostic::error(format!(
+                    "a generic struct such as `{}` cannot be used as `&dyn {}` yet",
+                    pointee, display_name
+                ))
+                .with_span(span),
+            );
+            return (expr, target.clone());
+        }
+
+        let vtable_ty = format!("__VTable_{}", trait_name);
+        let vtable = self.vtable(&trait_name, &type_name, span);
+        let vtable_ptr = Expr::new(
+            ExprKind::Cast(
+                Box::new(Expr::new(
+                    ExprKind::AddrOf(Box::new(Expr::new(ExprKind::Ident(vec![vtable]), span))),
+                    span,
+                )),
+                Type::Pointer(Box::new(Type::Struct(vec![vtable_ty], vec![]))),
+            ),
+            span,
+        );
+        let data_ptr = Expr::new(
+            ExprKind::Cast(Box::new(expr), Type::Pointer(Box::new(Type::Void))),
+            span,
+        );
+
+        let fat_ptr = Expr::new(
+            ExprKind::StructInit(
+                vec![format!("__Dyn_{}", trait_name)],
+                vec![
+                    ("data".to_string(), data_ptr),
+                    ("vtable".to_string(), vtable_ptr),
+                ],
+                vec![],
+            ),
+            span,
+        );
+        (fat_ptr, target.clone())
+    }
+
+    /// Infers `expr` where a `target` is expected, converting it to `&dyn`
+    /// when `target` is one. An array literal for an array of `&dyn` has
+    /// each of its elements converted, so they may point to different types.
+    fn infer_expr_as(&mut self, expr: Expr, target: &Type) -> (Expr, Type) {
+        let elem = match (&expr.kind, target) {
+            (ExprKind::Lit(Lit::Array(items)), Type::Array(elem, _))
+                if !items.is_empty()
+                    && matches!(&**elem, Type::Struct(path, _)
+                        if path.last().is_some_and(|n| self.dyn_structs.contains_key(n))) =>
+            {
+                (**elem).clone()
+            }
+            _ => {
+                let (expr, ty) = self.infer_expr(expr);
+                return self.coerce_to_dyn(expr, ty, target);
+            }
+        };
+
+        let span = expr.span;
+        let ExprKind::Lit(Lit::Array(items)) = expr.kind else {
+            unreachable!()
+        };
+        let len = items.len();
+        let items = items
+            .into_iter()
+            .map(|item| {
+                let (item, ty) = self.infer_expr(item);
+                self.coerce_to_dyn(item, ty, &elem).0
+            })
+            .collect();
+        (
+            Expr::new(ExprKind::Lit(Lit::Array(items)), span),
+            Type::Array(Box::new(elem), len),
+        )
+    }
+
+    /// The `const` vtable pointing `trait_name`'s methods at those of
+    /// `type_name`, created on first use.
+    fn vtable(&mut self, trait_name: &str, type_name: &str, span: Span) -> String {
+        let name = format!("__vtable_{}__{}", trait_name, type_name);
+        if self.vtables.iter().any(|v| v.name == name) {
+            return name;
+        }
+
+        let vtable_ty = format!("__VTable_{}", trait_name);
+        let slots = self
+            .concrete_structs
+            .iter()
+            .find(|s| s.name == vtable_ty)
+            .map(|s| s.fields.clone())
+            .unwrap_or_default();
+        let fields = slots
+            .into_iter()
+            .map(|(method, slot_ty)| {
+                let func = format!("{}__{}", type_name, method);
+                let func = Expr::new(ExprKind::Ident(vec![func]), span);
+                let value = Expr::new(ExprKind::Cast(Box::new(func), slot_ty), span);
+                (method, value)
+            })
+            .collect();
+
+        self.vtables.push(StaticDef {
+            is_pub: false,
+            is_persistent: false,
+            name: name.clone(),
+            generics: vec![],
+            ty: Type::Struct(vec![vtable_ty.clone()], vec![]),
+            value: Expr::new(ExprKind::StructInit(vec![vtable_ty], fields, vec![]), span),
+            span,
+        });
+        name
+    }
+
+    /// Lowers `receiver.method(args)` on a `&dyn Trait` to a call through its
+    /// vtable, `receiver.vtable.method(receiver.data, args)`.
+    fn call_dyn_method(
+        &mut self,
+        mut receiver: Expr,
+        trait_name: &str,
+        method_name: String,
+        args: Vec<Expr>,
+        span: Span,
+    ) -> (Expr, Type) {
+        let dyn_ty = Type::Struct(vec![format!("__Dyn_{}", trait_name)], vec![]);
+        let method = self.traits[trait_name]
+            .methods
+            .iter()
+            .find(|m| m.name == method_name)
+            .cloned();
+        let Some(method) = method else {
+            self.error(
+                Diagnostic::error(format!(
+                    "no method `{}` on `&dyn {}`",
+                    method_name,
+                    trait_name.replace("__", "::")
+                ))
+                .with_span(span),
+            );
+            return (
+                Expr::new(
+                    ExprKind::MethodCall(Box::new(receiver), method_name, args, vec![]),
+                    span,
+                ),
+                Type::Void,
+            );
+        };
+
+        if method.params.len() != args.len() + 1 {
+            self.error(
+                Diagnostic::error(format!(
+                    "method '{}' takes {} argument(s) but {} were supplied",
+                    method_name,
+                    method.params.len().saturating_sub(1),
+                    args.len()
+                ))
+                .with_span(span),
+            );
+        }
+
+        // The receiver is read twice, for its data and its vtable.
+        if !receiver.is_place() {
+            let name = format!("__dyn_{}", self.temp_counter);
+            self.temp_counter += 1;
+
+            self.hoisted.push(Stmt::new(
+                StmtKind::Let(name.clone(), Some(dyn_ty.clone()), Some(receiver)),
+                span,
+            ));
+            self.register_var(name.clone(), dyn_ty);
+            receiver = Expr::new(ExprKind::Ident(vec![name]), span);
+        }
+
+        let member = |expr: Expr, field: &str| {
+            Expr::new(ExprKind::Member(Box::new(expr), field.to_string()), span)
+        };
+        let mut call_args = vec![member(receiver.clone(), "data")];
+        for (arg, (_, param_ty)) in args.into_iter().zip(method.params.iter().skip(1)) {
+            let (arg, arg_ty) = self.infer_expr(arg);
+            let param_ty = self.concrete_type(param_ty);
+            call_args.push(self.coerce_to_dyn(arg, arg_ty, &param_ty).0);
+        }
+
+        let vtable = Expr::new(ExprKind::Deref(Box::new(member(receiver, "vtable"))), span);
+        let callee = member(vtable, &method_name);
+        let ret_ty = self.concrete_type(&method.return_type);
+        (
+            Expr::new(ExprKind::Call(Box::new(callee), call_args, vec![]), span),
+            ret_ty,
+        )
+    }
+
+    /// The fat pointer struct for `&fn(args): ret`, `{ call, env }`, where
+    /// `call` takes the environment before the arguments.
+    fn closure_struct(&mut self, args: &[Type], ret: &Type, span: Span) -> String {
+        let name = format!(
+            "__{}",
+            Type::Closure(args.to_vec(), Box::new(ret.clone())).get_name()
+        );
+        if !self.closure_structs.insert(name.clone()) {
+            return name;
+        }
+
+        let env = Type::Pointer(Box::new(Type::Void));
+        let mut call_args = vec![env.clone()];
+        call_args.extend(args.iter().cloned());
+        self.concrete_structs.push(StructDef {
+            is_pub: true,
+            name: name.clone(),
+            generics: vec![],
+            fields: vec![
+                (
+                    "call".to_string(),
+                    Type::Function(call_args, Box::new(ret.clone()), vec![]),
+                ),
+                ("env".to_string(), env),
+            ],
+            span,
+        });
+        name
+    }
+
+    /// The fat pointer struct for `[]elem`, `{ ptr, len }`.
+    fn slice_struct(&mut self, elem: &Type, span: Span) -> Type {
+        let name = format!("__{}", Type::Slice(Box::new(elem.clone())).get_name());
+        if !self.slice_structs.contains_key(&name) {
+            self.slice_structs.insert(name.clone(), elem.clone());
+            self.concrete_structs.push(StructDef {
+                is_pub: true,
+                name: name.clone(),
+                generics: vec![],
+                fields: vec![
+                    ("ptr".to_string(), Type::Pointer(Box::new(elem.clone()))),
+                    ("len".to_string(), Type::I64),
+                ],
+                span,
+            });
+        }
+        Type::Struct(vec![name], vec![])
+    }
+
+    /// The element type of `ty` if it is a lowered `[]T`.
+    fn slice_elem(&self, ty: &Type) -> Option<Type> {
+        match ty {
+            Type::Struct(path, generics) if generics.is_empty() => {
+                path.last().and_then(|n| self.slice_structs.get(n)).cloned()
+            }
+            _ => None,
+        }
+    }
+
+    /// Stores `expr` in a fresh local named after `prefix` unless it can be
+    /// read twice as it is, returning what to read instead.
+    fn read_twice(&mut self, expr: Expr, ty: &Type, prefix: &str) -> Expr {
+        if reads_place(&expr) || matches!(expr.kind, ExprKind::Lit(Lit::Int(_))) {
+            return expr;
+        }
+
+        let span = expr.span;
+        let name = format!("__{}_{}", prefix, self.temp_counter);
+        self.temp_counter += 1;
+
+        self.hoisted.push(Stmt::new(
+            StmtKind::Let(name.clone(), Some(ty.clone()), Some(expr)),
+            span,
+        ));
+        self.register_var(name.clone(), ty.clone());
+        Expr::new(ExprKind::Ident(vec![name]), span)
+    }
+
+    /// `ptr`, checked against null in a debug build. An address taken with
+    /// `&` or a literal is never null.
+    fn checked_pointer(&self, ptr: Expr, ty: &Type) -> Expr {
+        if !self.debug
+            || !matches!(ty, Type::Pointer(_))
+            || matches!(ptr.kind, ExprKind::AddrOf(_) | ExprKind::Lit(_))
+            || checked_operand(&ptr).is_some()
+        {
+            return ptr;
+        }
+        let span = ptr.span;
+        let check = runtime_check("null", vec![ptr], span);
+        Expr::new(ExprKind::Cast(Box::new(check), ty.clone()), span)
+    }
+
+    /// `value` of the union struct `ty`, checked in a debug build to hold the
+    /// variant of type `variant` before that variant is read.
+    fn checked_variant(&mut self, value: Expr, ty: Type, variant: &Type) -> Expr {
+        if !self.debug {
+            return value;
+        }
+        let span = value.span;
+        let tag = Expr::new(ExprKind::Lit(Lit::Int(self.get_type_tag(variant))), span);
+        let value = self.read_twice(value, &ty, "union");
+        let held = Expr::new(
+            ExprKind::Member(Box::new(value.clone()), "tag".to_string()),
+            span,
+        );
+        let addr = Expr::new(ExprKind::AddrOf(Box::new(value)), span);
+        let check = runtime_check("tag", vec![addr, held, tag], span);
+        let check = Expr::new(
+            ExprKind::Cast(Box::new(check), Type::Pointer(Box::new(ty))),
+            span,
+        );
+        Expr::new(ExprKind::Deref(Box::new(check)), span)
+    }
+
+    /// `base[lo..hi]` as a slice of an array, slice or pointer. The bounds
+    /// default to the whole of `base`; a pointer has no length, so it needs
+    /// `hi`. In a debug build the range is checked against the length.
+    fn build_slice(
+        &mut self,
+        base: Expr,
+        base_ty: Type,
+        lo: Option<Expr>,
+        hi: Option<Expr>,
+        span: Span,
+    ) -> (Expr, Type) {
+        let int = |value: i64| Expr::new(ExprKind::Lit(Lit::Int(value)), span);
+        let member = |expr: Expr, field: &str| {
+            Expr::new(ExprKind::Member(Box::new(expr), field.to_string()), span)
+        };
+
+        let (elem, data, len) = match &base_ty {
+            Type::Array(elem, n) => {
+                let base = self.read_twice(base, &base_ty, "slice");
+                ((**elem).clone(), base, Some(int(*n as i64)))
+            }
+            Type::Pointer(elem) => ((**elem).clone(), base, None),
+            _ => match self.slice_elem(&base_ty) {
+                Some(elem) => {
+                    let base = self.read_twice(base, &base_ty, "slice");
+                    (elem, member(base.clone(), "ptr"), Some(member(base, "len")))
+                }
+                None => {
+                    self.error(
+                        Diagnostic::error(format!("cannot slice type {}", base_ty)).with_span(span),
+                    );
+                    return (base, Type::Void);
+                }
+            },
+        };
+        let slice_ty = self.slice_struct(&elem, span);
+
+        let Some(hi) = hi.or_else(|| len.clone()) else {
+            self.error(
+                Diagnostic::error(format!("cannot slice {} without an end bound", base_ty))
+                    .with_span(span)
+                    .with_note("a pointer has no length; write `p[a..b]`"),
+            );
+            return (data, slice_ty);
+        };
+        let lo = lo.unwrap_or_else(|| int(0));
+        let lo = self.read_twice(lo, &Type::I64, "lo");
+        let hi = self.read_twice(hi, &Type::I64, "hi");
+
+        let start = match len {
+            Some(len) if self.debug => {
+                runtime_check("range", vec![lo.clone(), hi.clone(), len], span)
+            }
+            _ => lo.clone(),
+        };
+        let ptr = Expr::new(
+            ExprKind::AddrOf(Box::new(Expr::new(
+                ExprKind::Index(Box::new(data), Box::new(start)),
+                span,
+            ))),
+            span,
+        );
+        let len = Expr::new(
+            ExprKind::Binary(Box::new(hi), BinaryOp::Sub, Box::new(lo)),
+            span,
+        );
+
+        let Type::Struct(path, _) = &slice_ty else {
+            unreachable!()
+        };
+        (
+            Expr::new(
+                ExprKind::StructInit(
+                    path.clone(),
+                    vec![("ptr".to_string(), ptr), ("len".to_string(), len)],
+                    vec![],
+                ),
+                span,
+            ),
+            slice_ty,
+        )
+    }
+
+    /// Lifts a closure into the trampoline `__closure_N(env, params)`, whose
+    /// prologue unpacks the captures from `env`, and builds the `&fn` value
+    /// pairing it with an environment hoisted into the current frame.
+    fn build_closure(&mut self, closure: Closure, span: Span) -> (Expr, Type) {
+        let id = self.temp_counter;
+        self.temp_counter += 1;
+
+        let Closure {
+            captures,
+            params,
+            return_type,
+            body,
+        } = closure;
+        let params: Vec<(String, Type)> = params
+            .into_iter()
+            .map(|(name, ty)| (name, self.concrete_type(&ty)))
+            .collect();
+        let return_type = self.concrete_type(&return_type);
+        let captures = match captures {
+            Some(captures) => captures,
+            None => self.implicit_captures(&params, &body, span),
+        };
+
+        let env_name = format!("__closure_{}_env", id);
+        let env_ptr = Type::Pointer(Box::new(Type::Struct(vec![env_name.clone()], vec![])));
+        let mut fields: Vec<(String, Type)> = Vec::new();
+        let mut inits = Vec::new();
+        let mut prologue = Vec::new();
+        let mut refs = HashMap::new();
+
+        for Capture { name, by_ref, span } in captures {
+            if fields.iter().any(|(n, _)| *n == name) {
+                self.error(
+                    Diagnostic::error(format!("`{}` is captured twice", name)).with_span(span),
+                );
+                continue;
+            }
+            let Some(ty) = self.local_var_type(&name) else {
+                self.error(
+                    Diagnostic::error(format!("cannot capture `{}`", name))
+                        .with_span(span)
+                        .with_note("only local variables of the enclosing function are captured"),
+                );
+                continue;
+            };
+
+            let local = Expr::new(ExprKind::Ident(vec![name.clone()]), span);
+            let env = Expr::new(
+                ExprKind::Cast(
+                    Box::new(Expr::new(ExprKind::Ident(vec!["__env".to_string()]), span)),
+                    env_ptr.clone(),
+                ),
+                span,
+            );
+            let unpacked = Expr::new(ExprKind::Member(Box::new(env), name.clone()), span);
+
+            let (binding, field_ty, init) = if by_ref {
+                refs.insert(name.clone(), ty.clone());
+                let ptr = Type::Pointer(Box::new(ty));
+                let init = Expr::new(ExprKind::AddrOf(Box::new(local)), span);
+                (format!("__ref_{}", name), ptr, init)
+            } else {
+                (name.clone(), ty, local)
+            };
+            prologue.push(Stmt::new(
+                StmtKind::Let(binding, Some(field_ty.clone()), Some(unpacked)),
+                span,
+            ));
+            fields.push((name.clone(), field_ty));
+            inits.push((name, init));
+        }
+
+        let env = if fields.is_empty() {
+            Expr::new(ExprKind::Lit(Lit::Null), span)
+        } else {
+            self.concrete_structs.push(StructDef {
+                is_pub: false,
+                name: env_name.clone(),
+                generics: vec![],
+                fields,
+                span,
+            });
+
+            let local = format!("__env_{}", id);
+            let env_ty = Type::Struct(vec![env_name.clone()], vec![]);
+            let init = Expr::new(ExprKind::StructInit(vec![env_name], inits, vec![]), span);
+            let (init, _) = self.infer_expr(init);
+            self.hoisted.push(Stmt::new(
+                StmtKind::Let(local.clone(), Some(env_ty.clone()), Some(init)),
+                span,
+            ));
+            self.register_var(local.clone(), env_ty);
+
+            let local = Expr::new(ExprKind::Ident(vec![local]), span);
+            Expr::new(
+                ExprKind::Cast(
+                    Box::new(Expr::new(ExprKind::AddrOf(Box::new(local)), span)),
+                    Type::Pointer(Box::new(Type::Void)),
+                ),
+                span,
+            )
+        };
+
+        let trampoline_name = format!("__closure_{}", id);
+        let mut trampoline_params =
+            vec![("__env".to_string(), Type::Pointer(Box::new(Type::Void)))];
+        trampoline_params.extend(params.iter().cloned());
+        prologue.extend(body);
+        let mut trampoline = FunctionDef {
+            is_pub: false,
+            name: trampoline_name.clone(),
+            generics: vec![],
+            bounds: vec![],
+            params: trampoline_params,
+            return_type: return_type.clone(),
+            body: FunctionBody::UserDefined(prologue),
+            is_variadic: false,
+            span,
+        };
+
+        // The body sees the statics and its captures, not the caller's locals.
+        let globals = self.scopes[0].clone();
+        let outer_scopes = std::mem::replace(&mut self.scopes, vec![globals]);
+        let outer_narrowings = std::mem::replace(&mut self.narrowings, vec![HashMap::new()]);
+        let outer_refs = std::mem::replace(&mut self.ref_captures, refs);
+        self.check_function(&mut trampoline);
+        self.scopes = outer_scopes;
+        self.narrowings = outer_narrowings;
+        self.ref_captures = outer_refs;
+        self.concrete_funcs.push(trampoline);
+
+        let args: Vec<Type> = params.into_iter().map(|(_, ty)| ty).collect();
+        let closure_name = self.closure_struct(&args, &return_type, span);
+        let call = Expr::new(ExprKind::Ident(vec![trampoline_name]), span);
+        (
+            Expr::new(
+                ExprKind::StructInit(
+                    vec![closure_name.clone()],
+                    vec![("call".to_string(), call), ("env".to_string(), env)],
+                    vec![],
+                ),
+                span,
+            ),
+            Type::Struct(vec![closure_name], vec![]),
+        )
+    }
+
+    /// The outer locals a closure without a capture list names, all by value.
+    fn implicit_captures(
+        &self,
+        params: &[(String, Type)],
+        body: &[Stmt],
+        span: Span,
+    ) -> Vec<Capture> {
+        let mut names: Vec<String> = Vec::new();
+        for stmt in body {
+            walk_stmt(stmt, &mut |expr| {
+                let name = match &expr.kind {
+                    ExprKind::Ident(path) if path.len() == 1 => &path[0],
+                    _ => return,
+                };
+                if !names.contains(name) {
+                    names.push(name.clone());
+                }
+            });
+        }
+
+        names
+            .into_iter()
+            .filter(|name| params.iter().all(|(p, _)| p != name))
+            .filter(|name| self.local_var_type(name).is_some())
+            .map(|name| Capture {
+                name,
+                by_ref: false,
+                span,
+            })
+            .collect()
+    }
+
+    /// Splits a call through a closure into its function pointer and the
+    /// environment to pass first; other callees come back unchanged.
+    fn unpack_closure(&mut self, callee: Expr, callee_ty: Type) -> (Expr, Option<Expr>, Type) {
+        let def = match &callee_ty {
+            Type::Struct(path, _) if self.closure_structs.contains(&path[0]) => self
+                .concrete_structs
+                .iter()
+                .find(|s| s.name == path[0])
+                .cloned(),
+            _ => None,
+        };
+        let Some(def) = def else {
+            return (callee, None, callee_ty);
+        };
+
+        let span = callee.span;
+        let mut closure = callee;
+        // The closure is read twice, for its function and its environment.
+        if !closure.is_place() {
+            let name = format!("__callee_{}", self.temp_counter);
+            self.temp_counter += 1;
+
+            self.hoisted.push(Stmt::new(
+                StmtKind::Let(name.clone(), Some(callee_ty.clone()), Some(closure)),
+                span,
+            ));
+            self.register_var(name.clone(), callee_ty);
+            closure = Expr::new(ExprKind::Ident(vec![name]), span);
+        }
+
+        let member = |field: &str| {
+            Expr::new(
+                ExprKind::Member(Box::new(closure.clone()), field.to_string()),
+                span,
+            )
+        };
+        let call_ty = match &def.fields[0].1 {
+            Type::Function(args, ret, _) => Type::Function(args[1..].to_vec(), ret.clone(), vec![]),
+            ty => ty.clone(),
+        };
+        (member("call"), Some(member("env")), call_ty)
+    }
+
+    /// Whether values of `ty` can be called: function pointers and closures.
+    fn is_callable(&self, ty: &Type) -> bool {
+        match ty {
+            Type::Function(..) => true,
+            Type::Struct(path, _) => self.closure_structs.contains(&path[0]),
+            _ => false,
+        }
+    }
+
+    /// The type of the local variable `name`, statics excluded; inside a
+    /// closure this includes what it captured by reference.
+    fn local_var_type(&self, name: &str) -> Option<Type> {
+        let local = self.scopes.iter().skip(1).rev().find_map(|s| s.get(name));
+        match local {
+            Some(ty) => Some(ty.clone()),
+            None => self.ref_captures.get(name).cloned(),
+        }
+    }
+
+    /// The type of `name` when it refers to a local captured by reference,
+    /// rather than a variable declared since.
+    fn captured_by_ref(&self, name: &str) -> Option<Type> {
+        if self.scopes.iter().skip(1).any(|s| s.contains_key(name)) {
+            return None;
+        }
+        self.ref_captures.get(name).cloned()
+    }
+
     fn resolve_generics_in_func(&self, func: &mut FunctionDef) {
-        let generics = func.generics.clone();
+        let generics = generic_names(&func.generics);
 
         for (_, param_ty) in &mut func.params {
             self.convert_struct_to_generic(param_ty, &generics);
@@ -321,29 +1910,38 @@ impl TypeChecker {
                     self.convert_struct_to_generic(arg, generic_names);
                 }
             }
-            Type::Pointer(inner) | Type::Array(inner, _) => {
+            Type::Pointer(inner)
+            | Type::Array(inner, _)
+            | Type::ConstArray(inner, _)
+            | Type::Slice(inner)
+            | Type::Optional(inner) => {
                 self.convert_struct_to_generic(inner, generic_names);
             }
-            Type::Function(args, ret, _) => {
+            Type::Function(args, ret, _) | Type::Closure(args, ret) => {
                 for arg in args {
                     self.convert_struct_to_generic(arg, generic_names);
                 }
                 self.convert_struct_to_generic(ret, generic_names);
             }
+            Type::Union(variants) => {
+                for variant in variants {
+                    self.convert_struct_to_generic(variant, generic_names);
+                }
+            }
             _ => {}
         }
     }
 
     fn resolve_generics_in_struct(&self, struct_def: &mut StructDef) {
-        let generics = struct_def.generics.clone();
+        let generics = generic_names(&struct_def.generics);
         for (_, field_ty) in &mut struct_def.fields {
             self.convert_struct_to_generic(field_ty, &generics);
         }
     }
 
     fn resolve_generics_in_stmt(&self, stmt: &mut Stmt, generic_names: &[String]) {
-        match stmt {
-            Stmt::Let(_, ty_opt, expr_opt) => {
+        match &mut stmt.kind {
+            StmtKind::Let(_, ty_opt, expr_opt) => {
                 if let Some(ty) = ty_opt {
                     self.convert_struct_to_generic(ty, generic_names);
                 }
@@ -351,25 +1949,26 @@ impl TypeChecker {
                     self.resolve_generics_in_expr(expr, generic_names);
                 }
             }
-            Stmt::Assign(lhs, rhs) => {
+            StmtKind::Assign(lhs, rhs) => {
                 self.resolve_generics_in_expr(lhs, generic_names);
                 self.resolve_generics_in_expr(rhs, generic_names);
             }
-            Stmt::Expr(expr) | Stmt::Ret(expr) => {
+            StmtKind::Expr(expr) | StmtKind::Ret(expr) => {
                 self.resolve_generics_in_expr(expr, generic_names);
             }
-            Stmt::If(cond, then_b, else_b) => {
+            StmtKind::If(cond, then_b, else_b) => {
                 self.resolve_generics_in_expr(cond, generic_names);
                 self.resolve_generics_in_stmt(then_b, generic_names);
                 if let Some(e) = else_b {
                     self.resolve_generics_in_stmt(e, generic_names);
                 }
             }
-            Stmt::While(cond, body) => {
+            StmtKind::While(cond, body) => {
                 self.resolve_generics_in_expr(cond, generic_names);
                 self.resolve_generics_in_stmt(body, generic_names);
             }
-            Stmt::Block(stmts) => {
+            StmtKind::Defer(body) => self.resolve_generics_in_stmt(body, generic_names),
+            StmtKind::Block(stmts) => {
                 for s in stmts {
                     self.resolve_generics_in_stmt(s, generic_names);
                 }
@@ -379,17 +1978,17 @@ impl TypeChecker {
     }
 
     fn resolve_generics_in_expr(&self, expr: &mut Expr, generic_names: &[String]) {
-        match expr {
-            Expr::Cast(inner, ty) => {
+        match &mut expr.kind {
+            ExprKind::Cast(inner, ty) => {
                 self.resolve_generics_in_expr(inner, generic_names);
                 self.convert_struct_to_generic(ty, generic_names);
             }
 
-            Expr::Binary(lhs, _, rhs) => {
+            ExprKind::Binary(lhs, _, rhs) | ExprKind::OrElse(lhs, rhs) => {
                 self.resolve_generics_in_expr(lhs, generic_names);
                 self.resolve_generics_in_expr(rhs, generic_names);
             }
-            Expr::Call(callee, args, generics) => {
+            ExprKind::Call(callee, args, generics) => {
                 self.resolve_generics_in_expr(callee, generic_names);
                 for arg in args {
                     self.resolve_generics_in_expr(arg, generic_names);
@@ -398,7 +1997,7 @@ impl TypeChecker {
                     self.convert_struct_to_generic(g, generic_names);
                 }
             }
-            Expr::StructInit(_, fields, generics) => {
+            ExprKind::StructInit(_, fields, generics) => {
                 for (_, val) in fields {
                     self.resolve_generics_in_expr(val, generic_names);
                 }
@@ -406,24 +2005,50 @@ impl TypeChecker {
                     self.convert_struct_to_generic(g, generic_names);
                 }
             }
-            Expr::Unary(_, inner)
-            | Expr::Deref(inner)
-            | Expr::AddrOf(inner)
-            | Expr::Member(inner, _) => {
+            ExprKind::Unary(_, inner)
+            | ExprKind::Deref(inner)
+            | ExprKind::AddrOf(inner)
+            | ExprKind::Try(inner)
+            | ExprKind::Member(inner, _) => {
                 self.resolve_generics_in_expr(inner, generic_names);
             }
-            Expr::SizeOf(ty) => {
+            ExprKind::SizeOf(ty) | ExprKind::Reflect(_, ty) => {
                 self.convert_struct_to_generic(ty, generic_names);
             }
-            Expr::Index(arr, idx) => {
+            ExprKind::Index(arr, idx) => {
                 self.resolve_generics_in_expr(arr, generic_names);
                 self.resolve_generics_in_expr(idx, generic_names);
             }
-            Expr::Lit(Lit::Array(exprs)) => {
+            ExprKind::Range(lo, hi) => {
+                for bound in [lo, hi].into_iter().flatten() {
+                    self.resolve_generics_in_expr(bound, generic_names);
+                }
+            }
+            ExprKind::Lit(Lit::Array(exprs)) => {
                 for e in exprs {
                     self.resolve_generics_in_expr(e, generic_names);
                 }
             }
+            ExprKind::Match(subject, arms) => {
+                self.resolve_generics_in_expr(subject, generic_names);
+                for arm in arms {
+                    if let Pattern::Variant(ty, _) = &mut arm.pattern {
+                        self.convert_struct_to_generic(ty, generic_names);
+                    }
+                    for stmt in &mut arm.body {
+                        self.resolve_generics_in_stmt(stmt, generic_names);
+                    }
+                }
+            }
+            ExprKind::Closure(closure) => {
+                for (_, ty) in &mut closure.params {
+                    self.convert_struct_to_generic(ty, generic_names);
+                }
+                self.convert_struct_to_generic(&mut closure.return_type, generic_names);
+                for stmt in &mut closure.body {
+                    self.resolve_generics_in_stmt(stmt, generic_names);
+                }
+            }
             _ => {}
         }
     }
@@ -437,6 +2062,8 @@ impl TypeChecker {
 
                 *param_type = Type::Struct(vec![struct_name], vec![]);
             }
+            self.substitute_type(param_type, &HashMap::new());
+            self.lower_fat_pointers(param_type, func.span);
 
             self.register_var(param_name.clone(), param_type.clone());
         }
@@ -445,24 +2072,114 @@ impl TypeChecker {
             let struct_name = self.get_or_create_union_struct(variants);
             func.return_type = Type::Struct(vec![struct_name], vec![]);
         }
+        self.substitute_type(&mut func.return_type, &HashMap::new());
+        self.lower_fat_pointers(&mut func.return_type, func.span);
+        if let Type::Array(..) = func.return_type {
+            self.error(
+                Diagnostic::error(format!(
+                    "function '{}' cannot return the array type {}",
+                    func.name, func.return_type
+                ))
+                .with_span(func.span)
+                .with_note("wrap the array in a struct to return it by value"),
+            );
+        }
 
+        let outer_return = std::mem::replace(&mut self.return_type, func.return_type.clone());
         if let FunctionBody::UserDefined(ref mut stmts) = func.body {
             self.check_stmts(stmts);
         }
+        self.return_type = outer_return;
 
         self.exit_scope();
     }
-    fn check_stmts(&mut self, stmts: &mut [Stmt]) {
-        for stmt in stmts {
-            self.check_stmt(stmt);
+    fn check_stmts(&mut self, stmts: &mut Vec<Stmt>) {
+        let outer = std::mem::take(&mut self.hoisted);
+        let mut checked = Vec::with_capacity(stmts.len());
+
+        for mut stmt in stmts.drain(..) {
+            // `if x is T { ret }` leaves `x` narrowed for the rest of the block.
+            let narrowed_after = match &stmt.kind {
+                StmtKind::If(cond, then_block, else_block) => {
+                    let else_exits = else_block.as_ref().is_some_and(|e| e.always_exits());
+                    match (then_block.always_exits(), else_exits) {
+                        (true, false) => type_tests(cond, false),
+                        (false, true) => type_tests(cond, true),
+                        _ => Vec::new(),
+                    }
+                }
+                _ => Vec::new(),
+            };
+            self.check_stmt(&mut stmt);
+            checked.append(&mut self.hoisted);
+            checked.push(stmt);
+            self.narrow(&narrowed_after);
         }
+
+        *stmts = checked;
+        self.hoisted = outer;
     }
 
     fn check_stmt(&mut self, stmt: &mut Stmt) {
-        match stmt {
-            Stmt::Let(name, ty_opt, expr_opt) => {
+        match &mut stmt.kind {
+            StmtKind::Let(name, ty_opt, Some(expr)) if matches!(expr.kind, ExprKind::Match(..)) => {
+                let (new_expr, expr_ty) = self.check_match(expr.clone(), true);
+                *expr = new_expr;
+
+                if let Some(Type::Union(variants)) = ty_opt {
+                    let struct_name = self.get_or_create_union_struct(variants);
+                    *ty_opt = Some(Type::Struct(vec![struct_name], vec![]));
+                }
+
+                match ty_opt {
+                    Some(explicit_ty) => {
+                        if !self.are_types_compatible(explicit_ty, &expr_ty)
+                            && !self.same_number_kind(explicit_ty, &expr_ty)
+                        {
+                            let diagnostic = Diagnostic::error(format!(
+                                "mismatched types in let binding for '{}': expected {}, found {}",
+                                name, explicit_ty, expr_ty
+                            ))
+                            .with_span(expr.span);
+                            self.error(diagnostic);
+                        }
+                    }
+                    None => *ty_opt = Some(expr_ty),
+                }
+
+                let final_ty = ty_opt.as_ref().unwrap().clone();
+                self.register_var(name.clone(), final_ty);
+            }
+            StmtKind::Expr(expr) if matches!(expr.kind, ExprKind::Match(..)) => {
+                *expr = self.check_match(expr.clone(), false).0;
+            }
+            StmtKind::Ret(expr) if matches!(expr.kind, ExprKind::Match(..)) => {
+                *expr = self.check_match(expr.clone(), true).0;
+            }
+            StmtKind::Expr(Expr {
+                kind: ExprKind::Binary(lhs, BinaryOp::Assign, rhs),
+                ..
+            }) if matches!(rhs.kind, ExprKind::Match(..)) => {
+                **lhs = self.infer_expr((**lhs).clone()).0;
+                **rhs = self.check_match((**rhs).clone(), true).0;
+            }
+            StmtKind::Let(name, ty_opt, expr_opt) => {
+                if let Some(ty) = ty_opt {
+                    self.substitute_type(ty, &HashMap::new());
+                    self.lower_fat_pointers(ty, stmt.span);
+                }
+
                 if let Some(expr) = expr_opt {
-                    let (mut new_expr, mut expr_ty) = self.infer_expr(expr.clone());
+                    let span = expr.span;
+                    let (mut new_expr, mut expr_ty) = match ty_opt {
+                        Some(ty) => {
+                            let (new_expr, expr_ty) = self.infer_expr_as(expr.clone(), ty);
+                            let (new_expr, expr_ty) = self.coerce_array(new_expr, expr_ty, ty);
+                            let (new_expr, expr_ty) = self.coerce_null(new_expr, expr_ty, ty);
+                            self.coerce_fn_ptr(new_expr, expr_ty, ty)
+                        }
+                        None => self.infer_expr(expr.clone()),
+                    };
 
                     if let Some(Type::Union(variants)) = ty_opt {
                         if !variants.contains(&expr_ty) {
@@ -473,7 +2190,10 @@ impl TypeChecker {
                                     self.is_float(variant) && self.is_float(&expr_ty);
 
                                 if is_int_conv || is_float_conv {
-                                    new_expr = Expr::Cast(Box::new(new_expr), variant.clone());
+                                    new_expr = Expr::new(
+                                        ExprKind::Cast(Box::new(new_expr), variant.clone()),
+                                        span,
+                                    );
                                     expr_ty = variant.clone();
                                     break;
                                 }
@@ -501,18 +2221,27 @@ impl TypeChecker {
                             let variant_index =
                                 sorted_variants.iter().position(|t| t == &expr_ty).unwrap();
 
-                            let inner_init = Expr::UnionInit(
-                                vec![inner_struct_name],
-                                vec![(format!("variant_{}", variant_index), new_expr)],
+                            let inner_init = Expr::new(
+                                ExprKind::UnionInit(
+                                    vec![inner_struct_name],
+                                    vec![(format!("variant_{}", variant_index), new_expr)],
+                                ),
+                                span,
                             );
 
-                            new_expr = Expr::StructInit(
-                                vec![struct_name.clone()],
-                                vec![
-                                    ("tag".to_string(), Expr::Lit(Lit::Int(tag_val))),
-                                    ("data".to_string(), inner_init),
-                                ],
-                                vec![],
+                            new_expr = Expr::new(
+                                ExprKind::StructInit(
+                                    vec![struct_name.clone()],
+                                    vec![
+                                        (
+                                            "tag".to_string(),
+                                            Expr::new(ExprKind::Lit(Lit::Int(tag_val)), span),
+                                        ),
+                                        ("data".to_string(), inner_init),
+                                    ],
+                                    vec![],
+                                ),
+                                span,
                             );
 
                             let concrete_struct_type = Type::Struct(vec![struct_name], vec![]);
@@ -533,12 +2262,17 @@ impl TypeChecker {
                                     self.is_float(explicit_ty) && self.is_float(&expr_ty);
 
                                 if is_int_conversion || is_float_conversion {
-                                    *expr = Expr::Cast(Box::new(expr.clone()), explicit_ty.clone());
+                                    *expr = Expr::new(
+                                        ExprKind::Cast(Box::new(expr.clone()), explicit_ty.clone()),
+                                        span,
+                                    );
                                 } else {
-                                    panic!(
-                                        "Type mismatch in let binding for '{}': expected {:?}, found {:?}",
+                                    let diagnostic = Diagnostic::error(format!(
+                                        "mismatched types in let binding for '{}': expected {}, found {}",
                                         name, explicit_ty, expr_ty
-                                    );
+                                    ))
+                                    .with_span(span);
+                                    self.error(diagnostic);
                                 }
                             }
                         }
@@ -561,42 +2295,113 @@ impl TypeChecker {
                             self.register_var(name.clone(), explicit_ty.clone());
                         }
                         None => {
-                            panic!(
-                                "Type annotation required for uninitialized variable '{}'",
-                                name
+                            self.error(
+                                Diagnostic::error(format!(
+                                    "type annotation required for uninitialized variable '{}'",
+                                    name
+                                ))
+                                .with_span(stmt.span),
                             );
+                            self.register_var(name.clone(), Type::Void);
                         }
                     }
                 }
             }
 
-            Stmt::Expr(expr) => {
+            StmtKind::Expr(expr) => {
                 let (new_expr, _) = self.infer_expr(expr.clone());
-                *stmt = Stmt::Expr(new_expr);
+                *expr = new_expr;
             }
-            Stmt::Ret(expr) => {
-                let (new_expr, _) = self.infer_expr(expr.clone());
-                *stmt = Stmt::Ret(new_expr);
+            StmtKind::Ret(expr) => {
+                let return_type = self.return_type.clone();
+                let (new_expr, ty) = self.infer_expr_as(expr.clone(), &return_type);
+                let (new_expr, ty) = self.coerce_union(new_expr, ty, &return_type);
+                // A union that may still hold several variants is not any one of them.
+                if ty != return_type && self.union_variants(&ty).is_some() {
+                    self.error(
+                        Diagnostic::error(format!(
+                            "mismatched types: expected {} from `ret`, found {}",
+                            self.type_name(&return_type),
+                            self.type_name(&ty)
+                        ))
+                        .with_span(new_expr.span)
+                        .with_note("test it with `is` first, or `match` on it"),
+                    );
+                }
+                *expr = new_expr;
+            }
+            // `x += y`, checked as the assignment `x = x + y` it stands for.
+            StmtKind::Assign(lhs, rhs) => {
+                let assign = Expr::new(
+                    ExprKind::Binary(
+                        Box::new(lhs.clone()),
+                        BinaryOp::Assign,
+                        Box::new(rhs.clone()),
+                    ),
+                    stmt.span,
+                );
+                if let ExprKind::Binary(new_lhs, _, new_rhs) = self.infer_expr(assign).0.kind {
+                    *lhs = *new_lhs;
+                    *rhs = *new_rhs;
+                }
             }
-            Stmt::If(cond, then_block, else_block) => {
+            StmtKind::If(cond, then_block, else_block) => {
+                let then_tests = type_tests(cond, true);
+                let else_tests = type_tests(cond, false);
                 let (new_cond, _) = self.infer_expr(cond.clone());
                 *cond = new_cond;
+
+                self.enter_scope();
+                self.narrow(&then_tests);
                 self.check_stmt(then_block);
+                self.exit_scope();
                 if let Some(else_b) = else_block {
+                    self.enter_scope();
+                    self.narrow(&else_tests);
                     self.check_stmt(else_b);
+                    self.exit_scope();
                 }
             }
-            Stmt::While(cond, body) => {
+            StmtKind::While(cond, body) => {
+                // The condition is checked again after every iteration, so
+                // nothing known before the loop holds for what it assigns.
+                for name in assigned_vars(cond, body) {
+                    self.forget_narrowing(&name);
+                }
+                let tests = type_tests(cond, true);
                 let (new_cond, _) = self.infer_expr(cond.clone());
                 *cond = new_cond;
+
+                self.enter_scope();
+                self.narrow(&tests);
                 self.check_stmt(body);
+                self.exit_scope();
             }
-            Stmt::Block(inner_stmts) => {
+            StmtKind::Block(inner_stmts) => {
                 self.enter_scope();
                 self.check_stmts(inner_stmts);
                 self.exit_scope();
             }
-            Stmt::FunctionDef(func_def) => {
+            StmtKind::Defer(body) => {
+                // Checked as a block of its own, so whatever it hoists runs
+                // when it does rather than where it is deferred.
+                let span = body.span;
+                let placeholder = Stmt::new(StmtKind::Block(Vec::new()), span);
+                let mut stmts = vec![std::mem::replace(&mut **body, placeholder)];
+                self.enter_scope();
+                self.check_stmts(&mut stmts);
+                self.exit_scope();
+                **body = Stmt::new(StmtKind::Block(stmts), span);
+
+                if let Some(exit) = leaves_defer(body, false) {
+                    self.error(
+                        Diagnostic::error("a deferred statement cannot `ret`, `out` or `next`")
+                            .with_span(exit.span)
+                            .with_note("it already runs while its block is being left"),
+                    );
+                }
+            }
+            StmtKind::FunctionDef(func_def) => {
                 self.register_local_func(func_def.name.clone(), *func_def.clone());
 
                 self.check_function(func_def);
@@ -606,10 +2411,28 @@ impl TypeChecker {
     }
 
     fn infer_expr(&mut self, expr: Expr) -> (Expr, Type) {
-        match expr {
-            Expr::Binary(lhs, BinaryOp::Assign, rhs) => {
-                let (new_lhs, lhs_ty) = self.infer_expr(*lhs);
-                let (mut new_rhs, rhs_ty) = self.infer_expr(*rhs);
+        let span = expr.span;
+        match expr.kind {
+            ExprKind::Binary(lhs, BinaryOp::Assign, rhs) => {
+                // Assigning a narrowed union variable assigns the whole union.
+                let widened = match &lhs.kind {
+                    ExprKind::Ident(path) if path.len() == 1 => self
+                        .narrowed_type(&path[0])
+                        .and_then(|_| self.get_var_type(&path[0]))
+                        .map(|ty| (path[0].clone(), ty)),
+                    _ => None,
+                };
+                let (new_lhs, lhs_ty) = match &widened {
+                    Some((_, ty)) => (*lhs, ty.clone()),
+                    None => self.infer_expr(*lhs),
+                };
+                let (new_rhs, rhs_ty) = self.infer_expr_as(*rhs, &lhs_ty);
+                if let Some((name, _)) = &widened {
+                    self.forget_narrowing(name);
+                }
+                let (new_rhs, rhs_ty) = self.coerce_array(new_rhs, rhs_ty, &lhs_ty);
+                let (new_rhs, rhs_ty) = self.coerce_null(new_rhs, rhs_ty, &lhs_ty);
+                let (mut new_rhs, rhs_ty) = self.coerce_fn_ptr(new_rhs, rhs_ty, &lhs_ty);
 
                 if let Type::Struct(names, _) = &lhs_ty {
                     let struct_name = &names[0];
@@ -639,29 +2462,133 @@ impl TypeChecker {
                 }
 
                 (
-                    Expr::Binary(Box::new(new_lhs), BinaryOp::Assign, Box::new(new_rhs)),
+                    Expr::new(
+                        ExprKind::Binary(Box::new(new_lhs), BinaryOp::Assign, Box::new(new_rhs)),
+                        span,
+                    ),
                     lhs_ty,
                 )
             }
 
-            Expr::Lit(lit) => self.infer_lit(lit),
+            ExprKind::Lit(lit) => self.infer_lit(lit, span),
 
-            Expr::Ident(path) => {
+            ExprKind::Ident(path) => {
                 let name = path.last().unwrap();
-                if let Some(ty) = self.get_var_type(name) {
-                    (Expr::Ident(path), ty)
+                if let Some(ty) = self.captured_by_ref(name) {
+                    let ptr = Expr::new(ExprKind::Ident(vec![format!("__ref_{}", name)]), span);
+                    (Expr::new(ExprKind::Deref(Box::new(ptr)), span), ty)
+                } else if let Some((ty, index)) = self.narrowed_type(name) {
+                    let member = |expr: Expr, field: String| {
+                        Expr::new(ExprKind::Member(Box::new(expr), field), span)
+                    };
+                    let value = Expr::new(ExprKind::Ident(path), span);
+                    match index {
+                        Some(index) => {
+                            let data = member(value, "data".to_string());
+                            (member(data, format!("variant_{}", index)), ty)
+                        }
+                        None => (value, ty),
+                    }
+                } else if let Some(ty) = self.get_var_type(name) {
+                    (Expr::new(ExprKind::Ident(path), span), ty)
+                } else if let Some((def, variant)) = self.enum_variant(name) {
+                    let (def, variant) = (def.clone(), variant.clone());
+                    self.construct_variant(def, variant, Vec::new(), span)
+                } else if let Some(ty) = self.function_type(&path.join("__")) {
+                    (Expr::new(ExprKind::Ident(path), span), ty)
                 } else {
-                    panic!("Undefined variable: {}", name);
+                    self.error(
+                        Diagnostic::error(format!("undefined variable '{}'", name)).with_span(span),
+                    );
+                    (Expr::new(ExprKind::Ident(path), span), Type::Void)
                 }
             }
 
-            Expr::Call(callee, args, generics) => {
-                self.handle_function_call(*callee, args, generics)
+            ExprKind::Call(callee, args, generics) => {
+                self.handle_function_call(*callee, args, generics, span)
             }
 
-            Expr::Binary(lhs, op, rhs) => {
+            ExprKind::Binary(lhs, op, rhs) => {
+                // `x is T and x.f` reads `x` as a `T` on the right.
+                let tests = match op {
+                    BinaryOp::And => type_tests(&lhs, true),
+                    BinaryOp::Or => type_tests(&lhs, false),
+                    _ => Vec::new(),
+                };
                 let (new_lhs, ty_lhs) = self.infer_expr(*lhs);
-                let (new_rhs, _) = self.infer_expr(*rhs);
+                let (new_rhs, ty_rhs) = if tests.is_empty() {
+                    self.infer_expr(*rhs)
+                } else {
+                    self.enter_scope();
+                    self.narrow(&tests);
+                    let rhs = self.infer_expr(*rhs);
+                    self.exit_scope();
+                    rhs
+                };
+                // `x == null` on a `T | null` compares its tag.
+                let null_test = match (&new_lhs.kind, &new_rhs.kind) {
+                    (_, ExprKind::Lit(Lit::Null)) => Some((&new_lhs, &ty_lhs)),
+                    (ExprKind::Lit(Lit::Null), _) => Some((&new_rhs, &ty_rhs)),
+                    _ => None,
+                };
+                if let (BinaryOp::Eq | BinaryOp::Neq, Some((value, value_ty))) = (op, null_test)
+                    && self
+                        .union_variants(value_ty)
+                        .is_some_and(|variants| variants.contains(&Type::Null))
+                {
+                    let tag = Expr::new(
+                        ExprKind::Member(Box::new(value.clone()), "tag".to_string()),
+                        span,
+                    );
+                    let null_tag = self.get_type_tag(&Type::Null);
+                    let null_tag = Expr::new(ExprKind::Lit(Lit::Int(null_tag)), span);
+                    return (
+                        Expr::new(
+                            ExprKind::Binary(Box::new(tag), op, Box::new(null_tag)),
+                            span,
+                        ),
+                        Type::Bool,
+                    );
+                }
+                let what = match op {
+                    BinaryOp::Assign | BinaryOp::And | BinaryOp::Or => None,
+                    BinaryOp::Eq
+                    | BinaryOp::Neq
+                    | BinaryOp::Lt
+                    | BinaryOp::Gt
+                    | BinaryOp::Lte
+                    | BinaryOp::Gte => Some("compare"),
+                    _ => Some("do arithmetic with"),
+                };
+                for (operand, operand_ty) in [(&new_lhs, &ty_lhs), (&new_rhs, &ty_rhs)] {
+                    // Two `?&T` compare as the pointers they are.
+                    let pointers = matches!(op, BinaryOp::Eq | BinaryOp::Neq)
+                        && matches!(operand_ty, Type::Optional(_));
+                    if let Some(what) = what.filter(|_| !pointers)
+                        && !self.reject_optional(operand_ty, what, operand.span)
+                    {
+                        self.reject_union(operand_ty, what, operand.span);
+                    }
+                }
+                if let Some((check, ty, bits)) =
+                    overflow_check(&new_lhs, &ty_lhs, op, &new_rhs, &ty_rhs).filter(|_| self.debug)
+                {
+                    let bits = Expr::new(ExprKind::Lit(Lit::Int(bits)), span);
+                    let check = runtime_check(check, vec![new_lhs, new_rhs, bits], span);
+                    return (Expr::new(ExprKind::Cast(Box::new(check), ty), span), ty_lhs);
+                }
+                let new_rhs = match op {
+                    BinaryOp::Div | BinaryOp::Mod
+                        if self.debug
+                            && self.is_integer(&ty_lhs)
+                            && self.is_integer(&ty_rhs)
+                            && !matches!(new_rhs.kind, ExprKind::Lit(Lit::Int(d)) if d != 0) =>
+                    {
+                        let check = runtime_check("zero", vec![new_rhs], span);
+                        Expr::new(ExprKind::Cast(Box::new(check), ty_rhs), span)
+                    }
+                    _ => new_rhs,
+                };
 
                 match op {
                     BinaryOp::Eq
@@ -670,28 +2597,49 @@ impl TypeChecker {
                     | BinaryOp::Gt
                     | BinaryOp::Lte
                     | BinaryOp::Gte => (
-                        Expr::Binary(Box::new(new_lhs), op, Box::new(new_rhs)),
+                        Expr::new(
+                            ExprKind::Binary(Box::new(new_lhs), op, Box::new(new_rhs)),
+                            span,
+                        ),
                         Type::Bool,
                     ),
                     _ => (
-                        Expr::Binary(Box::new(new_lhs), op, Box::new(new_rhs)),
+                        Expr::new(
+                            ExprKind::Binary(Box::new(new_lhs), op, Box::new(new_rhs)),
+                            span,
+                        ),
                         ty_lhs,
                     ),
                 }
             }
-            Expr::Is(inner, check_ty) => {
+            ExprKind::Is(inner, check_ty) => {
                 let (new_inner, inner_ty) = self.infer_expr(*inner);
 
+                let variant = match &check_ty {
+                    Type::Struct(path, _) => self.enum_variant(&path.join("__")),
+                    _ => None,
+                };
+                if let Some((def, variant)) = variant {
+                    let (def, value) = (def.clone(), variant.value);
+                    return self.check_is_variant(new_inner, &inner_ty, &def, value, span);
+                }
+
                 if let Type::Union(variants) = &inner_ty {
                     if !variants.contains(&check_ty) {
-                        return (Expr::Lit(Lit::Bool(false)), Type::Bool);
+                        return (Expr::new(ExprKind::Lit(Lit::Bool(false)), span), Type::Bool);
                     }
-                    let tag_access = Expr::Member(Box::new(new_inner), "tag".to_string());
+                    let tag_access = Expr::new(
+                        ExprKind::Member(Box::new(new_inner), "tag".to_string()),
+                        span,
+                    );
                     let target_tag = self.get_type_tag(&check_ty);
-                    let comparison = Expr::Binary(
-                        Box::new(tag_access),
-                        BinaryOp::Eq,
-                        Box::new(Expr::Lit(Lit::Int(target_tag))),
+                    let comparison = Expr::new(
+                        ExprKind::Binary(
+                            Box::new(tag_access),
+                            BinaryOp::Eq,
+                            Box::new(Expr::new(ExprKind::Lit(Lit::Int(target_tag)), span)),
+                        ),
+                        span,
                     );
                     return (comparison, Type::Bool);
                 }
@@ -709,29 +2657,48 @@ impl TypeChecker {
                                 .unwrap_or(false);
 
                             if union_contains_type {
-                                let tag_access =
-                                    Expr::Member(Box::new(new_inner), "tag".to_string());
+                                let tag_access = Expr::new(
+                                    ExprKind::Member(Box::new(new_inner), "tag".to_string()),
+                                    span,
+                                );
                                 let target_tag = self.get_type_tag(&check_ty);
-                                let comparison = Expr::Binary(
-                                    Box::new(tag_access),
-                                    BinaryOp::Eq,
-                                    Box::new(Expr::Lit(Lit::Int(target_tag))),
+                                let comparison = Expr::new(
+                                    ExprKind::Binary(
+                                        Box::new(tag_access),
+                                        BinaryOp::Eq,
+                                        Box::new(Expr::new(
+                                            ExprKind::Lit(Lit::Int(target_tag)),
+                                            span,
+                                        )),
+                                    ),
+                                    span,
                                 );
                                 return (comparison, Type::Bool);
                             } else {
-                                return (Expr::Lit(Lit::Bool(false)), Type::Bool);
+                                return (
+                                    Expr::new(ExprKind::Lit(Lit::Bool(false)), span),
+                                    Type::Bool,
+                                );
                             }
                         }
                     }
                 }
 
                 let result = inner_ty == check_ty;
-                (Expr::Lit(Lit::Bool(result)), Type::Bool)
+                (
+                    Expr::new(ExprKind::Lit(Lit::Bool(result)), span),
+                    Type::Bool,
+                )
             }
 
-            Expr::Cast(inner, target_ty) => {
+            ExprKind::Cast(inner, mut target_ty) => {
                 let (new_inner, inner_ty) = self.infer_expr(*inner);
 
+                if let Type::Dyn(_) = target_ty {
+                    self.lower_fat_pointers(&mut target_ty, span);
+                    return self.coerce_to_dyn(new_inner, inner_ty, &target_ty);
+                }
+
                 if let Type::Union(variants) = &target_ty {
                     let is_variant = variants
                         .iter()
@@ -748,7 +2715,13 @@ impl TypeChecker {
 
                 if let Type::Union(variants) = &inner_ty {
                     if variants.contains(&target_ty) {
-                        let data_access = Expr::Member(Box::new(new_inner), "data".to_string());
+                        let union_ty =
+                            Type::Struct(vec![self.get_or_create_union_struct(variants)], vec![]);
+                        let new_inner = self.checked_variant(new_inner, union_ty, &target_ty);
+                        let data_access = Expr::new(
+                            ExprKind::Member(Box::new(new_inner), "data".to_string()),
+                            span,
+                        );
                         let mut sorted_variants = variants.clone();
                         sorted_variants.sort_by_key(|t| t.get_name());
 
@@ -758,9 +2731,12 @@ impl TypeChecker {
                             .expect("Target type not in union");
 
                         return (
-                            Expr::Member(
-                                Box::new(data_access),
-                                format!("variant_{}", variant_index),
+                            Expr::new(
+                                ExprKind::Member(
+                                    Box::new(data_access),
+                                    format!("variant_{}", variant_index),
+                                ),
+                                span,
                             ),
                             target_ty.clone(),
                         );
@@ -780,11 +2756,22 @@ impl TypeChecker {
                                 if let Some((field_name, _)) =
                                     union_def.fields.iter().find(|(_, f_ty)| f_ty == &target_ty)
                                 {
-                                    let data_access =
-                                        Expr::Member(Box::new(new_inner), "data".to_string());
+                                    let field_name = field_name.clone();
+                                    let new_inner = self.checked_variant(
+                                        new_inner,
+                                        inner_ty.clone(),
+                                        &target_ty,
+                                    );
+                                    let data_access = Expr::new(
+                                        ExprKind::Member(Box::new(new_inner), "data".to_string()),
+                                        span,
+                                    );
 
                                     return (
-                                        Expr::Member(Box::new(data_access), field_name.clone()),
+                                        Expr::new(
+                                            ExprKind::Member(Box::new(data_access), field_name),
+                                            span,
+                                        ),
                                         target_ty.clone(),
                                     );
                                 }
@@ -794,46 +2781,156 @@ impl TypeChecker {
                 }
 
                 (
-                    Expr::Cast(Box::new(new_inner), target_ty.clone()),
+                    Expr::new(ExprKind::Cast(Box::new(new_inner), target_ty.clone()), span),
                     target_ty,
                 )
             }
 
-            Expr::SizeOf(ty) => (Expr::SizeOf(ty.clone()), Type::I64),
+            ExprKind::SizeOf(mut ty) => {
+                self.substitute_type(&mut ty, &HashMap::new());
+                (Expr::new(ExprKind::SizeOf(ty), span), Type::I64)
+            }
+
+            ExprKind::Reflect(query, mut ty) => {
+                self.substitute_type(&mut ty, &HashMap::new());
+                self.lower_fat_pointers(&mut ty, span);
+                self.reflect(query, ty, span)
+            }
+
+            ExprKind::Unary(op, inner) => {
+                let (new_inner, inner_ty) = self.infer_expr(*inner);
+                if matches!(op, UnaryOp::Neg)
+                    && self.debug
+                    && !matches!(new_inner.kind, ExprKind::Lit(_))
+                    && let Some(bits) = signed_bits(&inner_ty)
+                {
+                    let bits = Expr::new(ExprKind::Lit(Lit::Int(bits)), span);
+                    let check = runtime_check("neg", vec![new_inner, bits], span);
+                    return (
+                        Expr::new(ExprKind::Cast(Box::new(check), inner_ty.clone()), span),
+                        inner_ty,
+                    );
+                }
+                let ty = match op {
+                    UnaryOp::Not => Type::Bool,
+                    UnaryOp::Neg | UnaryOp::BitNot => inner_ty,
+                };
+                (
+                    Expr::new(ExprKind::Unary(op, Box::new(new_inner)), span),
+                    ty,
+                )
+            }
+
+            ExprKind::AddrOf(inner) => {
+                let (new_inner, inner_ty) = self.infer_expr(*inner);
+                (
+                    Expr::new(ExprKind::AddrOf(Box::new(new_inner)), span),
+                    Type::Pointer(Box::new(inner_ty)),
+                )
+            }
+
+            ExprKind::Deref(inner) => {
+                let (new_inner, inner_ty) = self.infer_expr(*inner);
+                if self.reject_optional(&inner_ty, "dereference", span) {
+                    return (
+                        Expr::new(ExprKind::Deref(Box::new(new_inner)), span),
+                        Type::Void,
+                    );
+                }
+                let new_inner = self.checked_pointer(new_inner, &inner_ty);
+                let ty = match inner_ty {
+                    Type::Pointer(pointee) => *pointee,
+                    _ => Type::Void,
+                };
+                (Expr::new(ExprKind::Deref(Box::new(new_inner)), span), ty)
+            }
 
-            Expr::Index(arr, idx) => {
+            ExprKind::Index(arr, idx) => {
+                let arr_span = arr.span;
                 let (new_arr, arr_ty) = self.infer_expr(*arr);
+                if self.reject_optional(&arr_ty, "index", arr_span) {
+                    return (
+                        Expr::new(ExprKind::Index(Box::new(new_arr), idx), span),
+                        Type::Void,
+                    );
+                }
+
+                if let ExprKind::Range(lo, hi) = idx.kind {
+                    let lo = lo.map(|lo| self.infer_expr(*lo).0);
+                    let hi = hi.map(|hi| self.infer_expr(*hi).0);
+                    return self.build_slice(new_arr, arr_ty, lo, hi, span);
+                }
                 let (new_idx, _) = self.infer_expr(*idx);
 
+                // `s[i]` reads `s.ptr[i]`, with `i` checked against `s.len`
+                // in a debug build.
+                if let Some(elem_ty) = self.slice_elem(&arr_ty) {
+                    let member = |expr: Expr, field: &str| {
+                        Expr::new(ExprKind::Member(Box::new(expr), field.to_string()), span)
+                    };
+                    let (slice, new_idx) = if self.debug {
+                        let slice = self.read_twice(new_arr, &arr_ty, "slice");
+                        let len = member(slice.clone(), "len");
+                        (slice, runtime_check("bounds", vec![new_idx, len], span))
+                    } else {
+                        (new_arr, new_idx)
+                    };
+                    return (
+                        Expr::new(
+                            ExprKind::Index(Box::new(member(slice, "ptr")), Box::new(new_idx)),
+                            span,
+                        ),
+                        elem_ty,
+                    );
+                }
+
+                let (new_arr, new_idx) = match &arr_ty {
+                    Type::Array(_, n) if self.debug => {
+                        let len = Expr::new(ExprKind::Lit(Lit::Int(*n as i64)), span);
+                        (new_arr, runtime_check("bounds", vec![new_idx, len], span))
+                    }
+                    Type::Pointer(_) => (self.checked_pointer(new_arr, &arr_ty), new_idx),
+                    _ => (new_arr, new_idx),
+                };
                 let elem_ty = match arr_ty {
                     Type::Array(inner, _) => *inner,
                     Type::Pointer(inner) => *inner,
-                    _ => panic!("Cannot index type {:?}", arr_ty),
+                    _ => {
+                        self.error(
+                            Diagnostic::error(format!("cannot index type {}", arr_ty))
+                                .with_span(arr_span),
+                        );
+                        Type::Void
+                    }
                 };
 
-                (Expr::Index(Box::new(new_arr), Box::new(new_idx)), elem_ty)
+                (
+                    Expr::new(ExprKind::Index(Box::new(new_arr), Box::new(new_idx)), span),
+                    elem_ty,
+                )
             }
 
-            Expr::StructInit(path, fields, generics) => {
-                let mut resolved_fields = Vec::new();
-                for (name, val) in fields {
-                    let (new_val, ty) = self.infer_expr(val);
-                    resolved_fields.push((name, new_val, ty));
-                }
-
+            ExprKind::StructInit(path, fields, generics) => {
                 let struct_name = path.join("__");
                 let final_struct_name;
 
                 if self.generic_struct_templates.contains_key(&struct_name) {
-                    let final_generics: Vec<Type>;
                     if !generics.is_empty() {
-                        final_generics = generics;
+                        final_struct_name = self.monomorphize_struct(&struct_name, generics);
                     } else {
-                        panic!(
-                            "Implicit struct generics logic needed here or explicit generics required"
+                        self.error(
+                            Diagnostic::error(format!(
+                                "cannot infer generic arguments for struct '{}'",
+                                struct_name
+                            ))
+                            .with_span(span)
+                            .with_note(format!(
+                                "specify them explicitly, e.g. `struct {}::<T> {{ ... }}`",
+                                struct_name
+                            )),
                         );
+                        final_struct_name = struct_name;
                     }
-                    final_struct_name = self.monomorphize_struct(&struct_name, final_generics);
                 } else {
                     final_struct_name = struct_name;
                 }
@@ -844,6 +2941,19 @@ impl TypeChecker {
                     .find(|s| s.name == final_struct_name)
                     .cloned();
 
+                let mut resolved_fields = Vec::new();
+                for (name, val) in fields {
+                    let expected_ty = target_def
+                        .as_ref()
+                        .and_then(|def| def.fields.iter().find(|(n, _)| n == &name))
+                        .map(|(_, ty)| ty.clone());
+                    let (new_val, ty) = match &expected_ty {
+                        Some(expected_ty) => self.infer_expr_as(val, expected_ty),
+                        None => self.infer_expr(val),
+                    };
+                    resolved_fields.push((name, new_val, ty));
+                }
+
                 let mut final_fields = Vec::new();
 
                 if let Some(def) = target_def {
@@ -851,6 +2961,8 @@ impl TypeChecker {
                         if let Some((_, expected_ty)) =
                             def.fields.iter().find(|(n, _)| n == &f_name)
                         {
+                            let (null_expr, f_ty) = self.coerce_null(f_expr, f_ty, expected_ty);
+                            f_expr = null_expr;
                             if let Type::Struct(names, _) = expected_ty {
                                 if let Some(inner_name) = names.first() {
                                     if inner_name.starts_with("__Union_")
@@ -872,6 +2984,8 @@ impl TypeChecker {
                                     }
                                 }
                             }
+                            let (array_expr, f_ty) = self.coerce_array(f_expr, f_ty, expected_ty);
+                            f_expr = self.coerce_fn_ptr(array_expr, f_ty, expected_ty).0;
                         }
                         final_fields.push((f_name, f_expr));
                     }
@@ -882,46 +2996,104 @@ impl TypeChecker {
                 }
 
                 (
-                    Expr::StructInit(vec![final_struct_name.clone()], final_fields, vec![]),
+                    Expr::new(
+                        ExprKind::StructInit(vec![final_struct_name.clone()], final_fields, vec![]),
+                        span,
+                    ),
                     Type::Struct(vec![final_struct_name], vec![]),
                 )
             }
 
-            Expr::Member(inner, field_name) => {
+            ExprKind::Member(inner, field_name) => {
                 let (new_inner, mut inner_ty) = self.infer_expr(*inner);
+                let access = format!("read the field '{}' of", field_name);
+                if self.reject_optional(&inner_ty, &access, span) {
+                    return (
+                        Expr::new(ExprKind::Member(Box::new(new_inner), field_name), span),
+                        Type::Void,
+                    );
+                }
 
                 let mut current_expr = new_inner;
 
                 while let Type::Pointer(pointed_to) = inner_ty.clone() {
+                    current_expr = self.checked_pointer(current_expr, &inner_ty);
                     inner_ty = *pointed_to;
-                    current_expr = Expr::Deref(Box::new(current_expr));
+                    current_expr = Expr::new(ExprKind::Deref(Box::new(current_expr)), span);
                 }
 
                 if let Type::Struct(path, _) = inner_ty {
                     let struct_name = path.last().unwrap();
-                    let def = self
+                    let Some(def) = self
                         .concrete_structs
                         .iter()
                         .find(|s| &s.name == struct_name)
-                        .expect("Struct definition not found");
+                    else {
+                        self.error(
+                            Diagnostic::error(format!(
+                                "struct definition not found for '{}'",
+                                struct_name
+                            ))
+                            .with_span(span),
+                        );
+                        return (
+                            Expr::new(ExprKind::Member(Box::new(current_expr), field_name), span),
+                            Type::Void,
+                        );
+                    };
 
-                    let (_, field_ty) = def
+                    let field_ty = def
                         .fields
                         .iter()
                         .find(|(n, _)| n == &field_name)
-                        .expect("Field not found");
+                        .map(|(_, ty)| ty.clone());
+
+                    let field_ty = match field_ty {
+                        Some(ty) => ty,
+                        None => {
+                            let known = def
+                                .fields
+                                .iter()
+                                .map(|(n, _)| n.as_str())
+                                .collect::<Vec<_>>()
+                                .join(", ");
+                            let diagnostic = Diagnostic::error(format!(
+                                "no field '{}' on struct '{}'",
+                                field_name, struct_name
+                            ))
+                            .with_span(span)
+                            .with_note(format!("available fields: {}", known));
+                            self.error(diagnostic);
+                            Type::Void
+                        }
+                    };
 
                     (
-                        Expr::Member(Box::new(current_expr), field_name),
-                        field_ty.clone(),
+                        Expr::new(ExprKind::Member(Box::new(current_expr), field_name), span),
+                        field_ty,
+                    )
+                } else if let (Type::Array(_, len), "len") = (&inner_ty, field_name.as_str()) {
+                    (
+                        Expr::new(ExprKind::Lit(Lit::Int(*len as i64)), span),
+                        Type::I64,
                     )
                 } else {
-                    panic!("Accessing member of non-struct type");
+                    self.error(
+                        Diagnostic::error(format!(
+                            "cannot access field '{}' on non-struct type {}",
+                            field_name, inner_ty
+                        ))
+                        .with_span(span),
+                    );
+                    (
+                        Expr::new(ExprKind::Member(Box::new(current_expr), field_name), span),
+                        Type::Void,
+                    )
                 }
             }
 
-            Expr::MethodCall(receiver, method_name, args, generics) => {
-                let static_struct_name = if let Expr::Ident(ref path) = *receiver {
+            ExprKind::MethodCall(receiver, method_name, args, generics) => {
+                let static_struct_name = if let ExprKind::Ident(ref path) = receiver.kind {
                     let potential_struct_name = path.join("__");
                     let var_name = path.last().unwrap();
 
@@ -950,18 +3122,51 @@ impl TypeChecker {
                     let func_mangled_name = format!("{}__{}", struct_name, method_name);
 
                     return self.handle_function_call(
-                        Expr::Ident(vec![func_mangled_name]),
+                        Expr::new(ExprKind::Ident(vec![func_mangled_name]), span),
                         args,
                         generics,
+                        span,
                     );
                 }
 
                 let (new_receiver, mut receiver_ty) = self.infer_expr(*receiver);
+                let call = format!("call '{}' on", method_name);
+                if self.reject_optional(&receiver_ty, &call, span) {
+                    return (
+                        Expr::new(
+                            ExprKind::MethodCall(
+                                Box::new(new_receiver),
+                                method_name,
+                                args,
+                                generics,
+                            ),
+                            span,
+                        ),
+                        Type::Void,
+                    );
+                }
                 let mut base_receiver_expr = new_receiver;
 
                 while let Type::Pointer(sub) = receiver_ty.clone() {
                     receiver_ty = *sub;
-                    base_receiver_expr = Expr::Deref(Box::new(base_receiver_expr));
+                    base_receiver_expr =
+                        Expr::new(ExprKind::Deref(Box::new(base_receiver_expr)), span);
+                }
+
+                let dyn_trait = match &receiver_ty {
+                    Type::Struct(path, _) => {
+                        path.last().and_then(|n| self.dyn_structs.get(n)).cloned()
+                    }
+                    _ => None,
+                };
+                if let Some(trait_name) = dyn_trait {
+                    return self.call_dyn_method(
+                        base_receiver_expr,
+                        &trait_name,
+                        method_name,
+                        args,
+                        span,
+                    );
                 }
 
                 if let Type::Struct(path, struct_generics) = &receiver_ty {
@@ -977,6 +3182,31 @@ impl TypeChecker {
 
                     let func_mangled_name = format!("{}__{}", base_struct_name, method_name);
 
+                    // Without a method of that name, `obj.cb(x)` calls the
+                    // function pointer stored in the field `cb`.
+                    let is_method = self.generic_func_templates.contains_key(&func_mangled_name)
+                        || self
+                            .concrete_funcs
+                            .iter()
+                            .chain(self.pending_funcs.iter())
+                            .any(|f| f.name == func_mangled_name);
+                    let is_fn_field = self
+                        .concrete_structs
+                        .iter()
+                        .find(|s| &s.name == current_struct_name)
+                        .is_some_and(|s| {
+                            s.fields
+                                .iter()
+                                .any(|(name, ty)| name == &method_name && self.is_callable(ty))
+                        });
+                    if !is_method && is_fn_field {
+                        let field = Expr::new(
+                            ExprKind::Member(Box::new(base_receiver_expr), method_name),
+                            span,
+                        );
+                        return self.call_through_pointer(field, args, span);
+                    }
+
                     let mut combined_generics = Vec::new();
                     combined_generics.extend(base_generics);
                     combined_generics.extend(generics);
@@ -1017,7 +3247,8 @@ impl TypeChecker {
                     }
 
                     if should_pass_ref {
-                        final_receiver = Expr::AddrOf(Box::new(final_receiver));
+                        final_receiver =
+                            Expr::new(ExprKind::AddrOf(Box::new(final_receiver)), span);
                         final_args.push(final_receiver);
                     } else {
                         final_args.push(final_receiver);
@@ -1026,36 +3257,647 @@ impl TypeChecker {
                     final_args.extend(args);
 
                     return self.handle_function_call(
-                        Expr::Ident(vec![func_mangled_name]),
+                        Expr::new(ExprKind::Ident(vec![func_mangled_name]), span),
                         final_args,
                         combined_generics,
+                        span,
+                    );
+                } else {
+                    self.error(
+                        Diagnostic::error(format!(
+                            "cannot call method '{}' on non-struct type {}",
+                            method_name, receiver_ty
+                        ))
+                        .with_span(span),
+                    );
+                    (
+                        Expr::new(
+                            ExprKind::MethodCall(
+                                Box::new(base_receiver_expr),
+                                method_name,
+                                args,
+                                generics,
+                            ),
+                            span,
+                        ),
+                        Type::Void,
+                    )
+                }
+            }
+
+            ExprKind::Match(subject, arms) => {
+                self.error(
+                    Diagnostic::error("`match` cannot be used inside another expression")
+                        .with_span(span)
+                        .with_note(
+                            "use it as a statement, a `let` initializer, a `ret` value or the right side of an assignment",
+                        ),
+                );
+                (Expr::new(ExprKind::Match(subject, arms), span), Type::Void)
+            }
+
+            ExprKind::Closure(closure) => self.build_closure(*closure, span),
+
+            ExprKind::OrElse(value, default) => self.check_orelse(*value, *default, span),
+
+            ExprKind::Try(value) => self.check_try(*value, span),
+
+            kind => (Expr::new(kind, span), Type::Void),
+        }
+    }
+
+    /// `value orelse default`: the value an optional holds, or `default` when
+    /// it is null. `value` is read twice, so it is moved into a temporary
+    /// unless it is a place.
+    fn check_orelse(&mut self, value: Expr, default: Expr, span: Span) -> (Expr, Type) {
+        let (value, value_ty) = self.infer_expr(value);
+        let (default, default_ty) = self.infer_expr(default);
+        let Some(inner) = self.optional_inner(&value_ty) else {
+            self.error(
+                Diagnostic::error(format!(
+                    "`orelse` expects an optional, found {}",
+                    self.type_name(&value_ty)
+                ))
+                .with_span(value.span),
+            );
+            return (value, value_ty);
+        };
+        if !self.are_types_compatible(&inner, &default_ty)
+            && !self.same_number_kind(&inner, &default_ty)
+        {
+            self.error(
+                Diagnostic::error(format!(
+                    "mismatched types: `orelse` default must be {}, found {}",
+                    self.type_name(&inner),
+                    self.type_name(&default_ty)
+                ))
+                .with_span(default.span),
+            );
+        }
+
+        let value = self.read_twice(value, &value_ty, "opt");
+        let expr = |kind: ExprKind| Expr::new(kind, span);
+        let (is_set, held) = match &value_ty {
+            Type::Optional(_) => (
+                ExprKind::Binary(
+                    Box::new(value.clone()),
+                    BinaryOp::Neq,
+                    Box::new(expr(ExprKind::Lit(Lit::Null))),
+                ),
+                value,
+            ),
+            _ => {
+                let variants = self.union_variants(&value_ty).unwrap_or_default();
+                let index = variants.iter().position(|v| *v == inner).unwrap_or(0);
+                let null_tag = self.get_type_tag(&Type::Null);
+                let tag = ExprKind::Member(Box::new(value.clone()), "tag".to_string());
+                let data = ExprKind::Member(Box::new(value), "data".to_string());
+                (
+                    ExprKind::Binary(
+                        Box::new(expr(tag)),
+                        BinaryOp::Neq,
+                        Box::new(expr(ExprKind::Lit(Lit::Int(null_tag)))),
+                    ),
+                    expr(ExprKind::Member(
+                        Box::new(expr(data)),
+                        format!("variant_{}", index),
+                    )),
+                )
+            }
+        };
+        (
+            expr(ExprKind::Ternary(
+                Box::new(expr(is_set)),
+                Box::new(held),
+                Box::new(default),
+            )),
+            inner,
+        )
+    }
+
+    /// `value?`: the value a `T | Error` union holds. For every error variant
+    /// a test is hoisted in front of the statement that returns the error
+    /// from the enclosing function, whose return type must be able to hold it.
+    fn check_try(&mut self, value: Expr, span: Span) -> (Expr, Type) {
+        let (value, value_ty) = self.infer_expr(value);
+        let variants = self.union_variants(&value_ty).unwrap_or_default();
+        let (errors, values): (Vec<_>, Vec<_>) = variants
+            .into_iter()
+            .enumerate()
+            .partition(|(_, v)| self.enum_of(v).is_some_and(|def| def.is_error));
+        let ([(ok_index, ok_ty)], false) = (values.as_slice(), errors.is_empty()) else {
+            self.error(
+                Diagnostic::error(format!(
+                    "`?` expects a union of one value type and its errors, found {}",
+                    self.type_name(&value_ty)
+                ))
+                .with_span(value.span),
+            );
+            return (value, Type::Void);
+        };
+        let (ok_index, ok_ty) = (*ok_index, ok_ty.clone());
+
+        let return_type = self.return_type.clone();
+        let returnable = self.union_variants(&return_type);
+        for (_, err) in &errors {
+            let holds = *err == return_type || returnable.as_ref().is_some_and(|r| r.contains(err));
+            if !holds {
+                self.error(
+                    Diagnostic::error(format!(
+                        "`?` cannot return {} from a function returning {}",
+                        self.type_name(err),
+                        self.type_name(&return_type)
+                    ))
+                    .with_span(span)
+                    .with_note(format!(
+                        "add `| {}` to the function's return type",
+                        self.type_name(err)
+                    )),
+                );
+            }
+        }
+
+        let value = self.read_twice(value, &value_ty, "try");
+        let expr = |kind: ExprKind| Expr::new(kind, span);
+        let variant = |index: usize| {
+            let data = expr(ExprKind::Member(
+                Box::new(value.clone()),
+                "data".to_string(),
+            ));
+            expr(ExprKind::Member(
+                Box::new(data),
+                format!("variant_{}", index),
+            ))
+        };
+        for (index, err) in errors {
+            let tag = expr(ExprKind::Member(Box::new(value.clone()), "tag".to_string()));
+            let is_err = ExprKind::Binary(
+                Box::new(tag),
+                BinaryOp::Eq,
+                Box::new(expr(ExprKind::Lit(Lit::Int(self.get_type_tag(&err))))),
+            );
+            let (ret, _) = self.coerce_union(variant(index), err, &return_type);
+            self.hoisted.push(Stmt::new(
+                StmtKind::If(
+                    expr(is_err),
+                    Box::new(Stmt::new(
+                        StmtKind::Block(vec![Stmt::new(StmtKind::Ret(ret), span)]),
+                        span,
+                    )),
+                    None,
+                ),
+                span,
+            ));
+        }
+        (variant(ok_index), ok_ty)
+    }
+
+    /// Checks a `match` in one of the statement positions it may appear in.
+    ///
+    /// Integer, bool and C-style enum matches keep their subject. Union and
+    /// payload enum matches switch on the tag instead, and each arm starts by
+    /// binding its variant's payload. The subject is moved into a temporary
+    /// when it has to be read more than once. With `wants_value`, every arm
+    /// that does not leave the function or loop ends in an expression, and all
+    /// of them must agree on its type.
+    fn check_match(&mut self, expr: Expr, wants_value: bool) -> (Expr, Type) {
+        let span = expr.span;
+        let ExprKind::Match(subject, arms) = expr.kind else {
+            return self.infer_expr(expr);
+        };
+
+        let subject_span = subject.span;
+        let (mut subject, subject_ty) = self.infer_expr(*subject);
+
+        let kind = if let Some(variants) = self.union_variants(&subject_ty) {
+            MatchSubject::Union(variants)
+        } else if let Some(def) = self.enum_of(&subject_ty) {
+            MatchSubject::Enum(def.clone())
+        } else if self.is_integer(&subject_ty) {
+            MatchSubject::Int
+        } else if subject_ty == Type::Bool {
+            MatchSubject::Bool
+        } else {
+            self.error(
+                Diagnostic::error(format!("cannot match on a value of type {}", subject_ty))
+                    .with_span(subject_span)
+                    .with_note("`match` works on integers, bools, enums and unions"),
+            );
+            return (
+                Expr::new(ExprKind::Match(Box::new(subject), arms), span),
+                Type::Void,
+            );
+        };
+
+        // Tagged arms read the payload, and a match lowered to `if`s (so that
+        // `out` still leaves the loop) tests the subject once per arm.
+        let reads_subject_again = kind.is_tagged()
+            || arms
+                .iter()
+                .any(|arm| arm.body.iter().any(Stmt::breaks_loop));
+        if reads_subject_again && !matches!(subject.kind, ExprKind::Ident(_)) {
+            let name = format!("__match_{}", self.temp_counter);
+            self.temp_counter += 1;
+
+            self.hoisted.push(Stmt::new(
+                StmtKind::Let(name.clone(), Some(subject_ty.clone()), Some(subject)),
+                subject_span,
+            ));
+            self.register_var(name.clone(), subject_ty.clone());
+            subject = Expr::new(ExprKind::Ident(vec![name]), subject_span);
+        }
+
+        let mut checked_arms = Vec::new();
+        let mut covered = Vec::new();
+        let mut has_wildcard = false;
+        let mut result_ty: Option<Type> = None;
+
+        for mut arm in arms {
+            if has_wildcard {
+                self.error(
+                    Diagnostic::warning("unreachable `match` arm")
+                        .with_span(arm.span)
+                        .with_note("an earlier `_` arm already matches everything"),
+                );
+                continue;
+            }
+
+            let resolved = self.resolve_pattern(&arm.pattern, &subject_ty, &kind, arm.span);
+
+            self.enter_scope();
+            if let Some((_, bindings)) = &resolved {
+                for binding in bindings {
+                    self.register_var(binding.name.clone(), binding.ty.clone());
+                }
+            }
+
+            let value = if wants_value {
+                match arm.body.last().map(|s| &s.kind) {
+                    Some(StmtKind::Expr(_)) => arm.body.pop(),
+                    Some(StmtKind::Ret(_) | StmtKind::Break | StmtKind::Continue) => None,
+                    _ => {
+                        self.error(
+                            Diagnostic::error("this `match` arm has no value")
+                                .with_span(arm.span)
+                                .with_note(
+                                    "end it with an expression, or leave with `ret`, `out` or `next`",
+                                ),
+                        );
+                        None
+                    }
+                }
+            } else {
+                None
+            };
+
+            self.check_stmts(&mut arm.body);
+
+            if let Some(Stmt {
+                kind: StmtKind::Expr(value),
+                span: value_span,
+            }) = value
+            {
+                let outer = std::mem::take(&mut self.hoisted);
+                let (value, value_ty) = if matches!(value.kind, ExprKind::Match(..)) {
+                    self.check_match(value, true)
+                } else {
+                    self.infer_expr(value)
+                };
+                arm.body.append(&mut self.hoisted);
+                arm.body.push(Stmt::new(StmtKind::Expr(value), value_span));
+                self.hoisted = outer;
+
+                match &result_ty {
+                    None => result_ty = Some(value_ty),
+                    Some(expected) => {
+                        if !self.are_types_compatible(expected, &value_ty)
+                            && !self.same_number_kind(expected, &value_ty)
+                        {
+                            self.error(
+                                Diagnostic::error(format!(
+                                    "`match` arms have incompatible types: expected {}, found {}",
+                                    expected, value_ty
+                                ))
+                                .with_span(value_span),
+                            );
+                        }
+                    }
+                }
+            }
+
+            self.exit_scope();
+
+            let Some((case, bindings)) = resolved else {
+                continue;
+            };
+
+            let pattern = match case {
+                Some(value) if covered.contains(&value) => {
+                    self.error(
+                        Diagnostic::warning("unreachable `match` arm")
+                            .with_span(arm.span)
+                            .with_note("an earlier arm already matches this pattern"),
+                    );
+                    continue;
+                }
+                Some(value) => {
+                    covered.push(value);
+                    Pattern::Lit(Lit::Int(value))
+                }
+                None => {
+                    has_wildcard = true;
+                    Pattern::Wildcard
+                }
+            };
+
+            let binds = bindings.into_iter().map(|binding| {
+                let payload = binding
+                    .fields
+                    .into_iter()
+                    .fold(subject.clone(), |obj, field| {
+                        Expr::new(ExprKind::Member(Box::new(obj), field), arm.span)
+                    });
+                Stmt::new(
+                    StmtKind::Let(binding.name, Some(binding.ty), Some(payload)),
+                    arm.span,
+                )
+            });
+            arm.body.splice(0..0, binds);
+
+            checked_arms.push(MatchArm {
+                pattern,
+                body: arm.body,
+                span: arm.span,
+            });
+        }
+
+        if wants_value && !has_wildcard {
+            let missing: Vec<String> = match &kind {
+                MatchSubject::Union(variants) => variants
+                    .iter()
+                    .filter(|v| !covered.contains(&self.get_type_tag(v)))
+                    .map(|v| format!("`{}`", v))
+                    .collect(),
+                MatchSubject::Enum(def) => def
+                    .variants
+                    .iter()
+                    .filter(|v| !covered.contains(&v.value))
+                    .map(|v| format!("`{}::{}`", def.name, v.name))
+                    .collect(),
+                MatchSubject::Bool => [(1, "`true`"), (0, "`false`")]
+                    .iter()
+                    .filter(|(value, _)| !covered.contains(value))
+                    .map(|(_, name)| name.to_string())
+                    .collect(),
+                MatchSubject::Int => vec!["`_`".to_string()],
+            };
+
+            if !missing.is_empty() {
+                self.error(
+                    Diagnostic::error(format!(
+                        "non-exhaustive `match`: {} not covered",
+                        missing.join(", ")
+                    ))
+                    .with_span(span)
+                    .with_note("add the missing arms, or a `_` arm"),
+                );
+            }
+        }
+
+        if kind.is_tagged() {
+            subject = Expr::new(
+                ExprKind::Member(Box::new(subject), "tag".to_string()),
+                subject_span,
+            );
+        }
+
+        let ty = if wants_value {
+            result_ty.unwrap_or(Type::Void)
+        } else {
+            Type::Void
+        };
+        (
+            Expr::new(ExprKind::Match(Box::new(subject), checked_arms), span),
+            ty,
+        )
+    }
+
+    /// Resolves `pattern` into the integer case it stands for (`None` for `_`)
+    /// and the payload values it binds. Reports and returns `None` when the
+    /// pattern cannot match a subject of type `subject_ty`.
+    fn resolve_pattern(
+        &mut self,
+        pattern: &Pattern,
+        subject_ty: &Type,
+        kind: &MatchSubject,
+        span: Span,
+    ) -> Option<(Option<i64>, Vec<MatchBinding>)> {
+        let diagnostic = match (pattern, kind) {
+            (Pattern::Wildcard, _) => return Some((None, Vec::new())),
+
+            (Pattern::Variant(ty, bindings), MatchSubject::Union(variants)) => {
+                let Some(index) = variants.iter().position(|v| v == ty) else {
+                    self.error(
+                        Diagnostic::error(format!(
+                            "`{}` is not a variant of {}",
+                            ty,
+                            Type::Union(variants.to_vec())
+                        ))
+                        .with_span(span),
+                    );
+                    return None;
+                };
+
+                if bindings.len() > 1 {
+                    self.error(
+                        Diagnostic::error(format!(
+                            "a union variant binds a single value, found {} names",
+                            bindings.len()
+                        ))
+                        .with_span(span),
+                    );
+                    return None;
+                }
+
+                let binding = bindings
+                    .iter()
+                    .filter(|name| *name != "_")
+                    .map(|name| MatchBinding {
+                        name: name.clone(),
+                        ty: ty.clone(),
+                        fields: vec!["data".to_string(), format!("variant_{}", index)],
+                    })
+                    .collect();
+                return Some((Some(self.get_type_tag(ty)), binding));
+            }
+
+            (Pattern::Variant(ty, bindings), MatchSubject::Enum(def)) => {
+                let variant = match ty {
+                    Type::Struct(path, _) => self
+                        .enum_variant(&path.join("__"))
+                        .filter(|(owner, _)| owner.name == def.name)
+                        .map(|(_, variant)| variant.clone()),
+                    _ => None,
+                };
+                let Some(variant) = variant else {
+                    self.error(
+                        Diagnostic::error(format!(
+                            "`{}` is not a variant of `{}`",
+                            ty.to_string().replace("__", "::"),
+                            def.name
+                        ))
+                        .with_span(span)
+                        .with_note(format!("name it as `{}::Variant`", def.name)),
                     );
-                } else {
-                    panic!(
-                        "Method call '{}' on non-struct type {:?}",
-                        method_name, receiver_ty
+                    return None;
+                };
+
+                if !bindings.is_empty() && bindings.len() != variant.payload.len() {
+                    self.error(
+                        Diagnostic::error(format!(
+                            "`{}::{}` carries {} value(s), but the pattern binds {}",
+                            def.name,
+                            variant.name,
+                            variant.payload.len(),
+                            bindings.len()
+                        ))
+                        .with_span(span),
                     );
+                    return None;
+                }
+
+                let mut binds = Vec::new();
+                for (i, (name, ty)) in bindings.iter().zip(&variant.payload).enumerate() {
+                    if name != "_" {
+                        binds.push(MatchBinding {
+                            name: name.clone(),
+                            ty: self.concrete_type(ty),
+                            fields: vec![
+                                "data".to_string(),
+                                variant.name.clone(),
+                                format!("_{}", i),
+                            ],
+                        });
+                    }
                 }
+                return Some((Some(variant.value), binds));
+            }
+
+            (Pattern::Lit(Lit::Int(value)), MatchSubject::Int) => {
+                return Some((Some(*value), Vec::new()));
+            }
+            (Pattern::Lit(Lit::Bool(value)), MatchSubject::Bool) => {
+                return Some((Some(*value as i64), Vec::new()));
             }
 
-            _ => (expr, Type::Void),
+            (Pattern::Variant(ty, _), MatchSubject::Int | MatchSubject::Bool) => {
+                Diagnostic::error(format!(
+                    "cannot match variant `{}` on a value of type {}",
+                    ty, subject_ty
+                ))
+            }
+            (Pattern::Lit(_), MatchSubject::Union(variants)) => Diagnostic::error(format!(
+                "expected a variant of {}, found a literal",
+                Type::Union(variants.to_vec())
+            ))
+            .with_note("name the variant by its type, e.g. `i64(n) => ...`"),
+            (Pattern::Lit(_), MatchSubject::Enum(def)) => Diagnostic::error(format!(
+                "expected a variant of `{}`, found a literal",
+                def.name
+            ))
+            .with_note(format!("name it as `{}::Variant`", def.name)),
+            (Pattern::Lit(Lit::Int(_) | Lit::Bool(_)), _) => Diagnostic::error(format!(
+                "mismatched pattern: expected a {} literal",
+                subject_ty
+            )),
+            (Pattern::Lit(_), _) => {
+                Diagnostic::error("`match` patterns must be integer or bool literals")
+            }
+        };
+
+        self.error(diagnostic.with_span(span));
+        None
+    }
+
+    /// `value is Enum::Variant`, a comparison of the value or its tag.
+    fn check_is_variant(
+        &mut self,
+        value: Expr,
+        value_ty: &Type,
+        def: &EnumDef,
+        variant_value: i64,
+        span: Span,
+    ) -> (Expr, Type) {
+        if self.enum_of(value_ty).is_none_or(|e| e.name != def.name) {
+            self.error(
+                Diagnostic::error(format!(
+                    "cannot test a value of type {} for a variant of `{}`",
+                    value_ty, def.name
+                ))
+                .with_span(span),
+            );
+            return (Expr::new(ExprKind::Lit(Lit::Bool(false)), span), Type::Bool);
+        }
+
+        let discriminant = if def.has_payload() {
+            Expr::new(ExprKind::Member(Box::new(value), "tag".to_string()), span)
+        } else {
+            value
+        };
+        let comparison = ExprKind::Binary(
+            Box::new(discriminant),
+            BinaryOp::Eq,
+            Box::new(Expr::new(ExprKind::Lit(Lit::Int(variant_value)), span)),
+        );
+        (Expr::new(comparison, span), Type::Bool)
+    }
+
+    /// The variants of a union-typed value, in the order of its `variant_N` fields.
+    fn union_variants(&mut self, ty: &Type) -> Option<Vec<Type>> {
+        match ty {
+            Type::Union(variants) => {
+                let struct_name = self.get_or_create_union_struct(variants);
+                self.variant_cache.get(&struct_name).cloned()
+            }
+            Type::Struct(path, _) => path.last().and_then(|n| self.variant_cache.get(n)).cloned(),
+            _ => None,
         }
     }
 
-    fn infer_lit(&mut self, lit: Lit) -> (Expr, Type) {
+    /// Whether C converts between the two implicitly: both integers or both floats.
+    fn same_number_kind(&self, a: &Type, b: &Type) -> bool {
+        (self.is_integer(a) && self.is_integer(b)) || (self.is_float(a) && self.is_float(b))
+    }
+
+    fn infer_lit(&mut self, lit: Lit, span: Span) -> (Expr, Type) {
         match lit {
-            Lit::Int(_) => (Expr::Lit(lit), Type::I64),
-            Lit::Float(_) => (Expr::Lit(lit), Type::F64),
-            Lit::Bool(_) => (Expr::Lit(lit), Type::Bool),
+            Lit::Int(_) => (Expr::new(ExprKind::Lit(lit), span), Type::I64),
+            Lit::Float(_) => (Expr::new(ExprKind::Lit(lit), span), Type::F64),
+            Lit::Bool(_) => (Expr::new(ExprKind::Lit(lit), span), Type::Bool),
             Lit::Str(ref s) => {
-                let len = s.len() - 1;
-                (Expr::Lit(lit), Type::Array(Box::new(Type::U8), len))
+                // The decoded bytes plus the terminating NUL, as the IR emits them.
+                let content = s
+                    .strip_prefix('"')
+                    .and_then(|s| s.strip_suffix('"'))
+                    .unwrap_or(s);
+                let mut len = 1;
+                let mut chars = content.chars();
+                while let Some(c) = chars.next() {
+                    if c == '\\' {
+                        chars.next();
+                    }
+                    len += 1;
+                }
+                (
+                    Expr::new(ExprKind::Lit(lit), span),
+                    Type::Array(Box::new(Type::U8), len),
+                )
             }
 
             Lit::Array(exprs) => {
                 if exprs.is_empty() {
                     return (
-                        Expr::Lit(Lit::Array(exprs)),
+                        Expr::new(ExprKind::Lit(Lit::Array(exprs)), span),
                         Type::Array(Box::new(Type::Void), 0),
                     );
                 }
@@ -1065,28 +3907,35 @@ impl TypeChecker {
 
                 for expr in exprs {
                     let (new_expr, ty) = self.infer_expr(expr);
-                    new_exprs.push(new_expr);
 
                     if first_ty.is_none() {
                         first_ty = Some(ty);
                     } else if first_ty.as_ref() != Some(&ty) {
-                        panic!(
-                            "Array elements mismatch: expected {:?}, found {:?}",
-                            first_ty, ty
+                        self.error(
+                            Diagnostic::error(format!(
+                                "mismatched array element types: expected {}, found {}",
+                                first_ty.as_ref().unwrap(),
+                                ty
+                            ))
+                            .with_span(new_expr.span),
                         );
                     }
+                    new_exprs.push(new_expr);
                 }
 
                 let len = new_exprs.len();
                 let elem_ty = first_ty.unwrap();
 
                 (
-                    Expr::Lit(Lit::Array(new_exprs)),
+                    Expr::new(ExprKind::Lit(Lit::Array(new_exprs)), span),
                     Type::Array(Box::new(elem_ty), len),
                 )
             }
 
-            Lit::Null => (Expr::Lit(lit), Type::Pointer(Box::new(Type::Void))),
+            Lit::Null => (
+                Expr::new(ExprKind::Lit(lit), span),
+                Type::Pointer(Box::new(Type::Void)),
+            ),
         }
     }
     fn handle_function_call(
@@ -1094,10 +3943,26 @@ impl TypeChecker {
         callee: Expr,
         args: Vec<Expr>,
         explicit_generics: Vec<Type>,
+        span: Span,
     ) -> (Expr, Type) {
-        let func_name = match &callee {
-            Expr::Ident(path) => path.join("__"),
-            _ => panic!("Complex callee not supported yet"),
+        let variant = match &callee.kind {
+            ExprKind::Ident(path) => self.enum_variant(&path.join("__")),
+            _ => None,
+        };
+        if let Some((def, variant)) = variant {
+            let (def, variant) = (def.clone(), variant.clone());
+            return self.construct_variant(def, variant, args, span);
+        }
+
+        let func_name = match &callee.kind {
+            ExprKind::Ident(path)
+                if path.len() > 1
+                    || (self.get_var_type(&path[0]).is_none()
+                        && self.captured_by_ref(&path[0]).is_none()) =>
+            {
+                path.join("__")
+            }
+            _ => return self.call_through_pointer(callee, args, span),
         };
 
         let mut typed_args = Vec::new();
@@ -1108,31 +3973,65 @@ impl TypeChecker {
             arg_types.push(ty);
         }
 
+        // A debug check inserted by an earlier pass, which yields its operand.
+        if func_name.starts_with(CHECK_PREFIX) {
+            let ty = arg_types.into_iter().next().unwrap_or(Type::Void);
+            return (
+                Expr::new(
+                    ExprKind::Call(Box::new(callee), typed_args, explicit_generics),
+                    span,
+                ),
+                ty,
+            );
+        }
+
         if let Some(func) = self.get_local_func(&func_name) {
             if func.params.len() != typed_args.len() {
-                panic!(
-                    "Argument count mismatch for local function '{}': expected {}, found {}",
-                    func_name,
-                    func.params.len(),
-                    typed_args.len()
+                self.error(
+                    Diagnostic::error(format!(
+                        "function '{}' takes {} argument(s) but {} were supplied",
+                        func_name,
+                        func.params.len(),
+                        typed_args.len()
+                    ))
+                    .with_span(span),
                 );
             }
 
-            for (i, ((_, param_ty), arg_ty)) in func.params.iter().zip(arg_types.iter()).enumerate()
-            {
+            let (typed_args, arg_types): (Vec<Expr>, Vec<Type>) = typed_args
+                .into_iter()
+                .zip(arg_types)
+                .enumerate()
+                .map(|(i, (arg, arg_ty))| match func.params.get(i) {
+                    Some((_, param_ty)) => self.coerce_array(arg, arg_ty, param_ty),
+                    None => (arg, arg_ty),
+                })
+                .unzip();
+            let params = func
+                .params
+                .iter()
+                .zip(arg_types.iter())
+                .zip(typed_args.iter());
+            for (i, (((_, param_ty), arg_ty), arg)) in params.enumerate() {
                 if param_ty != arg_ty {
-                    panic!(
-                        "Type mismatch for argument {} in local function '{}': expected {:?}, found {:?}",
-                        i + 1,
-                        func_name,
-                        param_ty,
-                        arg_ty
+                    self.error(
+                        Diagnostic::error(format!(
+                            "mismatched types for argument {} of '{}': expected {}, found {}",
+                            i + 1,
+                            func_name,
+                            param_ty,
+                            arg_ty
+                        ))
+                        .with_span(arg.span),
                     );
                 }
             }
 
             return (
-                Expr::Call(Box::new(callee), typed_args, explicit_generics),
+                Expr::new(
+                    ExprKind::Call(Box::new(callee), typed_args, explicit_generics),
+                    span,
+                ),
                 func.return_type.clone(),
             );
         }
@@ -1141,12 +4040,31 @@ impl TypeChecker {
             let mut final_generics: Vec<Type>;
             if !explicit_generics.is_empty() {
                 if explicit_generics.len() != template.generics.len() {
-                    panic!("Generic count mismatch for function '{}'", func_name);
+                    self.error(
+                        Diagnostic::error(format!(
+                            "function '{}' takes {} generic argument(s) but {} were supplied",
+                            func_name,
+                            template.generics.len(),
+                            explicit_generics.len()
+                        ))
+                        .with_span(span),
+                    );
+                    return (
+                        Expr::new(
+                            ExprKind::Call(Box::new(callee), typed_args, explicit_generics),
+                            span,
+                        ),
+                        Type::Void,
+                    );
                 }
                 final_generics = explicit_generics;
             } else {
-                final_generics =
-                    self.infer_generics_from_args(&template.generics, &template.params, &arg_types);
+                final_generics = self.infer_generics_from_args(
+                    &generic_names(&template.generics),
+                    &template.params,
+                    &arg_types,
+                    span,
+                );
             }
 
             let empty_map = HashMap::new();
@@ -1154,6 +4072,26 @@ impl TypeChecker {
                 self.substitute_type(g, &empty_map);
             }
 
+            let mismatch = generic_kind_mismatch(&template.generics, &final_generics).map(|m| {
+                Diagnostic::error(format!(
+                    "in call to '{}': {}",
+                    func_name.replace("__", "::"),
+                    m
+                ))
+            });
+            let diagnostic =
+                mismatch.or_else(|| self.unsatisfied_bound(&template, &final_generics));
+            if let Some(diagnostic) = diagnostic {
+                self.error(diagnostic.with_span(span));
+                return (
+                    Expr::new(
+                        ExprKind::Call(Box::new(callee), typed_args, final_generics),
+                        span,
+                    ),
+                    Type::Void,
+                );
+            }
+
             let generics_key = format!("{:?}", final_generics);
             let cache_key = (func_name.clone(), generics_key);
 
@@ -1164,6 +4102,7 @@ impl TypeChecker {
                 let mut new_func = template.clone();
                 new_func.name = new_name.clone();
                 new_func.generics.clear();
+                new_func.bounds.clear();
 
                 self.monomorphization_cache
                     .insert(cache_key, new_name.clone());
@@ -1175,52 +4114,315 @@ impl TypeChecker {
             };
 
             let mut ret_ty = template.return_type.clone();
-            let mut map = HashMap::new();
-            for (name, ty) in template.generics.iter().zip(final_generics.iter()) {
-                map.insert(name.clone(), ty.clone());
-            }
+            let map = generic_map(&template.generics, &final_generics);
             self.substitute_type(&mut ret_ty, &map);
+            let ret_ty = self.concrete_type(&ret_ty);
+
+            // An array argument becomes the `[]T` its parameter asks for.
+            let typed_args = typed_args
+                .into_iter()
+                .zip(arg_types)
+                .enumerate()
+                .map(|(i, (arg, arg_ty))| match template.params.get(i) {
+                    Some((_, param_ty)) => {
+                        let mut param_ty = param_ty.clone();
+                        self.substitute_type(&mut param_ty, &map);
+                        let param_ty = self.concrete_type(&param_ty);
+                        self.coerce_array(arg, arg_ty, &param_ty).0
+                    }
+                    None => arg,
+                })
+                .collect();
 
             return (
-                Expr::Call(
-                    Box::new(Expr::Ident(vec![mangled_name])),
-                    typed_args,
-                    vec![],
+                Expr::new(
+                    ExprKind::Call(
+                        Box::new(Expr::new(ExprKind::Ident(vec![mangled_name]), span)),
+                        typed_args,
+                        vec![],
+                    ),
+                    span,
                 ),
                 ret_ty,
             );
         }
 
-        if let Some(func) = self.concrete_funcs.iter().find(|f| f.name == func_name) {
+        let signature = self
+            .concrete_funcs
+            .iter()
+            .chain(self.pending_funcs.iter())
+            .find(|f| f.name == func_name)
+            .map(|f| (f.params.clone(), f.return_type.clone()));
+        if let Some((params, return_type)) = signature {
+            let typed_args = typed_args
+                .into_iter()
+                .zip(arg_types)
+                .enumerate()
+                .map(|(i, (arg, arg_ty))| match params.get(i) {
+                    Some((_, param_ty)) => {
+                        let param_ty = self.concrete_type(param_ty);
+                        let (arg, arg_ty) = self.coerce_to_dyn(arg, arg_ty, &param_ty);
+                        let (arg, arg_ty) = self.coerce_array(arg, arg_ty, &param_ty);
+                        let (arg, arg_ty) = self.coerce_union(arg, arg_ty, &param_ty);
+                        self.coerce_fn_ptr(arg, arg_ty, &param_ty).0
+                    }
+                    None => arg,
+                })
+                .collect();
             return (
-                Expr::Call(Box::new(callee), typed_args, explicit_generics),
-                func.return_type.clone(),
+                Expr::new(
+                    ExprKind::Call(Box::new(callee), typed_args, explicit_generics),
+                    span,
+                ),
+                self.concrete_type(&return_type),
             );
         }
 
-        if let Some(func) = self.pending_funcs.iter().find(|f| f.name == func_name) {
+        self.error(
+            Diagnostic::error(format!("undefined function '{}'", func_name))
+                .with_span(callee.span)
+                .with_note("did you mean to use a full path (e.g. std::str::new)?"),
+        );
+        (
+            Expr::new(
+                ExprKind::Call(Box::new(callee), typed_args, explicit_generics),
+                span,
+            ),
+            Type::Void,
+        )
+    }
+
+    /// A call whose callee is a function pointer: a variable, a field, a
+    /// table entry or a vtable slot.
+    fn call_through_pointer(&mut self, callee: Expr, args: Vec<Expr>, span: Span) -> (Expr, Type) {
+        let (callee, callee_ty) = self.infer_expr(callee);
+        let (callee, env, callee_ty) = self.unpack_closure(callee, callee_ty);
+        let typed_args: Vec<(Expr, Type)> = args.into_iter().map(|a| self.infer_expr(a)).collect();
+
+        let Type::Function(params, ret, _) = callee_ty else {
+            self.error(
+                Diagnostic::error(format!("cannot call a value of type {}", callee_ty))
+                    .with_span(callee.span),
+            );
+            let typed_args = typed_args.into_iter().map(|(arg, _)| arg).collect();
             return (
-                Expr::Call(Box::new(callee), typed_args, explicit_generics),
-                func.return_type.clone(),
+                Expr::new(ExprKind::Call(Box::new(callee), typed_args, vec![]), span),
+                Type::Void,
+            );
+        };
+
+        if params.len() != typed_args.len() {
+            self.error(
+                Diagnostic::error(format!(
+                    "function pointer of type {} takes {} argument(s) but {} were supplied",
+                    Type::Function(params.clone(), ret.clone(), vec![]),
+                    params.len(),
+                    typed_args.len()
+                ))
+                .with_span(span),
+            );
+        }
+
+        let typed_args = env
+            .into_iter()
+            .chain(typed_args.into_iter().enumerate().map(
+                |(i, (arg, arg_ty))| match params.get(i) {
+                    Some(param_ty) => {
+                        let (arg, arg_ty) = self.coerce_array(arg, arg_ty, param_ty);
+                        let (arg, arg_ty) = self.coerce_union(arg, arg_ty, param_ty);
+                        self.coerce_fn_ptr(arg, arg_ty, param_ty).0
+                    }
+                    None => arg,
+                },
+            ))
+            .collect();
+        (
+            Expr::new(ExprKind::Call(Box::new(callee), typed_args, vec![]), span),
+            *ret,
+        )
+    }
+
+    /// Decays an array to a pointer to its first element when `target` is
+    /// `&T` (or `&pass`), slices all of it when `target` is `[]T`, and casts
+    /// the elements of an array literal to the element type of an array
+    /// `target` of the same length. Arrays are otherwise values and are
+    /// returned unchanged.
+    fn coerce_array(&mut self, expr: Expr, expr_ty: Type, target: &Type) -> (Expr, Type) {
+        let span = expr.span;
+        match (&expr_ty, target) {
+            (Type::Array(elem, _), Type::Struct(..))
+                if self.slice_elem(target).as_ref() == Some(&**elem) =>
+            {
+                self.build_slice(expr, expr_ty.clone(), None, None, span)
+            }
+            (Type::Array(elem, _), Type::Pointer(pointee))
+                if pointee == elem || **pointee == Type::Void =>
+            {
+                (
+                    Expr::new(ExprKind::Cast(Box::new(expr), target.clone()), span),
+                    target.clone(),
+                )
+            }
+            (Type::Array(elem, len), Type::Array(target_elem, target_len))
+                if len == target_len
+                    && elem != target_elem
+                    && self.same_number_kind(elem, target_elem)
+                    && matches!(expr.kind, ExprKind::Lit(Lit::Array(_))) =>
+            {
+                let ExprKind::Lit(Lit::Array(items)) = expr.kind else {
+                    unreachable!()
+                };
+                let items = items
+                    .into_iter()
+                    .map(|item| {
+                        let span = item.span;
+                        Expr::new(
+                            ExprKind::Cast(Box::new(item), (**target_elem).clone()),
+                            span,
+                        )
+                    })
+                    .collect();
+                (
+                    Expr::new(ExprKind::Lit(Lit::Array(items)), span),
+                    target.clone(),
+                )
+            }
+            _ => (expr, expr_ty),
+        }
+    }
+
+    /// Casts a function to the pointer type `target` when the two differ only
+    /// in their pointer parameters, as with a `qsort` comparator taking `&i64`
+    /// for `&pass`; anything else is returned unchanged.
+    fn coerce_fn_ptr(&mut self, expr: Expr, expr_ty: Type, target: &Type) -> (Expr, Type) {
+        let (Type::Function(params, ret, _), Type::Function(target_params, target_ret, _)) =
+            (&expr_ty, target)
+        else {
+            return (expr, expr_ty);
+        };
+        if expr_ty == *target {
+            return (expr, expr_ty);
+        }
+
+        let same_abi =
+            |a: &Type, b: &Type| a == b || matches!((a, b), (Type::Pointer(_), Type::Pointer(_)));
+        let compatible = params.len() == target_params.len()
+            && params
+                .iter()
+                .zip(target_params)
+                .all(|(a, b)| same_abi(a, b))
+            && same_abi(ret, target_ret);
+        if !compatible {
+            self.error(
+                Diagnostic::error(format!(
+                    "mismatched types: expected {}, found {}",
+                    target, expr_ty
+                ))
+                .with_span(expr.span),
             );
+            return (expr, target.clone());
+        }
+
+        let span = expr.span;
+        (
+            Expr::new(ExprKind::Cast(Box::new(expr), target.clone()), span),
+            target.clone(),
+        )
+    }
+
+    /// Gives `null` the type of the optional `target`: the null pointer of an
+    /// `?&T`, or the `null` variant of a `T | null`, to be wrapped like any
+    /// other variant. Anything else is returned unchanged.
+    fn coerce_null(&mut self, expr: Expr, expr_ty: Type, target: &Type) -> (Expr, Type) {
+        if !matches!(expr.kind, ExprKind::Lit(Lit::Null)) {
+            return (expr, expr_ty);
+        }
+        if let Type::Optional(_) = target {
+            return (expr, target.clone());
+        }
+        match self.union_variants(target) {
+            Some(variants) if variants.contains(&Type::Null) => {
+                (Expr::new(ExprKind::Lit(Lit::Int(0)), expr.span), Type::Null)
+            }
+            _ => (expr, expr_ty),
+        }
+    }
+
+    /// Wraps a value of one of the variants of the union `target` into it,
+    /// as for a `ret` value or an argument; anything else is returned
+    /// unchanged.
+    fn coerce_union(&mut self, expr: Expr, expr_ty: Type, target: &Type) -> (Expr, Type) {
+        let (expr, expr_ty) = self.coerce_null(expr, expr_ty, target);
+        let target = self.concrete_type(target);
+        let (Type::Struct(path, _), Some(variants)) = (&target, self.union_variants(&target))
+        else {
+            return (expr, expr_ty);
+        };
+        let is_variant = variants
+            .iter()
+            .any(|v| *v == expr_ty || self.same_number_kind(v, &expr_ty));
+        if expr_ty == target || !is_variant {
+            return (expr, expr_ty);
+        }
+        let name = path.last().cloned().unwrap_or_default();
+        self.wrap_expr_for_union(expr, expr_ty, &variants, name)
+    }
+
+    /// The type an optional holds when it is not null: the pointer of an
+    /// `?&T`, or the `T` of a `T | null`.
+    fn optional_inner(&mut self, ty: &Type) -> Option<Type> {
+        if let Type::Optional(pointer) = ty {
+            return Some((**pointer).clone());
+        }
+        match self.union_variants(ty)?.as_slice() {
+            [Type::Null, inner] | [inner, Type::Null] => Some(inner.clone()),
+            _ => None,
         }
+    }
 
-        panic!(
-            "Undefined function: '{}'. Did you mean to use a full path (e.g. std::str::new)?",
-            func_name
+    /// Reports an optional `ty` being used as the value it may hold, by
+    /// `what`, before it was checked for null. Returns whether it was one.
+    fn reject_optional(&mut self, ty: &Type, what: &str, span: Span) -> bool {
+        if self.optional_inner(ty).is_none() {
+            return false;
+        }
+        let diagnostic = Diagnostic::error(format!(
+            "cannot {} {} without checking it for null",
+            what,
+            self.type_name(ty)
+        ))
+        .with_span(span)
+        .with_note(
+            "unwrap it with `if let v = ... { }`, test it with `!= null` first, or give a default with `orelse`",
         );
+        self.error(diagnostic);
+        true
+    }
+
+    /// Reports a union `ty` being used as one of its variants, by `what`,
+    /// before it was narrowed. Returns whether it was one.
+    fn reject_union(&mut self, ty: &Type, what: &str, span: Span) -> bool {
+        if self.union_variants(ty).is_none() {
+            return false;
+        }
+        let diagnostic = Diagnostic::error(format!(
+            "cannot {} {} before narrowing it to one of its variants",
+            what,
+            self.type_name(ty)
+        ))
+        .with_span(span)
+        .with_note("test it with `is` first, or `match` on it");
+        self.error(diagnostic);
+        true
     }
 
     fn replace_generics_in_func(
         &mut self,
         func: &mut FunctionDef,
-        generic_names: &[String],
+        generics: &[GenericParam],
         concrete_types: &[Type],
     ) {
-        let mut map = HashMap::new();
-        for (name, ty) in generic_names.iter().zip(concrete_types.iter()) {
-            map.insert(name.clone(), ty.clone());
-        }
+        let map = generic_map(generics, concrete_types);
 
         for (_, ty) in &mut func.params {
             self.substitute_type(ty, &map);
@@ -1260,10 +4462,13 @@ impl TypeChecker {
         new_struct.name = new_name.clone();
         new_struct.generics.clear();
 
-        let mut map = HashMap::new();
-        for (name, ty) in template.generics.iter().zip(concrete_generics.iter()) {
-            map.insert(name.clone(), ty.clone());
+        if let Some(message) = generic_kind_mismatch(&template.generics, &concrete_generics) {
+            self.error(
+                Diagnostic::error(format!("in `{}`: {}", template_name, message))
+                    .with_span(template.span),
+            );
         }
+        let map = generic_map(&template.generics, &concrete_generics);
 
         for (_, field_ty) in &mut new_struct.fields {
             self.substitute_type_helper(field_ty, &map);
@@ -1286,15 +4491,29 @@ impl TypeChecker {
                     self.substitute_type(ty, map);
                 }
             }
-            Type::Pointer(inner) | Type::Array(inner, _) => {
+            Type::Pointer(inner)
+            | Type::Array(inner, _)
+            | Type::Slice(inner)
+            | Type::Optional(inner) => {
                 self.substitute_type(inner, map);
             }
-            Type::Function(args, ret, _) => {
+            Type::ConstArray(inner, name) => {
+                self.substitute_type(inner, map);
+                if let Some(Type::ConstArg(len)) = map.get(name.as_str()) {
+                    *ty = Type::Array(inner.clone(), *len);
+                }
+            }
+            Type::Function(args, ret, _) | Type::Closure(args, ret) => {
                 for arg in args {
                     self.substitute_type(arg, map);
                 }
                 self.substitute_type(ret, map);
             }
+            Type::Union(variants) => {
+                for variant in variants {
+                    self.substitute_type(variant, map);
+                }
+            }
             Type::Struct(path, generics) => {
                 for g in generics.iter_mut() {
                     self.substitute_type(g, map);
@@ -1316,8 +4535,8 @@ impl TypeChecker {
     }
 
     fn substitute_stmt(&mut self, stmt: &mut Stmt, map: &HashMap<String, Type>) {
-        match stmt {
-            Stmt::Let(_, ty_opt, expr_opt) => {
+        match &mut stmt.kind {
+            StmtKind::Let(_, ty_opt, expr_opt) => {
                 if let Some(ty) = ty_opt {
                     self.substitute_type(ty, map);
                 }
@@ -1325,25 +4544,26 @@ impl TypeChecker {
                     self.substitute_expr(expr, map);
                 }
             }
-            Stmt::Assign(lhs, rhs) => {
+            StmtKind::Assign(lhs, rhs) => {
                 self.substitute_expr(lhs, map);
                 self.substitute_expr(rhs, map);
             }
-            Stmt::Expr(expr) | Stmt::Ret(expr) => {
+            StmtKind::Expr(expr) | StmtKind::Ret(expr) => {
                 self.substitute_expr(expr, map);
             }
-            Stmt::If(cond, then_block, else_block) => {
+            StmtKind::If(cond, then_block, else_block) => {
                 self.substitute_expr(cond, map);
                 self.substitute_stmt(then_block, map);
                 if let Some(e) = else_block {
                     self.substitute_stmt(e, map);
                 }
             }
-            Stmt::While(cond, body) => {
+            StmtKind::While(cond, body) => {
                 self.substitute_expr(cond, map);
                 self.substitute_stmt(body, map);
             }
-            Stmt::Block(stmts) => {
+            StmtKind::Defer(body) => self.substitute_stmt(body, map),
+            StmtKind::Block(stmts) => {
                 for s in stmts {
                     self.substitute_stmt(s, map);
                 }
@@ -1353,8 +4573,15 @@ impl TypeChecker {
     }
 
     fn substitute_expr(&mut self, expr: &mut Expr, map: &HashMap<String, Type>) {
-        match expr {
-            Expr::Call(callee, args, generics) => {
+        match &mut expr.kind {
+            ExprKind::Ident(path) => {
+                if let [name] = path.as_slice() {
+                    if let Some(Type::ConstArg(value)) = map.get(name) {
+                        expr.kind = ExprKind::Lit(Lit::Int(*value as i64));
+                    }
+                }
+            }
+            ExprKind::Call(callee, args, generics) => {
                 self.substitute_expr(callee, map);
                 for arg in args {
                     self.substitute_expr(arg, map);
@@ -1363,22 +4590,41 @@ impl TypeChecker {
                     self.substitute_type(g, map);
                 }
             }
-            Expr::Binary(l, _, r) => {
+            ExprKind::Binary(l, _, r) | ExprKind::Index(l, r) | ExprKind::OrElse(l, r) => {
                 self.substitute_expr(l, map);
                 self.substitute_expr(r, map);
             }
-            Expr::Unary(_, inner)
-            | Expr::Deref(inner)
-            | Expr::AddrOf(inner)
-            | Expr::Member(inner, _) => {
+            ExprKind::MethodCall(receiver, _, args, generics) => {
+                self.substitute_expr(receiver, map);
+                for arg in args {
+                    self.substitute_expr(arg, map);
+                }
+                for g in generics {
+                    self.substitute_type(g, map);
+                }
+            }
+            ExprKind::Ternary(cond, then_expr, else_expr) => {
+                self.substitute_expr(cond, map);
+                self.substitute_expr(then_expr, map);
+                self.substitute_expr(else_expr, map);
+            }
+            ExprKind::Is(inner, ty) => {
+                self.substitute_expr(inner, map);
+                self.substitute_type(ty, map);
+            }
+            ExprKind::Unary(_, inner)
+            | ExprKind::Deref(inner)
+            | ExprKind::AddrOf(inner)
+            | ExprKind::Try(inner)
+            | ExprKind::Member(inner, _) => {
                 self.substitute_expr(inner, map);
             }
-            Expr::Cast(inner, ty) => {
+            ExprKind::Cast(inner, ty) => {
                 self.substitute_expr(inner, map);
                 self.substitute_type(ty, map);
             }
 
-            Expr::StructInit(_, fields, generics) => {
+            ExprKind::StructInit(_, fields, generics) => {
                 for (_, e) in fields {
                     self.substitute_expr(e, map);
                 }
@@ -1386,23 +4632,49 @@ impl TypeChecker {
                     self.substitute_type(g, map);
                 }
             }
-            Expr::SizeOf(ty) => {
+            ExprKind::SizeOf(ty) | ExprKind::Reflect(_, ty) => {
                 self.substitute_type(ty, map);
             }
-            Expr::Lit(Lit::Array(exprs)) => {
+            ExprKind::Lit(Lit::Array(exprs)) => {
                 for e in exprs {
                     self.substitute_expr(e, map);
                 }
             }
+            ExprKind::Match(subject, arms) => {
+                self.substitute_expr(subject, map);
+                for arm in arms {
+                    if let Pattern::Variant(ty, _) = &mut arm.pattern {
+                        self.substitute_type(ty, map);
+                    }
+                    for stmt in &mut arm.body {
+                        self.substitute_stmt(stmt, map);
+                    }
+                }
+            }
+            ExprKind::Closure(closure) => {
+                for (_, ty) in &mut closure.params {
+                    self.substitute_type(ty, map);
+                }
+                self.substitute_type(&mut closure.return_type, map);
+                for stmt in &mut closure.body {
+                    self.substitute_stmt(stmt, map);
+                }
+            }
+            ExprKind::Range(lo, hi) => {
+                for bound in [lo, hi].into_iter().flatten() {
+                    self.substitute_expr(bound, map);
+                }
+            }
             _ => {}
         }
     }
 
     fn infer_generics_from_args(
-        &self,
+        &mut self,
         generic_names: &[String],
         param_defs: &[(String, Type)],
         arg_types: &[Type],
+        span: Span,
     ) -> Vec<Type> {
         let mut resolved_map: HashMap<String, Type> = HashMap::new();
 
@@ -1414,7 +4686,13 @@ impl TypeChecker {
         for name in generic_names {
             match resolved_map.get(name) {
                 Some(ty) => result.push(ty.clone()),
-                None => panic!("Could not infer generic type '{}'", name),
+                None => {
+                    self.error(
+                        Diagnostic::error(format!("could not infer generic type '{}'", name))
+                            .with_span(span),
+                    );
+                    result.push(Type::Void);
+                }
             }
         }
         result
@@ -1435,6 +4713,18 @@ impl TypeChecker {
             (Type::Array(p_inner, _), Type::Array(a_inner, _)) => {
                 self.match_types(p_inner, a_inner, map);
             }
+            (Type::ConstArray(p_inner, name), Type::Array(a_inner, len)) => {
+                map.entry(name.clone()).or_insert(Type::ConstArg(*len));
+                self.match_types(p_inner, a_inner, map);
+            }
+            (Type::Slice(p_inner), Type::Array(a_inner, _)) => {
+                self.match_types(p_inner, a_inner, map);
+            }
+            (Type::Slice(p_inner), arg) => {
+                if let Some(elem) = self.slice_elem(arg) {
+                    self.match_types(p_inner, &elem, map);
+                }
+            }
 
             (Type::Struct(p_path, p_generics), Type::Struct(a_path, a_generics)) => {
                 if p_generics.len() == a_generics.len() {
@@ -1458,3 +4748,334 @@ impl TypeChecker {
         }
     }
 }
+
+/// The names of `generics`, types and constants alike.
+fn generic_names(generics: &[GenericParam]) -> Vec<String> {
+    generics.iter().map(|g| g.name.clone()).collect()
+}
+
+/// Each generic parameter's name mapped to the type or constant given for it.
+fn generic_map(generics: &[GenericParam], args: &[Type]) -> HashMap<String, Type> {
+    generics
+        .iter()
+        .zip(args)
+        .map(|(g, ty)| (g.name.clone(), ty.clone()))
+        .collect()
+}
+
+/// Describes the first argument that is a type where a constant is
+/// expected, or the other way round.
+fn generic_kind_mismatch(generics: &[GenericParam], args: &[Type]) -> Option<String> {
+    generics
+        .iter()
+        .zip(args)
+        .find_map(|(g, arg)| match (g.is_const(), arg) {
+            // Inference already failed and was reported.
+            (_, Type::Void) => None,
+            (true, Type::ConstArg(_)) => None,
+            (true, _) => Some(format!(
+                "expected a constant for `const {}`, found {}",
+                g.name, arg
+            )),
+            (false, Type::ConstArg(value)) => Some(format!(
+                "expected a type for `{}`, found the constant {}",
+                g.name, value
+            )),
+            (false, _) => None,
+        })
+}
+
+/// The `x is T` tests `cond` is built from that hold when it evaluates to
+/// `value`, as `(x, T, whether x is a T)`. `x == null` tests whether `x` is
+/// the `null` variant.
+fn type_tests(cond: &Expr, value: bool) -> Vec<(String, Type, bool)> {
+    match &cond.kind {
+        ExprKind::Is(inner, ty) => match &inner.kind {
+            ExprKind::Ident(path) if path.len() == 1 => vec![(path[0].clone(), ty.clone(), value)],
+            _ => Vec::new(),
+        },
+        ExprKind::Binary(lhs, op @ (BinaryOp::Eq | BinaryOp::Neq), rhs) => {
+            match (&lhs.kind, &rhs.kind) {
+                (ExprKind::Ident(path), ExprKind::Lit(Lit::Null))
+                | (ExprKind::Lit(Lit::Null), ExprKind::Ident(path))
+                    if path.len() == 1 =>
+                {
+                    let is_null = (*op == BinaryOp::Eq) == value;
+                    vec![(path[0].clone(), Type::Null, is_null)]
+                }
+                _ => Vec::new(),
+            }
+        }
+        ExprKind::Unary(UnaryOp::Not, inner) => type_tests(inner, !value),
+        ExprKind::Binary(lhs, BinaryOp::And, rhs) if value => {
+            let mut tests = type_tests(lhs, value);
+            tests.extend(type_tests(rhs, value));
+            tests
+        }
+        ExprKind::Binary(lhs, BinaryOp::Or, rhs) if !value => {
+            let mut tests = type_tests(lhs, value);
+            tests.extend(type_tests(rhs, value));
+            tests
+        }
+        _ => Vec::new(),
+    }
+}
+
+/// Smallest id a non-primitive type can get, clear of the primitives' own.
+const FIRST_TYPE_ID: i64 = 64;
+
+/// An id for the type named `name`: its 64-bit FNV-1a hash, kept positive.
+fn hashed_type_id(name: &str) -> i64 {
+    let hash = name.bytes().fold(0xcbf29ce484222325_u64, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+    FIRST_TYPE_ID + (hash >> 2) as i64
+}
+
+/// Prefix of the C runtime's debug checks, each of which returns its first
+/// argument once it holds.
+const CHECK_PREFIX: &str = "__abyss_check_";
+
+/// A call to the debug check `__abyss_check_<name>`.
+fn runtime_check(name: &str, args: Vec<Expr>, span: Span) -> Expr {
+    let callee = Expr::new(
+        ExprKind::Ident(vec![format!("{}{}", CHECK_PREFIX, name)]),
+        span,
+    );
+    Expr::new(ExprKind::Call(Box::new(callee), args, vec![]), span)
+}
+
+/// The width of the signed integer type `ty`.
+fn signed_bits(ty: &Type) -> Option<i64> {
+    match ty {
+        Type::I8 => Some(8),
+        Type::I16 => Some(16),
+        Type::I32 => Some(32),
+        Type::I64 | Type::Isize => Some(64),
+        _ => None,
+    }
+}
+
+/// The check for `lhs op rhs`, with the type it is done in and that type's
+/// width, if it is signed integer arithmetic that can overflow. An integer
+/// literal takes the type of the other operand, as it does in C. Division
+/// overflows on `MIN / -1` and is checked for a zero divisor along the way.
+fn overflow_check(
+    lhs: &Expr,
+    lhs_ty: &Type,
+    op: BinaryOp,
+    rhs: &Expr,
+    rhs_ty: &Type,
+) -> Option<(&'static str, Type, i64)> {
+    let check = match op {
+        BinaryOp::Add => "add",
+        BinaryOp::Sub => "sub",
+        BinaryOp::Mul => "mul",
+        BinaryOp::Div => "div",
+        BinaryOp::Mod => "rem",
+        _ => return None,
+    };
+    let is_lit = |expr: &Expr| matches!(expr.kind, ExprKind::Lit(Lit::Int(_)));
+    if matches!(op, BinaryOp::Div | BinaryOp::Mod)
+        && matches!(rhs.kind, ExprKind::Lit(Lit::Int(d)) if d != 0 && d != -1)
+    {
+        return None;
+    }
+
+    let ty = match (is_lit(lhs), is_lit(rhs)) {
+        (true, true) => return None,
+        (true, false) => rhs_ty,
+        (false, true) => lhs_ty,
+        (false, false) if signed_bits(lhs_ty)? >= signed_bits(rhs_ty)? => lhs_ty,
+        (false, false) => rhs_ty,
+    };
+    Some((check, ty.clone(), signed_bits(ty)?))
+}
+
+/// The operand of `expr` if it is a debug check, cast back or not.
+fn checked_operand(expr: &Expr) -> Option<&Expr> {
+    match &expr.kind {
+        ExprKind::Cast(inner, _) => checked_operand(inner),
+        ExprKind::Call(callee, args, _) => match &callee.kind {
+            ExprKind::Ident(path) if path[0].starts_with(CHECK_PREFIX) => args.first(),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Whether `expr` reads a place, looking through debug checks, so that it
+/// can be read again instead of being copied.
+fn reads_place(expr: &Expr) -> bool {
+    if let Some(operand) = checked_operand(expr) {
+        return reads_place(operand);
+    }
+    match &expr.kind {
+        ExprKind::Member(inner, _) | ExprKind::Deref(inner) | ExprKind::AddrOf(inner) => {
+            reads_place(inner)
+        }
+        ExprKind::Index(arr, idx) => {
+            let idx = checked_operand(idx).unwrap_or(idx);
+            reads_place(arr) && matches!(idx.kind, ExprKind::Ident(_) | ExprKind::Lit(_))
+        }
+        _ => expr.is_place(),
+    }
+}
+
+/// `fn(a, b): r`, as a signature is shown in diagnostics.
+fn signature(func: &FunctionDef) -> String {
+    let params: Vec<String> = func.params.iter().map(|(_, ty)| ty.to_string()).collect();
+    format!("fn({}): {}", params.join(", "), func.return_type)
+}
+
+/// Calls `visit` on every expression in `stmt`, nested ones included.
+pub(crate) fn walk_stmt<'a>(stmt: &'a Stmt, visit: &mut impl FnMut(&'a Expr)) {
+    match &stmt.kind {
+        StmtKind::Let(_, _, Some(expr))
+        | StmtKind::Const(_, _, Some(expr))
+        | StmtKind::Ret(expr)
+        | StmtKind::Expr(expr) => walk_expr(expr, visit),
+        StmtKind::Assign(lhs, rhs) => {
+            walk_expr(lhs, visit);
+            walk_expr(rhs, visit);
+        }
+        StmtKind::Block(stmts) => {
+            for s in stmts {
+                walk_stmt(s, visit);
+            }
+        }
+        StmtKind::If(cond, then_b, else_b) => {
+            walk_expr(cond, visit);
+            walk_stmt(then_b, visit);
+            if let Some(e) = else_b {
+                walk_stmt(e, visit);
+            }
+        }
+        StmtKind::While(cond, body) => {
+            walk_expr(cond, visit);
+            walk_stmt(body, visit);
+        }
+        StmtKind::Defer(body) => walk_stmt(body, visit),
+        _ => {}
+    }
+}
+
+/// The variables assigned anywhere in the loop `while cond body`.
+fn assigned_vars(cond: &Expr, body: &Stmt) -> HashSet<String> {
+    fn assigned_by_stmts(stmt: &Stmt, names: &mut HashSet<String>) {
+        match &stmt.kind {
+            StmtKind::Assign(lhs, _) => names.extend(var_name(lhs)),
+            StmtKind::Block(stmts) => {
+                for s in stmts {
+                    assigned_by_stmts(s, names);
+                }
+            }
+            StmtKind::If(_, then_b, else_b) => {
+                assigned_by_stmts(then_b, names);
+                if let Some(e) = else_b {
+                    assigned_by_stmts(e, names);
+                }
+            }
+            StmtKind::While(_, body) | StmtKind::Defer(body) => assigned_by_stmts(body, names),
+            _ => {}
+        }
+    }
+    fn var_name(target: &Expr) -> Option<String> {
+        match &target.kind {
+            ExprKind::Ident(path) if path.len() == 1 => Some(path[0].clone()),
+            _ => None,
+        }
+    }
+
+    let mut names = HashSet::new();
+    assigned_by_stmts(body, &mut names);
+    let mut visit = |expr: &Expr| match &expr.kind {
+        ExprKind::Binary(lhs, BinaryOp::Assign, _) => names.extend(var_name(lhs)),
+        ExprKind::Match(_, arms) => {
+            for s in arms.iter().flat_map(|arm| &arm.body) {
+                assigned_by_stmts(s, &mut names);
+            }
+        }
+        _ => {}
+    };
+    walk_expr(cond, &mut visit);
+    walk_stmt(body, &mut visit);
+    names
+}
+
+/// The first `ret` in `stmt`, or `out`/`next` outside a loop nested in it,
+/// as they would leave a deferred statement.
+fn leaves_defer(stmt: &Stmt, in_loop: bool) -> Option<&Stmt> {
+    match &stmt.kind {
+        StmtKind::Ret(_) => Some(stmt),
+        StmtKind::Break | StmtKind::Continue if !in_loop => Some(stmt),
+        StmtKind::Block(stmts) => stmts.iter().find_map(|s| leaves_defer(s, in_loop)),
+        StmtKind::If(_, then_b, else_b) => leaves_defer(then_b, in_loop)
+            .or_else(|| else_b.as_deref().and_then(|e| leaves_defer(e, in_loop))),
+        StmtKind::While(_, body) => leaves_defer(body, true),
+        _ => None,
+    }
+}
+
+fn walk_expr<'a>(expr: &'a Expr, visit: &mut impl FnMut(&'a Expr)) {
+    visit(expr);
+    match &expr.kind {
+        ExprKind::MethodCall(receiver, _, args, _) => {
+            walk_expr(receiver, visit);
+            for arg in args {
+                walk_expr(arg, visit);
+            }
+        }
+        ExprKind::Call(callee, args, _) => {
+            walk_expr(callee, visit);
+            for arg in args {
+                walk_expr(arg, visit);
+            }
+        }
+        ExprKind::Binary(lhs, _, rhs) | ExprKind::Index(lhs, rhs) | ExprKind::OrElse(lhs, rhs) => {
+            walk_expr(lhs, visit);
+            walk_expr(rhs, visit);
+        }
+        ExprKind::Unary(_, inner)
+        | ExprKind::Deref(inner)
+        | ExprKind::AddrOf(inner)
+        | ExprKind::Try(inner)
+        | ExprKind::Cast(inner, _)
+        | ExprKind::Is(inner, _)
+        | ExprKind::Member(inner, _) => walk_expr(inner, visit),
+        ExprKind::StructInit(_, fields, _) | ExprKind::UnionInit(_, fields) => {
+            for (_, value) in fields {
+                walk_expr(value, visit);
+            }
+        }
+        ExprKind::Lit(Lit::Array(exprs)) => {
+            for e in exprs {
+                walk_expr(e, visit);
+            }
+        }
+        ExprKind::Match(subject, arms) => {
+            walk_expr(subject, visit);
+            for arm in arms {
+                for stmt in &arm.body {
+                    walk_stmt(stmt, visit);
+                }
+            }
+        }
+        ExprKind::Ternary(cond, then_e, else_e) => {
+            walk_expr(cond, visit);
+            walk_expr(then_e, visit);
+            walk_expr(else_e, visit);
+        }
+        ExprKind::Range(lo, hi) => {
+            for bound in [lo, hi].into_iter().flatten() {
+                walk_expr(bound, visit);
+            }
+        }
+        ExprKind::Closure(closure) => {
+            for stmt in &closure.body {
+                walk_stmt(stmt, visit);
+            }
+        }
+        _ => {}
+    }
+}
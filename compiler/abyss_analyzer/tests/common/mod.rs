@@ -0,0 +1,56 @@
+// Shared by the test binaries, each of which uses only some of it.
+#![allow(dead_code)]
+
+use abyss_analyzer::{
+    collector::Collector, flattener::Flattener, ir::Ir, lir::LirProgram, type_checker::TypeChecker,
+};
+use abyss_parser::parser::Parser;
+
+/// Parses, flattens and type-checks `source`, returning the messages of the
+/// errors reported, or the lowered program when there are none.
+pub fn check(source: &str) -> Result<LirProgram, Vec<String>> {
+    let mut parser = Parser::new(source, "test.a");
+    let program = parser.parse_program();
+    assert!(!parser.has_errors(), "{}", parser.format_errors());
+
+    let mut flattener = Flattener::new();
+    let flat = flattener.flatten(program);
+    let mut tc = TypeChecker::new();
+    let typed = tc.check(flat);
+
+    let errors: Vec<String> = flattener
+        .diagnostics()
+        .iter()
+        .chain(tc.diagnostics())
+        .filter(|d| d.is_error())
+        .map(|d| d.message.clone())
+        .collect();
+    if !errors.is_empty() {
+        return Err(errors);
+    }
+    let ctx = Collector::collect(&typed).map_err(|e| vec![e])?;
+    Ok(Ir::build(&typed, ctx))
+}
+
+/// Asserts that `source` type-checks and lowers without errors.
+#[track_caller]
+pub fn accepts(source: &str) -> LirProgram {
+    match check(source) {
+        Ok(program) => program,
+        Err(errors) => panic!("expected no errors, found:\n{}", errors.join("\n")),
+    }
+}
+
+/// Asserts that `source` is rejected with an error containing `message`.
+#[track_caller]
+pub fn rejects(source: &str, message: &str) {
+    match check(source) {
+        Ok(_) => panic!("expected an error containing `{}`, found none", message),
+        Err(errors) => assert!(
+            errors.iter().any(|e| e.contains(message)),
+            "expected an error containing `{}`, found:\n{}",
+            message,
+            errors.join("\n")
+        ),
+    }
+}
@@ -0,0 +1,102 @@
+mod common;
+
+use common::{accepts, rejects};
+
+const FOO: &str = "
+struct Foo { a: i64 }
+struct Bar { b: i64 }
+fn foo_value: i64 | Foo { ret struct Foo { a: 1 } }
+";
+
+#[test]
+fn is_narrows_inside_the_branch() {
+    accepts(&format!(
+        "{FOO}
+fn get(x: i64 | Foo): i64 {{
+    if x is Foo {{ ret x.a }}
+    ret x
+}}"
+    ));
+}
+
+#[test]
+fn ruled_out_variants_accumulate() {
+    accepts(&format!(
+        "{FOO}
+fn get(v: i64 | Foo | Bar): i64 {{
+    if v is Foo {{ ret v.a }}
+    if v is Bar {{ ret v.b }}
+    ret v
+}}"
+    ));
+}
+
+#[test]
+fn returning_an_unnarrowed_union_is_rejected() {
+    rejects(
+        &format!(
+            "{FOO}
+fn get(v: i64 | Foo | Bar): i64 {{
+    if v is Foo {{ ret v.a }}
+    ret v
+}}"
+        ),
+        "expected i64 from `ret`",
+    );
+}
+
+#[test]
+fn assignment_ends_narrowing() {
+    rejects(
+        &format!(
+            "{FOO}
+fn get(x: i64 | Foo): i64 {{
+    if x is Foo {{ ret 0 }}
+    x = foo_value()
+    let y: i64 = x
+    ret y
+}}"
+        ),
+        "mismatched types in let binding for 'y'",
+    );
+}
+
+#[test]
+fn loop_assigning_the_variable_ends_narrowing() {
+    rejects(
+        &format!(
+            "{FOO}
+fn sum(x: i64 | Foo): i64 {{
+    if x is Foo {{ ret 0 }}
+    let t = 0
+    let i = 0
+    while i < 3 {{
+        let y: i64 = x
+        t += y
+        x = foo_value()
+        i += 1
+    }}
+    ret t
+}}"
+        ),
+        "mismatched types in let binding for 'y'",
+    );
+}
+
+#[test]
+fn loop_leaving_the_variable_alone_keeps_narrowing() {
+    accepts(&format!(
+        "{FOO}
+fn sum(x: i64 | Foo): i64 {{
+    if x is Foo {{ ret 0 }}
+    let t = 0
+    let i = 0
+    while i < 3 {{
+        let y: i64 = x
+        t += y
+        i += 1
+    }}
+    ret t
+}}"
+    ));
+}
@@ -0,0 +1,73 @@
+mod common;
+
+use abyss_analyzer::lir::LirProgram;
+use common::accepts;
+
+fn defines(program: &LirProgram, name: &str) -> bool {
+    program.functions.iter().any(|f| f.name == name)
+}
+
+#[test]
+fn fn_without_captures_stays_a_function() {
+    let program = accepts(
+        "fn outer(k: i64): i64 {
+    fn top(x: i64): i64 { ret x + 1 }
+    if k > 0 {
+        fn nested(x: i64): i64 { ret x * 2 }
+        ret nested(top(k))
+    }
+    ret 0
+}",
+    );
+    assert!(defines(&program, "outer__top"));
+    assert!(defines(&program, "outer__nested"));
+}
+
+#[test]
+fn fn_using_outer_locals_becomes_a_closure() {
+    let program = accepts(
+        "fn outer(k: i64): i64 {
+    let base = k
+    fn add(x: i64): i64 { ret x + base }
+    ret add(1)
+}",
+    );
+    assert!(!defines(&program, "outer__add"));
+}
+
+#[test]
+fn for_and_match_bindings_are_captured() {
+    let program = accepts(
+        "struct Foo { a: i64 }
+fn pick: i64 | Foo { ret struct Foo { a: 4 } }
+fn outer: i64 {
+    let total = 0
+    for i in 0 -> 3 {
+        fn add_i(x: i64): i64 { ret x + i }
+        total += add_i(0)
+    }
+    match pick() {
+        Foo(f) => {
+            fn with_a(x: i64): i64 { ret x + f.a }
+            total += with_a(0)
+        }
+        i64(n) => { total += n }
+    }
+    ret total
+}",
+    );
+    assert!(!defines(&program, "outer__add_i"));
+    assert!(!defines(&program, "outer__with_a"));
+}
+
+#[test]
+fn fn_calling_a_closure_becomes_one_too() {
+    let program = accepts(
+        "fn outer(k: i64): i64 {
+    fn add(x: i64): i64 { ret x + k }
+    fn twice(x: i64): i64 { ret add(add(x)) }
+    ret twice(1)
+}",
+    );
+    assert!(!defines(&program, "outer__twice"));
+}
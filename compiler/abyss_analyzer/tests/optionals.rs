@@ -0,0 +1,66 @@
+mod common;
+
+use common::{accepts, rejects};
+
+const DECLS: &str = "
+struct Node { v: i64, link: ?&Node }
+fn find(n: &Node, v: i64): ?Node { ret null }
+fn num: i64 | null { ret 3 }
+fn either: i64 | Node { ret 3 }
+";
+
+#[test]
+fn checked_optionals_are_read() {
+    accepts(&format!(
+        "{DECLS}
+fn get(n: &Node): i64 {{
+    if let m = find(n, 7) {{ ret m.v }}
+    let p = n.link
+    if p != null {{ ret p.v }}
+    let fallback = struct Node {{ v: 0, link: null }}
+    let q = n.link orelse &fallback
+    let k = num()
+    if k == null {{ ret q.v }}
+    ret k + 1
+}}"
+    ));
+}
+
+#[test]
+fn reading_an_unchecked_optional_is_rejected() {
+    rejects(
+        &format!("{DECLS}\nfn get(n: &Node): i64 {{ ret n.link.v }}"),
+        "cannot read the field 'v' of ?&Node without checking it for null",
+    );
+}
+
+#[test]
+fn arithmetic_on_an_unchecked_optional_is_rejected() {
+    rejects(
+        &format!("{DECLS}\nfn get: i64 {{ let x = num() + 1\n ret 0 }}"),
+        "cannot do arithmetic with ?i64 without checking it for null",
+    );
+}
+
+#[test]
+fn comparing_an_unchecked_optional_is_rejected() {
+    rejects(
+        &format!("{DECLS}\nfn get: i64 {{ if num() < 3 {{ ret 1 }}\n ret 0 }}"),
+        "cannot compare ?i64 without checking it for null",
+    );
+}
+
+#[test]
+fn arithmetic_on_an_unnarrowed_union_is_rejected() {
+    rejects(
+        &format!("{DECLS}\nfn get: i64 {{ let y = either() * 2\n ret 0 }}"),
+        "cannot do arithmetic with i64 | Node before narrowing it",
+    );
+}
+
+#[test]
+fn optional_pointers_compare_as_pointers() {
+    accepts(&format!(
+        "{DECLS}\nfn same(a: &Node, b: &Node): bool {{ ret a.link == b.link }}"
+    ));
+}
@@ -1,23 +1,78 @@
 use crate::target::Target;
 use abyss_analyzer::lir::{LirLiteral, LirType};
 use abyss_parser::ast::{BinaryOp, UnaryOp};
+
+/// File name `#line` directives use for code with no Abyss source location.
+pub const GENERATED_FILE: &str = "<generated>";
+
+/// Prefix of the runtime's debug checks, which are macros rather than functions.
+const CHECK_MACRO: &str = "__abyss_check_";
+
 pub struct CTarget {
     output: String,
+    /// Newlines in `output`, counted as it is written.
+    lines: usize,
     indent_level: usize,
     pending_newline: bool,
     in_variable_init: bool,
     init_state_stack: Vec<bool>,
+    line_directives: bool,
+    has_location: bool,
+    last_directive: Option<(usize, usize)>,
+    /// Whether each call being written is a check macro, innermost last.
+    calls: Vec<bool>,
+    /// A location set inside a check macro's arguments, where a directive is
+    /// undefined behaviour, written once the macro is closed.
+    deferred_location: Option<(String, usize)>,
+    debug: bool,
 }
 
 impl CTarget {
     pub fn new() -> Self {
         Self {
             output: String::new(),
+            lines: 0,
             indent_level: 0,
             pending_newline: false,
             in_variable_init: false,
             init_state_stack: Vec::new(),
+            line_directives: true,
+            has_location: false,
+            last_directive: None,
+            calls: Vec::new(),
+            deferred_location: None,
+            debug: false,
+        }
+    }
+
+    /// Controls whether `#line` directives mapping the C code back to the
+    /// Abyss source are emitted (enabled by default).
+    pub fn with_line_directives(mut self, enabled: bool) -> Self {
+        self.line_directives = enabled;
+        self
+    }
+
+    fn write_line_directive(&mut self, line: usize, file: &str) {
+        // A directive directly followed by another one covers no code.
+        if let Some((start, end)) = self.last_directive.take()
+            && end == self.output.len()
+        {
+            self.output.truncate(start);
         }
+        if !self.output.ends_with('\n') {
+            self.push_output("\n");
+        }
+        let file = file.replace('\\', "\\\\").replace('"', "\\\"");
+        let start = self.output.len();
+        self.push_output(&format!("#line {} \"{}\"", line, file));
+        self.last_directive = Some((start, self.output.len()));
+        self.pending_newline = true;
+    }
+
+    /// Appends `text` to the output, keeping count of its lines.
+    fn push_output(&mut self, text: &str) {
+        self.lines += text.matches('\n').count();
+        self.output.push_str(text);
     }
 
     fn push_indent(&mut self) {
@@ -32,11 +87,11 @@ impl CTarget {
 
     fn write(&mut self, text: &str) {
         if self.pending_newline {
-            self.output.push('\n');
-            self.output.push_str(&"    ".repeat(self.indent_level));
+            self.push_output("\n");
+            self.push_output(&"    ".repeat(self.indent_level));
             self.pending_newline = false;
         }
-        self.output.push_str(text);
+        self.push_output(text);
     }
 
     fn set_newline_pending(&mut self) {
@@ -60,14 +115,15 @@ impl CTarget {
             LirType::F32 => "float".to_string(),
             LirType::F64 => "double".to_string(),
             LirType::Char => "char".to_string(),
-            LirType::Bool => "int".to_string(),
+            LirType::Bool => "_Bool".to_string(),
             LirType::Void => "void".to_string(),
+            LirType::Pointer(_) if Self::has_declarator(ty) => self.declaration(ty, ""),
             LirType::Pointer(inner) => format!("{}*", self.type_to_c(inner)),
             LirType::Const(inner) => format!("const {}", self.type_to_c(inner)),
 
-            LirType::Array(inner, _) => format!("{}*", self.type_to_c(inner)),
+            LirType::Array(..) => self.declaration(ty, ""),
             LirType::Struct(name) => {
-                if name.starts_with("__UnionInner_") {
+                if name.starts_with("__UnionInner_") || name.starts_with("__EnumData_") {
                     format!("union {}", name)
                 } else {
                     format!("struct {}", name)
@@ -83,14 +139,40 @@ impl CTarget {
                 format!("struct __Union_{}", id)
             }
 
+            LirType::FunctionPtr(..) => self.declaration(ty, ""),
+        }
+    }
+
+    /// Whether C spells `ty` around the declared name rather than before it:
+    /// arrays, function pointers and pointers to either.
+    fn has_declarator(ty: &LirType) -> bool {
+        match ty {
+            LirType::Array(..) | LirType::FunctionPtr(..) => true,
+            LirType::Pointer(inner) => Self::has_declarator(inner),
+            _ => false,
+        }
+    }
+
+    /// `ty name` as a C declaration; arrays and function pointers wrap the name.
+    fn declaration(&self, ty: &LirType, name: &str) -> String {
+        match ty {
+            LirType::Array(inner, size) => self.declaration(inner, &format!("{}[{}]", name, size)),
+            LirType::Pointer(inner) if matches!(**inner, LirType::FunctionPtr(..)) => {
+                self.declaration(inner, &format!("*{}", name))
+            }
+            // A pointer to a whole array: `float (*name)[4]`.
+            LirType::Pointer(inner) if Self::has_declarator(inner) => {
+                self.declaration(inner, &format!("(*{})", name))
+            }
             LirType::FunctionPtr(args, ret) => {
                 let args_str = args
                     .iter()
                     .map(|t| self.type_to_c(t))
                     .collect::<Vec<_>>()
                     .join(", ");
-                format!("{} (*)({})", self.type_to_c(ret), args_str)
+                self.declaration(ret, &format!("(*{})({})", name, args_str))
             }
+            _ => format!("{} {}", self.type_to_c(ty), name),
         }
     }
 
@@ -157,7 +239,13 @@ impl CTarget {
         } else {
             params
                 .iter()
-                .map(|(name, ty)| format!("{} {}", self.type_to_c(ty), name))
+                .map(|(name, ty)| match ty {
+                    // C passes an array as a pointer to its first element.
+                    LirType::Array(inner, _) => {
+                        self.declaration(&LirType::Pointer(inner.clone()), name)
+                    }
+                    _ => self.declaration(ty, name),
+                })
                 .collect::<Vec<String>>()
                 .join(", ")
         };
@@ -176,12 +264,16 @@ impl CTarget {
         return_type: &LirType,
         is_variadic: bool,
     ) -> String {
-        format!(
-            "{} {}({})",
-            self.type_to_c(return_type),
+        let declarator = format!(
+            "{}({})",
             name,
             self.params_to_func_args(params, is_variadic)
-        )
+        );
+        if Self::has_declarator(return_type) {
+            self.declaration(return_type, &declarator)
+        } else {
+            format!("{} {}", self.type_to_c(return_type), declarator)
+        }
     }
 }
 
@@ -191,23 +283,50 @@ impl Target for CTarget {
     }
     fn start_program(&mut self) {
         self.output.clear();
-        self.output
-            .push_str("// --- Generated by Abyss CTarget ---\n");
-        self.output.push_str(include_str!("runtime/c_rt.c"));
+        self.lines = 0;
+        self.has_location = false;
+        self.last_directive = None;
+        self.push_output("// --- Generated by Abyss CTarget ---\n");
+        if self.debug {
+            self.push_output("#define ABYSS_DEBUG 1\n");
+        }
+        self.push_output(include_str!("runtime/c_rt.c"));
         self.pending_newline = false;
     }
 
+    fn set_debug(&mut self, enabled: bool) {
+        self.debug = enabled;
+    }
+
     fn end_program(&mut self) {
         self.indent_level = 0;
-        self.output.push_str("\n// --- End of generated code ---\n");
-        self.output.push_str("\nint main(void) {\n");
-        self.indent_level = 1;
-        self.pending_newline = true;
-        self.write("app_main();");
-        self.set_newline_pending();
-        self.write("return 0;");
-        self.indent_level = 0;
-        self.output.push_str("\n}");
+        self.push_output("\n// --- End of generated code ---\n");
+    }
+
+    fn define_entry_point(
+        &mut self,
+        name: &str,
+        params: &[(String, LirType)],
+        return_type: &LirType,
+    ) {
+        let args = match params {
+            [(_, argc_ty), (_, argv_ty)] => format!(
+                "({})argc, ({})argv",
+                self.type_to_c(argc_ty),
+                self.type_to_c(argv_ty)
+            ),
+            _ => String::new(),
+        };
+
+        self.push_output("\nint main(int argc, char **argv) {\n");
+        if *return_type == LirType::Void {
+            self.push_output(&format!("    {}({});\n", name, args));
+            self.push_output("    return 0;\n");
+        } else {
+            self.push_output(&format!("    return (int){}({});\n", name, args));
+        }
+        self.push_output("}\n");
+        self.pending_newline = false;
     }
 
     fn define_struct(&mut self, name: &str, fields: &[(String, LirType)]) {
@@ -215,12 +334,7 @@ impl Target for CTarget {
         self.push_indent();
         self.set_newline_pending();
         for (field_name, field_type) in fields {
-            let decl = match field_type {
-                LirType::Array(inner, size) => {
-                    format!("{} {}[{}]", self.type_to_c(inner), field_name, size)
-                }
-                _ => format!("{} {}", self.type_to_c(field_type), field_name),
-            };
+            let decl = self.declaration(field_type, field_name);
             self.write(&format!("{};", decl));
             self.set_newline_pending();
         }
@@ -236,12 +350,7 @@ impl Target for CTarget {
         self.push_indent();
         self.set_newline_pending();
         for (field_name, field_type) in variants {
-            let decl = match field_type {
-                LirType::Array(inner, size) => {
-                    format!("{} {}[{}]", self.type_to_c(inner), field_name, size)
-                }
-                _ => format!("{} {}", self.type_to_c(field_type), field_name),
-            };
+            let decl = self.declaration(field_type, field_name);
             self.write(&format!("{};", decl));
             self.set_newline_pending();
         }
@@ -269,10 +378,7 @@ impl Target for CTarget {
             self.write("const ");
         }
 
-        let decl = match ty {
-            LirType::Array(inner, size) => format!("{} {}[{}]", self.type_to_c(inner), name, size),
-            _ => format!("{} {}", self.type_to_c(ty), name),
-        };
+        let decl = self.declaration(ty, name);
 
         self.write(&decl);
     }
@@ -324,7 +430,7 @@ impl Target for CTarget {
         is_variadic: bool,
     ) {
         if !self.output.ends_with("\n\n") {
-            self.output.push('\n');
+            self.push_output("\n");
         }
         self.pending_newline = false;
         self.write(&format!(
@@ -339,14 +445,11 @@ impl Target for CTarget {
         self.pop_indent();
         self.write("}");
         self.set_newline_pending();
-        self.output.push('\n');
+        self.push_output("\n");
     }
 
     fn stmt_var_decl(&mut self, name: &str, ty: &LirType, has_init: bool) {
-        let decl = match ty {
-            LirType::Array(inner, size) => format!("{} {}[{}]", self.type_to_c(inner), name, size),
-            _ => format!("{} {}", self.type_to_c(ty), name),
-        };
+        let decl = self.declaration(ty, name);
 
         self.write(&decl);
 
@@ -473,8 +576,8 @@ impl Target for CTarget {
             self.write("{ ");
         } else {
             if let Some(inner_ty) = ty_opt {
-                let type_str = self.type_to_c(inner_ty);
-                self.write(&format!("({}[]) {{ ", type_str));
+                let type_str = self.declaration(inner_ty, "[]");
+                self.write(&format!("({}) {{ ", type_str));
             } else {
                 self.write("(long long[]) { ");
             }
@@ -491,6 +594,9 @@ impl Target for CTarget {
 
     fn expr_struct_init_start(&mut self, struct_name: &str) {
         self.write(&format!("(struct {}){{ ", struct_name));
+        // Array fields are filled in place with `{ ... }`, as in a declaration.
+        self.init_state_stack.push(self.in_variable_init);
+        self.in_variable_init = true;
     }
 
     fn expr_struct_init_field_start(&mut self, field_name: &str) {
@@ -503,6 +609,9 @@ impl Target for CTarget {
 
     fn expr_struct_init_end(&mut self) {
         self.write(" }");
+        if let Some(prev) = self.init_state_stack.pop() {
+            self.in_variable_init = prev;
+        }
     }
 
     fn expr_lit(&mut self, lit: &LirLiteral) {
@@ -537,6 +646,16 @@ impl Target for CTarget {
 
     fn expr_call_start(&mut self, name: &str) {
         self.write(&format!("{}(", name));
+        self.calls.push(name.starts_with(CHECK_MACRO));
+        self.init_state_stack.push(self.in_variable_init);
+        self.in_variable_init = false;
+    }
+    fn expr_call_ptr_start(&mut self) {
+        self.write("(");
+    }
+    fn expr_call_ptr_args(&mut self) {
+        self.write(")(");
+        self.calls.push(false);
         self.init_state_stack.push(self.in_variable_init);
         self.in_variable_init = false;
     }
@@ -549,6 +668,12 @@ impl Target for CTarget {
         if let Some(prev_state) = self.init_state_stack.pop() {
             self.in_variable_init = prev_state;
         }
+        self.calls.pop();
+        if !self.calls.contains(&true)
+            && let Some((file, line)) = self.deferred_location.take()
+        {
+            self.set_location(&file, line);
+        }
     }
 
     fn expr_member(&mut self, field: &str, is_pointer: bool) {
@@ -557,8 +682,7 @@ impl Target for CTarget {
     }
 
     fn expr_sizeof(&mut self, ty: &LirType) {
-        let type_str = self.type_to_c(ty);
-        self.write(&format!("sizeof({})", type_str));
+        self.write(&format!("sizeof({})", self.type_to_c(ty)));
     }
 
     fn expr_index_start(&mut self) {
@@ -569,9 +693,16 @@ impl Target for CTarget {
     }
 
     fn expr_cast_start(&mut self, target_ty: &LirType) {
-        self.write(&format!("({})", self.type_to_c(target_ty)));
+        self.write(&format!("(({})", self.type_to_c(target_ty)));
+        self.init_state_stack.push(self.in_variable_init);
+        self.in_variable_init = false;
+    }
+    fn expr_cast_end(&mut self) {
+        self.write(")");
+        if let Some(prev) = self.init_state_stack.pop() {
+            self.in_variable_init = prev;
+        }
     }
-    fn expr_cast_end(&mut self) {}
 
     fn expr_deref_start(&mut self) {
         self.write("(*");
@@ -587,12 +718,38 @@ impl Target for CTarget {
         self.write(")");
     }
 
+    fn expr_ternary_start(&mut self) {
+        self.write("(");
+    }
     fn expr_ternary_mid1(&mut self) {
         self.write(" ? ");
     }
     fn expr_ternary_mid2(&mut self) {
         self.write(" : ");
     }
+    fn expr_ternary_end(&mut self) {
+        self.write(")");
+    }
+
+    fn set_location(&mut self, file: &str, line: usize) {
+        if self.calls.contains(&true) {
+            self.deferred_location = Some((file.to_string(), line));
+        } else if self.line_directives {
+            self.write_line_directive(line, file);
+            self.has_location = true;
+        }
+    }
+
+    fn clear_location(&mut self) {
+        if self.has_location {
+            if !self.output.ends_with('\n') {
+                self.push_output("\n");
+            }
+            let next_line = self.lines + 2;
+            self.write_line_directive(next_line, GENERATED_FILE);
+            self.has_location = false;
+        }
+    }
 
     fn expr_is_start(&mut self) {
         self.write("(");
@@ -3,3 +3,130 @@ extern int printf(const char *, ...);
 void print(unsigned char *fmt, long long val) {
     printf((char *)fmt, val);
 }
+
+static void __abyss_copy(void *dst, const void *src, unsigned long long n) {
+    unsigned char *d = dst;
+    const unsigned char *s = src;
+    while (n--) {
+        *d++ = *s++;
+    }
+}
+
+#ifdef ABYSS_DEBUG
+extern int fflush(void *);
+extern int snprintf(char *, unsigned long, const char *, ...);
+extern void abort(void);
+extern void abyss_panic(const char *file, int line, const char *msg);
+
+/* Debug-build checks. Each macro passes the location the `#line` directives
+ * give it, so a failure is reported against the Abyss source. */
+#define __abyss_check_bounds(...) __abyss_bounds_at(__VA_ARGS__, __FILE__, __LINE__)
+#define __abyss_check_range(...) __abyss_range_at(__VA_ARGS__, __FILE__, __LINE__)
+#define __abyss_check_null(...) __abyss_null_at(__VA_ARGS__, __FILE__, __LINE__)
+#define __abyss_check_zero(...) __abyss_zero_at(__VA_ARGS__, __FILE__, __LINE__)
+#define __abyss_check_add(...) __abyss_add_at(__VA_ARGS__, __FILE__, __LINE__)
+#define __abyss_check_sub(...) __abyss_sub_at(__VA_ARGS__, __FILE__, __LINE__)
+#define __abyss_check_mul(...) __abyss_mul_at(__VA_ARGS__, __FILE__, __LINE__)
+#define __abyss_check_div(...) __abyss_div_at(__VA_ARGS__, __FILE__, __LINE__)
+#define __abyss_check_rem(...) __abyss_rem_at(__VA_ARGS__, __FILE__, __LINE__)
+#define __abyss_check_neg(...) __abyss_neg_at(__VA_ARGS__, __FILE__, __LINE__)
+#define __abyss_check_tag(...) __abyss_tag_at(__VA_ARGS__, __FILE__, __LINE__)
+
+static void __abyss_fail(const char *file, int line, const char *msg) {
+    fflush(0);
+    abyss_panic(file, line, msg);
+    abort();
+}
+
+static long long __abyss_bounds_at(long long index, long long len, const char *file, int line) {
+    if (index < 0 || index >= len) {
+        char msg[96];
+        snprintf(msg, sizeof msg, "index out of bounds: the len is %lld but the index is %lld", len, index);
+        __abyss_fail(file, line, msg);
+    }
+    return index;
+}
+
+static long long __abyss_range_at(long long lo, long long hi, long long len, const char *file, int line) {
+    if (lo < 0 || lo > hi || hi > len) {
+        char msg[96];
+        snprintf(msg, sizeof msg, "range %lld..%lld out of bounds for a len of %lld", lo, hi, len);
+        __abyss_fail(file, line, msg);
+    }
+    return lo;
+}
+
+static void *__abyss_null_at(void *ptr, const char *file, int line) {
+    if (!ptr) {
+        __abyss_fail(file, line, "null pointer dereference");
+    }
+    return ptr;
+}
+
+static long long __abyss_zero_at(long long divisor, const char *file, int line) {
+    if (!divisor) {
+        __abyss_fail(file, line, "division by zero");
+    }
+    return divisor;
+}
+
+/* The result of a signed operation on `bits`-wide integers, which must fit. */
+static long long __abyss_fits(long long value, int wrapped, int bits, const char *op, const char *file, int line) {
+    long long max = bits >= 64 ? 0x7fffffffffffffffLL : (1LL << (bits - 1)) - 1;
+    if (wrapped || value > max || value < -max - 1) {
+        char msg[64];
+        snprintf(msg, sizeof msg, "attempt to %s with overflow (i%d)", op, bits);
+        __abyss_fail(file, line, msg);
+    }
+    return value;
+}
+
+static long long __abyss_add_at(long long a, long long b, int bits, const char *file, int line) {
+    long long sum = (long long)((unsigned long long)a + (unsigned long long)b);
+    int wrapped = (a < 0) == (b < 0) && (sum < 0) != (a < 0);
+    return __abyss_fits(sum, wrapped, bits, "add", file, line);
+}
+
+static long long __abyss_sub_at(long long a, long long b, int bits, const char *file, int line) {
+    long long diff = (long long)((unsigned long long)a - (unsigned long long)b);
+    int wrapped = (a < 0) != (b < 0) && (diff < 0) != (a < 0);
+    return __abyss_fits(diff, wrapped, bits, "subtract", file, line);
+}
+
+static long long __abyss_mul_at(long long a, long long b, int bits, const char *file, int line) {
+    long long product = (long long)((unsigned long long)a * (unsigned long long)b);
+    int wrapped = a != 0 && ((a == -1 && b == -0x7fffffffffffffffLL - 1) || product / a != b);
+    return __abyss_fits(product, wrapped, bits, "multiply", file, line);
+}
+
+/* Whether `a / b` and `a % b` overflow: only `MIN / -1` does. */
+static int __abyss_div_wraps(long long a, long long b, int bits) {
+    long long min = bits >= 64 ? -0x7fffffffffffffffLL - 1 : -(1LL << (bits - 1));
+    return a == min && b == -1;
+}
+
+static long long __abyss_div_at(long long a, long long b, int bits, const char *file, int line) {
+    __abyss_zero_at(b, file, line);
+    int wrapped = __abyss_div_wraps(a, b, bits);
+    return __abyss_fits(wrapped ? 0 : a / b, wrapped, bits, "divide", file, line);
+}
+
+static long long __abyss_rem_at(long long a, long long b, int bits, const char *file, int line) {
+    __abyss_zero_at(b, file, line);
+    int wrapped = __abyss_div_wraps(a, b, bits);
+    return __abyss_fits(wrapped ? 0 : a % b, wrapped, bits, "calculate the remainder", file, line);
+}
+
+static long long __abyss_neg_at(long long a, int bits, const char *file, int line) {
+    long long min = bits >= 64 ? -0x7fffffffffffffffLL - 1 : -(1LL << (bits - 1));
+    int wrapped = a == min;
+    return __abyss_fits(wrapped ? 0 : -a, wrapped, bits, "negate", file, line);
+}
+
+static void *__abyss_tag_at(void *value, long long tag, long long expected, const char *file, int line) {
+    if (tag != expected) {
+        __abyss_fail(file, line, "union accessed as a variant it does not hold");
+    }
+    return value;
+}
+#endif
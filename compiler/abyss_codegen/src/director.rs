@@ -1,12 +1,15 @@
 use std::collections::{HashMap, HashSet};
 
 use abyss_analyzer::lir::{
-    LirExpr, LirFunctionDef, LirLiteral, LirProgram, LirStmt, LirStructDef, LirType, LirUnionDef,
+    LirExpr, LirExprKind, LirFunctionDef, LirLiteral, LirProgram, LirStmt, LirStmtKind,
+    LirStructDef, LirType, LirUnionDef,
 };
-use abyss_parser::ast::UnaryOp;
+use abyss_parser::source_map::{FileId, SourceFiles, Span};
 
 use crate::target::Target;
 
+pub const ENTRY_POINT: &str = "app_main";
+
 #[derive(Clone)]
 enum Definition<'a> {
     Struct(&'a LirStructDef),
@@ -24,21 +27,54 @@ impl<'a> Definition<'a> {
 
 pub struct Director<'a, T: Target> {
     target: &'a mut T,
+    files: Option<&'a SourceFiles>,
+    /// The Abyss line the code being emitted is attributed to.
+    location: Option<(FileId, usize)>,
 }
 
 impl<'a, T: Target> Director<'a, T> {
     pub fn new(target: &'a mut T) -> Self {
-        Self { target }
+        Self {
+            target,
+            files: None,
+            location: None,
+        }
+    }
+
+    /// Resolves LIR spans against `files` so the target can record where
+    /// each function, statement and expression came from.
+    pub fn with_source_files(mut self, files: &'a SourceFiles) -> Self {
+        self.files = Some(files);
+        self
+    }
+
+    fn mark_location(&mut self, span: Span) {
+        if let Some((file, pos)) = self.files.and_then(|files| files.locate(&span)) {
+            self.target.set_location(&file.path, pos.line);
+            self.location = Some((span.file, pos.line));
+        }
+    }
+
+    /// Marks an expression that starts on another line than the code around
+    /// it, so a check inside a multi-line expression reports its own line.
+    fn follow_location(&mut self, span: Span) {
+        let Some((_, pos)) = self.files.and_then(|files| files.locate(&span)) else {
+            return;
+        };
+        if self.location.is_some() && self.location != Some((span.file, pos.line)) {
+            self.mark_location(span);
+        }
     }
 
     fn infer_array_type(&self, items: &[LirExpr]) -> Option<LirType> {
         if let Some(first) = items.first() {
-            match first {
-                LirExpr::Lit(LirLiteral::Int(_)) => Some(LirType::I64),
-                LirExpr::Lit(LirLiteral::Byte(_)) => Some(LirType::U8),
+            match &first.kind {
+                LirExprKind::Lit(LirLiteral::Int(_)) => Some(LirType::I64),
+                LirExprKind::Lit(LirLiteral::Byte(_)) => Some(LirType::U8),
 
-                LirExpr::Lit(LirLiteral::Float(_)) => Some(LirType::F64),
-                LirExpr::Lit(LirLiteral::Bool(_)) => Some(LirType::Bool),
+                LirExprKind::Lit(LirLiteral::Float(_)) => Some(LirType::F64),
+                LirExprKind::Lit(LirLiteral::Bool(_)) => Some(LirType::Bool),
+                LirExprKind::Cast(_, ty) => Some(ty.clone()),
                 _ => Some(LirType::I64),
             }
         } else {
@@ -74,15 +110,6 @@ impl<'a, T: Target> Director<'a, T> {
             }
         }
 
-        for glob in &program.globals {
-            self.target.define_global_start(&glob.name, &glob.ty, false);
-            if let Some(init_expr) = &glob.init_value {
-                self.target.define_global_init_start();
-                self.process_expr(init_expr);
-            }
-            self.target.define_global_end();
-        }
-
         for func in &program.functions {
             let ret_ty = &func.return_type;
             let params = self.get_func_params(func);
@@ -95,12 +122,32 @@ impl<'a, T: Target> Director<'a, T> {
             }
         }
 
+        // After the prototypes, so initializers can take function addresses.
+        for glob in &program.globals {
+            self.target
+                .define_global_start(&glob.name, &glob.ty, glob.is_const);
+            if let Some(init_expr) = &glob.init_value {
+                self.target.define_global_init_start();
+                self.process_expr(init_expr);
+            }
+            self.target.define_global_end();
+        }
+
         for func in &program.functions {
             if !func.is_extern {
                 self.compile_function(func);
             }
         }
 
+        if let Some(entry) = program
+            .functions
+            .iter()
+            .find(|f| f.name == ENTRY_POINT && !f.is_extern)
+        {
+            self.target
+                .define_entry_point(&entry.name, &entry.params, &entry.return_type);
+        }
+
         self.target.end_program();
     }
 
@@ -171,6 +218,14 @@ impl<'a, T: Target> Director<'a, T> {
                 LirType::Struct(n) => {
                     deps.push(n.clone());
                 }
+                // A struct first named in a function pointer's parameters
+                // would be a different type, scoped to that prototype.
+                LirType::FunctionPtr(args, ret) => {
+                    for arg in args {
+                        extract_type_name(arg, deps);
+                    }
+                    extract_type_name(ret, deps);
+                }
                 _ => {}
             }
         }
@@ -198,6 +253,7 @@ impl<'a, T: Target> Director<'a, T> {
         let ret_ty = &func.return_type;
         let params = self.get_func_params(func);
 
+        self.mark_location(func.span);
         self.target
             .begin_function(&func.name, &params, ret_ty, func.is_variadic);
 
@@ -206,13 +262,19 @@ impl<'a, T: Target> Director<'a, T> {
         }
 
         self.target.end_function();
+        self.target.clear_location();
+        self.location = None;
     }
 
     // --- Statement Processor ---
 
     fn process_stmt(&mut self, stmt: &LirStmt) {
-        match stmt {
-            LirStmt::Let(name, ty, init_opt) => {
+        if !matches!(stmt.kind, LirStmtKind::Block(_)) {
+            self.mark_location(stmt.span);
+        }
+
+        match &stmt.kind {
+            LirStmtKind::Let(name, ty, init_opt) => {
                 let has_init = init_opt.is_some();
 
                 self.target.stmt_var_decl(name, ty, has_init);
@@ -222,8 +284,8 @@ impl<'a, T: Target> Director<'a, T> {
                     self.target.stmt_var_init_end();
                 }
             }
-            LirStmt::Assign(lhs, rhs) => {
-                let is_ptr_assign = matches!(lhs, LirExpr::Deref(_));
+            LirStmtKind::Assign(lhs, rhs) => {
+                let is_ptr_assign = matches!(lhs.kind, LirExprKind::Deref(_));
 
                 self.process_expr(lhs);
 
@@ -231,21 +293,21 @@ impl<'a, T: Target> Director<'a, T> {
                 self.process_expr(rhs);
                 self.target.stmt_assign_end();
             }
-            LirStmt::ExprStmt(expr) => {
+            LirStmtKind::ExprStmt(expr) => {
                 self.process_expr(expr);
                 self.target.stmt_expr_end();
             }
-            LirStmt::Return(expr_opt) => {
+            LirStmtKind::Return(expr_opt) => {
                 self.target.stmt_return_start();
                 if let Some(expr) = expr_opt {
                     self.process_expr(expr);
                 }
                 self.target.stmt_return_end();
             }
-            LirStmt::Break => self.target.stmt_break(),
-            LirStmt::Continue => self.target.stmt_continue(),
+            LirStmtKind::Break => self.target.stmt_break(),
+            LirStmtKind::Continue => self.target.stmt_continue(),
 
-            LirStmt::Block(stmts) => {
+            LirStmtKind::Block(stmts) => {
                 self.target.begin_block();
                 for s in stmts {
                     self.process_stmt(s);
@@ -253,7 +315,7 @@ impl<'a, T: Target> Director<'a, T> {
                 self.target.end_block();
             }
 
-            LirStmt::If {
+            LirStmtKind::If {
                 cond,
                 then_branch,
                 else_branch,
@@ -274,7 +336,7 @@ impl<'a, T: Target> Director<'a, T> {
                 self.target.end_if();
             }
 
-            LirStmt::While { cond, body } => {
+            LirStmtKind::While { cond, body } => {
                 self.target.begin_while();
                 self.process_expr(cond);
                 self.target.begin_while_body();
@@ -284,7 +346,7 @@ impl<'a, T: Target> Director<'a, T> {
                 self.target.end_while();
             }
 
-            LirStmt::Switch {
+            LirStmtKind::Switch {
                 expr,
                 cases,
                 default,
@@ -317,11 +379,13 @@ impl<'a, T: Target> Director<'a, T> {
     // --- Expression Processor ---
 
     fn process_expr(&mut self, expr: &LirExpr) {
-        match expr {
-            LirExpr::Lit(l) => self.target.expr_lit(l),
-            LirExpr::Ident(n) => self.target.expr_ident(n),
+        self.follow_location(expr.span);
 
-            LirExpr::Binary(lhs, op, rhs) => {
+        match &expr.kind {
+            LirExprKind::Lit(l) => self.target.expr_lit(l),
+            LirExprKind::Ident(n) => self.target.expr_ident(n),
+
+            LirExprKind::Binary(lhs, op, rhs) => {
                 self.target.expr_binary_start(*op);
                 self.process_expr(lhs);
                 self.target.expr_binary_mid(*op);
@@ -329,13 +393,13 @@ impl<'a, T: Target> Director<'a, T> {
                 self.target.expr_binary_end();
             }
 
-            LirExpr::Unary(op, operand) => {
+            LirExprKind::Unary(op, operand) => {
                 self.target.expr_unary_start(*op);
                 self.process_expr(operand);
                 self.target.expr_unary_end();
             }
 
-            LirExpr::Call { func_name, args } => {
+            LirExprKind::Call { func_name, args } => {
                 self.target.expr_call_start(func_name);
                 for (i, arg) in args.iter().enumerate() {
                     if i > 0 {
@@ -346,59 +410,65 @@ impl<'a, T: Target> Director<'a, T> {
                 self.target.expr_call_end();
             }
 
-            LirExpr::CallPtr(func_expr, _) => {
-                self.target.expr_unary_start(UnaryOp::Not);
-                self.target.expr_deref_start();
+            LirExprKind::CallPtr(func_expr, args) => {
+                self.target.expr_call_ptr_start();
                 self.process_expr(func_expr);
-                self.target.expr_deref_end();
+                self.target.expr_call_ptr_args();
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        self.target.expr_call_arg_sep();
+                    }
+                    self.process_expr(arg);
+                }
+                self.target.expr_call_end();
             }
 
-            LirExpr::MemberAccess(obj, field) => {
+            LirExprKind::MemberAccess(obj, field) => {
                 self.process_expr(obj);
                 self.target.expr_member(field, false); // .field
             }
 
-            LirExpr::MemberAccessPtr(obj, field) => {
+            LirExprKind::MemberAccessPtr(obj, field) => {
                 self.process_expr(obj);
                 self.target.expr_member(field, true); // ->field
             }
 
-            LirExpr::Index(arr, idx) => {
+            LirExprKind::Index(arr, idx) => {
                 self.process_expr(arr);
                 self.target.expr_index_start();
                 self.process_expr(idx);
                 self.target.expr_index_end();
             }
 
-            LirExpr::AddrOf(inner) => {
+            LirExprKind::AddrOf(inner) => {
                 self.target.expr_addrof_start();
                 self.process_expr(inner);
                 self.target.expr_addrof_end();
             }
 
-            LirExpr::Deref(inner) => {
+            LirExprKind::Deref(inner) => {
                 self.target.expr_deref_start();
                 self.process_expr(inner);
                 self.target.expr_deref_end();
             }
 
-            LirExpr::Cast(inner, ty) => {
+            LirExprKind::Cast(inner, ty) => {
                 self.target.expr_cast_start(ty);
                 self.process_expr(inner);
                 self.target.expr_cast_end();
             }
 
-            LirExpr::Is(inner, ty) => {
+            LirExprKind::Is(inner, ty) => {
                 self.target.expr_is_start();
                 self.process_expr(inner);
                 self.target.expr_is_end(ty);
             }
 
-            LirExpr::SizeOf(ty) => {
+            LirExprKind::SizeOf(ty) => {
                 self.target.expr_sizeof(ty);
             }
 
-            LirExpr::StructInit {
+            LirExprKind::StructInit {
                 struct_name,
                 fields,
             } => {
@@ -415,7 +485,7 @@ impl<'a, T: Target> Director<'a, T> {
                 self.target.expr_struct_init_end();
             }
 
-            LirExpr::UnionInit {
+            LirExprKind::UnionInit {
                 union_name,
                 variants,
             } => {
@@ -429,14 +499,16 @@ impl<'a, T: Target> Director<'a, T> {
                 self.target.expr_union_init_end();
             }
 
-            LirExpr::Ternary(cond, then_expr, else_expr) => {
+            LirExprKind::Ternary(cond, then_expr, else_expr) => {
+                self.target.expr_ternary_start();
                 self.process_expr(cond);
                 self.target.expr_ternary_mid1();
                 self.process_expr(then_expr);
                 self.target.expr_ternary_mid2();
                 self.process_expr(else_expr);
+                self.target.expr_ternary_end();
             }
-            LirExpr::ArrayInit(items) => {
+            LirExprKind::ArrayInit(items) => {
                 let ty_hint = self.infer_array_type(items);
 
                 self.target.expr_array_init_start(ty_hint.as_ref());
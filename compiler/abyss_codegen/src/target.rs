@@ -9,6 +9,8 @@ pub trait Target {
     // ========================================================================
 
     fn start_program(&mut self);
+    /// Includes the runtime checks a debug build calls into the program.
+    fn set_debug(&mut self, enabled: bool);
     fn end_program(&mut self);
     fn define_struct(&mut self, name: &str, fields: &[(String, LirType)]);
     fn define_union(&mut self, name: &str, variants: &[(String, LirType)]);
@@ -50,6 +52,13 @@ pub trait Target {
     );
     fn end_function(&mut self);
 
+    fn define_entry_point(
+        &mut self,
+        name: &str,
+        params: &[(String, LirType)],
+        return_type: &LirType,
+    );
+
     // ========================================================================
     // 4. Statements
     // ========================================================================
@@ -125,6 +134,10 @@ pub trait Target {
     fn expr_call_start(&mut self, name: &str);
     fn expr_call_arg_sep(&mut self);
     fn expr_call_end(&mut self);
+    /// A call through a function pointer: `start`, the callee, `args`, then
+    /// the arguments as for a named call.
+    fn expr_call_ptr_start(&mut self);
+    fn expr_call_ptr_args(&mut self);
 
     fn expr_member(&mut self, field: &str, is_pointer: bool);
 
@@ -144,6 +157,17 @@ pub trait Target {
     fn expr_addrof_start(&mut self);
     fn expr_addrof_end(&mut self);
 
+    fn expr_ternary_start(&mut self);
     fn expr_ternary_mid1(&mut self);
     fn expr_ternary_mid2(&mut self);
+    fn expr_ternary_end(&mut self);
+
+    // ========================================================================
+    // 8. Source Locations
+    // ========================================================================
+
+    /// Attributes the code emitted next to `line` of the Abyss source `file`.
+    fn set_location(&mut self, file: &str, line: usize);
+    /// Attributes the code emitted next to the generated output itself again.
+    fn clear_location(&mut self);
 }
@@ -43,8 +43,12 @@ pub enum TokenKind {
     Let,     // let
     Const,   // const
     Static,  // static
+    Persist, // persist
     Struct,  // struct
+    Enum,    // enum
     Impl,    // impl
+    Trait,   // trait
+    Dyn,     // dyn
     Fn,      // fn
     Pub,     // pub
     Ret,     // ret
@@ -55,12 +59,15 @@ pub enum TokenKind {
     Forever, // forever
     Out,     // out
     Next,    // next
+    Defer,   // defer
+    Match,   // match
     In,      // in
     As,      // as
     Is,      // is
     And,     // and
     Or,      // or
     Not,     // not
+    Orelse,  // orelse
     True,    // true
     False,   // false
     I8,      // i8
@@ -94,6 +101,7 @@ pub enum TokenKind {
     LeftShift,  // <<
     RightShift, // >>
     Tilde,      // ~
+    Question,   // ?
     Comma,      // ,
     Colon,      // :
     ColonColon, // ::
@@ -126,8 +134,12 @@ impl TokenKind {
             "let" => TokenKind::Let,
             "const" => TokenKind::Const,
             "static" => TokenKind::Static,
+            "persist" => TokenKind::Persist,
             "struct" => TokenKind::Struct,
+            "enum" => TokenKind::Enum,
             "impl" => TokenKind::Impl,
+            "trait" => TokenKind::Trait,
+            "dyn" => TokenKind::Dyn,
             "fn" => TokenKind::Fn,
             "pub" => TokenKind::Pub,
             "ret" => TokenKind::Ret,
@@ -138,10 +150,13 @@ impl TokenKind {
             "forever" => TokenKind::Forever,
             "out" => TokenKind::Out,
             "next" => TokenKind::Next,
+            "defer" => TokenKind::Defer,
+            "match" => TokenKind::Match,
             "in" => TokenKind::In,
             "and" => TokenKind::And,
             "or" => TokenKind::Or,
             "not" => TokenKind::Not,
+            "orelse" => TokenKind::Orelse,
             "as" => TokenKind::As,
             "is" => TokenKind::Is,
             "true" => TokenKind::True,
@@ -187,6 +202,7 @@ impl TokenKind {
             "<<" => TokenKind::LeftShift,
             ">>" => TokenKind::RightShift,
             "~" => TokenKind::Tilde,
+            "?" => TokenKind::Question,
             "," => TokenKind::Comma,
             ":" => TokenKind::Colon,
             "::" => TokenKind::ColonColon,
@@ -225,8 +241,12 @@ impl Display for TokenKind {
             TokenKind::Let => write!(f, "'let'"),
             TokenKind::Const => write!(f, "'const'"),
             TokenKind::Static => write!(f, "'static'"),
+            TokenKind::Persist => write!(f, "'persist'"),
             TokenKind::Struct => write!(f, "'struct'"),
+            TokenKind::Enum => write!(f, "'enum'"),
             TokenKind::Impl => write!(f, "'impl'"),
+            TokenKind::Trait => write!(f, "'trait'"),
+            TokenKind::Dyn => write!(f, "'dyn'"),
             TokenKind::Fn => write!(f, "'fn'"),
             TokenKind::Pub => write!(f, "'pub'"),
             TokenKind::Ret => write!(f, "'ret'"),
@@ -237,12 +257,15 @@ impl Display for TokenKind {
             TokenKind::Forever => write!(f, "'forever'"),
             TokenKind::Out => write!(f, "'out'"),
             TokenKind::Next => write!(f, "'next'"),
+            TokenKind::Defer => write!(f, "'defer'"),
+            TokenKind::Match => write!(f, "'match'"),
             TokenKind::In => write!(f, "'in'"),
             TokenKind::As => write!(f, "'as'"),
             TokenKind::Is => write!(f, "'is'"),
             TokenKind::And => write!(f, "'and'"),
             TokenKind::Or => write!(f, "'or'"),
             TokenKind::Not => write!(f, "'not'"),
+            TokenKind::Orelse => write!(f, "'orelse'"),
             TokenKind::True => write!(f, "'true'"),
             TokenKind::False => write!(f, "'false'"),
             TokenKind::I64 => write!(f, "'i64'"),
@@ -276,6 +299,7 @@ impl Display for TokenKind {
             TokenKind::LeftShift => write!(f, "'<<'"),
             TokenKind::RightShift => write!(f, "'>>'"),
             TokenKind::Tilde => write!(f, "'~'"),
+            TokenKind::Question => write!(f, "'?'"),
             TokenKind::Comma => write!(f, "','"),
             TokenKind::Colon => write!(f, "':'"),
             TokenKind::ColonColon => write!(f, " '::'"),
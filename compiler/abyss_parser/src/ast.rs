@@ -1,7 +1,62 @@
+use std::fmt::{self, Display, Formatter};
+
+use crate::source_map::Span;
+
 pub type Path = Vec<String>;
+/// `T: Trait` bounds on generics, one pair per trait.
+pub type Bounds = Vec<(String, Path)>;
+
+#[derive(Debug, Clone)]
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+impl Stmt {
+    pub fn new(kind: StmtKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+
+    /// Whether control never reaches the statement after this one, as every
+    /// path through it ends in `ret`, `out` or `next`.
+    pub fn always_exits(&self) -> bool {
+        match &self.kind {
+            StmtKind::Ret(_) | StmtKind::Break | StmtKind::Continue => true,
+            StmtKind::Block(stmts) => stmts.iter().any(Stmt::always_exits),
+            StmtKind::If(_, then_branch, Some(else_branch)) => {
+                then_branch.always_exits() && else_branch.always_exits()
+            }
+            _ => false,
+        }
+    }
+
+    /// Whether an `out` in this statement leaves a loop enclosing it.
+    pub fn breaks_loop(&self) -> bool {
+        match &self.kind {
+            StmtKind::Break => true,
+            StmtKind::Block(stmts) => stmts.iter().any(Stmt::breaks_loop),
+            StmtKind::If(_, then_branch, else_branch) => {
+                then_branch.breaks_loop() || else_branch.as_ref().is_some_and(|e| e.breaks_loop())
+            }
+            StmtKind::Expr(expr) | StmtKind::Let(_, _, Some(expr)) | StmtKind::Ret(expr) => {
+                let value = match &expr.kind {
+                    ExprKind::Binary(_, BinaryOp::Assign, rhs) => rhs,
+                    _ => expr,
+                };
+                match &value.kind {
+                    ExprKind::Match(_, arms) => arms
+                        .iter()
+                        .any(|arm| arm.body.iter().any(Stmt::breaks_loop)),
+                    _ => false,
+                }
+            }
+            _ => false,
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
-pub enum Stmt {
+pub enum StmtKind {
     Mod(Path, Option<Box<Stmt>>),
     Use(Path),
     Let(String, Option<Type>, Option<Expr>),
@@ -13,6 +68,9 @@ pub enum Stmt {
     Ret(Expr),
     Break,    // out
     Continue, // next
+    /// `defer stmt`: runs when control leaves the enclosing block, after
+    /// the statements deferred later in it.
+    Defer(Box<Stmt>),
     Block(Vec<Stmt>),
     If(Expr, Box<Stmt>, Option<Box<Stmt>>),
     While(Expr, Box<Stmt>),
@@ -20,7 +78,32 @@ pub enum Stmt {
 }
 
 #[derive(Debug, Clone)]
-pub enum Expr {
+pub struct Expr {
+    pub kind: ExprKind,
+    pub span: Span,
+}
+
+impl Expr {
+    pub fn new(kind: ExprKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+
+    /// Whether the expression only reads storage, so evaluating it twice has
+    /// no side effects.
+    pub fn is_place(&self) -> bool {
+        match &self.kind {
+            ExprKind::Ident(_) => true,
+            ExprKind::Member(inner, _) | ExprKind::Deref(inner) => inner.is_place(),
+            ExprKind::Index(arr, idx) => {
+                arr.is_place() && matches!(idx.kind, ExprKind::Ident(_) | ExprKind::Lit(_))
+            }
+            _ => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum ExprKind {
     Lit(Lit),
     Ident(Path),
     Binary(Box<Expr>, BinaryOp, Box<Expr>),
@@ -36,8 +119,18 @@ pub enum Expr {
     UnionInit(Path, Vec<(String, Expr)>),
     MethodCall(Box<Expr>, String, Vec<Expr>, Vec<Type>),
     SizeOf(Type),
-    Match(Box<Expr>, Vec<(Pattern, Expr)>),
+    Match(Box<Expr>, Vec<MatchArm>),
     Ternary(Box<Expr>, Box<Expr>, Box<Expr>),
+    Closure(Box<Closure>),
+    /// `a..b` inside an index, `buf[a..b]`; either bound may be left out.
+    Range(Option<Box<Expr>>, Option<Box<Expr>>),
+    /// A built-in question about a type, answered at compile time.
+    Reflect(Reflect, Type),
+    /// `a orelse b`: the value an optional `a` holds, or `b` when it is null.
+    OrElse(Box<Expr>, Box<Expr>),
+    /// `a?`: the value a `T | Error` union holds, with the error returned
+    /// from the enclosing function instead.
+    Try(Box<Expr>),
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -64,6 +157,22 @@ pub enum Type {
     Generic(String),
     Function(Vec<Type>, Box<Type>, Vec<Type>), // Function(args, return_type, generics)
     Union(Vec<Type>),
+    /// `&dyn Trait`: a pointer to any implementor, paired with its vtable.
+    Dyn(Path),
+    /// `&fn(args): ret`: a closure, a function pointer paired with the
+    /// environment it captured.
+    Closure(Vec<Type>, Box<Type>),
+    /// `[f32; N]`: an array whose length is the `const` generic `N`, an
+    /// `Array` once monomorphized.
+    ConstArray(Box<Type>, String),
+    /// The value given for a `const` generic, the `4800` in `Delay<4800>`.
+    ConstArg(usize),
+    /// `[]f32`: a pointer paired with the number of elements it points to.
+    Slice(Box<Type>),
+    /// The variant of `?T`, `T | null`, that holds nothing.
+    Null,
+    /// `?&T`: a pointer that may be null, stored as the pointer itself.
+    Optional(Box<Type>),
 }
 
 impl Type {
@@ -87,14 +196,99 @@ impl Type {
             Type::Pointer(ty) => format!("ptr_{}", ty.get_name()),
             Type::Const(ty) => format!("const_{}", ty.get_name()),
             Type::Array(ty, size) => format!("Arr_{}_{}", ty.get_name(), size),
+            Type::ConstArray(ty, name) => format!("Arr_{}_{}", ty.get_name(), name),
+            Type::ConstArg(value) => value.to_string(),
+            Type::Slice(ty) => format!("Slice_{}", ty.get_name()),
+            Type::Null => "null".to_string(),
+            Type::Optional(ty) => format!("opt_{}", ty.get_name()),
             Type::Struct(path, _) => format!("struct_{}", path.join("_")),
             Type::Generic(name) => name.clone(),
+            Type::Dyn(path) => format!("dyn_{}", path.join("_")),
+            Type::Function(args, ret, _) => {
+                let args: String = args.iter().map(|a| format!("{}_", a.get_name())).collect();
+                format!("fn_{}ret_{}", args, ret.get_name())
+            }
+            Type::Closure(args, ret) => {
+                let args: String = args.iter().map(|a| format!("{}_", a.get_name())).collect();
+                format!("closure_{}ret_{}", args, ret.get_name())
+            }
 
             _ => panic!("Type has no Name"),
         }
     }
 }
 
+impl Type {
+    /// Replaces `Self` with `ty`, as in the signatures of an `impl` block.
+    pub fn replace_self(&mut self, ty: &Type) {
+        match self {
+            Type::Struct(path, generics) if generics.is_empty() && *path == ["Self"] => {
+                *self = ty.clone();
+            }
+            Type::Struct(_, generics) => {
+                for g in generics {
+                    g.replace_self(ty);
+                }
+            }
+            Type::Pointer(inner)
+            | Type::Const(inner)
+            | Type::Array(inner, _)
+            | Type::ConstArray(inner, _)
+            | Type::Slice(inner)
+            | Type::Optional(inner) => {
+                inner.replace_self(ty);
+            }
+            Type::Function(args, ret, _) | Type::Closure(args, ret) => {
+                for arg in args {
+                    arg.replace_self(ty);
+                }
+                ret.replace_self(ty);
+            }
+            Type::Union(types) => {
+                for t in types {
+                    t.replace_self(ty);
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Display for Type {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        fn join(types: &[Type], sep: &str) -> String {
+            types
+                .iter()
+                .map(|t| t.to_string())
+                .collect::<Vec<_>>()
+                .join(sep)
+        }
+
+        match self {
+            Type::Pointer(inner) => write!(f, "&{}", inner),
+            Type::Const(inner) => write!(f, "const {}", inner),
+            Type::Array(inner, size) => write!(f, "{}[{}]", inner, size),
+            Type::ConstArray(inner, name) => write!(f, "{}[{}]", inner, name),
+            Type::Slice(inner) => write!(f, "[]{}", inner),
+            Type::Optional(inner) => write!(f, "?{}", inner),
+            Type::Struct(path, generics) => {
+                write!(f, "{}", path.join("::"))?;
+                if !generics.is_empty() {
+                    write!(f, "<{}>", join(generics, ", "))?;
+                }
+                Ok(())
+            }
+            Type::Generic(name) => write!(f, "{}", name),
+            Type::Function(args, ret, _) => write!(f, "fn({}): {}", join(args, ", "), ret),
+            Type::Union(types) => write!(f, "{}", join(types, " | ")),
+            Type::Dyn(path) => write!(f, "&dyn {}", path.join("::")),
+            Type::Closure(args, ret) => write!(f, "&fn({}): {}", join(args, ", "), ret),
+            Type::Void => write!(f, "pass"),
+            _ => write!(f, "{}", self.get_name()),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum Lit {
     Int(i64),
@@ -136,22 +330,90 @@ pub enum UnaryOp {
     BitNot, // ~x
 }
 
+/// What a reflection built-in asks about its type.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reflect {
+    /// `type_name(T)`: `T` as Abyss spells it.
+    Name,
+    /// `type_id(T)`: the tag a union holding a `T` carries.
+    Id,
+    /// `field_count(T)`: how many fields the struct `T` has.
+    FieldCount,
+    /// `offset(T, field)`: the byte offset of `field` within the struct `T`.
+    Offset(String),
+}
+
+impl Reflect {
+    /// Names of the built-ins, which take a type where a call takes values.
+    pub const BUILTINS: [&str; 4] = ["type_name", "type_id", "field_count", "offset"];
+}
+
 #[derive(Debug, Clone)]
 pub enum Pattern {
     Lit(Lit),
-    Variant(Path, Vec<String>),
+    /// A variant of a union, named by its type, with the payload's binding.
+    Variant(Type, Vec<String>),
     Wildcard,
 }
 
+/// `fn[captures](params): ret { body }`. Without a capture list, the outer
+/// locals the body names are captured by value.
+#[derive(Debug, Clone)]
+pub struct Closure {
+    pub captures: Option<Vec<Capture>>,
+    pub params: Vec<(String, Type)>,
+    pub return_type: Type,
+    pub body: Vec<Stmt>,
+}
+
+/// `name` copies the local into the closure, `&name` refers to it in place.
+#[derive(Debug, Clone)]
+pub struct Capture {
+    pub name: String,
+    pub by_ref: bool,
+    pub span: Span,
+}
+
+/// `pattern => body`. An arm written as a single expression has it as its
+/// only statement; the arm's value is its trailing expression statement.
+#[derive(Debug, Clone)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub body: Vec<Stmt>,
+    pub span: Span,
+}
+
+/// A generic parameter: a type `T`, or an integer constant `const N: usize`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GenericParam {
+    pub name: String,
+    pub const_type: Option<Type>,
+}
+
+impl GenericParam {
+    pub fn new(name: String) -> Self {
+        Self {
+            name,
+            const_type: None,
+        }
+    }
+
+    pub fn is_const(&self) -> bool {
+        self.const_type.is_some()
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct FunctionDef {
     pub is_pub: bool,
     pub name: String,
-    pub generics: Vec<String>,
+    pub generics: Vec<GenericParam>,
+    pub bounds: Bounds,
     pub params: Vec<(String, Type)>,
     pub return_type: Type,
     pub body: FunctionBody,
     pub is_variadic: bool,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
@@ -164,8 +426,9 @@ pub enum FunctionBody {
 pub struct StructDef {
     pub is_pub: bool,
     pub name: String,
-    pub generics: Vec<String>,
+    pub generics: Vec<GenericParam>,
     pub fields: Vec<(String, Type)>,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
@@ -175,13 +438,64 @@ pub struct UnionDef {
     pub fields: Vec<(String, Type)>,
 }
 
+/// `trait Name { fn method(self: &Self, ...): T }`. Methods are signatures
+/// only; `Self` stands for the implementing type.
+#[derive(Debug, Clone)]
+pub struct TraitDef {
+    pub is_pub: bool,
+    pub name: String,
+    pub methods: Vec<FunctionDef>,
+    pub span: Span,
+}
+
+/// `impl Trait for Type { ... }`. The methods themselves are parsed as those
+/// of a plain `impl Type` block; this records which trait they implement.
+#[derive(Debug, Clone)]
+pub struct TraitImpl {
+    pub trait_path: Path,
+    pub ty: Type,
+    pub methods: Vec<String>,
+    pub span: Span,
+}
+
+/// `enum Name { A, B = 7, C(u8, f64) }`. Without any payload the enum is a
+/// plain integer; otherwise it is a tagged struct over its variants' payloads.
+#[derive(Debug, Clone)]
+pub struct EnumDef {
+    pub is_pub: bool,
+    /// Declared `error Name { .. }`: its values are errors, which `?`
+    /// returns from the enclosing function.
+    pub is_error: bool,
+    pub name: String,
+    pub variants: Vec<EnumVariant>,
+    pub span: Span,
+}
+
+impl EnumDef {
+    pub fn has_payload(&self) -> bool {
+        self.variants.iter().any(|v| !v.payload.is_empty())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct EnumVariant {
+    pub name: String,
+    pub payload: Vec<Type>,
+    /// The explicit `= n`, or one past the previous variant's value as in C.
+    pub value: i64,
+    pub span: Span,
+}
+
 #[derive(Debug, Clone)]
 pub struct StaticDef {
     pub is_pub: bool,
+    /// Declared `persist static`: its value survives a hot reload.
+    pub is_persistent: bool,
     pub name: String,
     pub generics: Vec<String>,
     pub ty: Type,
     pub value: Expr,
+    pub span: Span,
 }
 
 #[derive(Debug, Clone)]
@@ -190,7 +504,10 @@ pub struct Program {
 
     pub structs: Vec<StructDef>,
     pub unions: Vec<UnionDef>,
+    pub enums: Vec<EnumDef>,
+    pub traits: Vec<TraitDef>,
+    pub trait_impls: Vec<TraitImpl>,
     pub functions: Vec<FunctionDef>,
     pub statics: Vec<StaticDef>,
-    pub uses: Vec<Path>,
+    pub uses: Vec<(Path, Span)>,
 }
@@ -0,0 +1,182 @@
+use colored::Colorize;
+use std::fmt::Write;
+
+use crate::{
+    error::ParseError,
+    source_map::{SourceFiles, Span},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+#[derive(Debug, Clone)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Option<Span>,
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: impl Into<String>) -> Self {
+        Self {
+            severity,
+            message: message.into(),
+            span: None,
+            labels: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Self {
+        Self::new(Severity::Error, message)
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self::new(Severity::Warning, message)
+    }
+
+    pub fn with_span(mut self, span: Span) -> Self {
+        self.span = Some(span);
+        self
+    }
+
+    pub fn with_label(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    pub fn render(&self, files: &SourceFiles) -> String {
+        let mut output = String::new();
+
+        let header = match self.severity {
+            Severity::Error => "error".bright_red().bold(),
+            Severity::Warning => "warning".yellow().bold(),
+        };
+        let _ = writeln!(&mut output, "{}: {}", header, self.message.bold());
+
+        let gutter_width = std::iter::once(self.span)
+            .flatten()
+            .chain(self.labels.iter().map(|l| l.span))
+            .filter_map(|span| files.locate(&span))
+            .map(|(_, pos)| pos.line.to_string().len())
+            .max()
+            .unwrap_or(1);
+        let padding = " ".repeat(gutter_width);
+
+        if let Some(span) = self.span {
+            Self::render_snippet(&mut output, files, span, "", true);
+        }
+
+        for label in &self.labels {
+            Self::render_snippet(&mut output, files, label.span, &label.message, false);
+        }
+
+        for note in &self.notes {
+            let _ = writeln!(
+                &mut output,
+                "{} {} note: {}",
+                padding,
+                "=".blue().bold(),
+                note
+            );
+        }
+
+        let _ = writeln!(&mut output);
+
+        output
+    }
+
+    fn render_snippet(
+        output: &mut String,
+        files: &SourceFiles,
+        span: Span,
+        message: &str,
+        is_primary: bool,
+    ) {
+        let Some((file, pos)) = files.locate(&span) else {
+            return;
+        };
+
+        let line_content = file.line(pos.line);
+
+        let line_num_str = pos.line.to_string();
+        let padding = " ".repeat(line_num_str.len());
+
+        if is_primary {
+            let _ = writeln!(
+                output,
+                "{} {}{}:{}:{}",
+                padding,
+                "-->".blue().bold(),
+                file.path,
+                pos.line,
+                pos.column
+            );
+        }
+
+        let _ = writeln!(output, "{} {}", padding, "|".blue().bold());
+
+        let _ = writeln!(
+            output,
+            "{} {} {}",
+            line_num_str.blue().bold(),
+            "|".blue().bold(),
+            line_content
+        );
+
+        let col_padding = if pos.column > 0 {
+            " ".repeat(pos.column - 1)
+        } else {
+            String::new()
+        };
+
+        let caret_len = if span.len() > 0 { span.len() } else { 1 };
+        let underline = if is_primary {
+            "^".repeat(caret_len).bright_red().bold()
+        } else {
+            "-".repeat(caret_len).blue().bold()
+        };
+
+        let _ = write!(
+            output,
+            "{} {} {}{}",
+            padding,
+            "|".blue().bold(),
+            col_padding,
+            underline
+        );
+        if !message.is_empty() {
+            let _ = write!(output, " {}", message);
+        }
+        let _ = writeln!(output);
+    }
+}
+
+impl From<&ParseError> for Diagnostic {
+    fn from(error: &ParseError) -> Self {
+        Diagnostic::error(error.message.clone()).with_span(error.pos)
+    }
+}
@@ -1,4 +1,5 @@
 pub mod ast;
+pub mod diagnostic;
 pub mod error;
 pub mod parser;
 pub mod source_map;
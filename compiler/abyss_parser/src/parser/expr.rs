@@ -3,7 +3,9 @@ use std::str::FromStr;
 use abyss_lexer::token::{LiteralKind, TokenKind};
 
 use crate::{
-    ast::{BinaryOp, Expr, Lit, Type, UnaryOp},
+    ast::{
+        BinaryOp, Expr, ExprKind, Lit, MatchArm, Pattern, Reflect, Stmt, StmtKind, Type, UnaryOp,
+    },
     error::ParseErrorKind,
     parser::Parser,
 };
@@ -13,18 +15,19 @@ use crate::{
 enum Precedence {
     _None = 0,
     Assignment = 1,
-    Or = 2,
-    And = 3,
-    BitwiseOr = 4,
-    BitwiseXor = 5,
-    BitwiseAnd = 6,
-    Equality = 7,
-    Comparison = 8,
-    Shift = 9,
-    Term = 10,
-    Factor = 11,
-    Unary = 12,
-    Call = 13,
+    OrElse = 2,
+    Or = 3,
+    And = 4,
+    BitwiseOr = 5,
+    BitwiseXor = 6,
+    BitwiseAnd = 7,
+    Equality = 8,
+    Comparison = 9,
+    Shift = 10,
+    Term = 11,
+    Factor = 12,
+    Unary = 13,
+    Call = 14,
 }
 
 impl Precedence {
@@ -64,7 +67,13 @@ impl<'a> Parser<'a> {
             && !self.stream.is(TokenKind::RightShift)
             && !self.stream.is_at_end()
         {
-            args.push(self.parse_type()?);
+            if let TokenKind::Literal(LiteralKind::Int) = self.stream.current().kind {
+                let value = self.parse_current_lit::<usize>()?;
+                self.advance();
+                args.push(Type::ConstArg(value));
+            } else {
+                args.push(self.parse_type()?);
+            }
 
             if self.stream.is(TokenKind::Comma) {
                 self.advance();
@@ -123,10 +132,19 @@ impl<'a> Parser<'a> {
                     break;
                 }
 
+                if current_kind == TokenKind::Orelse {
+                    self.advance();
+                    let rhs = self.parse_expr_bp(infix_prec.get_next_power())?;
+                    let span = lhs.span.to(rhs.span);
+                    lhs = Expr::new(ExprKind::OrElse(Box::new(lhs), Box::new(rhs)), span);
+                    continue;
+                }
+
                 if current_kind == TokenKind::Is {
                     self.advance();
                     let target_type = self.parse_type()?;
-                    lhs = Expr::Is(Box::new(lhs), target_type);
+                    let span = self.span_from(lhs.span.start);
+                    lhs = Expr::new(ExprKind::Is(Box::new(lhs), target_type), span);
                     continue;
                 }
 
@@ -140,7 +158,8 @@ impl<'a> Parser<'a> {
                     None => break,
                 };
 
-                lhs = Expr::Binary(Box::new(lhs), op, Box::new(rhs));
+                let span = lhs.span.to(rhs.span);
+                lhs = Expr::new(ExprKind::Binary(Box::new(lhs), op, Box::new(rhs)), span);
             } else {
                 break;
             }
@@ -151,39 +170,46 @@ impl<'a> Parser<'a> {
 
     fn parse_prefix(&mut self) -> Option<Expr> {
         self.skip_newlines();
+        let start = self.start();
+        let kind = self.parse_prefix_kind(start)?;
+        Some(Expr::new(kind, self.span_from(start)))
+    }
+
+    fn parse_prefix_kind(&mut self, start: usize) -> Option<ExprKind> {
         let token_kind = self.stream.current().kind;
 
         match token_kind {
             TokenKind::Literal(LiteralKind::Int) => {
                 let val = self.parse_current_lit::<i64>()?;
                 self.advance();
-                Some(Expr::Lit(Lit::Int(val)))
+                Some(ExprKind::Lit(Lit::Int(val)))
             }
             TokenKind::Literal(LiteralKind::Float) => {
                 let val = self.parse_current_lit::<f64>()?;
                 self.advance();
-                Some(Expr::Lit(Lit::Float(val)))
+                Some(ExprKind::Lit(Lit::Float(val)))
             }
             TokenKind::Literal(LiteralKind::Str) => {
                 let val = self.stream.current_lit().to_string();
                 self.advance();
-                Some(Expr::Lit(Lit::Str(val)))
+                Some(ExprKind::Lit(Lit::Str(val)))
             }
             TokenKind::True => {
                 self.advance();
-                Some(Expr::Lit(Lit::Bool(true)))
+                Some(ExprKind::Lit(Lit::Bool(true)))
             }
             TokenKind::False => {
                 self.advance();
-                Some(Expr::Lit(Lit::Bool(false)))
+                Some(ExprKind::Lit(Lit::Bool(false)))
             }
             TokenKind::Null => {
                 self.advance();
-                Some(Expr::Lit(Lit::Null))
+                Some(ExprKind::Lit(Lit::Null))
             }
 
             TokenKind::Ident => {
                 let path = self.parse_path()?;
+                let path_span = self.span_from(start);
 
                 let generics = if self.stream.is(TokenKind::ColonColon) {
                     if self.stream.is_peek(TokenKind::Lt) {
@@ -212,7 +238,11 @@ impl<'a> Parser<'a> {
                             }
                         }
                         self.consume(TokenKind::CParen)?;
-                        return Some(Expr::Call(Box::new(Expr::Ident(path)), args, generics));
+                        return Some(ExprKind::Call(
+                            Box::new(Expr::new(ExprKind::Ident(path), path_span)),
+                            args,
+                            generics,
+                        ));
                     } else {
                         if self.stream.is(TokenKind::OBrace) {
                             return self.parse_struct_literal(path, generics);
@@ -226,6 +256,13 @@ impl<'a> Parser<'a> {
                     }
                 }
 
+                if self.stream.is(TokenKind::OParen)
+                    && path.len() == 1
+                    && Reflect::BUILTINS.contains(&path[0].as_str())
+                {
+                    return self.parse_reflect(&path[0]);
+                }
+
                 if self.stream.is(TokenKind::OParen) {
                     self.advance();
                     let mut args = Vec::new();
@@ -238,29 +275,42 @@ impl<'a> Parser<'a> {
                         }
                     }
                     self.consume(TokenKind::CParen)?;
-                    return Some(Expr::Call(Box::new(Expr::Ident(path)), args, Vec::new()));
+                    return Some(ExprKind::Call(
+                        Box::new(Expr::new(ExprKind::Ident(path), path_span)),
+                        args,
+                        Vec::new(),
+                    ));
                 }
 
-                Some(Expr::Ident(path))
+                Some(ExprKind::Ident(path))
             }
 
             TokenKind::OParen => {
                 self.advance();
                 let expr = self.parse_expr()?;
                 self.consume(TokenKind::CParen)?;
-                Some(expr)
+                Some(expr.kind)
             }
             TokenKind::OBracket => {
                 self.advance();
                 self.parse_array_literal()
             }
 
+            TokenKind::Match => {
+                self.advance();
+                self.parse_match()
+            }
+            TokenKind::Fn => {
+                self.advance();
+                self.parse_closure()
+            }
+
             TokenKind::Size => {
                 self.advance();
                 self.consume(TokenKind::OParen)?;
                 let target_type = self.parse_type()?;
                 self.consume(TokenKind::CParen)?;
-                Some(Expr::SizeOf(target_type))
+                Some(ExprKind::SizeOf(target_type))
             }
 
             TokenKind::Minus
@@ -274,13 +324,13 @@ impl<'a> Parser<'a> {
                     TokenKind::Tilde => UnaryOp::BitNot,
                     TokenKind::Star => {
                         self.advance();
-                        return Some(Expr::Deref(Box::new(
+                        return Some(ExprKind::Deref(Box::new(
                             self.parse_expr_bp(Precedence::Unary as u8)?,
                         )));
                     }
                     TokenKind::Amp => {
                         self.advance();
-                        return Some(Expr::AddrOf(Box::new(
+                        return Some(ExprKind::AddrOf(Box::new(
                             self.parse_expr_bp(Precedence::Unary as u8)?,
                         )));
                     }
@@ -288,7 +338,7 @@ impl<'a> Parser<'a> {
                 };
                 self.advance();
                 let rhs = self.parse_expr_bp(Precedence::Unary as u8)?;
-                Some(Expr::Unary(op, Box::new(rhs)))
+                Some(ExprKind::Unary(op, Box::new(rhs)))
             }
 
             TokenKind::Struct => {
@@ -326,17 +376,29 @@ impl<'a> Parser<'a> {
 
     fn get_postfix_binding_power(&self, kind: TokenKind) -> Option<Precedence> {
         Some(match kind {
-            TokenKind::OParen | TokenKind::OBracket | TokenKind::As | TokenKind::Dot => {
-                Precedence::Call
-            }
+            TokenKind::OParen
+            | TokenKind::OBracket
+            | TokenKind::As
+            | TokenKind::Dot
+            | TokenKind::Question => Precedence::Call,
             _ => return None,
         })
     }
 
     fn parse_postfix(&mut self, lhs: Expr) -> Option<Expr> {
+        let start = lhs.span.start;
+        let kind = self.parse_postfix_kind(lhs)?;
+        Some(Expr::new(kind, self.span_from(start)))
+    }
+
+    fn parse_postfix_kind(&mut self, lhs: Expr) -> Option<ExprKind> {
         let token_kind = self.stream.current().kind;
 
         match token_kind {
+            TokenKind::Question => {
+                self.advance();
+                Some(ExprKind::Try(Box::new(lhs)))
+            }
             TokenKind::Dot => {
                 self.advance();
 
@@ -371,7 +433,7 @@ impl<'a> Parser<'a> {
                     }
                     self.consume(TokenKind::CParen)?;
 
-                    Some(Expr::MethodCall(Box::new(lhs), name, args, generics))
+                    Some(ExprKind::MethodCall(Box::new(lhs), name, args, generics))
                 } else {
                     if !generics.is_empty() {
                         self.emit_error_at_current(ParseErrorKind::Message(
@@ -379,7 +441,7 @@ impl<'a> Parser<'a> {
                                   ));
                         return None;
                     }
-                    Some(Expr::Member(Box::new(lhs), name))
+                    Some(ExprKind::Member(Box::new(lhs), name))
                 }
             }
 
@@ -395,29 +457,78 @@ impl<'a> Parser<'a> {
                     }
                 }
                 self.consume(TokenKind::CParen)?;
-                Some(Expr::Call(Box::new(lhs), args, Vec::new()))
+                Some(ExprKind::Call(Box::new(lhs), args, Vec::new()))
             }
             TokenKind::OBracket => {
                 self.advance();
-                let index_expr = self.parse_expr()?;
+                let index_expr = self.parse_index()?;
                 self.consume(TokenKind::CBracket)?;
-                Some(Expr::Index(Box::new(lhs), Box::new(index_expr)))
+                Some(ExprKind::Index(Box::new(lhs), Box::new(index_expr)))
             }
             TokenKind::As => {
                 self.advance();
                 let target_type = self.parse_type()?;
-                Some(Expr::Cast(Box::new(lhs), target_type))
+                Some(ExprKind::Cast(Box::new(lhs), target_type))
             }
 
             _ => unreachable!(),
         }
     }
 
-    fn parse_array_literal(&mut self) -> Option<Expr> {
+    /// The parenthesized arguments of the reflection built-in `name`: a type,
+    /// then a field name for `offset`.
+    fn parse_reflect(&mut self, name: &str) -> Option<ExprKind> {
+        self.consume(TokenKind::OParen)?;
+        let ty = self.parse_type()?;
+        let query = match name {
+            "type_name" => Reflect::Name,
+            "type_id" => Reflect::Id,
+            "field_count" => Reflect::FieldCount,
+            _ => {
+                self.consume(TokenKind::Comma)?;
+                if !self.is(TokenKind::Ident) {
+                    self.emit_error_at_current(ParseErrorKind::Expected(
+                        "field name after ','".to_string(),
+                    ));
+                    return None;
+                }
+                let field = self.stream.current_lit().to_string();
+                self.advance();
+                Reflect::Offset(field)
+            }
+        };
+        self.consume(TokenKind::CParen)?;
+        Some(ExprKind::Reflect(query, ty))
+    }
+
+    /// The index of `buf[i]`, or the range of `buf[a..b]`, `buf[a..]`,
+    /// `buf[..b]` or `buf[..]`.
+    fn parse_index(&mut self) -> Option<Expr> {
+        let start = self.start();
+        let lo = if self.is(TokenKind::DotDot) {
+            None
+        } else {
+            let index = self.parse_expr()?;
+            if !self.is(TokenKind::DotDot) {
+                return Some(index);
+            }
+            Some(Box::new(index))
+        };
+        self.advance();
+
+        let hi = if self.is(TokenKind::CBracket) {
+            None
+        } else {
+            Some(Box::new(self.parse_expr()?))
+        };
+        Some(Expr::new(ExprKind::Range(lo, hi), self.span_from(start)))
+    }
+
+    fn parse_array_literal(&mut self) -> Option<ExprKind> {
         let mut elements = Vec::new();
         if self.is(TokenKind::CBracket) {
             self.advance();
-            return Some(Expr::Lit(Lit::Array(elements)));
+            return Some(ExprKind::Lit(Lit::Array(elements)));
         }
 
         loop {
@@ -427,7 +538,86 @@ impl<'a> Parser<'a> {
             }
         }
         self.consume(TokenKind::CBracket)?;
-        Some(Expr::Lit(Lit::Array(elements)))
+        Some(ExprKind::Lit(Lit::Array(elements)))
+    }
+
+    fn parse_match(&mut self) -> Option<ExprKind> {
+        let scrutinee = self.parse_expr()?;
+        self.consume(TokenKind::OBrace)?;
+
+        let mut arms = Vec::new();
+        loop {
+            self.skip_newlines();
+            if self.stream.is(TokenKind::CBrace) || self.stream.is_at_end() {
+                break;
+            }
+
+            let start = self.start();
+            let pattern = self.parse_pattern()?;
+            self.consume(TokenKind::REqArrow)?;
+
+            let body = if self.stream.is(TokenKind::OBrace) {
+                self.parse_block()?
+            } else {
+                let expr = self.parse_expr()?;
+                let span = expr.span;
+                vec![Stmt::new(StmtKind::Expr(expr), span)]
+            };
+
+            arms.push(MatchArm {
+                pattern,
+                body,
+                span: self.span_from(start),
+            });
+            self.optional(TokenKind::Comma);
+        }
+
+        self.consume(TokenKind::CBrace)?;
+        Some(ExprKind::Match(Box::new(scrutinee), arms))
+    }
+
+    fn parse_pattern(&mut self) -> Option<Pattern> {
+        let lit = match self.stream.current().kind {
+            TokenKind::Ident if self.stream.current_lit() == "_" => {
+                self.advance();
+                return Some(Pattern::Wildcard);
+            }
+            TokenKind::Minus => {
+                self.advance();
+                if !self.stream.is(TokenKind::Literal(LiteralKind::Int)) {
+                    self.emit_error_at_current(ParseErrorKind::Expected(
+                        "integer after '-' in pattern".to_string(),
+                    ));
+                    return None;
+                }
+                Lit::Int(-self.parse_current_lit::<i64>()?)
+            }
+            TokenKind::Literal(LiteralKind::Int) => Lit::Int(self.parse_current_lit::<i64>()?),
+            TokenKind::Literal(LiteralKind::Float) => Lit::Float(self.parse_current_lit::<f64>()?),
+            TokenKind::Literal(LiteralKind::Str) => Lit::Str(self.stream.current_lit().to_string()),
+            TokenKind::True => Lit::Bool(true),
+            TokenKind::False => Lit::Bool(false),
+            _ => {
+                let ty = self.parse_unary_type()?;
+                let mut bindings = Vec::new();
+
+                if self.stream.consume(TokenKind::OParen) {
+                    while self.stream.is(TokenKind::Ident) {
+                        bindings.push(self.stream.current_lit().to_string());
+                        self.advance();
+                        if !self.stream.consume(TokenKind::Comma) {
+                            break;
+                        }
+                    }
+                    self.consume(TokenKind::CParen)?;
+                }
+
+                return Some(Pattern::Variant(ty, bindings));
+            }
+        };
+
+        self.advance();
+        Some(Pattern::Lit(lit))
     }
 
     fn parse_current_lit<T: FromStr>(&mut self) -> Option<T> {
@@ -438,6 +628,7 @@ impl<'a> Parser<'a> {
     fn get_infix_binding_power(&self, kind: TokenKind) -> Option<Precedence> {
         Some(match kind {
             TokenKind::Assign => Precedence::Assignment,
+            TokenKind::Orelse => Precedence::OrElse,
             TokenKind::Or => Precedence::Or,
             TokenKind::And => Precedence::And,
             TokenKind::Pipe => Precedence::BitwiseOr,
@@ -480,19 +671,43 @@ impl<'a> Parser<'a> {
     }
 
     fn parse_unary_type(&mut self) -> Option<Type> {
+        // `?&T` is a nullable pointer; any other `?T` is the union `T | null`.
+        if self.stream.consume(TokenKind::Question) {
+            return Some(match self.parse_unary_type()? {
+                pointer @ Type::Pointer(_) => Type::Optional(Box::new(pointer)),
+                inner => Type::Union(vec![inner, Type::Null]),
+            });
+        }
+
         if self.stream.is(TokenKind::Const) {
             self.advance();
             let inner_type = self.parse_unary_type()?;
             return Some(Type::Const(Box::new(inner_type)));
         }
 
-        if self.stream.is(TokenKind::Amp) {
+        if self.stream.is(TokenKind::Amp)
+            && !self.stream.is_peek(TokenKind::Dyn)
+            && !self.stream.is_peek(TokenKind::Fn)
+        {
             self.advance();
             let inner_type = self.parse_unary_type()?;
             return Some(Type::Pointer(Box::new(inner_type)));
         }
 
-        let mut base_type = if self.stream.consume(TokenKind::U8) {
+        let mut base_type = if self.stream.is(TokenKind::Amp) && self.stream.is_peek(TokenKind::Fn)
+        {
+            self.advance();
+            return self.parse_function_type(true);
+        } else if self.stream.consume(TokenKind::Amp) {
+            self.advance();
+            let Some(path) = self.parse_path() else {
+                self.emit_error_at_current(ParseErrorKind::Expected("trait name".to_string()));
+                return None;
+            };
+            Type::Dyn(path)
+        } else if self.stream.is(TokenKind::Fn) {
+            return self.parse_function_type(false);
+        } else if self.stream.consume(TokenKind::U8) {
             Type::U8
         } else if self.stream.consume(TokenKind::U16) {
             Type::U16
@@ -522,10 +737,43 @@ impl<'a> Parser<'a> {
             Type::Void
         } else if self.stream.consume(TokenKind::Char) {
             Type::Char
+        } else if self.stream.consume(TokenKind::Null) {
+            Type::Null
         } else if self.stream.is(TokenKind::Ident) {
             let path = self.parse_path()?;
             let generics = self.parse_generic_args()?;
             Type::Struct(path, generics)
+        } else if self.stream.is(TokenKind::OBracket) && self.stream.is_peek(TokenKind::CBracket) {
+            self.advance();
+            self.advance();
+            Type::Slice(Box::new(self.parse_unary_type()?))
+        } else if self.stream.consume(TokenKind::OBracket) {
+            // `[f32; 64]`, the same type as `f32[64]`.
+            let elem = self.parse_type()?;
+            if !self.stream.consume(TokenKind::Semi) {
+                self.emit_error_at_current(ParseErrorKind::Expected("';'".to_string()));
+                return None;
+            }
+            let array = match self.stream.current().kind {
+                TokenKind::Literal(LiteralKind::Int) => self
+                    .parse_current_lit::<usize>()
+                    .map(|size| Type::Array(Box::new(elem), size)),
+                TokenKind::Ident => Some(Type::ConstArray(
+                    Box::new(elem),
+                    self.stream.current_lit().to_string(),
+                )),
+                _ => None,
+            };
+            let Some(array) = array else {
+                self.emit_error_at_current(ParseErrorKind::Expected("Array size".to_string()));
+                return None;
+            };
+            self.advance();
+            if !self.stream.consume(TokenKind::CBracket) {
+                self.emit_error_at_current(ParseErrorKind::Expected("']'".to_string()));
+                return None;
+            }
+            array
         } else {
             self.emit_error_at_current(ParseErrorKind::Expected("type name".to_string()));
             return None;
@@ -545,6 +793,13 @@ impl<'a> Parser<'a> {
                             continue;
                         }
                     }
+                } else if self.stream.is(TokenKind::Ident) {
+                    let name = self.stream.current_lit().to_string();
+                    self.advance();
+                    if self.stream.consume(TokenKind::CBracket) {
+                        base_type = Type::ConstArray(Box::new(base_type), name);
+                        continue;
+                    }
                 }
                 self.emit_error_at_current(ParseErrorKind::Expected("Array size".to_string()));
                 return None;
@@ -556,6 +811,50 @@ impl<'a> Parser<'a> {
         Some(base_type)
     }
 
+    /// `fn(i64, f64): f64`, a function pointer, or after a `&` a closure. A
+    /// trailing `[N]` belongs to the pointer, not the return type, so
+    /// `fn(i64): i64[4]` is a table of four.
+    fn parse_function_type(&mut self, is_closure: bool) -> Option<Type> {
+        self.advance();
+        if !self.stream.consume(TokenKind::OParen) {
+            self.emit_error_at_current(ParseErrorKind::Expected("'(' after fn".to_string()));
+            return None;
+        }
+
+        let mut args = Vec::new();
+        while !self.stream.is(TokenKind::CParen) && !self.stream.is_at_end() {
+            args.push(self.parse_type()?);
+            if !self.stream.consume(TokenKind::Comma) {
+                break;
+            }
+        }
+        if !self.stream.consume(TokenKind::CParen) {
+            self.emit_error_at_current(ParseErrorKind::Expected("')'".to_string()));
+            return None;
+        }
+
+        let mut ret = if self.stream.consume(TokenKind::Colon) {
+            self.parse_unary_type()?
+        } else {
+            Type::Void
+        };
+
+        let mut sizes = Vec::new();
+        while let Type::Array(elem, size) = ret {
+            sizes.push(size);
+            ret = *elem;
+        }
+        let mut ty = if is_closure {
+            Type::Closure(args, Box::new(ret))
+        } else {
+            Type::Function(args, Box::new(ret), vec![])
+        };
+        for size in sizes.into_iter().rev() {
+            ty = Type::Array(Box::new(ty), size);
+        }
+        Some(ty)
+    }
+
     pub fn parse_type(&mut self) -> Option<Type> {
         let first_type = self.parse_unary_type()?;
         if self.stream.is(TokenKind::Pipe) {
@@ -569,7 +868,7 @@ impl<'a> Parser<'a> {
         Some(first_type)
     }
 
-    fn parse_struct_literal(&mut self, path: Vec<String>, generics: Vec<Type>) -> Option<Expr> {
+    fn parse_struct_literal(&mut self, path: Vec<String>, generics: Vec<Type>) -> Option<ExprKind> {
         self.consume(TokenKind::OBrace)?;
 
         let mut fields = Vec::new();
@@ -611,6 +910,6 @@ impl<'a> Parser<'a> {
 
         self.consume(TokenKind::CBrace)?;
 
-        Some(Expr::StructInit(path, fields, generics))
+        Some(ExprKind::StructInit(path, fields, generics))
     }
 }
@@ -1,9 +1,12 @@
 use std::{fs, path::PathBuf};
 
-use abyss_lexer::token::TokenKind;
+use abyss_lexer::token::{LiteralKind, TokenKind};
 
 use crate::{
-    ast::{FunctionBody, FunctionDef, Program, StaticDef, Stmt, StructDef, Type},
+    ast::{
+        Bounds, Capture, Closure, EnumDef, EnumVariant, ExprKind, FunctionBody, FunctionDef,
+        GenericParam, Program, StaticDef, Stmt, StmtKind, StructDef, TraitDef, TraitImpl, Type,
+    },
     error::ParseErrorKind,
     parser::Parser,
 };
@@ -13,7 +16,14 @@ impl<'a> Parser<'a> {
         self.stream.advance();
         while !self.stream.is_at_end() {
             match self.stream.current().kind {
-                TokenKind::Fn | TokenKind::Struct | TokenKind::Static | TokenKind::Pub => return,
+                TokenKind::Fn
+                | TokenKind::Struct
+                | TokenKind::Enum
+                | TokenKind::Trait
+                | TokenKind::Impl
+                | TokenKind::Static
+                | TokenKind::Persist
+                | TokenKind::Pub => return,
                 _ => {}
             }
             self.stream.advance();
@@ -44,14 +54,60 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn parse_generic_params(&mut self) -> Option<Vec<String>> {
+    fn parse_generic_params(&mut self) -> Option<Vec<GenericParam>> {
+        let (generics, bounds) = self.parse_bounded_generic_params()?;
+        if !bounds.is_empty() {
+            self.emit_error_at_current(ParseErrorKind::Message(
+                "trait bounds are only allowed on functions and impl blocks".to_string(),
+            ));
+        }
+        Some(generics)
+    }
+
+    /// `<T, U: Trait + Other, const N: usize>`, returning the parameters and
+    /// one pair per bound.
+    fn parse_bounded_generic_params(&mut self) -> Option<(Vec<GenericParam>, Bounds)> {
+        let mut generics = Vec::new();
+        let mut bounds = Vec::new();
+
         if self.stream.is(TokenKind::Lt) {
             self.advance();
-            let mut generics = Vec::new();
 
             while !self.stream.is(TokenKind::Gt) && !self.stream.is_at_end() {
+                if self.stream.consume(TokenKind::Const) {
+                    let name = self.read_ident()?;
+                    self.consume_safely(TokenKind::Colon)?;
+                    let const_type = self.parse_type()?;
+                    generics.push(GenericParam {
+                        name,
+                        const_type: Some(const_type),
+                    });
+
+                    if !self.stream.consume(TokenKind::Comma) {
+                        break;
+                    }
+                    continue;
+                }
+
                 let gen_name = self.read_ident()?;
-                generics.push(gen_name);
+
+                if self.stream.consume(TokenKind::Colon) {
+                    loop {
+                        let Some(trait_path) = self.parse_path() else {
+                            self.emit_error_at_current(ParseErrorKind::Expected(
+                                "trait name".to_string(),
+                            ));
+                            self.synchronize_func();
+                            return None;
+                        };
+                        bounds.push((gen_name.clone(), trait_path));
+
+                        if !self.stream.consume(TokenKind::Plus) {
+                            break;
+                        }
+                    }
+                }
+                generics.push(GenericParam::new(gen_name));
 
                 if self.stream.is(TokenKind::Comma) {
                     self.advance();
@@ -60,22 +116,23 @@ impl<'a> Parser<'a> {
                 }
             }
             self.consume_safely(TokenKind::Gt)?;
-            Some(generics)
-        } else {
-            Some(Vec::new())
         }
+
+        Some((generics, bounds))
     }
 
     pub fn parse_function(&mut self, is_pub: bool) -> Option<FunctionDef> {
+        let start = self.start();
         self.consume_safely(TokenKind::Fn)?;
 
         let name = self.read_ident()?;
 
-        let generics = self.parse_generic_params()?;
+        let (generics, bounds) = self.parse_bounded_generic_params()?;
 
         let (params, is_variadic) = self.parse_func_params()?;
 
         let return_type = self.parse_return_type();
+        let span = self.span_from(start);
 
         let body = if self.stream.is(TokenKind::Semi) {
             self.advance();
@@ -93,13 +150,16 @@ impl<'a> Parser<'a> {
             is_pub,
             name,
             generics,
+            bounds,
             params,
             return_type,
             body,
             is_variadic,
+            span,
         })
     }
     pub fn parse_struct_def(&mut self, is_pub: bool) -> Option<StructDef> {
+        let start = self.start();
         self.consume_safely(TokenKind::Struct)?;
 
         let name = self.read_ident()?;
@@ -135,10 +195,97 @@ impl<'a> Parser<'a> {
             name,
             generics,
             fields,
+            span: self.span_from(start),
         })
     }
 
-    pub fn parse_static_def(&mut self, is_pub: bool) -> Option<StaticDef> {
+    pub fn parse_enum_def(&mut self, is_pub: bool) -> Option<EnumDef> {
+        let start = self.start();
+        // `error Name { .. }` is an enum whose values are errors.
+        let is_error = self.stream.is(TokenKind::Ident);
+        if is_error {
+            self.advance();
+        } else {
+            self.consume_safely(TokenKind::Enum)?;
+        }
+
+        let name = self.read_ident()?;
+        self.consume_safely(TokenKind::OBrace)?;
+
+        let mut variants: Vec<EnumVariant> = Vec::new();
+
+        while !self.stream.is(TokenKind::CBrace) && !self.stream.is_at_end() {
+            self.skip_newlines();
+            if self.stream.is(TokenKind::CBrace) {
+                break;
+            }
+
+            let variant_start = self.start();
+            let variant_name = self.read_ident()?;
+
+            let mut payload = Vec::new();
+            if self.stream.consume(TokenKind::OParen) {
+                while !self.stream.is(TokenKind::CParen) && !self.stream.is_at_end() {
+                    payload.push(self.parse_type()?);
+                    if !self.stream.consume(TokenKind::Comma) {
+                        break;
+                    }
+                }
+                self.consume_safely(TokenKind::CParen)?;
+            }
+
+            let value = if self.stream.consume(TokenKind::Assign) {
+                self.parse_discriminant()?
+            } else {
+                variants.last().map_or(0, |prev| prev.value + 1)
+            };
+
+            variants.push(EnumVariant {
+                name: variant_name,
+                payload,
+                value,
+                span: self.span_from(variant_start),
+            });
+
+            if self.stream.is(TokenKind::Comma) {
+                self.advance();
+            }
+            self.skip_newlines();
+        }
+
+        self.consume_safely(TokenKind::CBrace)?;
+
+        Some(EnumDef {
+            is_pub,
+            is_error,
+            name,
+            variants,
+            span: self.span_from(start),
+        })
+    }
+
+    /// An enum variant's `= n`, an integer literal that may be negative.
+    fn parse_discriminant(&mut self) -> Option<i64> {
+        let negative = self.stream.consume(TokenKind::Minus);
+
+        let value = match self.stream.current().kind {
+            TokenKind::Literal(LiteralKind::Int) => self.stream.current_lit().parse::<i64>().ok(),
+            _ => None,
+        };
+        let Some(value) = value else {
+            self.emit_error_at_current(ParseErrorKind::Expected(
+                "integer value for enum variant".to_string(),
+            ));
+            self.synchronize_func();
+            return None;
+        };
+        self.advance();
+
+        Some(if negative { -value } else { value })
+    }
+
+    pub fn parse_static_def(&mut self, is_pub: bool, is_persistent: bool) -> Option<StaticDef> {
+        let start = self.start();
         self.consume_safely(TokenKind::Static)?;
         let name = self.read_ident()?;
         self.consume_safely(TokenKind::Colon)?;
@@ -170,9 +317,11 @@ impl<'a> Parser<'a> {
             Some(StaticDef {
                 generics: vec![],
                 is_pub,
+                is_persistent,
                 name,
                 ty,
                 value: val,
+                span: self.span_from(start),
             })
         } else {
             self.emit_error_at_current(ParseErrorKind::Message(
@@ -191,7 +340,7 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn parse_func_params(&mut self) -> Option<(Vec<(String, Type)>, bool)> {
+    pub fn parse_func_params(&mut self) -> Option<(Vec<(String, Type)>, bool)> {
         let mut params = Vec::new();
         let mut is_variadic = false;
 
@@ -228,7 +377,7 @@ impl<'a> Parser<'a> {
         Some((params, is_variadic))
     }
 
-    fn parse_return_type(&mut self) -> Type {
+    pub fn parse_return_type(&mut self) -> Type {
         if self.stream.is(TokenKind::Colon) {
             self.advance();
             if let Some(ty) = self.parse_type() {
@@ -238,27 +387,101 @@ impl<'a> Parser<'a> {
         Type::Void
     }
 
-    pub fn parse_impl_block(&mut self) -> Vec<FunctionDef> {
+    /// `fn[gain, &count](x: f32): f32 { ... }`, after the `fn`.
+    pub fn parse_closure(&mut self) -> Option<ExprKind> {
+        let captures = if self.stream.consume(TokenKind::OBracket) {
+            let mut captures = Vec::new();
+            while !self.stream.is(TokenKind::CBracket) && !self.stream.is_at_end() {
+                let start = self.start();
+                let by_ref = self.stream.consume(TokenKind::Amp);
+                let name = self.read_ident()?;
+                captures.push(Capture {
+                    name,
+                    by_ref,
+                    span: self.span_from(start),
+                });
+
+                if !self.stream.consume(TokenKind::Comma) {
+                    break;
+                }
+            }
+            self.consume(TokenKind::CBracket)?;
+            Some(captures)
+        } else {
+            None
+        };
+
+        let (params, is_variadic) = self.parse_func_params()?;
+        if is_variadic {
+            self.emit_error_at_current(ParseErrorKind::Message(
+                "closures cannot be variadic".to_string(),
+            ));
+        }
+        let return_type = self.parse_return_type();
+        let body = self.parse_block()?;
+
+        Some(ExprKind::Closure(Box::new(Closure {
+            captures,
+            params,
+            return_type,
+            body,
+        })))
+    }
+
+    /// Parses `impl Type { ... }` or `impl Trait for Type { ... }`, returning
+    /// the methods and, for the latter, the trait implementation they form.
+    pub fn parse_impl_block(&mut self) -> (Vec<FunctionDef>, Option<TraitImpl>) {
+        let start = self.start();
         if !self.consume_safely(TokenKind::Impl).is_some() {
-            return Vec::new();
+            return (Vec::new(), None);
         }
 
-        let impl_generics = self.parse_generic_params().unwrap_or_default();
+        let (impl_generics, impl_bounds) = self.parse_bounded_generic_params().unwrap_or_default();
 
-        let struct_name = match self.read_ident() {
+        let mut struct_name = match self.read_ident() {
             Some(name) => name,
-            None => return Vec::new(),
+            None => return (Vec::new(), None),
         };
 
+        let mut trait_path = None;
+        if self.stream.is(TokenKind::ColonColon) || self.stream.is(TokenKind::For) {
+            let mut path = vec![struct_name];
+            while self.stream.consume(TokenKind::ColonColon) {
+                match self.read_ident() {
+                    Some(segment) => path.push(segment),
+                    None => return (Vec::new(), None),
+                }
+            }
+            if self.consume_safely(TokenKind::For).is_none() {
+                return (Vec::new(), None);
+            }
+
+            trait_path = Some(path);
+            struct_name = match self.read_ident() {
+                Some(name) => name,
+                None => return (Vec::new(), None),
+            };
+        }
+
         if self.stream.is(TokenKind::Lt) {
             self.consume_generics_usage();
         }
 
+        let self_ty = Type::Struct(
+            vec![struct_name.clone()],
+            impl_generics
+                .iter()
+                .map(|g| Type::Struct(vec![g.name.clone()], vec![]))
+                .collect(),
+        );
+
+        let header_span = self.span_from(start);
         if !self.consume_safely(TokenKind::OBrace).is_some() {
-            return Vec::new();
+            return (Vec::new(), None);
         }
 
         let mut methods = Vec::new();
+        let mut method_names = Vec::new();
 
         while !self.stream.is(TokenKind::CBrace) && !self.stream.is_at_end() {
             self.skip_newlines();
@@ -279,11 +502,21 @@ impl<'a> Parser<'a> {
                     let old_name = func.name.clone();
                     let new_name = format!("{}__{}", struct_name, old_name);
                     func.name = new_name;
+                    method_names.push(old_name);
+
+                    for (_, ty) in &mut func.params {
+                        ty.replace_self(&self_ty);
+                    }
+                    func.return_type.replace_self(&self_ty);
 
                     if !impl_generics.is_empty() {
                         let mut combined_generics = impl_generics.clone();
                         combined_generics.extend(func.generics);
                         func.generics = combined_generics;
+
+                        let mut combined_bounds = impl_bounds.clone();
+                        combined_bounds.extend(func.bounds);
+                        func.bounds = combined_bounds;
                     }
 
                     methods.push(func);
@@ -300,10 +533,76 @@ impl<'a> Parser<'a> {
         }
 
         self.consume_safely(TokenKind::CBrace);
-        methods
+
+        let trait_impl = trait_path.map(|trait_path| TraitImpl {
+            trait_path,
+            ty: self_ty,
+            methods: method_names,
+            span: header_span,
+        });
+        (methods, trait_impl)
+    }
+
+    /// Parses `trait Name { fn method(self: &Self): T ... }`.
+    pub fn parse_trait_def(&mut self, is_pub: bool) -> Option<TraitDef> {
+        let start = self.start();
+        self.consume_safely(TokenKind::Trait)?;
+
+        let name = self.read_ident()?;
+        self.consume_safely(TokenKind::OBrace)?;
+
+        let mut methods = Vec::new();
+
+        while !self.stream.is(TokenKind::CBrace) && !self.stream.is_at_end() {
+            self.skip_newlines();
+            if self.stream.is(TokenKind::CBrace) {
+                break;
+            }
+
+            let method_start = self.start();
+            self.consume_safely(TokenKind::Fn)?;
+            let method_name = self.read_ident()?;
+            let (generics, bounds) = self.parse_bounded_generic_params()?;
+            let (params, is_variadic) = self.parse_func_params()?;
+            let return_type = self.parse_return_type();
+            let span = self.span_from(method_start);
+
+            if self.stream.is(TokenKind::OBrace) {
+                self.emit_error_at_current(ParseErrorKind::Message(
+                    "trait methods are declarations only; implement them in an `impl` block"
+                        .to_string(),
+                ));
+                self.synchronize_func();
+                return None;
+            }
+            self.stream.consume(TokenKind::Semi);
+
+            methods.push(FunctionDef {
+                is_pub: true,
+                name: method_name,
+                generics,
+                bounds,
+                params,
+                return_type,
+                body: FunctionBody::Extern,
+                is_variadic,
+                span,
+            });
+            self.skip_newlines();
+        }
+
+        self.consume_safely(TokenKind::CBrace)?;
+
+        Some(TraitDef {
+            is_pub,
+            name,
+            methods,
+            span: self.span_from(start),
+        })
     }
 
     pub fn parse_use(&mut self) -> Option<Stmt> {
+        let start = self.start();
         self.consume_safely(TokenKind::Use)?;
 
         let mut path = Vec::new();
@@ -315,7 +614,7 @@ impl<'a> Parser<'a> {
         }
 
         self.consume_safely(TokenKind::Semi)?;
-        Some(Stmt::Use(path))
+        Some(Stmt::new(StmtKind::Use(path), self.span_from(start)))
     }
 
     fn parse_mod_path(&mut self) -> Option<Vec<String>> {
@@ -385,6 +684,19 @@ impl<'a> Parser<'a> {
         None
     }
 
+    /// Parses extern `fn` declarations supplied from outside the program,
+    /// registering `source` under `path` so diagnostics can point into it.
+    pub fn parse_declarations(&mut self, path: &str, source: &str) -> Vec<FunctionDef> {
+        let files = std::mem::take(&mut self.files);
+        let mut sub_parser = Parser::with_files(source, path, files);
+        let program = sub_parser.parse_program();
+
+        self.errors.append(&mut sub_parser.errors);
+        self.files = sub_parser.files;
+
+        program.functions
+    }
+
     fn load_single_file_module(
         &mut self,
         mod_name: &str,
@@ -393,15 +705,12 @@ impl<'a> Parser<'a> {
         match fs::read_to_string(file_path) {
             Ok(content) => {
                 let path_str = file_path.to_string_lossy().to_string();
-                let mut sub_parser = Parser::new(&content, &path_str);
+                let files = std::mem::take(&mut self.files);
+                let mut sub_parser = Parser::with_files(&content, &path_str, files);
                 let program = sub_parser.parse_program();
 
-                if sub_parser.has_errors() {
-                    self.emit_error_at_current(ParseErrorKind::Message(format!(
-                        "Module '{}' (in {:?}) has parsing errors.",
-                        mod_name, file_path
-                    )));
-                }
+                self.errors.append(&mut sub_parser.errors);
+                self.files = sub_parser.files;
 
                 Some((mod_name.to_string(), program))
             }
@@ -426,6 +735,9 @@ impl<'a> Parser<'a> {
             statics: Vec::new(),
             structs: Vec::new(),
             unions: Vec::new(),
+            enums: Vec::new(),
+            traits: Vec::new(),
+            trait_impls: Vec::new(),
             uses: Vec::new(),
         };
         match fs::read_dir(dir_path) {
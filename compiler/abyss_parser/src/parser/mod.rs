@@ -1,15 +1,14 @@
 use abyss_lexer::token::TokenKind;
-use colored::Colorize;
 use std::{
     collections::HashSet,
-    fmt::Write,
     path::{Path, PathBuf},
 };
 
 use crate::{
-    ast::{FunctionDef, Program, Stmt},
+    ast::{FunctionDef, Program, StmtKind},
+    diagnostic::Diagnostic,
     error::{ParseError, ParseErrorKind},
-    source_map::{SourceMap, Span},
+    source_map::{SourceFiles, Span},
     stream::TokenStream,
 };
 
@@ -22,7 +21,7 @@ pub mod stmt;
 pub struct Parser<'a> {
     pub source: &'a str,
     stream: TokenStream<'a>,
-    source_map: SourceMap,
+    files: SourceFiles,
     errors: Vec<ParseError>,
     recorded_span: Span,
     unique_id_counter: u32,
@@ -32,6 +31,13 @@ pub struct Parser<'a> {
 
 impl<'a> Parser<'a> {
     pub fn new(input: &'a str, file_path: &str) -> Self {
+        Self::with_files(input, file_path, SourceFiles::default())
+    }
+
+    /// Creates a parser that registers `input` in an existing file table, so
+    /// spans from every module of a program resolve against one `SourceFiles`.
+    pub fn with_files(input: &'a str, file_path: &str, mut files: SourceFiles) -> Self {
+        let file = files.add(file_path, input);
         let path = Path::new(file_path);
         let root_dir = if path.is_file() {
             path.parent().unwrap_or(Path::new(".")).to_path_buf()
@@ -41,10 +47,10 @@ impl<'a> Parser<'a> {
 
         Self {
             source: input,
-            stream: TokenStream::new(input),
-            source_map: SourceMap::new(input),
+            stream: TokenStream::new(input, file),
+            files,
             errors: Vec::new(),
-            recorded_span: Span { start: 0, end: 0 },
+            recorded_span: Span::default(),
             unique_id_counter: 0,
             root_dir,
             loaded_paths: HashSet::new(),
@@ -90,72 +96,28 @@ impl<'a> Parser<'a> {
         !self.errors.is_empty()
     }
 
-    pub fn format_errors(&self, filename: &str) -> String {
-        let mut output = String::new();
-
-        for error in &self.errors {
-            let pos = self
-                .source_map
-                .position_from_span(&error.pos, self.source)
-                .unwrap();
-
-            let line_content = self.source.lines().nth(pos.line - 1).unwrap_or("");
-
-            let _ = writeln!(
-                &mut output,
-                "{}: {}",
-                "error".bright_red().bold(),
-                error.message.bold()
-            );
-
-            let line_num_str = pos.line.to_string();
-            let padding = " ".repeat(line_num_str.len());
-            let _ = writeln!(
-                &mut output,
-                "{} {}{}:{}:{}",
-                padding,
-                "-->".blue().bold(),
-                filename,
-                pos.line,
-                pos.column
-            );
-
-            let _ = writeln!(&mut output, "{} {}", padding, "|".blue().bold());
-
-            let _ = writeln!(
-                &mut output,
-                "{} {} {}",
-                line_num_str.blue().bold(),
-                "|".blue().bold(),
-                line_content
-            );
-
-            let col_padding = if pos.column > 0 {
-                " ".repeat(pos.column - 1)
-            } else {
-                String::new()
-            };
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        self.errors.iter().map(Diagnostic::from).collect()
+    }
 
-            let caret_len = if error.pos.len() > 0 {
-                error.pos.len()
-            } else {
-                1
-            };
-            let carets = "^".repeat(caret_len);
-
-            let _ = writeln!(
-                &mut output,
-                "{} {} {}{}",
-                padding,
-                "|".blue().bold(),
-                col_padding,
-                carets.bright_red().bold()
-            );
-
-            let _ = writeln!(&mut output, "");
-        }
+    pub fn files(&self) -> &SourceFiles {
+        &self.files
+    }
+
+    /// Every source file read so far, the entry file included, plus the
+    /// directories scanned for directory modules.
+    pub fn loaded_paths(&self) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = self.files.iter().map(|f| PathBuf::from(&f.path)).collect();
+        paths.extend(self.loaded_paths.iter().filter(|p| p.is_dir()).cloned());
+        paths
+    }
+
+    pub fn format_errors(&self) -> String {
+        self.format_diagnostics(&self.diagnostics())
+    }
 
-        output
+    pub fn format_diagnostics(&self, diagnostics: &[Diagnostic]) -> String {
+        diagnostics.iter().map(|d| d.render(&self.files)).collect()
     }
 
     pub fn parse_program(&mut self) -> Program {
@@ -170,6 +132,9 @@ impl<'a> Parser<'a> {
         };
         let mut structs = Vec::new();
         let unions = Vec::new();
+        let mut enums = Vec::new();
+        let mut traits = Vec::new();
+        let mut trait_impls = Vec::new();
         let mut statics = Vec::new();
         let mut modules = Vec::new();
         let mut uses = Vec::new();
@@ -194,6 +159,14 @@ impl<'a> Parser<'a> {
                 false
             };
 
+            let is_persistent = self.stream.consume(TokenKind::Persist);
+            if is_persistent && !self.stream.is(TokenKind::Static) {
+                self.emit_error_at_current(ParseErrorKind::UnexpectedToken {
+                    expected: TokenKind::Static,
+                    found: self.stream.current().kind,
+                });
+            }
+
             match self.stream.current().kind {
                 TokenKind::Fn => {
                     if let Some(func) = self.parse_function(is_pub) {
@@ -205,12 +178,31 @@ impl<'a> Parser<'a> {
                         structs.push(st);
                     }
                 }
+                TokenKind::Enum => {
+                    if let Some(e) = self.parse_enum_def(is_pub) {
+                        enums.push(e);
+                    }
+                }
+                TokenKind::Ident
+                    if self.stream.current_lit() == "error"
+                        && self.stream.is_peek(TokenKind::Ident) =>
+                {
+                    if let Some(e) = self.parse_enum_def(is_pub) {
+                        enums.push(e);
+                    }
+                }
                 TokenKind::Impl => {
-                    let impl_methods = self.parse_impl_block();
+                    let (impl_methods, trait_impl) = self.parse_impl_block();
                     functions.extend(impl_methods);
+                    trait_impls.extend(trait_impl);
+                }
+                TokenKind::Trait => {
+                    if let Some(t) = self.parse_trait_def(is_pub) {
+                        traits.push(t);
+                    }
                 }
                 TokenKind::Static => {
-                    if let Some(st) = self.parse_static_def(is_pub) {
+                    if let Some(st) = self.parse_static_def(is_pub, is_persistent) {
                         statics.push(st);
                     }
                 }
@@ -221,8 +213,8 @@ impl<'a> Parser<'a> {
                 }
                 TokenKind::Use => {
                     if let Some(stmt) = self.parse_use() {
-                        if let Stmt::Use(path) = stmt {
-                            uses.push(path);
+                        if let StmtKind::Use(path) = stmt.kind {
+                            uses.push((path, stmt.span));
                         }
                     }
                 }
@@ -247,12 +239,23 @@ impl<'a> Parser<'a> {
             modules,
             structs,
             unions,
+            enums,
+            traits,
+            trait_impls,
             functions,
             statics,
             uses,
         }
     }
 
+    fn start(&self) -> usize {
+        self.stream.current_offset()
+    }
+
+    fn span_from(&self, start: usize) -> Span {
+        self.stream.span_from(start)
+    }
+
     fn optional(&mut self, kind: TokenKind) {
         if self.stream.is(kind) {
             self.advance();
@@ -1,12 +1,16 @@
 use crate::{
-    ast::{BinaryOp, Expr, Lit, Stmt, Type},
+    ast::{BinaryOp, Expr, ExprKind, Lit, Stmt, StmtKind, Type},
+    error::ParseErrorKind,
     parser::Parser,
+    source_map::Span,
 };
 use abyss_lexer::token::TokenKind as Tk;
 
 impl<'a> Parser<'a> {
     pub fn parse_stmt(&mut self, scope: &mut Vec<Stmt>) -> Option<Stmt> {
-        let stmt = match self.stream.current().kind {
+        let start = self.start();
+
+        let kind = match self.stream.current().kind {
             Tk::Let => self.parse_let_stmt()?,
             Tk::Fn => self.parse_nested_function()?,
             Tk::Ret => self.parse_ret_stmt()?,
@@ -18,63 +22,75 @@ impl<'a> Parser<'a> {
 
             Tk::Out => self.parse_out_stmt()?,
             Tk::Next => self.parse_next_stmt()?,
+            Tk::Defer => self.parse_defer_stmt(scope)?,
 
             _ => self.parse_assignment_or_expr_stmt()?,
         };
+        let stmt = Stmt::new(kind, self.span_from(start));
 
         self.optional(Tk::Semi);
         self.optional(Tk::Newline);
         Some(stmt)
     }
 
-    fn parse_assignment_or_expr_stmt(&mut self) -> Option<Stmt> {
+    fn parse_block_stmt(&mut self) -> Option<Stmt> {
+        let start = self.start();
+        let stmts = self.parse_block()?;
+        Some(Stmt::new(StmtKind::Block(stmts), self.span_from(start)))
+    }
+
+    fn parse_assignment_or_expr_stmt(&mut self) -> Option<StmtKind> {
         let lhs_expr = self.parse_expr()?;
 
         if self.stream.is(Tk::Assign) {
             self.advance();
             let rhs_expr = self.parse_expr()?;
-            return Some(Stmt::Assign(lhs_expr, rhs_expr));
+            return Some(StmtKind::Assign(lhs_expr, rhs_expr));
         }
 
-        if self.stream.is(Tk::Plus) && self.stream.is_peek(Tk::Assign) {
-            self.advance();
-            self.advance();
-            let rhs = self.parse_expr()?;
-            return Some(Stmt::Assign(
-                lhs_expr.clone(),
-                Expr::Binary(Box::new(lhs_expr), BinaryOp::Add, Box::new(rhs)),
-            ));
-        }
-        if self.stream.is(Tk::Minus) && self.stream.is_peek(Tk::Assign) {
+        let compound_op = if self.stream.is(Tk::Plus) && self.stream.is_peek(Tk::Assign) {
+            Some(BinaryOp::Add)
+        } else if self.stream.is(Tk::Minus) && self.stream.is_peek(Tk::Assign) {
+            Some(BinaryOp::Sub)
+        } else {
+            None
+        };
+
+        if let Some(op) = compound_op {
             self.advance();
             self.advance();
             let rhs = self.parse_expr()?;
-            return Some(Stmt::Assign(
+            let span = lhs_expr.span.to(rhs.span);
+            return Some(StmtKind::Assign(
                 lhs_expr.clone(),
-                Expr::Binary(Box::new(lhs_expr), BinaryOp::Sub, Box::new(rhs)),
+                Expr::new(
+                    ExprKind::Binary(Box::new(lhs_expr), op, Box::new(rhs)),
+                    span,
+                ),
             ));
         }
 
-        Some(Stmt::Expr(lhs_expr))
+        Some(StmtKind::Expr(lhs_expr))
     }
 
-    fn parse_nested_function(&mut self) -> Option<Stmt> {
+    fn parse_nested_function(&mut self) -> Option<StmtKind> {
         let func_def = self.parse_function(false)?;
 
-        Some(Stmt::FunctionDef(Box::new(func_def)))
+        Some(StmtKind::FunctionDef(Box::new(func_def)))
     }
-    fn parse_forever_stmt(&mut self) -> Option<Stmt> {
+    fn parse_forever_stmt(&mut self) -> Option<StmtKind> {
+        let keyword_span = self.stream.current_span();
         self.consume(Tk::Forever)?;
 
-        let body_stmts = self.parse_block()?;
+        let body = self.parse_block_stmt()?;
 
-        Some(Stmt::While(
-            Expr::Lit(Lit::Bool(true)),
-            Box::new(Stmt::Block(body_stmts)),
+        Some(StmtKind::While(
+            Expr::new(ExprKind::Lit(Lit::Bool(true)), keyword_span),
+            Box::new(body),
         ))
     }
 
-    fn parse_let_stmt(&mut self) -> Option<Stmt> {
+    fn parse_let_stmt(&mut self) -> Option<StmtKind> {
         self.consume(Tk::Let)?;
 
         let name = self.consume_ident()?;
@@ -93,23 +109,68 @@ impl<'a> Parser<'a> {
             None
         };
 
-        Some(Stmt::Let(name, explicit_type, expr))
+        Some(StmtKind::Let(name, explicit_type, expr))
     }
 
-    fn parse_ret_stmt(&mut self) -> Option<Stmt> {
+    fn parse_ret_stmt(&mut self) -> Option<StmtKind> {
         self.consume(Tk::Ret)?;
         let expr = self.parse_expr()?;
-        Some(Stmt::Ret(expr))
+        Some(StmtKind::Ret(expr))
     }
 
-    fn parse_if_stmt(&mut self, scope: &mut Vec<Stmt>) -> Option<Stmt> {
+    fn parse_if_stmt(&mut self, scope: &mut Vec<Stmt>) -> Option<StmtKind> {
+        let start = self.start();
         self.consume(Tk::If)?;
 
+        if self.stream.consume(Tk::Let) {
+            return self.parse_if_let(start, scope);
+        }
+
         let condition = self.parse_expr()?;
 
-        let then_stmts = self.parse_block()?;
-        let then_branch = Box::new(Stmt::Block(then_stmts));
+        let then_branch = Box::new(self.parse_block_stmt()?);
+        let else_branch = self.parse_else_branch(scope)?;
+
+        Some(StmtKind::If(condition, then_branch, else_branch))
+    }
+
+    /// `if let v = opt { .. } else { .. }`, desugared into
+    /// `{ let tmp = opt; if tmp != null { let v = tmp; .. } else { .. } }`.
+    /// Testing `tmp` narrows it, so `v` gets the type `opt` holds.
+    fn parse_if_let(&mut self, start: usize, scope: &mut Vec<Stmt>) -> Option<StmtKind> {
+        let Some(name) = self.consume_ident() else {
+            self.emit_error_at_current(ParseErrorKind::Expected(
+                "Identifier after 'if let'".to_string(),
+            ));
+            return None;
+        };
+        self.consume(Tk::Assign)?;
+        let value = self.parse_expr()?;
+        let span = self.span_from(start);
+
+        let temp = self.get_unique_identifier();
+        let temp_expr = || Expr::new(ExprKind::Ident(vec![temp.clone()]), span);
+        let null = Expr::new(ExprKind::Lit(Lit::Null), span);
+        let condition = Expr::new(
+            ExprKind::Binary(Box::new(temp_expr()), BinaryOp::Neq, Box::new(null)),
+            span,
+        );
+
+        let body = self.parse_block_stmt()?;
+        let binding = Stmt::new(StmtKind::Let(name, None, Some(temp_expr())), span);
+        let then_branch = Stmt::new(StmtKind::Block(vec![binding, body]), span);
+        let else_branch = self.parse_else_branch(scope)?;
+
+        Some(StmtKind::Block(vec![
+            Stmt::new(StmtKind::Let(temp.clone(), None, Some(value)), span),
+            Stmt::new(
+                StmtKind::If(condition, Box::new(then_branch), else_branch),
+                span,
+            ),
+        ]))
+    }
 
+    fn parse_else_branch(&mut self, scope: &mut Vec<Stmt>) -> Option<Option<Box<Stmt>>> {
         let else_branch = if self.stream.is(Tk::Else) {
             self.advance();
 
@@ -117,21 +178,20 @@ impl<'a> Parser<'a> {
                 let nested_if = self.parse_stmt(scope)?;
                 Some(Box::new(nested_if))
             } else {
-                let else_stmts = self.parse_block()?;
-                Some(Box::new(Stmt::Block(else_stmts)))
+                Some(Box::new(self.parse_block_stmt()?))
             }
         } else {
             None
         };
 
-        Some(Stmt::If(condition, then_branch, else_branch))
+        Some(else_branch)
     }
 
-    fn parse_while_stmt(&mut self) -> Option<Stmt> {
+    fn parse_while_stmt(&mut self) -> Option<StmtKind> {
         self.consume(Tk::While)?;
         let condition = self.parse_expr()?;
-        let body_stmts = self.parse_block()?;
-        Some(Stmt::While(condition, Box::new(Stmt::Block(body_stmts))))
+        let body = self.parse_block_stmt()?;
+        Some(StmtKind::While(condition, Box::new(body)))
     }
 
     fn consume_ident(&mut self) -> Option<String> {
@@ -145,100 +205,228 @@ impl<'a> Parser<'a> {
         }
     }
 
-    fn parse_for_stmt(&mut self, _: &mut Vec<Stmt>) -> Option<Stmt> {
+    fn parse_for_stmt(&mut self, _: &mut Vec<Stmt>) -> Option<StmtKind> {
+        let start = self.start();
         self.consume(Tk::For)?;
 
+        // Every node of the desugared loop points back at the `for` header.
+        let expr = |kind: ExprKind, span: Span| Expr::new(kind, span);
+        let ident_expr =
+            |name: &str, span: Span| expr(ExprKind::Ident(vec![name.to_string()]), span);
+        let int_expr = |value: i64, span: Span| expr(ExprKind::Lit(Lit::Int(value)), span);
+        let binary = |lhs: Expr, op: BinaryOp, rhs: Expr, span: Span| {
+            expr(ExprKind::Binary(Box::new(lhs), op, Box::new(rhs)), span)
+        };
+
         if self.stream.is(Tk::Ident) && self.stream.is_peek(Tk::In) {
             let ident = self.consume_ident()?;
             self.consume(Tk::In)?;
-            let start = self.parse_expr()?;
+            let start_expr = self.parse_expr()?;
+            if !self.is(Tk::RArrow) {
+                return self.parse_for_each(start, ident, start_expr);
+            }
             self.consume(Tk::RArrow)?;
             let end = self.parse_expr()?;
+            let span = self.span_from(start);
 
             let i_type = Type::I64;
 
-            let inc_stmt = Stmt::Assign(
-                Expr::Ident(vec![ident.clone()]),
-                Expr::Binary(
-                    Box::new(Expr::Ident(vec![ident.clone()])),
-                    BinaryOp::Add,
-                    Box::new(Expr::Lit(Lit::Int(1))),
+            let inc_stmt = Stmt::new(
+                StmtKind::Assign(
+                    ident_expr(&ident, span),
+                    binary(
+                        ident_expr(&ident, span),
+                        BinaryOp::Add,
+                        int_expr(1, span),
+                        span,
+                    ),
                 ),
+                span,
             );
 
+            let body_start = self.start();
             let mut body_stmts = vec![inc_stmt];
             body_stmts.extend(self.parse_block()?);
-
-            return Some(Stmt::Block(vec![
-                Stmt::Let(
-                    ident.clone(),
-                    Some(i_type),
-                    Some(Expr::Binary(
-                        Box::new(start),
-                        BinaryOp::Sub,
-                        Box::new(Expr::Lit(Lit::Int(1))),
-                    )),
+            let body_span = self.span_from(body_start);
+
+            return Some(StmtKind::Block(vec![
+                Stmt::new(
+                    StmtKind::Let(
+                        ident.clone(),
+                        Some(i_type),
+                        Some(binary(start_expr, BinaryOp::Sub, int_expr(1, span), span)),
+                    ),
+                    span,
                 ),
-                Stmt::While(
-                    Expr::Binary(
-                        Box::new(Expr::Binary(
-                            Box::new(Expr::Ident(vec![ident.clone()])),
-                            BinaryOp::Add,
-                            Box::new(Expr::Lit(Lit::Int(1))),
-                        )),
-                        BinaryOp::Lt,
-                        Box::new(end),
+                Stmt::new(
+                    StmtKind::While(
+                        binary(
+                            binary(
+                                ident_expr(&ident, span),
+                                BinaryOp::Add,
+                                int_expr(1, span),
+                                span,
+                            ),
+                            BinaryOp::Lt,
+                            end,
+                            span,
+                        ),
+                        Box::new(Stmt::new(StmtKind::Block(body_stmts), body_span)),
                     ),
-                    Box::new(Stmt::Block(body_stmts)),
+                    span,
                 ),
             ]));
         } else {
             let ident = self.get_unique_identifier();
             let end = self.parse_expr()?;
+            let span = self.span_from(start);
 
-            let start_expr = Expr::Lit(Lit::Int(-1));
+            let start_expr = int_expr(-1, span);
             let i_type = Type::I64;
 
             let end_ident = self.get_unique_identifier();
 
-            let inc_stmt = Stmt::Assign(
-                Expr::Ident(vec![ident.clone()]),
-                Expr::Binary(
-                    Box::new(Expr::Ident(vec![ident.clone()])),
-                    BinaryOp::Add,
-                    Box::new(Expr::Lit(Lit::Int(1))),
+            let inc_stmt = Stmt::new(
+                StmtKind::Assign(
+                    ident_expr(&ident, span),
+                    binary(
+                        ident_expr(&ident, span),
+                        BinaryOp::Add,
+                        int_expr(1, span),
+                        span,
+                    ),
                 ),
+                span,
             );
 
+            let body_start = self.start();
             let mut body_stmts = vec![inc_stmt];
             body_stmts.extend(self.parse_block()?);
+            let body_span = self.span_from(body_start);
 
-            return Some(Stmt::Block(vec![
-                Stmt::Let(ident.clone(), Some(i_type.clone()), Some(start_expr)),
-                Stmt::Let(end_ident.clone(), Some(i_type), Some(end)),
-                Stmt::While(
-                    Expr::Binary(
-                        Box::new(Expr::Binary(
-                            Box::new(Expr::Ident(vec![ident])),
-                            BinaryOp::Add,
-                            Box::new(Expr::Lit(Lit::Int(1))),
-                        )),
-                        BinaryOp::Lt,
-                        Box::new(Expr::Ident(vec![end_ident])),
+            return Some(StmtKind::Block(vec![
+                Stmt::new(
+                    StmtKind::Let(ident.clone(), Some(i_type.clone()), Some(start_expr)),
+                    span,
+                ),
+                Stmt::new(
+                    StmtKind::Let(end_ident.clone(), Some(i_type), Some(end)),
+                    span,
+                ),
+                Stmt::new(
+                    StmtKind::While(
+                        binary(
+                            binary(
+                                ident_expr(&ident, span),
+                                BinaryOp::Add,
+                                int_expr(1, span),
+                                span,
+                            ),
+                            BinaryOp::Lt,
+                            ident_expr(&end_ident, span),
+                            span,
+                        ),
+                        Box::new(Stmt::new(StmtKind::Block(body_stmts), body_span)),
                     ),
-                    Box::new(Stmt::Block(body_stmts)),
+                    span,
                 ),
             ]));
         }
     }
 
-    pub fn parse_out_stmt(&mut self) -> Option<Stmt> {
+    /// `for x in s { .. }` over the elements of a slice or array, which is
+    /// sliced once and walked by a hidden index:
+    ///
+    /// `let s = xs[..]`, `let i = -1`, `while i + 1 < s.len { i = i + 1; let x = s.ptr[i] .. }`
+    fn parse_for_each(&mut self, start: usize, ident: String, iterable: Expr) -> Option<StmtKind> {
+        let span = self.span_from(start);
+        let slice = self.get_unique_identifier();
+        let index = self.get_unique_identifier();
+
+        let expr = |kind: ExprKind| Expr::new(kind, span);
+        let ident_expr = |name: &str| expr(ExprKind::Ident(vec![name.to_string()]));
+        let member = |name: &str, field: &str| {
+            expr(ExprKind::Member(
+                Box::new(ident_expr(name)),
+                field.to_string(),
+            ))
+        };
+        let next_index = || {
+            expr(ExprKind::Binary(
+                Box::new(ident_expr(&index)),
+                BinaryOp::Add,
+                Box::new(expr(ExprKind::Lit(Lit::Int(1)))),
+            ))
+        };
+
+        let body_start = self.start();
+        let mut body_stmts = vec![
+            Stmt::new(StmtKind::Assign(ident_expr(&index), next_index()), span),
+            Stmt::new(
+                StmtKind::Let(
+                    ident,
+                    None,
+                    Some(expr(ExprKind::Index(
+                        Box::new(member(&slice, "ptr")),
+                        Box::new(ident_expr(&index)),
+                    ))),
+                ),
+                span,
+            ),
+        ];
+        body_stmts.extend(self.parse_block()?);
+        let body_span = self.span_from(body_start);
+
+        let whole = expr(ExprKind::Range(None, None));
+        Some(StmtKind::Block(vec![
+            Stmt::new(
+                StmtKind::Let(
+                    slice.clone(),
+                    None,
+                    Some(expr(ExprKind::Index(Box::new(iterable), Box::new(whole)))),
+                ),
+                span,
+            ),
+            Stmt::new(
+                StmtKind::Let(
+                    index.clone(),
+                    Some(Type::I64),
+                    Some(expr(ExprKind::Lit(Lit::Int(-1)))),
+                ),
+                span,
+            ),
+            Stmt::new(
+                StmtKind::While(
+                    expr(ExprKind::Binary(
+                        Box::new(next_index()),
+                        BinaryOp::Lt,
+                        Box::new(member(&slice, "len")),
+                    )),
+                    Box::new(Stmt::new(StmtKind::Block(body_stmts), body_span)),
+                ),
+                span,
+            ),
+        ]))
+    }
+
+    pub fn parse_out_stmt(&mut self) -> Option<StmtKind> {
         self.consume(Tk::Out)?;
-        Some(Stmt::Break)
+        Some(StmtKind::Break)
     }
 
-    pub fn parse_next_stmt(&mut self) -> Option<Stmt> {
+    pub fn parse_next_stmt(&mut self) -> Option<StmtKind> {
         self.consume(Tk::Next)?;
-        Some(Stmt::Continue)
+        Some(StmtKind::Continue)
+    }
+
+    /// `defer free(p)` or `defer { .. }`.
+    fn parse_defer_stmt(&mut self, scope: &mut Vec<Stmt>) -> Option<StmtKind> {
+        self.consume(Tk::Defer)?;
+        let body = if self.is(Tk::OBrace) {
+            self.parse_block_stmt()?
+        } else {
+            self.parse_stmt(scope)?
+        };
+        Some(StmtKind::Defer(Box::new(body)))
     }
 }
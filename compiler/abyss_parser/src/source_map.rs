@@ -1,19 +1,33 @@
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct FileId(pub usize);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
 pub struct Span {
     pub start: usize,
     pub end: usize,
+    pub file: FileId,
 }
 
 impl Span {
-    pub fn new(start: usize, end: usize) -> Self {
-        Self { start, end }
+    pub fn new(start: usize, end: usize, file: FileId) -> Self {
+        Self { start, end, file }
     }
 
     pub fn len(&self) -> usize {
-        (self.end - self.start) as usize
+        self.end - self.start
+    }
+
+    /// Span covering both `self` and `other`, which must be in the same file.
+    pub fn to(self, other: Span) -> Span {
+        Span::new(
+            self.start.min(other.start),
+            self.end.max(other.end),
+            self.file,
+        )
     }
 }
 
+#[derive(Debug, Clone, Copy)]
 pub struct Position {
     pub line: usize,
     pub column: usize,
@@ -52,3 +66,49 @@ impl SourceMap {
         self.find_position(span.start, source)
     }
 }
+
+pub struct SourceFile {
+    pub path: String,
+    pub source: String,
+    source_map: SourceMap,
+}
+
+impl SourceFile {
+    pub fn position(&self, span: &Span) -> Option<Position> {
+        self.source_map.position_from_span(span, &self.source)
+    }
+
+    pub fn line(&self, line: usize) -> &str {
+        self.source.lines().nth(line - 1).unwrap_or("")
+    }
+}
+
+/// Every file read while parsing a program, indexed by the `FileId` stored in spans.
+#[derive(Default)]
+pub struct SourceFiles {
+    files: Vec<SourceFile>,
+}
+
+impl SourceFiles {
+    pub fn add(&mut self, path: &str, source: &str) -> FileId {
+        self.files.push(SourceFile {
+            path: path.to_string(),
+            source: source.to_string(),
+            source_map: SourceMap::new(source),
+        });
+        FileId(self.files.len() - 1)
+    }
+
+    pub fn get(&self, id: FileId) -> Option<&SourceFile> {
+        self.files.get(id.0)
+    }
+
+    pub fn locate(&self, span: &Span) -> Option<(&SourceFile, Position)> {
+        let file = self.get(span.file)?;
+        Some((file, file.position(span)?))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &SourceFile> {
+        self.files.iter()
+    }
+}
@@ -3,7 +3,7 @@ use abyss_lexer::{
     token::{Token, TokenKind},
 };
 
-use crate::source_map::Span;
+use crate::source_map::{FileId, Span};
 
 pub struct TokenStream<'a> {
     source: &'a str,
@@ -12,10 +12,12 @@ pub struct TokenStream<'a> {
     offset: usize,
     peek: Token,
     peek_offset: usize,
+    prev_end: usize,
+    file: FileId,
 }
 
 impl<'a> TokenStream<'a> {
-    pub fn new(source: &'a str) -> Self {
+    pub fn new(source: &'a str, file: FileId) -> Self {
         let mut stream = Self {
             source,
             lexer: Lexer::new(source),
@@ -23,6 +25,8 @@ impl<'a> TokenStream<'a> {
             offset: 0,
             peek: Token::dummy(),
             peek_offset: 0,
+            prev_end: 0,
+            file,
         };
 
         stream.advance();
@@ -32,6 +36,10 @@ impl<'a> TokenStream<'a> {
     }
 
     pub fn advance(&mut self) {
+        if self.current.kind != TokenKind::Newline {
+            self.prev_end = self.offset + self.current.len;
+        }
+
         if self.peek.kind == TokenKind::Eof {
             self.current = self.peek.clone();
             self.offset = self.peek_offset;
@@ -85,11 +93,20 @@ impl<'a> TokenStream<'a> {
     }
 
     pub fn current_span(&self) -> Span {
-        Span::new(self.offset, self.offset + self.current.len)
+        Span::new(self.offset, self.offset + self.current.len, self.file)
     }
 
     pub fn peek_span(&self) -> Span {
-        Span::new(self.peek_offset, self.peek_offset + self.peek.len)
+        Span::new(
+            self.peek_offset,
+            self.peek_offset + self.peek.len,
+            self.file,
+        )
+    }
+
+    /// Span from `start` up to the end of the last consumed non-newline token.
+    pub fn span_from(&self, start: usize) -> Span {
+        Span::new(start, self.prev_end.max(start), self.file)
     }
 
     pub fn is_at_end(&self) -> bool {
@@ -0,0 +1,286 @@
+use std::{collections::HashMap, ffi::c_void, marker::PhantomData};
+
+use abyss_analyzer::{
+    lir::{LirFunctionDef, LirProgram, LirType},
+    symbols::FunctionInfo,
+};
+use abyss_parser::{ast::FunctionBody, parser::Parser};
+
+/// Path host declarations are reported under in diagnostics.
+pub const HOST_FILE: &str = "<host>";
+
+/// A Rust type that can cross into Abyss code, named by its `LirType`.
+pub trait AbyssType {
+    fn lir_type() -> LirType;
+}
+
+macro_rules! impl_abyss_type {
+    ($($rust:ty => $lir:expr),* $(,)?) => {
+        $(impl AbyssType for $rust {
+            fn lir_type() -> LirType {
+                $lir
+            }
+        })*
+    };
+}
+
+impl_abyss_type! {
+    () => LirType::Void,
+    c_void => LirType::Void,
+    bool => LirType::Bool,
+    i8 => LirType::I8,
+    i16 => LirType::I16,
+    i32 => LirType::I32,
+    i64 => LirType::I64,
+    isize => LirType::Isize,
+    u8 => LirType::U8,
+    u16 => LirType::U16,
+    u32 => LirType::U32,
+    u64 => LirType::U64,
+    usize => LirType::Usize,
+    f32 => LirType::F32,
+    f64 => LirType::F64,
+}
+
+impl<T: AbyssType> AbyssType for *const T {
+    fn lir_type() -> LirType {
+        LirType::Pointer(Box::new(T::lir_type()))
+    }
+}
+
+impl<T: AbyssType> AbyssType for *mut T {
+    fn lir_type() -> LirType {
+        LirType::Pointer(Box::new(T::lir_type()))
+    }
+}
+
+/// An `extern "C" fn` whose signature can be checked against Abyss code.
+///
+/// # Safety
+///
+/// `params` and `ret` must describe exactly how the function is called: code
+/// is called through, and handed out as, any `AbyssFn` whose description
+/// matches the program's.
+pub unsafe trait AbyssFn: Copy {
+    /// The arguments, as a tuple.
+    type Args;
+    type Output;
+
+    fn params() -> Vec<LirType>;
+    fn ret() -> LirType;
+    fn as_ptr(self) -> *const c_void;
+    fn call(self, args: Self::Args) -> Self::Output;
+
+    /// # Safety
+    ///
+    /// `ptr` must point to a function with exactly this signature.
+    unsafe fn from_ptr(ptr: *const c_void) -> Self;
+}
+
+macro_rules! impl_abyss_fn {
+    ($($arg:ident),*) => {
+        // SAFETY: each parameter and the result are described by their own
+        // `AbyssType`, in order.
+        unsafe impl<R: AbyssType, $($arg: AbyssType),*> AbyssFn for extern "C" fn($($arg),*) -> R {
+            type Args = ($($arg,)*);
+            type Output = R;
+
+            fn params() -> Vec<LirType> {
+                vec![$($arg::lir_type()),*]
+            }
+
+            fn ret() -> LirType {
+                R::lir_type()
+            }
+
+            fn as_ptr(self) -> *const c_void {
+                self as *const c_void
+            }
+
+            #[allow(non_snake_case)]
+            fn call(self, ($($arg,)*): Self::Args) -> R {
+                self($($arg),*)
+            }
+
+            unsafe fn from_ptr(ptr: *const c_void) -> Self {
+                unsafe { std::mem::transmute::<*const c_void, Self>(ptr) }
+            }
+        }
+    };
+}
+
+impl_abyss_fn!();
+impl_abyss_fn!(A);
+impl_abyss_fn!(A, B);
+impl_abyss_fn!(A, B, C);
+impl_abyss_fn!(A, B, C, D);
+impl_abyss_fn!(A, B, C, D, E);
+impl_abyss_fn!(A, B, C, D, E, F);
+impl_abyss_fn!(A, B, C, D, E, F, G);
+impl_abyss_fn!(A, B, C, D, E, F, G, H);
+
+/// A Rust function made callable from Abyss under its declared signature.
+#[derive(Debug, Clone)]
+pub struct HostFn {
+    pub(crate) name: String,
+    pub(crate) declaration: String,
+    pub(crate) ptr: *const c_void,
+    params: Vec<LirType>,
+    ret: LirType,
+}
+
+// SAFETY: `ptr` is a plain function pointer.
+unsafe impl Send for HostFn {}
+unsafe impl Sync for HostFn {}
+
+impl HostFn {
+    /// Pairs `func` with an Abyss declaration such as `fn gain(x: f64): f64`.
+    pub fn new<F: AbyssFn>(declaration: &str, func: F) -> Result<Self, String> {
+        let mut declaration = declaration.trim().to_string();
+        if !declaration.ends_with([';', '}']) {
+            declaration.push(';');
+        }
+
+        let mut parser = Parser::new(&declaration, HOST_FILE);
+        let program = parser.parse_program();
+        if parser.has_errors() {
+            return Err(parser.format_errors());
+        }
+
+        let name = match program.functions.as_slice() {
+            [func] if matches!(func.body, FunctionBody::Extern) && func.generics.is_empty() => {
+                func.name.clone()
+            }
+            _ => {
+                return Err(format!(
+                    "expected a single bodyless `fn` declaration, found `{}`",
+                    declaration
+                ));
+            }
+        };
+
+        Ok(Self {
+            name,
+            declaration,
+            ptr: func.as_ptr(),
+            params: F::params(),
+            ret: F::ret(),
+        })
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Checks the Rust signature against the one the program declared.
+    pub(crate) fn check(&self, declared: &FunctionInfo) -> Result<(), String> {
+        if signature_matches(declared, &self.params, &self.ret) {
+            return Ok(());
+        }
+        Err(format!(
+            "host function `{}` is declared as {} but registered as {}",
+            self.name,
+            describe(&declared.params, &declared.ret_ty),
+            describe(&self.params, &self.ret)
+        ))
+    }
+}
+
+/// Checks every host function against its extern declaration in `ir`.
+pub(crate) fn check_host_fns(host_fns: &[HostFn], ir: &LirProgram) -> Result<(), String> {
+    for host in host_fns {
+        let declared = ir
+            .functions
+            .iter()
+            .find(|f| f.is_extern && f.name == host.name);
+
+        if let Some(declared) = declared {
+            host.check(&signature(declared))?;
+        }
+    }
+    Ok(())
+}
+
+/// The functions `ir` defines, by name, as a host can look them up.
+pub(crate) fn exports(ir: &LirProgram) -> HashMap<String, FunctionInfo> {
+    ir.functions
+        .iter()
+        .filter(|f| !f.is_extern)
+        .map(|f| (f.name.clone(), signature(f)))
+        .collect()
+}
+
+fn signature(func: &LirFunctionDef) -> FunctionInfo {
+    FunctionInfo {
+        params: func.params.iter().map(|(_, ty)| ty.clone()).collect(),
+        ret_ty: func.return_type.clone(),
+    }
+}
+
+/// A function exported by a loaded program, typed after checking its signature.
+/// It borrows whatever owns the code so it cannot outlive it, and is only
+/// called through `call`: the bare `F` would be a copy free of that borrow.
+pub struct Export<'a, F> {
+    func: F,
+    _code: PhantomData<&'a ()>,
+}
+
+impl<F: AbyssFn> Export<'_, F> {
+    /// Types `ptr` as `F` once `info`, the signature `name` was compiled
+    /// with, matches it.
+    pub(crate) fn new(name: &str, info: &FunctionInfo, ptr: *const c_void) -> Result<Self, String> {
+        if !signature_matches(info, &F::params(), &F::ret()) {
+            return Err(format!(
+                "`{}` is defined as {} but requested as {}",
+                name,
+                describe(&info.params, &info.ret_ty),
+                describe(&F::params(), &F::ret())
+            ));
+        }
+
+        Ok(Self {
+            func: unsafe { F::from_ptr(ptr) },
+            _code: PhantomData,
+        })
+    }
+
+    /// Calls the function with `args`, a tuple: `process.call((0.5,))`.
+    pub fn call(&self, args: F::Args) -> F::Output {
+        self.func.call(args)
+    }
+}
+
+fn signature_matches(declared: &FunctionInfo, params: &[LirType], ret: &LirType) -> bool {
+    declared.params.len() == params.len()
+        && declared
+            .params
+            .iter()
+            .zip(params)
+            .all(|(declared, host)| same_abi(declared, host))
+        && same_abi(&declared.ret_ty, ret)
+}
+
+/// Whether values of the two types are passed the same way. Pointers are
+/// interchangeable, and `char` is a byte.
+fn same_abi(declared: &LirType, host: &LirType) -> bool {
+    match (strip_const(declared), strip_const(host)) {
+        (
+            LirType::Pointer(_) | LirType::FunctionPtr(..),
+            LirType::Pointer(_) | LirType::FunctionPtr(..),
+        ) => true,
+        (LirType::Char, LirType::I8 | LirType::U8) => true,
+        (declared, host) => declared == host,
+    }
+}
+
+fn strip_const(ty: &LirType) -> &LirType {
+    match ty {
+        LirType::Const(inner) => strip_const(inner),
+        _ => ty,
+    }
+}
+
+fn describe(params: &[LirType], ret: &LirType) -> String {
+    let params: Vec<String> = params.iter().map(|p| format!("{:?}", p)).collect();
+    format!("fn({}) -> {:?}", params.join(", "), ret)
+}
@@ -1,17 +1,31 @@
 use abyss_analyzer::{
-    collector::Collector, flattener::Flattener, hir::FlatProgram, ir::Ir, lir::LirProgram,
+    collector::Collector,
+    flattener::Flattener,
+    hir::FlatProgram,
+    ir::Ir,
+    lir::{LirFunctionDef, LirProgram, LirType},
+    symbols::FunctionInfo,
     type_checker::TypeChecker,
 };
 use abyss_codegen::{director::Director, target::Target};
-use abyss_parser::{ast::Program, parser::Parser};
+use abyss_parser::{ast::Program, diagnostic::Diagnostic, parser::Parser};
 use include_dir::{Dir, include_dir};
 use std::{
-    ffi::{CString, c_char, c_int, c_void},
-    time::Instant,
+    cell::RefCell,
+    collections::HashMap,
+    ffi::{CStr, CString, c_char, c_int, c_void},
+    fmt,
+    path::{Path, PathBuf},
 };
 use tempfile::TempDir;
 
 pub use abyss_codegen::ctarget::c_target::CTarget;
+pub use abyss_codegen::director::ENTRY_POINT;
+pub use embed::{AbyssFn, AbyssType, Export, HOST_FILE, HostFn};
+pub use module::{AbyssModule, ModuleInstance};
+
+mod embed;
+mod module;
 
 static TCC_MINIMAL_FS: Dir = include_dir!("tcc_minimal");
 
@@ -23,6 +37,10 @@ pub struct TCCState {
 pub const TCC_OUTPUT_MEMORY: i32 = 1;
 pub const TCC_RELOCATE_AUTO: *mut c_void = 1 as *mut c_void;
 pub const TCC_OUTPUT_EXE: i32 = 2;
+pub const TCC_OUTPUT_OBJ: i32 = 3;
+pub const TCC_OUTPUT_DLL: i32 = 4;
+
+pub type TCCErrorFunc = extern "C" fn(opaque: *mut c_void, msg: *const c_char);
 
 unsafe extern "C" {
     pub fn tcc_new() -> *mut TCCState;
@@ -32,20 +50,233 @@ unsafe extern "C" {
     pub fn tcc_relocate(s: *mut TCCState, ptr: *mut c_void) -> c_int;
     pub fn tcc_get_symbol(s: *mut TCCState, name: *const c_char) -> *mut c_void;
     pub fn tcc_add_include_path(s: *mut TCCState, pathname: *const c_char) -> c_int;
+    pub fn tcc_add_library_path(s: *mut TCCState, pathname: *const c_char) -> c_int;
+    pub fn tcc_add_library(s: *mut TCCState, libraryname: *const c_char) -> c_int;
+    pub fn tcc_add_file(s: *mut TCCState, filename: *const c_char) -> c_int;
     pub fn tcc_set_lib_path(s: *mut TCCState, pathname: *const c_char) -> c_int;
     pub fn tcc_add_symbol(s: *mut TCCState, name: *const c_char, func_ptr: *const c_void) -> c_int;
     pub fn tcc_set_options(s: *mut TCCState, options: *const c_char) -> c_int;
     pub fn tcc_output_file(s: *mut TCCState, filename: *const c_char) -> c_int;
+    pub fn tcc_set_error_func(s: *mut TCCState, opaque: *mut c_void, func: TCCErrorFunc);
+}
+
+extern "C" fn collect_tcc_message(opaque: *mut c_void, msg: *const c_char) {
+    unsafe {
+        let messages = &*(opaque as *const RefCell<Vec<String>>);
+        messages
+            .borrow_mut()
+            .push(CStr::from_ptr(msg).to_string_lossy().into_owned());
+    }
+}
+
+/// Receives a failed run-time check of a debug build as `abyss_panic`: the
+/// Abyss file and line, and what went wrong. The program aborts once it returns.
+pub type PanicHandler = extern "C" fn(file: *const c_char, line: c_int, msg: *const c_char);
+
+/// The default `abyss_panic`, which reports the failure on stderr.
+extern "C" fn report_panic(file: *const c_char, line: c_int, msg: *const c_char) {
+    let (file, msg) = unsafe { (CStr::from_ptr(file), CStr::from_ptr(msg)) };
+    eprintln!(
+        "panic at {}:{}: {}",
+        file.to_string_lossy(),
+        line,
+        msg.to_string_lossy()
+    );
+}
+
+/// `abyss_panic` for a native executable, which has no host to report to.
+const NATIVE_PANIC: &str = r#"
+extern int dprintf(int, const char *, ...);
+
+void abyss_panic(const char *file, int line, const char *msg) {
+    dprintf(2, "panic at %s:%d: %s\n", file, line, msg);
+}
+"#;
+
+/// Lines of generated C shown on either side of the one an error points at.
+const SNIPPET_CONTEXT: usize = 2;
+
+/// A failure reported by TCC while compiling, outputting or relocating code.
+#[derive(Debug, Clone)]
+pub struct JitError {
+    /// Every message TCC reported, in order.
+    pub messages: Vec<String>,
+    /// Line of the generated C code the first error points at, if any.
+    pub line: Option<usize>,
+    /// The generated C code surrounding `line`.
+    pub snippet: Option<String>,
+}
+
+impl JitError {
+    fn new(messages: Vec<String>, fallback: &str) -> Self {
+        let messages = if messages.is_empty() {
+            vec![fallback.to_string()]
+        } else {
+            messages
+        };
+        Self {
+            messages,
+            line: None,
+            snippet: None,
+        }
+    }
+
+    /// Resolves the first error to a line of `c_code` and captures the code around it.
+    fn locate(mut self, c_code: &str) -> Self {
+        self.line = generated_error_line(c_code);
+        self.snippet = self.line.map(|line| snippet(c_code, line));
+        self
+    }
+}
+
+impl fmt::Display for JitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.messages.join("\n"))?;
+        if let (Some(line), Some(snippet)) = (self.line, &self.snippet) {
+            write!(f, "\n  --> generated C, line {}\n{}", line, snippet)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for JitError {}
+
+/// Splits a TCC message such as `main.a:9: error: ...` into its file and line.
+fn error_location(msg: &str) -> Option<(&str, usize)> {
+    let (location, _) = msg.split_once(": error: ")?;
+    let (file, line) = location.rsplit_once(':')?;
+    Some((file, line.parse().ok()?))
+}
+
+/// The line of `c_code` its first compile error is on. The `#line`
+/// directives only say which Abyss line the error came from, and one Abyss
+/// line can become several lines of C, so `c_code` is compiled again with
+/// them blanked out: TCC then reports the generated line itself.
+fn generated_error_line(c_code: &str) -> Option<usize> {
+    let bare: Vec<&str> = c_code
+        .lines()
+        .map(|text| if text.starts_with("#line ") { "" } else { text })
+        .collect();
+    let bare = CString::new(bare.join("\n")).ok()?;
+
+    let jit = AbyssJit::new().ok()?;
+    unsafe {
+        tcc_compile_string(jit.state, bare.as_ptr());
+    }
+    jit.messages
+        .take()
+        .iter()
+        .find_map(|msg| error_location(msg))
+        .map(|(_, line)| line)
+}
+
+fn snippet(c_code: &str, line: usize) -> String {
+    let first = line.saturating_sub(SNIPPET_CONTEXT).max(1);
+    let last = line + SNIPPET_CONTEXT;
+    let width = last.to_string().len();
+
+    c_code
+        .lines()
+        .enumerate()
+        .map(|(index, text)| (index + 1, text))
+        .filter(|(number, _)| (first..=last).contains(number))
+        .map(|(number, text)| {
+            let marker = if number == line { '>' } else { ' ' };
+            format!("{} {:>width$} | {}", marker, number, text, width = width)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Where TCC looks for `libc` and `-l` libraries besides the `-L` paths. With
+/// the C library linked, TCC resolves every undefined symbol through the
+/// dynamic loader, which also sees the host process.
+const SYSTEM_LIBRARY_PATHS: &[&str] = &[
+    "/usr/local/lib",
+    "/usr/lib/x86_64-linux-gnu",
+    "/lib/x86_64-linux-gnu",
+    "/usr/lib/aarch64-linux-gnu",
+    "/lib/aarch64-linux-gnu",
+    "/usr/lib64",
+    "/lib64",
+    "/usr/lib",
+    "/lib",
+];
+
+/// Libraries every native executable or shared library is linked against.
+const NATIVE_LIBRARIES: &[&str] = &["c", "m"];
+
+/// The first of `SYSTEM_LIBRARY_PATHS` holding the C runtime startup files.
+fn find_crt_dir() -> Option<PathBuf> {
+    SYSTEM_LIBRARY_PATHS
+        .iter()
+        .map(Path::new)
+        .find(|dir| dir.join("crti.o").is_file())
+        .map(Path::to_path_buf)
+}
+
+/// What `Abyss::build_as` writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputKind {
+    /// An executable whose `main` calls `app_main`.
+    Executable,
+    /// A relocatable object, to be linked or archived by other tools.
+    Object,
+    /// A shared library exporting every function of the program.
+    SharedLibrary,
+}
+
+impl OutputKind {
+    /// Guesses the kind from the extension of `path`: `.o` for objects, `.so`
+    /// for shared libraries and an executable otherwise.
+    pub fn from_path(path: &str) -> Self {
+        match Path::new(path).extension().and_then(|e| e.to_str()) {
+            Some("o") => OutputKind::Object,
+            Some("so") => OutputKind::SharedLibrary,
+            _ => OutputKind::Executable,
+        }
+    }
+
+    pub fn output_type(self) -> i32 {
+        match self {
+            OutputKind::Executable => TCC_OUTPUT_EXE,
+            OutputKind::Object => TCC_OUTPUT_OBJ,
+            OutputKind::SharedLibrary => TCC_OUTPUT_DLL,
+        }
+    }
+}
+
+impl std::str::FromStr for OutputKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "exe" => Ok(OutputKind::Executable),
+            "obj" => Ok(OutputKind::Object),
+            "shared" => Ok(OutputKind::SharedLibrary),
+            other => Err(format!(
+                "unknown output kind `{}`, expected `exe`, `obj` or `shared`",
+                other
+            )),
+        }
+    }
 }
 
 pub struct AbyssJit {
     state: *mut TCCState,
-    _temp_dir: TempDir,
+    temp_dir: TempDir,
     relocated: bool,
+    messages: Box<RefCell<Vec<String>>>,
+    /// Where the C runtime startup files are, when linking a native binary.
+    crt_dir: Option<PathBuf>,
 }
 
 impl AbyssJit {
     pub fn new() -> Result<Self, String> {
+        Self::with_output_type(TCC_OUTPUT_MEMORY)
+    }
+
+    pub fn with_output_type(output_type: i32) -> Result<Self, String> {
         unsafe {
             let temp_dir =
                 TempDir::new().map_err(|e| format!("Failed to create temp dir: {}", e))?;
@@ -64,53 +295,151 @@ impl AbyssJit {
                 return Err("Failed to create TCC state".to_string());
             }
 
-            tcc_set_output_type(state, TCC_OUTPUT_MEMORY);
-
+            // The lib path must be set before the output type, which resolves
+            // the runtime paths from it.
             tcc_set_lib_path(state, c_root_path.as_ptr());
 
+            // TCC looks for the startup files under the prefix it was
+            // configured with, which misses multiarch directories. Native
+            // binaries get their startup files and libraries from `link_native`.
+            let native = output_type == TCC_OUTPUT_EXE || output_type == TCC_OUTPUT_DLL;
+            if native {
+                tcc_set_options(state, c"-nostdlib".as_ptr());
+            }
+
+            // The unwind tables TCC writes into objects are rejected by GNU
+            // ld, which is what usually links them.
+            if output_type == TCC_OUTPUT_OBJ {
+                tcc_set_options(state, c"-fno-asynchronous-unwind-tables".as_ptr());
+            }
+
+            tcc_set_output_type(state, output_type);
+
             tcc_add_include_path(state, c_include_path.as_ptr());
 
-            let opt_nostdlib = CString::new("-nostdlib").unwrap();
-            tcc_set_options(state, opt_nostdlib.as_ptr());
+            tcc_add_library_path(state, c_root_path.as_ptr());
+            for dir in SYSTEM_LIBRARY_PATHS {
+                if Path::new(dir).is_dir() {
+                    let c_dir = CString::new(*dir).unwrap();
+                    tcc_add_library_path(state, c_dir.as_ptr());
+                }
+            }
+
+            let messages = Box::new(RefCell::new(Vec::new()));
+            tcc_set_error_func(
+                state,
+                &*messages as *const RefCell<Vec<String>> as *mut c_void,
+                collect_tcc_message,
+            );
 
-            Ok(AbyssJit {
+            let mut jit = AbyssJit {
                 state,
-                _temp_dir: temp_dir,
+                temp_dir,
                 relocated: false,
-            })
+                messages,
+                crt_dir: None,
+            };
+
+            if native {
+                let crt_dir = find_crt_dir().ok_or(
+                    "C runtime startup files (crti.o) not found in the system library paths",
+                )?;
+                if output_type == TCC_OUTPUT_EXE {
+                    jit.add_file(&crt_dir.join("crt1.o"))
+                        .map_err(|e| e.to_string())?;
+                }
+                jit.add_file(&crt_dir.join("crti.o"))
+                    .map_err(|e| e.to_string())?;
+                jit.crt_dir = Some(crt_dir);
+            }
+
+            Ok(jit)
+        }
+    }
+
+    fn add_file(&mut self, path: &Path) -> Result<(), JitError> {
+        let c_path = CString::new(path.to_str().unwrap()).unwrap();
+        unsafe {
+            if tcc_add_file(self.state, c_path.as_ptr()) == -1 {
+                return Err(self.take_error(&format!("cannot add `{}`", path.display())));
+            }
         }
+        Ok(())
     }
 
-    pub fn compile(&mut self, c_code: &str) -> Result<(), String> {
+    /// Adds what follows the program on a native link line: the C library,
+    /// TCC's runtime support and the closing startup file.
+    fn link_native(&mut self) -> Result<(), JitError> {
+        let Some(crt_dir) = self.crt_dir.clone() else {
+            return Ok(());
+        };
+
+        for library in NATIVE_LIBRARIES {
+            self.add_library(library)?;
+        }
+        let runtime = self.temp_dir.path().join("libtcc1.a");
+        self.add_file(&runtime)?;
+        self.add_file(&crt_dir.join("crtn.o"))
+    }
+
+    pub fn compile(&mut self, c_code: &str) -> Result<(), JitError> {
         if self.relocated {
-            return Err("Cannot compile after relocation. Create a new instance.".to_string());
+            return Err(JitError::new(
+                Vec::new(),
+                "Cannot compile after relocation. Create a new instance.",
+            ));
         }
 
         let c_str = CString::new(c_code).unwrap();
         unsafe {
             let ret = tcc_compile_string(self.state, c_str.as_ptr());
             if ret == -1 {
-                return Err("Compilation failed".to_string());
+                return Err(self.take_error("Compilation failed").locate(c_code));
             }
         }
+        self.messages.borrow_mut().clear();
         Ok(())
     }
 
-    pub fn compile_to_file(&mut self, c_code: &str, output_filename: &str) -> Result<(), String> {
+    /// Drains the messages TCC reported since the last call into an error,
+    /// falling back to `fallback` when it gave no details.
+    fn take_error(&self, fallback: &str) -> JitError {
+        JitError::new(self.messages.take(), fallback)
+    }
+
+    pub fn compile_to_file(&mut self, c_code: &str, output_filename: &str) -> Result<(), JitError> {
         let c_str = CString::new(c_code).unwrap();
         let out_name = CString::new(output_filename).unwrap();
 
         unsafe {
-            tcc_set_output_type(self.state, TCC_OUTPUT_EXE);
-
             let ret = tcc_compile_string(self.state, c_str.as_ptr());
             if ret == -1 {
-                return Err("Compilation failed".to_string());
+                return Err(self.take_error("Compilation failed").locate(c_code));
             }
 
+            self.link_native()?;
+
             let ret = tcc_output_file(self.state, out_name.as_ptr());
             if ret == -1 {
-                return Err("Failed to output file".to_string());
+                return Err(self.take_error("Failed to output file"));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn add_library_path(&mut self, path: &str) {
+        let c_path = CString::new(path).unwrap();
+        unsafe {
+            tcc_add_library_path(self.state, c_path.as_ptr());
+        }
+    }
+
+    /// Links the library `name` (`m` for `libm`), searched in the library paths.
+    pub fn add_library(&mut self, name: &str) -> Result<(), JitError> {
+        let c_name = CString::new(name).unwrap();
+        unsafe {
+            if tcc_add_library(self.state, c_name.as_ptr()) == -1 {
+                return Err(self.take_error(&format!("library `{}` not found", name)));
             }
         }
         Ok(())
@@ -123,7 +452,7 @@ impl AbyssJit {
         }
     }
 
-    pub fn finalize(&mut self) -> Result<(), String> {
+    pub fn finalize(&mut self) -> Result<(), JitError> {
         if self.relocated {
             return Ok(());
         }
@@ -131,7 +460,7 @@ impl AbyssJit {
         unsafe {
             let ret = tcc_relocate(self.state, TCC_RELOCATE_AUTO);
             if ret < 0 {
-                return Err("Failed to relocate code (memory/permission error)".to_string());
+                return Err(self.take_error("Failed to relocate code (memory/permission error)"));
             }
         }
 
@@ -147,15 +476,19 @@ impl AbyssJit {
             }
         }
 
-        let c_name = CString::new(func_name).unwrap();
-        unsafe {
-            let sym = tcc_get_symbol(self.state, c_name.as_ptr());
-            if sym.is_null() {
-                None
-            } else {
-                Some(std::mem::transmute_copy(&sym))
-            }
+        let sym = self.symbol(func_name)?;
+        Some(unsafe { std::mem::transmute_copy(&sym) })
+    }
+
+    /// Address of `name` in the relocated code, or `None` before `finalize`.
+    pub fn symbol(&self, name: &str) -> Option<*const c_void> {
+        if !self.relocated {
+            return None;
         }
+
+        let c_name = CString::new(name).unwrap();
+        let sym = unsafe { tcc_get_symbol(self.state, c_name.as_ptr()) };
+        if sym.is_null() { None } else { Some(sym) }
     }
 }
 
@@ -175,6 +508,13 @@ pub struct Abyss<'a, T: Target> {
     jit: AbyssJit,
     path: String,
     compiled_code: String,
+    entry: Option<LirFunctionDef>,
+    host_fns: Vec<HostFn>,
+    exports: HashMap<String, FunctionInfo>,
+    library_paths: Vec<String>,
+    libraries: Vec<String>,
+    debug: bool,
+    panic_handler: PanicHandler,
 }
 
 impl<'a, T: Target> Abyss<'a, T> {
@@ -185,48 +525,128 @@ impl<'a, T: Target> Abyss<'a, T> {
             target,
             jit: AbyssJit::new().unwrap(),
             compiled_code: String::new(),
+            entry: None,
+            host_fns: Vec::new(),
+            exports: HashMap::new(),
+            library_paths: Vec::new(),
+            libraries: Vec::new(),
+            debug: false,
+            panic_handler: report_panic,
         }
     }
 
+    /// Compiles a debug build, which checks indices, pointers, divisors,
+    /// signed arithmetic and union reads at run time and calls `abyss_panic`
+    /// when a check fails.
+    pub fn set_debug(&mut self, enabled: bool) {
+        self.debug = enabled;
+        self.target.set_debug(enabled);
+    }
+
+    /// Replaces the `abyss_panic` of programs loaded into the JIT. Shared
+    /// libraries and object files leave it to whoever links them, while an
+    /// executable reports to stderr.
+    pub fn set_panic_handler(&mut self, handler: PanicHandler) {
+        self.panic_handler = handler;
+    }
+
     pub fn parse_error(&self) -> String {
-        self.parser.format_errors("main.a")
+        self.parser.format_errors()
+    }
+
+    pub fn format_diagnostics(&self, diagnostics: &[Diagnostic]) -> String {
+        self.parser.format_diagnostics(diagnostics)
+    }
+
+    pub fn parse(&mut self) -> Result<Program, String> {
+        let mut prog = self.parser.parse_program();
+
+        if !self.host_fns.is_empty() {
+            let declarations: Vec<&str> = self
+                .host_fns
+                .iter()
+                .map(|h| h.declaration.as_str())
+                .collect();
+            let externs = self
+                .parser
+                .parse_declarations(HOST_FILE, &declarations.join("\n"));
+            prog.functions.splice(0..0, externs);
+        }
+
+        if self.parser.has_errors() {
+            return Err(self.parse_error());
+        }
+        Ok(prog)
     }
 
-    pub fn parse(&mut self) -> Program {
-        let prog = self.parser.parse_program();
-        println!("{}", self.parser.format_errors(&self.path));
-        prog
+    fn flatten(&mut self) -> Result<(FlatProgram, Vec<Diagnostic>), String> {
+        let program = self.parse()?;
+
+        let mut flattener = Flattener::new();
+        let flat = flattener.flatten(program);
+
+        Ok((flat, flattener.diagnostics().to_vec()))
+    }
+
+    pub fn parse_flatten(&mut self) -> Result<FlatProgram, String> {
+        let (flat, diagnostics) = self.flatten()?;
+
+        self.report(&diagnostics)?;
+        Ok(flat)
     }
 
-    pub fn parse_flatten(&mut self) -> FlatProgram {
-        let program = self.parse();
+    pub fn parse_typed(&mut self) -> Result<FlatProgram, String> {
+        let (flat, mut diagnostics) = self.flatten()?;
 
-        let flattener = Flattener::new();
+        let mut tc = TypeChecker::new().with_debug(self.debug);
+        let typed = tc.check(flat);
 
-        flattener.flatten(program)
+        diagnostics.extend_from_slice(tc.diagnostics());
+        self.report(&diagnostics)?;
+        Ok(typed)
     }
 
-    pub fn parse_typed(&mut self) -> FlatProgram {
-        let program = self.parse_flatten();
+    fn report(&self, diagnostics: &[Diagnostic]) -> Result<(), String> {
+        if diagnostics.iter().any(|d| d.is_error()) {
+            return Err(self.format_diagnostics(diagnostics));
+        }
+        if !diagnostics.is_empty() {
+            eprint!("{}", self.format_diagnostics(diagnostics));
+        }
+        Ok(())
+    }
 
-        let tc = TypeChecker::new();
-        tc.check(program)
+    pub fn check(&mut self) -> Result<(), String> {
+        self.parse_typed().map(|_| ())
     }
 
-    pub fn build_ir(&mut self) -> LirProgram {
-        let program = self.parse_typed();
+    pub fn build_ir(&mut self) -> Result<LirProgram, String> {
+        let program = self.parse_typed()?;
 
-        let ctx = Collector::collect(&program);
-        let ir = Ir::build(&program, ctx.expect("Context error."));
+        let ctx = Collector::collect(&program)
+            .map_err(|e| self.format_diagnostics(&[Diagnostic::error(e)]))?;
+        let ir = Ir::build(&program, ctx);
 
-        ir
+        embed::check_host_fns(&self.host_fns, &ir)?;
+        self.exports = embed::exports(&ir);
+        self.entry = ir
+            .functions
+            .iter()
+            .find(|f| f.name == ENTRY_POINT && !f.is_extern)
+            .cloned();
+
+        Ok(ir)
     }
 
-    pub fn compile(&mut self) -> String {
-        let ir = self.build_ir();
+    pub fn compile(&mut self) -> Result<String, String> {
+        let ir = self.build_ir()?;
+        Ok(self.generate(&ir))
+    }
 
-        let mut compiler = Director::new(&mut self.target);
-        compiler.process_program(&ir);
+    /// Runs an already built program through the target.
+    pub fn generate(&mut self, ir: &LirProgram) -> String {
+        let mut compiler = Director::new(&mut self.target).with_source_files(self.parser.files());
+        compiler.process_program(ir);
 
         self.target.emit()
     }
@@ -235,70 +655,235 @@ impl<'a, T: Target> Abyss<'a, T> {
         self.compiled_code.clone()
     }
 
-    pub fn add_fn(&mut self, name: &str, func: *const c_void) {
-        self.jit.add_function(name, func);
+    /// Makes `func` callable from the script under `declaration`, e.g.
+    /// `fn gain(x: f64): f64`. The declaration is added to the program as an
+    /// extern, and the Rust signature is checked against it when building.
+    pub fn register_fn<F: AbyssFn>(&mut self, declaration: &str, func: F) -> Result<(), String> {
+        self.register(HostFn::new(declaration, func)?);
+        Ok(())
+    }
+
+    pub fn register(&mut self, host: HostFn) {
+        self.host_fns.push(host);
     }
-    pub fn get_fn<F>(&mut self, name: &str) -> Option<F> {
-        self.jit.get_function(name)
+
+    /// Looks up a function of the loaded program, checking that it was
+    /// compiled with the signature of `F`.
+    pub fn get_fn<F: AbyssFn>(&self, name: &str) -> Result<Export<'_, F>, String> {
+        let info = self
+            .exports
+            .get(name)
+            .ok_or_else(|| format!("no function `{}` in the program", name))?;
+        let ptr = self
+            .jit
+            .symbol(name)
+            .ok_or_else(|| format!("`{}` is not loaded; call `process` first", name))?;
+
+        Export::new(name, info, ptr)
     }
 
+    /// Binds the registered host functions. Every other extern is left to
+    /// TCC, which looks it up in the linked libraries and the host process.
     fn link(&mut self) {
-        unsafe extern "C" {
-            fn printf(format: *const c_char, ...) -> c_int;
-            fn memset(s: *mut c_void, c: c_int, n: usize) -> *mut c_void;
-            fn memcpy(dest: *mut c_void, src: *const c_void, n: usize) -> *mut c_void;
+        for host in &self.host_fns {
+            self.jit.add_function(&host.name, host.ptr);
+        }
+        if self.debug {
+            self.jit
+                .add_function("abyss_panic", self.panic_handler as *const c_void);
+        }
+    }
+
+    pub fn add_library_path(&mut self, path: &str) {
+        self.jit.add_library_path(path);
+        self.library_paths.push(path.to_string());
+    }
 
-            fn malloc(size: usize) -> *mut c_void;
-            fn realloc(ptr: *mut c_void, size: usize) -> *mut c_void;
-            fn free(ptr: *mut c_void);
+    /// Links the library `name` into the JIT right away, and into every
+    /// native binary built later.
+    pub fn add_library(&mut self, name: &str) -> Result<(), String> {
+        self.jit.add_library(name).map_err(|e| e.to_string())?;
+        self.libraries.push(name.to_string());
+        Ok(())
+    }
 
-            fn exit(status: c_int) -> !;
+    pub fn process(&mut self) -> Result<(), String> {
+        let code = self.compile()?;
+        self.load(&code)
+    }
 
-            fn scanf(format: *const std::ffi::c_char, ...) -> std::ffi::c_int;
+    /// Compiles generated `code` into the JIT and relocates it.
+    pub fn load(&mut self, code: &str) -> Result<(), String> {
+        self.compiled_code = code.to_string();
 
-            fn getchar() -> std::ffi::c_int;
-            fn atoll(s: *const std::ffi::c_char) -> std::ffi::c_longlong;
-            fn atof(s: *const std::ffi::c_char) -> std::ffi::c_double;
-        }
+        self.link();
+        let jit = &mut self.jit;
 
-        let add_fn = |name: &str, ptr: *const c_void| {
-            self.jit.add_function(name, ptr);
-        };
+        jit.compile(code).map_err(|e| e.to_string())?;
+        jit.finalize().map_err(|e| e.to_string())?;
 
-        add_fn("printf", printf as *const c_void);
-        add_fn("memset", memset as *const c_void);
-        add_fn("memcpy", memcpy as *const c_void);
-        add_fn("malloc", malloc as *const c_void);
-        add_fn("realloc", realloc as *const c_void);
-        add_fn("free", free as *const c_void);
-        add_fn("exit", exit as *const c_void);
-        add_fn("scanf", scanf as *const std::ffi::c_void);
-        add_fn("getchar", getchar as *const std::ffi::c_void);
-        add_fn("atoll", atoll as *const std::ffi::c_void);
-        add_fn("atof", atof as *const std::ffi::c_void);
+        Ok(())
     }
 
-    pub fn process(&mut self) {
-        let t = Instant::now();
+    /// Gives up the pipeline, keeping only the JIT holding the loaded code.
+    pub fn into_jit(self) -> AbyssJit {
+        self.jit
+    }
 
-        let code = self.compile();
+    /// Builds `output_filename`, picking the kind of binary from its extension.
+    pub fn build(&mut self, output_filename: &str) -> Result<(), String> {
+        self.build_as(output_filename, OutputKind::from_path(output_filename))
+    }
+
+    /// Compiles the program ahead of time into a native binary of `kind`,
+    /// linked against libc, libm and the libraries added so far.
+    pub fn build_as(&mut self, output_filename: &str, kind: OutputKind) -> Result<(), String> {
+        let mut code = self.compile()?;
+        if self.debug && kind == OutputKind::Executable {
+            code.push_str(NATIVE_PANIC);
+        }
         self.compiled_code = code.clone();
-        //println!("Compiled code: {}", code);
 
-        self.link();
-        let jit = &mut self.jit;
+        let mut jit = AbyssJit::with_output_type(kind.output_type())?;
+        for path in &self.library_paths {
+            jit.add_library_path(path);
+        }
+        if kind != OutputKind::Object {
+            for library in &self.libraries {
+                jit.add_library(library).map_err(|e| e.to_string())?;
+            }
+        }
 
-        jit.compile(&code).expect("Compile error");
-        jit.finalize().expect("Relocation error");
+        jit.compile_to_file(&code, output_filename)
+            .map_err(|e| e.to_string())
+    }
+
+    pub fn run(&mut self) -> Result<i32, String> {
+        let path = self.path.clone();
+        self.run_with_args(&[path])
+    }
 
-        println!("Finished in: {}ms", t.elapsed().as_millis());
+    /// Runs `app_main`, handing it `args` as `argc`/`argv` when it declares
+    /// them, and returns its result as the process exit code.
+    pub fn run_with_args(&mut self, args: &[String]) -> Result<i32, String> {
+        self.process()?;
+        self.entry_point()?.call(args)
     }
-    pub fn run(&mut self) {
-        self.process();
-        println!("Running...\n");
-        (self
+
+    /// Resolves `app_main` in the loaded program, checking its signature.
+    pub fn entry_point(&mut self) -> Result<EntryPoint, String> {
+        let entry = self
+            .entry
+            .clone()
+            .ok_or_else(|| format!("`{}` not found", ENTRY_POINT))?;
+
+        let ret = match &entry.return_type {
+            LirType::Void => EntryInt::None,
+            LirType::Bool => EntryInt::Bool,
+            LirType::I32 => EntryInt::I32,
+            LirType::I64 | LirType::Isize => EntryInt::I64,
+            other => {
+                return Err(format!(
+                    "`{}` must return nothing, bool, i32 or i64, found {:?}",
+                    ENTRY_POINT, other
+                ));
+            }
+        };
+
+        let param_types: Vec<&LirType> = entry.params.iter().map(|(_, ty)| ty).collect();
+        let argc = match param_types.as_slice() {
+            [] => EntryInt::None,
+            [LirType::I32, LirType::Pointer(_)] => EntryInt::I32,
+            [LirType::I64, LirType::Pointer(_)] => EntryInt::I64,
+            _ => {
+                return Err(format!(
+                    "`{}` must take no parameters or `(argc: i32, argv: &&u8)`",
+                    ENTRY_POINT
+                ));
+            }
+        };
+
+        let ptr = self
             .jit
-            .get_function::<extern "C" fn()>("app_main")
-            .expect("app_main not found"))();
+            .get_function::<*const c_void>(ENTRY_POINT)
+            .ok_or_else(|| format!("`{}` not found", ENTRY_POINT))?;
+
+        Ok(EntryPoint { ptr, argc, ret })
+    }
+
+    /// The entry file and every module file and directory the last parse read.
+    pub fn loaded_paths(&self) -> Vec<PathBuf> {
+        self.parser.loaded_paths()
+    }
+}
+
+/// Width of the integer `app_main` takes as `argc` or returns, if any. It
+/// may also return a one-byte `bool`, which is never an `argc`.
+#[derive(Debug, Clone, Copy)]
+enum EntryInt {
+    None,
+    Bool,
+    I32,
+    I64,
+}
+
+/// `app_main` of a loaded program. It can be called from any thread, but
+/// only while the `AbyssJit` holding its code is alive.
+#[derive(Debug, Clone, Copy)]
+pub struct EntryPoint {
+    ptr: *const c_void,
+    argc: EntryInt,
+    ret: EntryInt,
+}
+
+unsafe impl Send for EntryPoint {}
+
+impl EntryPoint {
+    /// Calls `app_main` with `args` as `argc`/`argv` when it declares them and
+    /// returns its result as an exit code.
+    pub fn call(&self, args: &[String]) -> Result<i32, String> {
+        let c_args = args
+            .iter()
+            .map(|a| CString::new(a.as_str()).map_err(|e| e.to_string()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut argv: Vec<*const c_char> = c_args.iter().map(|a| a.as_ptr()).collect();
+        argv.push(std::ptr::null());
+        let argv = argv.as_ptr();
+
+        macro_rules! call_entry {
+            ($($arg_ty:ty),* ; $($arg:expr),*) => {
+                unsafe {
+                    match self.ret {
+                        EntryInt::None => {
+                            std::mem::transmute::<*const c_void, extern "C" fn($($arg_ty),*)>(
+                                self.ptr,
+                            )($($arg),*);
+                            0
+                        }
+                        EntryInt::Bool => std::mem::transmute::<
+                            *const c_void,
+                            extern "C" fn($($arg_ty),*) -> bool,
+                        >(self.ptr)($($arg),*) as i32,
+                        EntryInt::I32 => std::mem::transmute::<
+                            *const c_void,
+                            extern "C" fn($($arg_ty),*) -> i32,
+                        >(self.ptr)($($arg),*),
+                        EntryInt::I64 => std::mem::transmute::<
+                            *const c_void,
+                            extern "C" fn($($arg_ty),*) -> i64,
+                        >(self.ptr)($($arg),*) as i32,
+                    }
+                }
+            };
+        }
+
+        let code = match self.argc {
+            EntryInt::None => call_entry!(;),
+            EntryInt::Bool => unreachable!("`{}` never takes a bool", ENTRY_POINT),
+            EntryInt::I32 => call_entry!(i32, *const *const c_char; args.len() as i32, argv),
+            EntryInt::I64 => call_entry!(i64, *const *const c_char; args.len() as i64, argv),
+        };
+
+        Ok(code)
     }
 }
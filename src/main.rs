@@ -1,8 +1,328 @@
-use abyss::{Abyss, CTarget};
+use std::{
+    env, fs,
+    path::PathBuf,
+    process::{self, Child},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use abyss::{Abyss, CTarget, OutputKind};
+
+const USAGE: &str = "\
+Usage: abyss <command> <file.a> [options]
+
+Commands:
+    run <file.a> [--watch] [--debug] [-l lib] [-L dir] [-- args...]
+                                JIT-compile and run `app_main`, rebuilding
+                                on every change with `--watch`
+    build <file.a> -o <output> [--emit exe|obj|shared] [--debug] [-l lib] [-L dir]
+                                Compile to a native executable, object file
+                                or shared library; without `--emit` the kind
+                                follows the extension of <output> (.o, .so)
+    check <file.a>              Parse and type-check only
+    emit-c <file.a>             Print the generated C code
+
+Externs are resolved against libraries given with `-l` (searched in `-L`
+directories), then against the symbols already loaded in the process.
+`--debug` checks indices, null pointers, division by zero, signed overflow
+(`MIN / -1` and `-MIN` included) and union variant reads at run time,
+reporting the failing source line.
+";
+
+enum Command {
+    Run { args: Vec<String>, watch: bool },
+    Build { output: String, kind: OutputKind },
+    Check,
+    EmitC,
+}
+
+struct Cli {
+    command: Command,
+    path: String,
+    link: LinkOptions,
+    debug: bool,
+}
+
+/// Libraries the program's externs are resolved against, from `-l`/`-L`.
+#[derive(Default)]
+struct LinkOptions {
+    libraries: Vec<String>,
+    library_paths: Vec<String>,
+}
+
+impl LinkOptions {
+    /// Consumes `arg` (and its value from `rest`) if it is `-l`/`-L`.
+    fn parse_flag(
+        &mut self,
+        arg: &str,
+        rest: &mut impl Iterator<Item = String>,
+    ) -> Result<bool, String> {
+        let (list, value) = match arg {
+            "-l" | "-L" => {
+                let value = rest
+                    .next()
+                    .ok_or_else(|| format!("`{}` requires a value", arg))?;
+                (arg, value)
+            }
+            _ if arg.starts_with("-l") || arg.starts_with("-L") => {
+                (&arg[..2], arg[2..].to_string())
+            }
+            _ => return Ok(false),
+        };
+
+        if list == "-l" {
+            self.libraries.push(value);
+        } else {
+            self.library_paths.push(value);
+        }
+        Ok(true)
+    }
+
+    fn apply(&self, abyss: &mut Abyss<CTarget>) -> Result<(), String> {
+        for path in &self.library_paths {
+            abyss.add_library_path(path);
+        }
+        for library in &self.libraries {
+            abyss.add_library(library)?;
+        }
+        Ok(())
+    }
+}
+
+impl Cli {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let command = args.next().ok_or("missing command")?;
+        let path = args.next().ok_or("missing source file")?;
+        let mut link = LinkOptions::default();
+        let mut debug = false;
+
+        let command = match command.as_str() {
+            "run" => {
+                let mut watch = false;
+                let mut script_args = Vec::new();
+
+                while let Some(arg) = args.next() {
+                    match arg.as_str() {
+                        "--watch" => watch = true,
+                        "--debug" => debug = true,
+                        "--" => script_args = args.by_ref().collect(),
+                        _ if link.parse_flag(&arg, &mut args)? => {}
+                        _ => return Err(format!("unexpected argument `{}`", arg)),
+                    }
+                }
+
+                Command::Run {
+                    args: script_args,
+                    watch,
+                }
+            }
+            "build" => {
+                let mut output = None;
+                let mut kind = None;
+
+                while let Some(arg) = args.next() {
+                    match arg.as_str() {
+                        "-o" => output = Some(args.next().ok_or("`-o` requires a value")?),
+                        "--emit" => {
+                            kind = Some(args.next().ok_or("`--emit` requires a value")?.parse()?)
+                        }
+                        "--debug" => debug = true,
+                        _ if link.parse_flag(&arg, &mut args)? => {}
+                        _ => return Err(format!("unexpected argument `{}`", arg)),
+                    }
+                }
+
+                let output = output.ok_or("`build` requires `-o <output>`")?;
+                Command::Build {
+                    kind: kind.unwrap_or_else(|| OutputKind::from_path(&output)),
+                    output,
+                }
+            }
+            "check" => Command::Check,
+            "emit-c" => Command::EmitC,
+            other => return Err(format!("unknown command `{}`", other)),
+        };
+
+        if let Some(arg) = args.next() {
+            return Err(format!("unexpected argument `{}`", arg));
+        }
+
+        Ok(Self {
+            command,
+            path,
+            link,
+            debug,
+        })
+    }
+}
+
+fn execute(cli: Cli) -> Result<i32, String> {
+    if let Command::Run { watch: true, .. } = &cli.command {
+        watch(&cli.path, &cli.link, cli.debug);
+    }
+
+    let source =
+        fs::read_to_string(&cli.path).map_err(|e| format!("cannot read `{}`: {}", cli.path, e))?;
+
+    let mut abyss = Abyss::new(&source, &cli.path, CTarget::new());
+    abyss.set_debug(cli.debug);
+    cli.link.apply(&mut abyss)?;
+
+    match cli.command {
+        Command::Run { args, .. } => {
+            let mut argv = vec![cli.path.clone()];
+            argv.extend(args);
+            abyss.run_with_args(&argv)
+        }
+        Command::Build { output, kind } => abyss.build_as(&output, kind).map(|_| 0),
+        Command::Check => abyss.check().map(|_| 0),
+        Command::EmitC => {
+            println!("{}", abyss.compile()?);
+            Ok(0)
+        }
+    }
+}
+
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// A good build running as a child process.
+struct Run {
+    generation: usize,
+    child: Child,
+}
+
+/// Builds and runs `path` again every time it or one of its modules changes.
+///
+/// Each build is run by a child process of its own, so the previous one can
+/// be killed before the next starts, wherever its `app_main` is. A build that
+/// fails to compile only prints its errors, so whatever the last good build
+/// started keeps running.
+fn watch(path: &str, link: &LinkOptions, debug: bool) -> ! {
+    let mut run: Option<Run> = None;
+    let mut generation = 0;
+
+    loop {
+        generation += 1;
+        let (paths, build) = build(path, link, debug);
+
+        match build.and_then(|_| spawn_run()) {
+            Ok(child) => {
+                if let Some(mut previous) = run.take() {
+                    stop(&mut previous);
+                }
+                eprintln!("[watch] running build #{}", generation);
+                run = Some(Run { generation, child });
+            }
+            Err(e) => {
+                eprint!("{}", e);
+                if !e.ends_with('\n') {
+                    eprintln!();
+                }
+                eprintln!(
+                    "[watch] build #{} failed, keeping the previous build",
+                    generation
+                );
+            }
+        }
+
+        let snapshot = modification_times(&paths);
+        while modification_times(&paths) == snapshot {
+            thread::sleep(POLL_INTERVAL);
+            reap(&mut run);
+        }
+    }
+}
+
+/// Checks that `path` builds, returning the files it read even when it fails.
+fn build(path: &str, link: &LinkOptions, debug: bool) -> (Vec<PathBuf>, Result<(), String>) {
+    let source = match fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(e) => {
+            let error = format!("cannot read `{}`: {}", path, e);
+            return (vec![PathBuf::from(path)], Err(error));
+        }
+    };
+
+    let mut abyss = Abyss::new(&source, path, CTarget::new());
+    abyss.set_debug(debug);
+    let built = link
+        .apply(&mut abyss)
+        .and_then(|_| abyss.process())
+        .and_then(|_| abyss.entry_point())
+        .map(|_| ());
+
+    (abyss.loaded_paths(), built)
+}
+
+/// Starts this executable again with the arguments it was given, minus
+/// `--watch`: a single run of the script.
+fn spawn_run() -> Result<Child, String> {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let options_end = args.iter().position(|a| a == "--").unwrap_or(args.len());
+    if let Some(index) = args[..options_end].iter().position(|a| a == "--watch") {
+        args.remove(index);
+    }
+
+    let exe = env::current_exe().map_err(|e| format!("cannot find `abyss` itself: {}", e))?;
+    process::Command::new(exe)
+        .args(args)
+        .spawn()
+        .map_err(|e| format!("cannot start the run: {}", e))
+}
+
+fn modification_times(paths: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    paths
+        .iter()
+        .map(|p| fs::metadata(p).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+/// Kills `run` if its `app_main` has not returned yet.
+fn stop(run: &mut Run) {
+    if let Ok(None) = run.child.try_wait() {
+        let _ = run.child.kill();
+        let _ = run.child.wait();
+        eprintln!("[watch] stopped build #{}", run.generation);
+    } else {
+        report(run);
+    }
+}
+
+/// Reports and forgets `run` once its `app_main` has returned.
+fn reap(run: &mut Option<Run>) {
+    if run
+        .as_mut()
+        .is_some_and(|r| !matches!(r.child.try_wait(), Ok(None)))
+        && let Some(mut finished) = run.take()
+    {
+        report(&mut finished);
+    }
+}
+
+fn report(run: &mut Run) {
+    match run.child.wait() {
+        Ok(status) => match status.code() {
+            Some(code) => eprintln!("[watch] build #{} exited with {}", run.generation, code),
+            None => eprintln!("[watch] build #{} was killed: {}", run.generation, status),
+        },
+        Err(e) => eprintln!("[watch] build #{}: {}", run.generation, e),
+    }
+}
 
 fn main() {
-    let mut abyss = Abyss::new(include_str!("../main.a"), "main.a", CTarget::new());
-    abyss.run();
-    //println!("{}", abyss.emit())
-    //println!("{}", abyss.compile());
+    let cli = match Cli::parse(env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+
+    match execute(cli) {
+        Ok(code) => process::exit(code),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
 }
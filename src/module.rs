@@ -0,0 +1,207 @@
+use std::{
+    collections::HashMap,
+    ffi::c_void,
+    fs,
+    sync::{Arc, Mutex, RwLock},
+};
+
+use abyss_analyzer::{lir::LirType, symbols::FunctionInfo};
+
+use crate::{Abyss, AbyssFn, AbyssJit, CTarget, Export, HostFn, embed};
+
+/// Prefix of the symbols holding `sizeof` each persistent global, appended to
+/// the generated code so layouts can be compared across reloads.
+const PERSIST_SIZE_PREFIX: &str = "__abyss_sizeof_";
+
+struct PersistentGlobal {
+    name: String,
+    ty: LirType,
+    ptr: *mut u8,
+    size: usize,
+}
+
+/// One compiled generation of an `AbyssModule`.
+///
+/// Its TCC state is deleted when the last handle to it is dropped, so function
+/// pointers taken from an instance stay valid for as long as it is held.
+pub struct ModuleInstance {
+    _jit: AbyssJit,
+    generation: u64,
+    functions: HashMap<String, (*const c_void, FunctionInfo)>,
+    persistent: Vec<PersistentGlobal>,
+}
+
+// SAFETY: the code is relocated before the instance is shared and never
+// modified afterwards. Only the addresses resolved while building are read;
+// the TCC state itself is touched again only when the instance is dropped.
+unsafe impl Send for ModuleInstance {}
+unsafe impl Sync for ModuleInstance {}
+
+impl ModuleInstance {
+    fn build(
+        source: &str,
+        path: &str,
+        host_fns: &[HostFn],
+        generation: u64,
+    ) -> Result<Self, String> {
+        let mut abyss = Abyss::new(source, path, CTarget::new());
+        for host in host_fns {
+            abyss.register(host.clone());
+        }
+
+        let ir = abyss.build_ir()?;
+        let mut code = abyss.generate(&ir);
+
+        let persistent_globals: Vec<_> = ir.globals.iter().filter(|g| g.is_persistent).collect();
+        for global in &persistent_globals {
+            code.push_str(&format!(
+                "unsigned long long {}{} = sizeof({});\n",
+                PERSIST_SIZE_PREFIX, global.name, global.name
+            ));
+        }
+
+        abyss.load(&code)?;
+        let mut jit = abyss.into_jit();
+
+        let functions = embed::exports(&ir)
+            .into_iter()
+            .filter_map(|(name, info)| {
+                let ptr = jit.symbol(&name)?;
+                Some((name, (ptr, info)))
+            })
+            .collect();
+
+        let mut persistent = Vec::new();
+        for global in persistent_globals {
+            let missing = || format!("Persistent global `{}` not found", global.name);
+            let ptr = jit
+                .get_function::<*mut u8>(&global.name)
+                .ok_or_else(missing)?;
+            let size = jit
+                .get_function::<*const u64>(&format!("{}{}", PERSIST_SIZE_PREFIX, global.name))
+                .ok_or_else(missing)?;
+
+            persistent.push(PersistentGlobal {
+                name: global.name.clone(),
+                ty: global.ty.clone(),
+                ptr,
+                size: unsafe { *size } as usize,
+            });
+        }
+
+        Ok(Self {
+            _jit: jit,
+            generation,
+            functions,
+            persistent,
+        })
+    }
+
+    /// Copies every persistent global `previous` shares with this instance by
+    /// name, type and size.
+    fn migrate_from(&self, previous: &ModuleInstance) {
+        for global in &self.persistent {
+            let old = previous.persistent.iter().find(|old| {
+                old.name == global.name && old.ty == global.ty && old.size == global.size
+            });
+
+            if let Some(old) = old {
+                unsafe {
+                    std::ptr::copy_nonoverlapping(old.ptr, global.ptr, global.size);
+                }
+            }
+        }
+    }
+
+    /// How many reloads preceded this instance; the first build is generation 0.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Looks up an exported function, checking that it was compiled with the
+    /// signature of `F`.
+    pub fn get_fn<F: AbyssFn>(&self, name: &str) -> Result<Export<'_, F>, String> {
+        let (ptr, info) = self
+            .functions
+            .get(name)
+            .ok_or_else(|| format!("no function `{}` in the module", name))?;
+        Export::new(name, info, *ptr)
+    }
+
+    pub fn functions(&self) -> impl Iterator<Item = &str> {
+        self.functions.keys().map(String::as_str)
+    }
+}
+
+/// A script that can be rebuilt from changed source while the host keeps
+/// calling into it.
+///
+/// Each reload compiles into a fresh TCC state and swaps the current
+/// `ModuleInstance` in one step, so callers never see functions from two
+/// generations mixed. Globals declared `persist static` carry their value over
+/// when name, type and size still match; pointers they hold into the old
+/// instance are not rewritten.
+pub struct AbyssModule {
+    path: String,
+    host_fns: Vec<HostFn>,
+    current: RwLock<Arc<ModuleInstance>>,
+    reloading: Mutex<()>,
+}
+
+impl AbyssModule {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let source = fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        Self::from_source(&source, path)
+    }
+
+    pub fn from_source(source: &str, path: &str) -> Result<Self, String> {
+        Self::with_host_fns(source, path, Vec::new())
+    }
+
+    /// Builds the module with `host_fns` callable from it, in this and every
+    /// later generation.
+    pub fn with_host_fns(source: &str, path: &str, host_fns: Vec<HostFn>) -> Result<Self, String> {
+        let instance = ModuleInstance::build(source, path, &host_fns, 0)?;
+
+        Ok(Self {
+            path: path.to_string(),
+            host_fns,
+            current: RwLock::new(Arc::new(instance)),
+            reloading: Mutex::new(()),
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    /// The instance currently in use. Holding it keeps its code alive across reloads.
+    pub fn instance(&self) -> Arc<ModuleInstance> {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Rebuilds the module from the file it was loaded from.
+    pub fn reload(&self) -> Result<u64, String> {
+        let source = fs::read_to_string(&self.path).map_err(|e| format!("{}: {}", self.path, e))?;
+        self.reload_source(&source)
+    }
+
+    /// Rebuilds the module from `source` and swaps it in, returning the new
+    /// generation. On error the current instance stays in place.
+    ///
+    /// Persistent globals are copied before the swap, so writes made through
+    /// the old instance while the reload runs are lost.
+    pub fn reload_source(&self, source: &str) -> Result<u64, String> {
+        let _reloading = self.reloading.lock().unwrap();
+
+        let previous = self.instance();
+        let instance =
+            ModuleInstance::build(source, &self.path, &self.host_fns, previous.generation + 1)?;
+        instance.migrate_from(&previous);
+
+        let generation = instance.generation;
+        *self.current.write().unwrap() = Arc::new(instance);
+
+        Ok(generation)
+    }
+}
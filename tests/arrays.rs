@@ -0,0 +1,74 @@
+mod common;
+
+use common::{rejects, runs};
+
+#[test]
+fn arrays_are_copied_on_assignment() {
+    runs(
+        "struct Fir { k: [i64; 4] }
+fn app_main: i32 {
+    let a: [i64; 4] = [1, 2, 3, 4]
+    let b = a
+    b[0] = 100
+    let f: Fir
+    f.k = a
+    a[0] = 7
+    let g = f
+    g.k[0] = 9
+    ret (a[0] + b[0] + f.k[0] + g.k[0]) as i32
+}",
+        &[],
+        7 + 100 + 1 + 9,
+    );
+}
+
+#[test]
+fn arrays_are_passed_by_value() {
+    runs(
+        "fn clear(a: [i64; 3]): i64 {
+    a[0] = 0
+    ret a[0]
+}
+fn app_main: i32 {
+    let a: [i64; 3] = [5, 6, 7]
+    ret (clear(a) + a[0]) as i32
+}",
+        &[],
+        5,
+    );
+}
+
+#[test]
+fn length_and_size_are_known_at_compile_time() {
+    runs(
+        "struct Fir { k: [f32; 64] }
+fn sum(p: &i64, n: i64): i64 {
+    let s = 0
+    let i = 0
+    while i < n { s += p[i]  i += 1 }
+    ret s
+}
+fn app_main: i32 {
+    let a: i64[3] = [1, 2, 3]
+    ret (a.len + size([f32; 64]) / 64 + size(Fir) / 64 + sum(a, a.len)) as i32
+}",
+        &[],
+        3 + 4 + 4 + 6,
+    );
+}
+
+#[test]
+fn array_type_errors_are_reported() {
+    rejects(
+        "fn mk: [i64; 3] {\n    let a: [i64; 3]\n    ret a\n}\nfn app_main {}",
+        "function 'mk' cannot return the array type i64[3]",
+    );
+    rejects(
+        "fn app_main {\n    let a: [i64; 3] = [1, 2, 3]\n    let b: [i64; 4] = a\n}",
+        "expected i64[4], found i64[3]",
+    );
+    rejects(
+        "fn app_main {\n    let c = [1, 2.0]\n}",
+        "mismatched array element types: expected i64, found f64",
+    );
+}
@@ -0,0 +1,75 @@
+mod common;
+
+use common::{rejects, runs};
+
+#[test]
+fn captures_are_by_value_unless_listed_by_reference() {
+    runs(
+        "fn app_main: i32 {
+    let gain = 3
+    let f = fn(x: i64): i64 { ret x * gain }
+    gain = 10
+    let total = 0
+    let add = fn[&total](x: i64) { total += x }
+    add(f(2))
+    add(gain)
+    ret total as i32
+}",
+        &[],
+        16,
+    );
+}
+
+#[test]
+fn closures_are_passed_stored_and_nested() {
+    runs(
+        "struct Voice { level: i64, shape: &fn(i64): i64 }
+fn apply(f: &fn(i64): i64, x: i64): i64 { ret f(x) }
+fn app_main: i32 {
+    let k = 3
+    let total = apply(fn(x: i64): i64 { ret x * k }, 7)
+    let v = struct Voice { level: 2, shape: fn[k](x: i64): i64 { ret x + k } }
+    total += v.shape(1) * v.level
+    let counter = 0
+    let outer = fn[&counter] {
+        let inner = fn[&counter](step: i64) { counter += step }
+        inner(2)
+        inner(3)
+    }
+    outer()
+    ret (total + counter) as i32
+}",
+        &[],
+        21 + 8 + 5,
+    );
+}
+
+#[test]
+fn nested_fn_sees_the_enclosing_locals() {
+    runs(
+        "fn make_adder(k: i64): i64 {
+    let base = k
+    fn add(x: i64): i64 { ret x + base }
+    ret add(1)
+}
+fn app_main: i32 { ret make_adder(41) as i32 }",
+        &[],
+        42,
+    );
+}
+
+#[test]
+fn bad_captures_are_rejected() {
+    rejects(
+        "fn app_main {\n    let a = 1\n    let f = fn[a, a](x: i64): i64 { ret x + a }\n}",
+        "`a` is captured twice",
+    );
+    rejects(
+        "fn app_main {\n    let f = fn[&nope] {}\n}",
+        "cannot capture `nope`",
+    );
+    rejects(
+        "static limit: i64 = 4;\nfn app_main {\n    let g = fn[limit]: i64 { ret limit }\n}",
+        "cannot capture `limit`",
+    );
+}
@@ -0,0 +1,69 @@
+// Shared by the test binaries, each of which uses only some of it.
+#![allow(dead_code)]
+
+use std::{fs, process::Command};
+
+/// What running a script through the `abyss` binary gave.
+pub struct Output {
+    pub code: Option<i32>,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+/// Writes `source` to a scratch `test.a` and runs `abyss <command> test.a`
+/// on it with `flags` after the file.
+pub fn abyss(command: &str, source: &str, flags: &[&str]) -> Output {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("test.a");
+    fs::write(&path, source).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_abyss"))
+        .current_dir(dir.path())
+        .arg(command)
+        .arg("test.a")
+        .args(flags)
+        .output()
+        .unwrap();
+    Output {
+        code: output.status.code(),
+        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+    }
+}
+
+/// Runs `source` and asserts that `app_main` returned `code`.
+#[track_caller]
+pub fn runs(source: &str, flags: &[&str], code: i32) -> Output {
+    let output = abyss("run", source, flags);
+    assert_eq!(output.code, Some(code), "stderr:\n{}", output.stderr);
+    output
+}
+
+/// Runs a debug build of `source` and asserts that it panicked with
+/// `message` at `line` of the script.
+#[track_caller]
+pub fn panics(source: &str, line: usize, message: &str) {
+    let output = abyss("run", source, &["--debug"]);
+    let expected = format!("panic at test.a:{}: {}", line, message);
+    assert!(
+        output.stderr.contains(&expected),
+        "expected `{}`, found:\n{}",
+        expected,
+        output.stderr
+    );
+    assert_ne!(output.code, Some(0));
+}
+
+/// Type-checks `source` and asserts that it is rejected with an error
+/// containing `message`.
+#[track_caller]
+pub fn rejects(source: &str, message: &str) {
+    let output = abyss("check", source, &[]);
+    assert!(
+        output.stderr.contains(message),
+        "expected an error containing `{}`, found:\n{}",
+        message,
+        output.stderr
+    );
+    assert_ne!(output.code, Some(0));
+}
@@ -0,0 +1,59 @@
+mod common;
+
+use common::{rejects, runs};
+
+#[test]
+fn const_parameters_size_structs_and_functions() {
+    runs(
+        "struct Delay<const N: usize> { buf: [i64; N], pos: usize }
+impl<const N: usize> Delay<N> {
+    fn tick(self: &Delay<N>, x: i64): i64 {
+        let y = self.buf[self.pos]
+        self.buf[self.pos] = x
+        self.pos += 1
+        if self.pos == N { self.pos = 0 }
+        ret y
+    }
+}
+fn total<const N: usize>(xs: [i64; N]): i64 {
+    let s = 0
+    let i = 0
+    while i < N { s += xs[i]  i += 1 }
+    ret s
+}
+fn count<T, const N: usize>(v: T): i64 {
+    let xs: T[N]
+    ret xs.len
+}
+fn app_main: i32 {
+    let d: Delay<4>
+    d.pos = 0
+    d.buf = [0, 0, 0, 0]
+    let last = 0
+    let i = 1
+    while i <= 10 { last = d.tick(i)  i += 1 }
+    let a: i64[3] = [1, 2, 3]
+    let b: i64[5] = [1, 2, 3, 4, 5]
+    let sizes = size(Delay<4800>) / 4800
+    ret (last + total(a) + total(b) + count::<f64, 16>(1.0) + sizes) as i32
+}",
+        &[],
+        6 + 6 + 15 + 16 + 8,
+    );
+}
+
+#[test]
+fn types_and_constants_are_not_interchangeable() {
+    rejects(
+        "struct Delay<const N: usize> { buf: [f32; N] }\nfn app_main { let d: Delay<f32> }",
+        "in `Delay`: expected a constant for `const N`, found f32",
+    );
+    rejects(
+        "struct Box2<T> { v: T }\nfn app_main { let b: Box2<4> }",
+        "in `Box2`: expected a type for `T`, found the constant 4",
+    );
+    rejects(
+        "fn total<const N: usize>(xs: [i64; N]): i64 { ret N }\nfn app_main { let x = total::<i64>(1) }",
+        "in call to 'total': expected a constant for `const N`, found i64",
+    );
+}
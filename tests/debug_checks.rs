@@ -0,0 +1,131 @@
+mod common;
+
+use common::{panics, runs};
+
+const MIN: &str = "let a: i64 = -9223372036854775807 - 1";
+
+#[test]
+fn index_out_of_bounds() {
+    panics(
+        "fn app_main {
+    let buf: i64[4] = [1, 2, 3, 4]
+    let i = 4
+    buf[i] = 0
+}",
+        4,
+        "index out of bounds: the len is 4 but the index is 4",
+    );
+}
+
+#[test]
+fn division_by_zero() {
+    panics(
+        "fn app_main: i32 {
+    let a = 7
+    let b = 0
+    ret (a / b) as i32
+}",
+        4,
+        "division by zero",
+    );
+}
+
+#[test]
+fn signed_add_overflow() {
+    panics(
+        "fn app_main: i32 {
+    let a: i32 = 2147483647
+    let b: i32 = 1
+    ret a + b
+}",
+        4,
+        "attempt to add with overflow (i32)",
+    );
+}
+
+#[test]
+fn min_divided_by_minus_one() {
+    panics(
+        &format!("fn app_main: i32 {{\n    {MIN}\n    let b = -1\n    ret (a / b) as i32\n}}"),
+        4,
+        "attempt to divide with overflow (i64)",
+    );
+}
+
+#[test]
+fn min_remainder_minus_one() {
+    panics(
+        &format!("fn app_main: i32 {{\n    {MIN}\n    let b = -1\n    ret (a % b) as i32\n}}"),
+        4,
+        "attempt to calculate the remainder with overflow (i64)",
+    );
+}
+
+#[test]
+fn negating_min() {
+    panics(
+        "fn app_main: i32 {
+    let a: i32 = -2147483647 - 1
+    ret -a
+}",
+        3,
+        "attempt to negate with overflow (i32)",
+    );
+}
+
+#[test]
+fn null_pointer_dereference() {
+    panics(
+        "struct Foo { a: i64 }
+fn app_main: i32 {
+    let p: &Foo = null
+    ret p.a as i32
+}",
+        4,
+        "null pointer dereference",
+    );
+}
+
+#[test]
+fn union_read_as_another_variant() {
+    panics(
+        "struct Foo { a: i64 }
+fn app_main: i32 {
+    let u: i64 | Foo = 5
+    let f = u as Foo
+    ret f.a as i32
+}",
+        4,
+        "union accessed as a variant it does not hold",
+    );
+}
+
+#[test]
+fn checked_arithmetic_in_range_passes() {
+    let source = "fn app_main: i32 {
+    let a = -7
+    let b = -2
+    ret (a / b + a % b - a) as i32
+}";
+    runs(source, &["--debug"], 9);
+    runs(source, &[], 9);
+}
+
+#[test]
+fn check_reports_the_line_of_its_expression() {
+    panics(
+        "fn add(a: i64, b: i64): i64 {
+    ret a + b
+}
+
+fn app_main: i32 {
+    let buf: i64[4] = [1, 2, 3, 4]
+    let i = 7
+    let total = add(buf[0],
+        buf[i])
+    ret total as i32
+}",
+        9,
+        "index out of bounds: the len is 4 but the index is 7",
+    );
+}
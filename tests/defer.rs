@@ -0,0 +1,117 @@
+mod common;
+
+use common::{rejects, runs};
+
+// Each test appends digits to a log in the order the code runs.
+const LOG: &str = "fn push(log: &i64, d: i64) { log[0] = log[0] * 10 + d }\n";
+
+#[test]
+fn deferred_statements_run_in_reverse_order() {
+    runs(
+        &format!(
+            "{LOG}
+fn work(log: &i64) {{
+    defer push(log, 1)
+    defer {{ push(log, 2) }}
+    push(log, 3)
+    if true {{
+        defer push(log, 4)
+        push(log, 5)
+    }}
+    push(log, 6)
+}}
+fn app_main: i32 {{
+    let log = 0
+    work(&log)
+    if log == 354621 {{ ret 0 }}
+    ret 1
+}}"
+        ),
+        &[],
+        0,
+    );
+}
+
+#[test]
+fn ret_computes_its_value_before_the_defers() {
+    runs(
+        "fn f: i64 {
+    let x = 1
+    defer x = 100
+    ret x + 1
+}
+fn pick(n: i64, log: &i64): i64 {
+    defer log[0] = 7
+    ret match n { 1 => 10, _ => 20 }
+}
+fn app_main: i32 {
+    let log = 0
+    ret (f() + pick(1, &log) + log) as i32
+}",
+        &[],
+        2 + 10 + 7,
+    );
+}
+
+#[test]
+fn loop_exits_run_the_loop_defers() {
+    runs(
+        &format!(
+            "{LOG}
+fn app_main: i32 {{
+    let log = 0
+    for i in 1 -> 5 {{
+        defer push(&log, 0)
+        if i == 2 {{ next }}
+        if i == 4 {{ out }}
+        push(&log, i)
+    }}
+    if log == 100300 {{ ret 0 }}
+    ret 1
+}}"
+        ),
+        &[],
+        0,
+    );
+}
+
+#[test]
+fn question_mark_runs_the_defers() {
+    runs(
+        &format!(
+            "{LOG}
+error IoError {{ Missing }}
+fn open(n: i64): i64 | IoError {{
+    if n < 0 {{ ret IoError::Missing }}
+    ret n
+}}
+fn load(n: i64, log: &i64): i64 | IoError {{
+    defer push(log, 9)
+    let v = open(n)?
+    push(log, 1)
+    ret v
+}}
+fn app_main: i32 {{
+    let log = 0
+    load(1, &log)
+    load(-1, &log)
+    if log == 199 {{ ret 0 }}
+    ret 1
+}}"
+        ),
+        &[],
+        0,
+    );
+}
+
+#[test]
+fn deferred_statements_cannot_jump() {
+    rejects(
+        "fn f: i64 {\n    defer { ret 3 }\n    ret 1\n}\nfn app_main {}",
+        "a deferred statement cannot `ret`, `out` or `next`",
+    );
+    rejects(
+        "fn app_main {\n    for i in 0 -> 3 { defer out }\n}",
+        "a deferred statement cannot `ret`, `out` or `next`",
+    );
+}
@@ -0,0 +1,123 @@
+mod common;
+
+use common::{rejects, runs};
+
+const DECLS: &str = "
+trait Node {
+    fn process(self: &Self, x: i64): i64
+}
+struct Gain { g: i64 }
+struct Offset { o: i64 }
+struct Plain { v: i64 }
+impl Node for Gain {
+    fn process(self: &Self, x: i64): i64 { ret x * self.g }
+}
+impl Node for Offset {
+    fn process(self: &Self, x: i64): i64 { ret x + self.o }
+}
+struct Slot { node: &dyn Node }
+struct Pair { nodes: &dyn Node[2] }
+";
+
+#[test]
+fn calls_go_through_the_vtable() {
+    runs(
+        &format!(
+            "{DECLS}
+fn run(n: &dyn Node, x: i64): i64 {{ ret n.process(x) }}
+fn pick(g: &Gain): &dyn Node {{ ret g }}
+fn app_main: i32 {{
+    let g = struct Gain {{ g: 2 }}
+    let o = struct Offset {{ o: 1 }}
+    let n: &dyn Node = &g
+    let x = n.process(3)
+    n = &o
+    x = n.process(x)
+    x = run(&g, x) + pick(&g).process(1)
+    ret (x + run((&o) as &dyn Node, 0)) as i32
+}}"
+        ),
+        &[],
+        17,
+    );
+}
+
+#[test]
+fn fields_and_array_literals_convert_to_dyn() {
+    runs(
+        &format!(
+            "{DECLS}
+fn app_main: i32 {{
+    let g = struct Gain {{ g: 2 }}
+    let o = struct Offset {{ o: 1 }}
+    let s = struct Slot {{ node: &g }}
+    let nodes: &dyn Node[2] = [&g, &o]
+    let p = struct Pair {{ nodes: [&o, &g] }}
+    let x = s.node.process(1)
+    x = nodes[1].process(nodes[0].process(x))
+    x = p.nodes[1].process(p.nodes[0].process(x))
+    nodes = [&o, &o]
+    ret nodes[0].process(x) as i32
+}}"
+        ),
+        &[],
+        13,
+    );
+}
+
+#[test]
+fn non_implementors_do_not_convert() {
+    rejects(
+        &format!(
+            "{DECLS}\nfn app_main {{\n    let p = struct Plain {{ v: 1 }}\n    let a: &dyn Node = &p\n}}"
+        ),
+        "the trait `Node` is not implemented for `Plain`",
+    );
+    rejects(
+        &format!(
+            "{DECLS}\nfn app_main {{\n    let p = struct Plain {{ v: 1 }}\n    let s = struct Slot {{ node: &p }}\n}}"
+        ),
+        "the trait `Node` is not implemented for `Plain`",
+    );
+    rejects(
+        &format!(
+            "{DECLS}\nfn app_main {{\n    let g = struct Gain {{ g: 2 }}\n    let nodes: &dyn Node[2] = [&g, 5]\n}}"
+        ),
+        "mismatched types: expected `&dyn Node`, found i64",
+    );
+    rejects(
+        &format!(
+            "{DECLS}\nfn app_main {{\n    let g = struct Gain {{ g: 2 }}\n    let b: &dyn Node = g\n}}"
+        ),
+        "mismatched types: expected `&dyn Node`, found Gain",
+    );
+}
+
+#[test]
+fn dyn_calls_are_checked() {
+    let body = "fn app_main {\n    let g = struct Gain { g: 2 }\n    let n: &dyn Node = &g\n";
+    rejects(
+        &format!("{DECLS}\n{body}    n.reset()\n}}"),
+        "no method `reset` on `&dyn Node`",
+    );
+    rejects(
+        &format!("{DECLS}\n{body}    n.process()\n}}"),
+        "method 'process' takes 1 argument(s) but 0 were supplied",
+    );
+}
+
+#[test]
+fn traits_returning_self_are_not_dyn_compatible() {
+    rejects(
+        &format!(
+            "{DECLS}
+trait Cloner {{ fn dup(self: &Self): Self }}
+impl Cloner for Gain {{ fn dup(self: &Self): Gain {{ ret struct Gain {{ g: self.g }} }} }}
+fn app_main {{
+    let g = struct Gain {{ g: 2 }}
+    let c: &dyn Cloner = &g
+}}"
+        ),
+        "trait `Cloner` cannot be used as `dyn`",
+    );
+}
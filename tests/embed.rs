@@ -0,0 +1,100 @@
+use abyss::{Abyss, AbyssModule, CTarget};
+
+extern "C" fn gain(x: f64, g: f64) -> f64 {
+    x * g
+}
+
+extern "C" fn is_big(x: i64) -> bool {
+    x > 10
+}
+
+const SOURCE: &str = "
+fn process(x: f64): f64 { ret gain(x, 2.0) + 1.0 }
+fn check(x: i64): i64 {
+    if is_big(x) { ret 1 }
+    ret 0
+}
+fn both(a: bool, b: bool): bool { ret a and b }
+";
+
+fn load() -> Abyss<'static, CTarget> {
+    let mut abyss = Abyss::new(SOURCE, "test.a", CTarget::new());
+    abyss
+        .register_fn(
+            "fn gain(x: f64, g: f64): f64",
+            gain as extern "C" fn(f64, f64) -> f64,
+        )
+        .unwrap();
+    abyss
+        .register_fn(
+            "fn is_big(x: i64): bool",
+            is_big as extern "C" fn(i64) -> bool,
+        )
+        .unwrap();
+    abyss.process().unwrap();
+    abyss
+}
+
+#[test]
+fn exports_are_called_with_their_signature() {
+    let abyss = load();
+    let process = abyss
+        .get_fn::<extern "C" fn(f64) -> f64>("process")
+        .unwrap();
+    let check = abyss.get_fn::<extern "C" fn(i64) -> i64>("check").unwrap();
+    let both = abyss
+        .get_fn::<extern "C" fn(bool, bool) -> bool>("both")
+        .unwrap();
+
+    assert_eq!(process.call((0.5,)), 2.0);
+    assert_eq!((check.call((5,)), check.call((50,))), (0, 1));
+    assert!(!both.call((true, false)));
+    assert!(both.call((true, true)));
+}
+
+#[test]
+fn export_with_another_signature_is_refused() {
+    let abyss = load();
+    let err = abyss
+        .get_fn::<extern "C" fn(i64) -> f64>("process")
+        .err()
+        .unwrap();
+    assert!(err.contains("`process` is defined as"), "{}", err);
+    assert!(abyss.get_fn::<extern "C" fn()>("missing").is_err());
+}
+
+#[test]
+fn host_fn_with_another_signature_is_refused() {
+    let mut abyss = Abyss::new(SOURCE, "test.a", CTarget::new());
+    abyss
+        .register_fn(
+            "fn gain(x: f64, g: f64): f64",
+            is_big as extern "C" fn(i64) -> bool,
+        )
+        .unwrap();
+    abyss
+        .register_fn(
+            "fn is_big(x: i64): bool",
+            is_big as extern "C" fn(i64) -> bool,
+        )
+        .unwrap();
+    let err = abyss.process().unwrap_err();
+    assert!(
+        err.contains("host function `gain` is declared as"),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn module_instances_outlive_reloads() {
+    let module = AbyssModule::from_source("fn version: i64 { ret 1 }", "test.a").unwrap();
+    let first = module.instance();
+    let version = first.get_fn::<extern "C" fn() -> i64>("version").unwrap();
+
+    module.reload_source("fn version: i64 { ret 2 }").unwrap();
+    let second = module.instance();
+    let reloaded = second.get_fn::<extern "C" fn() -> i64>("version").unwrap();
+
+    assert_eq!((version.call(()), reloaded.call(())), (1, 2));
+}
@@ -0,0 +1,23 @@
+mod common;
+
+use common::{abyss, runs};
+
+#[test]
+fn result_becomes_the_exit_code() {
+    runs("fn app_main: i32 { ret 5 }", &[], 5);
+    runs("fn app_main: i64 { ret 6 }", &[], 6);
+    runs("fn app_main {}", &[], 0);
+}
+
+#[test]
+fn bool_result_is_read_as_a_byte() {
+    runs("fn app_main: bool { ret true }", &[], 1);
+    runs("fn app_main: bool { ret false }", &[], 0);
+}
+
+#[test]
+fn arguments_reach_argv() {
+    let source = "fn app_main(argc: i32, argv: &&u8): i32 { ret argc }";
+    let output = abyss("run", source, &["--", "a", "b"]);
+    assert_eq!(output.code, Some(3), "{}", output.stderr);
+}
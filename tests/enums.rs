@@ -0,0 +1,73 @@
+mod common;
+
+use common::{rejects, runs};
+
+#[test]
+fn c_style_enums_are_integers() {
+    runs(
+        "enum Wave { Sine, Saw, Square = 7, Noise }
+fn app_main: i32 {
+    let w = Wave::Noise
+    if w is Wave::Noise { ret (w as i64 + Wave::Saw as i64) as i32 }
+    ret 0
+}",
+        &[],
+        9,
+    );
+}
+
+#[test]
+fn payload_variants_are_built_tested_and_matched() {
+    runs(
+        "enum Msg { NoteOn(u8, u8), Gain(f64 | i64), Tick }
+struct Voice { last: Msg }
+fn velocity(m: Msg): i64 {
+    ret match m {
+        Msg::NoteOn(_, vel) => vel as i64,
+        Msg::Gain(g) => match g { i64(n) => n, f64(x) => (x * 2.0) as i64 },
+        Msg::Tick => 1
+    }
+}
+fn app_main: i32 {
+    let voice = struct Voice { last: Msg::NoteOn(60, 100) }
+    if not (voice.last is Msg::NoteOn) { ret 1 }
+    let total = velocity(voice.last) + velocity(Msg::Gain(3)) + velocity(Msg::Gain(2.5))
+    ret (total + velocity(Msg::Tick)) as i32
+}",
+        &[],
+        109,
+    );
+}
+
+#[test]
+fn duplicate_values_and_variants_are_rejected() {
+    rejects(
+        "enum A { X, Y = 0 }\nfn app_main {}",
+        "`A::X` and `A::Y` both have the value 0",
+    );
+    rejects(
+        "enum A { X, X }\nfn app_main {}",
+        "variant `X` is defined more than once in `A`",
+    );
+}
+
+#[test]
+fn wrong_payloads_and_patterns_are_rejected() {
+    let decls = "enum M { P(i64), Q }\nenum N { R }\nfn app_main {}\n";
+    rejects(
+        &format!("{decls}fn f: M {{ ret M::P(1, 2) }}"),
+        "`M::P` carries 1 value(s) but 2 were supplied",
+    );
+    rejects(
+        &format!("{decls}fn f(m: M): i64 {{ ret match m {{ M::P(x, y) => 1, _ => 2 }} }}"),
+        "`M::P` carries 1 value(s), but the pattern binds 2",
+    );
+    rejects(
+        &format!("{decls}fn f(m: M): i64 {{ ret match m {{ N::R => 2, _ => 1 }} }}"),
+        "`N::R` is not a variant of `M`",
+    );
+    rejects(
+        &format!("{decls}fn f(m: M): i64 {{ ret match m {{ M::P(x) => x }} }}"),
+        "non-exhaustive `match`: `M::Q` not covered",
+    );
+}
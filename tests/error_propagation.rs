@@ -0,0 +1,71 @@
+mod common;
+
+use common::{rejects, runs};
+
+const DECLS: &str = "
+error IoError { NotFound, Denied }
+error ParseError { BadDigit(u8), Empty }
+fn open(n: i64): i64 | IoError {
+    if n < 0 { ret IoError::NotFound }
+    if n == 0 { ret IoError::Denied }
+    ret n * 10
+}
+fn parse(c: u8): i64 | ParseError {
+    if c < 48 { ret ParseError::BadDigit(c) }
+    ret (c - 48) as i64
+}
+";
+
+#[test]
+fn question_mark_unwraps_or_returns_the_error() {
+    runs(
+        &format!(
+            "{DECLS}
+fn load(n: i64): i64 | IoError {{
+    let fd = open(n)?
+    ret fd + 1
+}}
+fn both(n: i64, c: u8): i64 | IoError | ParseError {{
+    ret open(n)? + parse(c)?
+}}
+fn code(r: i64 | IoError | ParseError): i64 {{
+    ret match r {{
+        i64(v) => v,
+        IoError(e) => match e {{ IoError::NotFound => 1, IoError::Denied => 2 }},
+        ParseError(e) => 3
+    }}
+}}
+fn app_main: i32 {{
+    let a = load(4)
+    if not (a is i64) {{ ret 100 }}
+    let b = load(-1)
+    if not (b is IoError) {{ ret 101 }}
+    ret (a + code(both(2, 55)) + code(both(0, 55)) + code(both(2, 10))) as i32
+}}"
+        ),
+        &[],
+        41 + 27 + 2 + 3,
+    );
+}
+
+#[test]
+fn operand_must_be_a_value_and_its_errors() {
+    rejects(
+        &format!(
+            "{DECLS}\nstruct Foo {{ a: i64 }}\nfn a: i64 | Foo {{ ret 1 }}\nfn c: i64 | IoError {{ let x = a()?\n ret x }}"
+        ),
+        "`?` expects a union of one value type and its errors, found i64 | Foo",
+    );
+    rejects(
+        &format!("{DECLS}\nfn d: i64 | IoError {{ let x = 5?\n ret x }}"),
+        "`?` expects a union of one value type and its errors, found i64",
+    );
+}
+
+#[test]
+fn enclosing_function_must_hold_the_error() {
+    rejects(
+        &format!("{DECLS}\nfn e: i64 | ParseError {{ let x = open(1)?\n ret x }}"),
+        "`?` cannot return IoError from a function returning i64 | ParseError",
+    );
+}
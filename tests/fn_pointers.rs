@@ -0,0 +1,81 @@
+mod common;
+
+use common::{rejects, runs};
+
+const DECLS: &str = "
+fn twice(x: i64): i64 { ret x * 2 }
+fn square(x: i64): i64 { ret x * x }
+fn halve(x: f64): f64 { ret x / 2.0 }
+";
+
+#[test]
+fn functions_are_values() {
+    runs(
+        &format!(
+            "{DECLS}
+struct Button {{ clicks: i64, on_click: fn(i64): i64 }}
+impl Button {{
+    fn press(self: &Button): i64 {{
+        self.clicks += 1
+        ret self.on_click(self.clicks)
+    }}
+}}
+fn pick(big: bool): fn(i64): i64 {{
+    if big {{ ret square }}
+    ret twice
+}}
+fn apply(f: fn(i64): i64, x: i64): i64 {{ ret f(x) }}
+fn app_main: i32 {{
+    let f: fn(i64): i64 = twice
+    let total = f(1)
+    f = square
+    total += apply(f, 2)
+    let table: fn(i64): i64[2]
+    table[0] = twice
+    table[1] = square
+    let i = 1
+    total += table[0](3) + table[i](3)
+    let b = struct Button {{ clicks: 2, on_click: square }}
+    total += b.press() + pick(true)(4)
+    ret total as i32
+}}"
+        ),
+        &[],
+        2 + 4 + 6 + 9 + 9 + 16,
+    );
+}
+
+#[test]
+fn functions_are_handed_to_c() {
+    runs(
+        "fn qsort(base: &pass, n: usize, width: usize, cmp: fn(&pass, &pass): i32);
+fn by_value(a: &i64, b: &i64): i32 {
+    if *a < *b { ret -1 }
+    if *a > *b { ret 1 }
+    ret 0
+}
+fn app_main: i32 {
+    let nums: i64[5] = [5, 3, 9, 1, 7]
+    qsort(&nums[0], 5, 8, by_value)
+    ret (nums[0] * 10 + nums[4]) as i32
+}",
+        &[],
+        19,
+    );
+}
+
+#[test]
+fn function_pointer_misuse_is_rejected() {
+    rejects(
+        &format!("{DECLS}\nfn app_main {{ let f: fn(i64): i64 = halve }}"),
+        "mismatched types: expected fn(i64): i64, found fn(f64): f64",
+    );
+    rejects(
+        &format!("{DECLS}\nfn app_main {{\n    let g: fn(i64): i64 = twice\n    g(1, 2)\n}}"),
+        "function pointer of type fn(i64): i64 takes 1 argument(s) but 2 were supplied",
+    );
+    rejects(
+        &format!("{DECLS}\nfn app_main {{\n    let n = 3\n    n(4)\n}}"),
+        "cannot call a value of type i64",
+    );
+}
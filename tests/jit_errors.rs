@@ -0,0 +1,30 @@
+use abyss::AbyssJit;
+
+/// Compiles `c_code`, which must fail, and returns the generated line the
+/// error was resolved to.
+fn error_line(c_code: &str) -> Option<usize> {
+    let mut jit = AbyssJit::new().unwrap();
+    jit.compile(c_code).unwrap_err().line
+}
+
+#[test]
+fn error_is_located_in_the_generated_code() {
+    let c_code = "int ok(void) { return 1; }\nint bad(void) { return missing; }\n";
+    assert_eq!(error_line(c_code), Some(2));
+}
+
+#[test]
+fn repeated_source_line_is_told_apart() {
+    // Both functions come from line 3 of the script; only the second is wrong.
+    let c_code = "\
+#line 3 \"test.a\"
+int first(void) { return 1; }
+#line 3 \"test.a\"
+int second(void) { return missing; }
+";
+    let mut jit = AbyssJit::new().unwrap();
+    let err = jit.compile(c_code).unwrap_err();
+    assert_eq!(err.line, Some(4));
+    assert!(err.messages[0].starts_with("test.a:3:"), "{}", err);
+    assert!(err.to_string().contains("> 4 | int second"), "{}", err);
+}
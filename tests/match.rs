@@ -0,0 +1,84 @@
+mod common;
+
+use common::{rejects, runs};
+
+const DECLS: &str = "
+enum Wave { Sine, Saw, Square = 7 }
+enum Msg { NoteOn(u8, u8), Tick }
+struct Foo { a: i64 }
+";
+
+#[test]
+fn match_selects_the_arm() {
+    runs(
+        &format!(
+            "{DECLS}
+fn classify(x: i64): i64 {{
+    ret match x {{ 1 => 10, 2 => 20, _ => 90 }}
+}}
+fn wave(w: Wave): i64 {{
+    ret match w {{ Wave::Sine => 1, Wave::Saw => 2, Wave::Square => 3 }}
+}}
+fn msg(m: Msg): i64 {{
+    ret match m {{ Msg::NoteOn(n, v) => n + v, Msg::Tick => 0 }}
+}}
+fn un(v: i64 | Foo): i64 {{
+    ret match v {{ i64(n) => n, Foo(f) => f.a * 2 }}
+}}
+fn app_main: i32 {{
+    let f = struct Foo {{ a: 4 }}
+    let total = classify(2) + classify(5) + wave(Wave::Square) + msg(Msg::NoteOn(1, 2))
+    ret (total + msg(Msg::Tick) + un(5) + un(f)) as i32
+}}"
+        ),
+        &[],
+        20 + 90 + 3 + 3 + 5 + 8,
+    );
+}
+
+#[test]
+fn match_as_statement_and_assignment() {
+    runs(
+        "fn app_main: i32 {
+    let y = 0
+    let i = 0
+    while i < 10 {
+        match i {
+            3 => { y += 100 }
+            7 => { out }
+            _ => { y += 1 }
+        }
+        i += 1
+    }
+    let z = 0
+    z = match y { 106 => 1, _ => 2 }
+    ret (y + z) as i32
+}",
+        &[],
+        107,
+    );
+}
+
+#[test]
+fn non_exhaustive_match_is_rejected() {
+    rejects(
+        "fn f(x: i64): i64 { ret match x { 1 => 10, 2 => 20 } }\nfn app_main {}",
+        "non-exhaustive `match`: `_` not covered",
+    );
+    rejects(
+        "fn f(x: i64 | f64): i64 { ret match x { i64(n) => n } }\nfn app_main {}",
+        "non-exhaustive `match`: `f64` not covered",
+    );
+}
+
+#[test]
+fn arms_of_different_types_are_rejected() {
+    rejects(
+        "fn f(x: i64): i64 {
+    let y = match x { 1 => 2, _ => 2.5 }
+    ret 0
+}
+fn app_main {}",
+        "`match` arms have incompatible types: expected i64, found f64",
+    );
+}
@@ -0,0 +1,50 @@
+mod common;
+
+use common::runs;
+
+#[test]
+fn narrowed_unions_read_the_variant_they_hold() {
+    runs(
+        "struct Foo { a: i64 }
+struct Bar { b: i64 }
+fn early(v: i64 | Foo | Bar): i64 {
+    if v is Foo { ret v.a }
+    if v is Bar { ret v.b }
+    ret v
+}
+fn negated(v: i64 | Foo | Bar): i64 {
+    if not (v is Foo) {
+        if not (v is i64) { ret v.b * 10 }
+        ret v * 10
+    }
+    ret v.a * 10
+}
+fn app_main: i32 {
+    let a: i64 | Foo | Bar = 1
+    let f: i64 | Foo | Bar = struct Foo { a: 2 }
+    let b: i64 | Foo | Bar = struct Bar { b: 3 }
+    ret (early(a) + early(f) + early(b) + negated(a) + negated(f) + negated(b)) as i32
+}",
+        &[],
+        66,
+    );
+}
+
+#[test]
+fn reassigned_unions_are_tested_again() {
+    runs(
+        "struct Foo { a: i64 }
+fn app_main: i32 {
+    let v: i64 | Foo = 4
+    let total = 0
+    let i = 0
+    while i < 3 {
+        if v is i64 { total += v  v = struct Foo { a: 10 } } else { total += v.a  v = i }
+        i += 1
+    }
+    ret total as i32
+}",
+        &[],
+        4 + 10 + 1,
+    );
+}
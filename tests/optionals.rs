@@ -0,0 +1,51 @@
+mod common;
+
+use common::runs;
+
+#[test]
+fn optionals_unwrap_to_their_value_or_a_default() {
+    runs(
+        "struct Foo { a: i64 }
+fn mk(x: i64): ?Foo {
+    if x > 0 { ret struct Foo { a: x } }
+    ret null
+}
+fn app_main: i32 {
+    let total = 0
+    if let f = mk(3) { total += f.a } else { total += 100 }
+    if let f = mk(0) { total += f.a } else { total += 10 }
+    let d = mk(0) orelse struct Foo { a: 40 }
+    let o = mk(5)
+    if o is Foo { total += o.a }
+    ret (total + d.a) as i32
+}",
+        &[],
+        3 + 10 + 5 + 40,
+    );
+}
+
+#[test]
+fn optional_pointers_are_checked_for_null() {
+    runs(
+        "struct Node { v: i64, link: ?&Node }
+fn sum(n: ?&Node): i64 {
+    let total = 0
+    let p = n
+    while p != null {
+        total += p.v
+        p = p.link
+    }
+    ret total
+}
+fn app_main: i32 {
+    let c = struct Node { v: 3, link: null }
+    let b = struct Node { v: 2, link: &c }
+    let a = struct Node { v: 1, link: &b }
+    let fallback = struct Node { v: 50, link: null }
+    let q = c.link orelse &fallback
+    ret (sum(&a) + q.v) as i32
+}",
+        &[],
+        56,
+    );
+}
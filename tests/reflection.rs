@@ -0,0 +1,66 @@
+mod common;
+
+use common::{rejects, runs};
+
+#[test]
+fn layout_queries_are_answered_at_compile_time() {
+    runs(
+        "struct BA { a: i64, b: f32, c: u8 }
+struct Box<T> { v: T, w: i64 }
+fn app_main: i32 {
+    ret (field_count(BA) * 10 + offset(BA, c) + offset(Box<u8>, w)) as i32
+}",
+        &[],
+        3 * 10 + 12 + 8,
+    );
+}
+
+#[test]
+fn type_names_are_spelled_like_the_source() {
+    runs(
+        "fn strcmp(a: const &char, b: const &char): i32;
+struct AB { a: i64 }
+struct Box<T> { v: T }
+fn app_main: i32 {
+    let wrong = 0
+    if strcmp(type_name(AB), \"AB\") != 0 { wrong += 1 }
+    if strcmp(type_name(Box<f32>), \"Box<f32>\") != 0 { wrong += 1 }
+    if strcmp(type_name(i64 | AB), \"i64 | AB\") != 0 { wrong += 1 }
+    ret wrong
+}",
+        &[],
+        0,
+    );
+}
+
+#[test]
+fn type_ids_tell_similar_types_apart() {
+    runs(
+        "struct AB { a: i64 }
+struct BA { a: i64 }
+fn app_main: i32 {
+    let same = 0
+    if type_id(AB) == type_id(BA) { same += 1 }
+    if type_id(u8) == type_id(u8[4]) { same += 1 }
+    let v: u8[4] | u8 = 7 as u8
+    if v is u8[4] { same += 1 }
+    let w: AB | BA = struct BA { a: 1 }
+    if w is AB { same += 1 }
+    ret same
+}",
+        &[],
+        0,
+    );
+}
+
+#[test]
+fn bad_reflection_arguments_are_rejected() {
+    rejects(
+        "struct AB { a: u8, b: i64 }\nfn app_main { let x = offset(AB, c) }",
+        "no field 'c' on struct 'AB'",
+    );
+    rejects(
+        "fn app_main { let y = field_count(i64) }",
+        "`field_count` expects a struct, found i64",
+    );
+}
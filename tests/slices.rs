@@ -0,0 +1,60 @@
+mod common;
+
+use common::{panics, rejects, runs};
+
+#[test]
+fn slices_view_arrays_and_pointers() {
+    runs(
+        "fn malloc(n: usize): &pass;
+fn sum(s: []i64): i64 {
+    let acc = 0
+    for x in s { acc += x }
+    ret acc
+}
+fn scale(s: []i64, g: i64) {
+    for i in 0 -> s.len { s[i] = s[i] * g }
+}
+fn count<T>(s: []T): i64 { ret s.len }
+fn app_main: i32 {
+    let buf: i64[6] = [1, 2, 3, 4, 5, 6]
+    let s: []i64 = buf
+    let mid = buf[2..5]
+    scale(buf[..2], 10)
+    let p = malloc(3 * size(i64)) as &i64
+    p[0] = 100
+    p[1] = 20
+    p[2] = 30
+    let total = sum(s) + mid.len + mid[0] + s[4..].len + count(p[1..3]) + sum(p[1..3])
+    ret total as i32
+}",
+        &[],
+        48 + 3 + 3 + 2 + 2 + 50,
+    );
+}
+
+#[test]
+fn debug_builds_check_indices_and_ranges() {
+    let decls = "fn app_main(argc: i32, argv: &&u8): i32 {\n    let arr: [i64; 4]\n    let s: []i64 = arr[..]\n    let k = argc + 3\n";
+    panics(
+        &format!("{decls}    ret s[k] as i32\n}}"),
+        5,
+        "index out of bounds: the len is 4 but the index is 4",
+    );
+    panics(
+        &format!("{decls}    let q = s[1..k + 2]\n    ret 0\n}}"),
+        5,
+        "range 1..6 out of bounds for a len of 4",
+    );
+}
+
+#[test]
+fn invalid_slices_are_rejected() {
+    rejects(
+        "fn app_main {\n    let p = 0 as &i64\n    let s = p[1..]\n}",
+        "cannot slice &i64 without an end bound",
+    );
+    rejects(
+        "fn app_main {\n    let x: i64 = 5\n    let t = x[0..1]\n}",
+        "cannot slice type i64",
+    );
+}
@@ -0,0 +1,85 @@
+mod common;
+
+use common::{rejects, runs};
+
+const DECLS: &str = "
+trait Processor {
+    fn tick(self: &Self, x: i64): i64
+    fn id(self: &Self): i64
+}
+struct Gain { g: i64 }
+struct Offset { o: i64 }
+struct Plain { v: i64 }
+impl Processor for Gain {
+    fn tick(self: &Gain, x: i64): i64 { ret x * self.g }
+    fn id(self: &Self): i64 { ret 1 }
+}
+impl Processor for Offset {
+    fn tick(self: &Self, x: i64): i64 { ret x + self.o }
+    fn id(self: &Self): i64 { ret 2 }
+}
+fn run<T: Processor>(p: &T, x: i64): i64 { ret p.tick(x) + p.id() }
+";
+
+#[test]
+fn bounded_generics_are_monomorphized() {
+    runs(
+        &format!(
+            "{DECLS}
+fn app_main: i32 {{
+    let g = struct Gain {{ g: 3 }}
+    let o = struct Offset {{ o: 10 }}
+    ret (run(&g, 5) + run(&o, 5) + o.id()) as i32
+}}"
+        ),
+        &[],
+        16 + 17 + 2,
+    );
+}
+
+#[test]
+fn missing_impl_is_reported_at_the_call() {
+    rejects(
+        &format!(
+            "{DECLS}\nfn app_main {{\n    let v = struct Plain {{ v: 1 }}\n    run(&v, 1)\n}}"
+        ),
+        "the trait `Processor` is not implemented for `Plain`",
+    );
+}
+
+#[test]
+fn unknown_method_on_a_type_parameter_is_rejected() {
+    rejects(
+        &format!(
+            "{DECLS}\nfn twice<T: Processor>(p: &T): i64 {{ ret p.process(1) }}\nfn app_main {{}}"
+        ),
+        "no method `process` on type parameter `T`",
+    );
+}
+
+#[test]
+fn impls_must_match_their_trait() {
+    let decls = "trait P {\n    fn tick(self: &Self, x: f64): f64\n    fn reset(self: &Self)\n}\nstruct Gain { g: f64 }\nfn app_main {}\n";
+    rejects(
+        &format!(
+            "{decls}impl P for Gain {{\n    fn tick(self: &Gain, x: i64): f64 {{ ret 1.0 }}\n    fn reset(self: &Self) {{}}\n}}"
+        ),
+        "method `tick` does not match its declaration in trait `P`",
+    );
+    rejects(
+        &format!(
+            "{decls}impl P for Gain {{\n    fn tick(self: &Gain, x: f64): f64 {{ ret x }}\n}}"
+        ),
+        "`impl P for Gain` is missing `reset`",
+    );
+    rejects(
+        &format!(
+            "{decls}impl P for Gain {{\n    fn tick(self: &Gain, x: f64): f64 {{ ret x }}\n    fn reset(self: &Self) {{}}\n    fn extra(self: &Self) {{}}\n}}"
+        ),
+        "method `extra` is not a member of trait `P`",
+    );
+    rejects(
+        &format!("{decls}impl Nope for Gain {{}}"),
+        "cannot find trait `Nope`",
+    );
+}